@@ -0,0 +1,134 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use eventsourcing::eventstore::{EventStore, ExpectedVersion};
+use eventsourcing::filestore::FileEventStore;
+use eventsourcing::group_commit::{GroupCommitConfig, GroupCommitEventStore};
+use eventsourcing::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const APPENDS_PER_WRITER: u64 = 20;
+
+#[derive(Debug, Default)]
+struct Account {
+    generation: u64,
+}
+
+impl Aggregate for Account {
+    fn aggregate_type() -> &'static str {
+        "Account"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Credited {
+    amount: u64,
+}
+
+impl Event for Credited {
+    fn event_type(&self) -> &'static str {
+        "credited"
+    }
+}
+
+impl AggregateEvent<Account> for Credited {
+    type Error = std::fmt::Error;
+
+    fn apply_to(self, _aggregate: &mut Account) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Time it takes `writers` threads to each append `APPENDS_PER_WRITER` events to their own stream.
+fn run_writers<S>(event_store: Arc<S>, writers: usize) -> Duration
+where
+    S: EventStore<Account, Credited> + Send + Sync + 'static,
+{
+    let barrier = Arc::new(Barrier::new(writers + 1));
+
+    let handles: Vec<_> = (0..writers)
+        .map(|writer| {
+            let event_store = Arc::clone(&event_store);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let id = writer.to_string();
+                barrier.wait();
+                for version in 0..APPENDS_PER_WRITER {
+                    event_store
+                        .append_events(
+                            &id,
+                            vec![Credited { amount: version }],
+                            ExpectedVersion::from_generation(version),
+                        )
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn append_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append_throughput");
+    group.sample_size(10);
+
+    for writers in [1, 8, 64].iter() {
+        group.throughput(Throughput::Elements(*writers as u64 * APPENDS_PER_WRITER));
+
+        group.bench_with_input(BenchmarkId::new("file", writers), writers, |b, &writers| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let dir = TempDir::new().unwrap();
+                        let event_store =
+                            FileEventStore::open(dir.path().join("events.log")).unwrap();
+                        run_writers(Arc::new(event_store), writers)
+                    })
+                    .sum()
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("file_group_commit", writers),
+            writers,
+            |b, &writers| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| {
+                            let dir = TempDir::new().unwrap();
+                            let event_store =
+                                FileEventStore::open(dir.path().join("events.log")).unwrap();
+                            let event_store = GroupCommitEventStore::new(
+                                event_store,
+                                GroupCommitConfig::default(),
+                            )
+                            .unwrap();
+                            run_writers(Arc::new(event_store), writers)
+                        })
+                        .sum()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, append_throughput);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use eventsourcing::eventstore::{
+    EventEnvelope, EventStore, ExpectedVersion, InMemoryEventStore, Position,
+};
+use eventsourcing::projection::{Projection, ProjectionRunner, ReadModelError, ReadModelStore};
+use eventsourcing::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const STREAMS: u64 = 200;
+const EVENTS: u64 = 20_000;
+
+#[derive(Debug, Default)]
+struct Account {
+    generation: u64,
+}
+
+impl Aggregate for Account {
+    fn aggregate_type() -> &'static str {
+        "Account"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+/// Carries its body as JSON, so projecting it costs a decode like reading a stored event does.
+#[derive(Debug, Clone)]
+struct Transacted {
+    body: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransactedBody {
+    amount: i64,
+    reference: String,
+}
+
+impl Event for Transacted {
+    fn event_type(&self) -> &'static str {
+        "transacted"
+    }
+}
+
+impl AggregateEvent<Account> for Transacted {
+    type Error = std::fmt::Error;
+
+    fn apply_to(self, _aggregate: &mut Account) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Balances whose transactions are gathered outside the lock and applied together with the
+/// checkpoint, so commits of different workers overlap.
+#[derive(Default)]
+struct BalanceStore {
+    state: Mutex<(HashMap<String, i64>, HashMap<String, Position>)>,
+}
+
+impl ReadModelStore for BalanceStore {
+    type Tx = Vec<(String, i64)>;
+
+    fn checkpoint(&self, projection: &str) -> Result<Position, ReadModelError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.1.get(projection).cloned().unwrap_or(0))
+    }
+
+    fn commit<F>(
+        &self,
+        projection: &str,
+        position: Position,
+        write: F,
+    ) -> Result<(), ReadModelError>
+    where
+        F: FnOnce(&mut Self::Tx) -> Result<(), ReadModelError>,
+    {
+        let mut changes = Vec::new();
+        write(&mut changes)?;
+
+        let mut state = self.state.lock().unwrap();
+        for (account, amount) in changes {
+            *state.0.entry(account).or_insert(0) += amount;
+        }
+        state.1.insert(projection.to_owned(), position);
+
+        Ok(())
+    }
+}
+
+struct Balances;
+
+impl Projection<Transacted, BalanceStore> for Balances {
+    fn name(&self) -> &str {
+        "balances"
+    }
+
+    fn handle(
+        &self,
+        tx: &mut Vec<(String, i64)>,
+        envelope: &EventEnvelope<Transacted>,
+    ) -> Result<(), ReadModelError> {
+        let body: TransactedBody = serde_json::from_str(&envelope.event.body)
+            .map_err(|err| ReadModelError::Projection(err.to_string()))?;
+        tx.push((envelope.stream_id.clone(), body.amount));
+        Ok(())
+    }
+
+    fn reset(&self, tx: &mut Vec<(String, i64)>) -> Result<(), ReadModelError> {
+        tx.clear();
+        Ok(())
+    }
+}
+
+fn event_store() -> Arc<InMemoryEventStore<Account, Transacted>> {
+    let event_store = Arc::new(InMemoryEventStore::new());
+
+    for event in 0..EVENTS {
+        let body = TransactedBody {
+            amount: event as i64 % 100 - 50,
+            reference: format!("transaction {} of the replay benchmark", event),
+        };
+        event_store
+            .append_events(
+                &format!("Account-{}", event % STREAMS),
+                vec![Transacted {
+                    body: serde_json::to_string(&body).unwrap(),
+                }],
+                ExpectedVersion::Any,
+            )
+            .unwrap();
+    }
+
+    event_store
+}
+
+fn replay(c: &mut Criterion) {
+    let mut group = c.benchmark_group("projection_replay");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(EVENTS));
+
+    let event_store = event_store();
+
+    for workers in [1, 2, 4, 8].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("workers", workers),
+            workers,
+            |b, &workers| {
+                b.iter(|| {
+                    let runner = ProjectionRunner::new(
+                        event_store.clone(),
+                        Arc::new(BalanceStore::default()),
+                        Balances,
+                    )
+                    .with_batch_size(1000)
+                    .with_workers(workers);
+                    runner.run_once().unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, replay);
+criterion_main!(benches);
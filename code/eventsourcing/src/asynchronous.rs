@@ -0,0 +1,422 @@
+//! Futures based counterparts of the store, repository and handler traits.
+//!
+//! `AsyncAdapter` exposes a synchronous implementation through the async traits and
+//! `BlockingAdapter` does the opposite by waiting on the returned futures.
+
+use crate::eventstore::{EventStore, ExpectedVersion, Version, VersionedEvent};
+use crate::handler::CommandHandler;
+use crate::repository::Repository;
+use crate::snapshot::SnapshotStore;
+use crate::{Aggregate, AggregateEvent, CqrsError};
+use futures::future::{self, Future};
+
+pub type BoxFuture<T, E> = Box<dyn Future<Item = T, Error = E>>;
+
+pub trait AsyncEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+{
+    type Error: CqrsError;
+
+    fn read_events(
+        &self,
+        id: &str,
+        since: Version,
+    ) -> BoxFuture<Vec<VersionedEvent<E>>, Self::Error>;
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> BoxFuture<Version, Self::Error>;
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> BoxFuture<(), Self::Error>;
+}
+
+pub trait AsyncSnapshotStore<A>
+where
+    A: Aggregate,
+{
+    type Error: CqrsError;
+
+    fn load_snapshot(&self, id: &str) -> BoxFuture<Option<A>, Self::Error>;
+    fn save_snapshot(&self, id: &str, aggregate: A) -> BoxFuture<(), Self::Error>;
+}
+
+pub trait AsyncRepository<A>
+where
+    A: Aggregate,
+{
+    type Event: AggregateEvent<A>;
+    type Error: CqrsError;
+
+    fn load(&self, id: &str) -> BoxFuture<A, Self::Error>;
+    fn save(&self, id: &str, aggregate: A, events: Vec<Self::Event>) -> BoxFuture<A, Self::Error>;
+}
+
+pub trait AsyncCommandHandler<C> {
+    type Error: CqrsError;
+
+    fn handle(&self, command: C) -> BoxFuture<(), Self::Error>;
+}
+
+/// Runs a synchronous implementation and hands out its result as an already resolved future.
+#[derive(Debug, Clone, Default)]
+pub struct AsyncAdapter<S> {
+    inner: S,
+}
+
+impl<S> AsyncAdapter<S> {
+    pub fn new(inner: S) -> AsyncAdapter<S> {
+        AsyncAdapter { inner }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<A, E, S> AsyncEventStore<A, E> for AsyncAdapter<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + 'static,
+    S: EventStore<A, E>,
+{
+    type Error = S::Error;
+
+    fn read_events(
+        &self,
+        id: &str,
+        since: Version,
+    ) -> BoxFuture<Vec<VersionedEvent<E>>, Self::Error> {
+        Box::new(future::result(self.inner.read_events(id, since)))
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> BoxFuture<Version, Self::Error> {
+        Box::new(future::result(
+            self.inner.append_events(id, events, expected),
+        ))
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> BoxFuture<(), Self::Error> {
+        Box::new(future::result(self.inner.delete_stream(id, expected)))
+    }
+}
+
+impl<A, S> AsyncSnapshotStore<A> for AsyncAdapter<S>
+where
+    A: Aggregate + 'static,
+    S: SnapshotStore<A>,
+{
+    type Error = S::Error;
+
+    fn load_snapshot(&self, id: &str) -> BoxFuture<Option<A>, Self::Error> {
+        Box::new(future::result(self.inner.load_snapshot(id)))
+    }
+
+    fn save_snapshot(&self, id: &str, aggregate: A) -> BoxFuture<(), Self::Error> {
+        Box::new(future::result(self.inner.save_snapshot(id, &aggregate)))
+    }
+}
+
+impl<A, S> AsyncRepository<A> for AsyncAdapter<S>
+where
+    A: Aggregate + 'static,
+    S: Repository<A>,
+{
+    type Event = S::Event;
+    type Error = S::Error;
+
+    fn load(&self, id: &str) -> BoxFuture<A, Self::Error> {
+        Box::new(future::result(self.inner.load(id)))
+    }
+
+    fn save(&self, id: &str, aggregate: A, events: Vec<Self::Event>) -> BoxFuture<A, Self::Error> {
+        Box::new(future::result(self.inner.save(id, aggregate, events)))
+    }
+}
+
+impl<C, S> AsyncCommandHandler<C> for AsyncAdapter<S>
+where
+    S: CommandHandler<C>,
+{
+    type Error = S::Error;
+
+    fn handle(&self, command: C) -> BoxFuture<(), Self::Error> {
+        Box::new(future::result(self.inner.handle(command)))
+    }
+}
+
+/// Waits on the current thread for every future returned by the wrapped async implementation.
+#[derive(Debug, Clone, Default)]
+pub struct BlockingAdapter<S> {
+    inner: S,
+}
+
+impl<S> BlockingAdapter<S> {
+    pub fn new(inner: S) -> BlockingAdapter<S> {
+        BlockingAdapter { inner }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<A, E, S> EventStore<A, E> for BlockingAdapter<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: AsyncEventStore<A, E>,
+{
+    type Error = S::Error;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, S::Error> {
+        self.inner.read_events(id, since).wait()
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, S::Error> {
+        self.inner.append_events(id, events, expected).wait()
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), S::Error> {
+        self.inner.delete_stream(id, expected).wait()
+    }
+}
+
+impl<A, S> SnapshotStore<A> for BlockingAdapter<S>
+where
+    A: Aggregate + Clone,
+    S: AsyncSnapshotStore<A>,
+{
+    type Error = S::Error;
+
+    fn load_snapshot(&self, id: &str) -> Result<Option<A>, S::Error> {
+        self.inner.load_snapshot(id).wait()
+    }
+
+    fn save_snapshot(&self, id: &str, aggregate: &A) -> Result<(), S::Error> {
+        self.inner.save_snapshot(id, aggregate.clone()).wait()
+    }
+}
+
+impl<A, S> Repository<A> for BlockingAdapter<S>
+where
+    A: Aggregate,
+    S: AsyncRepository<A>,
+{
+    type Event = S::Event;
+    type Error = S::Error;
+
+    fn load(&self, id: &str) -> Result<A, S::Error> {
+        self.inner.load(id).wait()
+    }
+
+    fn save(&self, id: &str, aggregate: A, events: Vec<S::Event>) -> Result<A, S::Error> {
+        self.inner.save(id, aggregate, events).wait()
+    }
+}
+
+impl<C, S> CommandHandler<C> for BlockingAdapter<S>
+where
+    S: AsyncCommandHandler<C>,
+{
+    type Error = S::Error;
+
+    fn handle(&self, command: C) -> Result<(), S::Error> {
+        self.inner.handle(command).wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynchronous::*;
+    use crate::eventstore::{EventStoreError, InMemoryEventStore};
+    use crate::repository::{EventSourcedRepository, RepositoryError};
+    use crate::snapshot::{InMemorySnapshotStore, SnapshotStoreError};
+    use crate::test_helpers::{CounterAggregate, CounterError, CounterEvent};
+    use crate::Aggregate;
+    use futures::executor;
+    use std::sync::Arc;
+
+    type CounterEventStore = InMemoryEventStore<CounterAggregate, CounterEvent>;
+    type CounterRepository = EventSourcedRepository<
+        CounterAggregate,
+        CounterEvent,
+        Arc<CounterEventStore>,
+        Arc<InMemorySnapshotStore<CounterAggregate>>,
+    >;
+    type CounterRepositoryError =
+        RepositoryError<EventStoreError, SnapshotStoreError, CounterError>;
+
+    struct AddToCounter {
+        id: &'static str,
+        value: u64,
+    }
+
+    struct AddToCounterHandler {
+        repository: Arc<CounterRepository>,
+    }
+
+    impl CommandHandler<AddToCounter> for AddToCounterHandler {
+        type Error = CounterRepositoryError;
+
+        fn handle(&self, command: AddToCounter) -> Result<(), Self::Error> {
+            let aggregate = self.repository.load(command.id)?;
+            self.repository.save(
+                command.id,
+                aggregate,
+                vec![CounterEvent::Added(command.value)],
+            )?;
+            Ok(())
+        }
+    }
+
+    fn build_repository() -> Arc<CounterRepository> {
+        Arc::new(EventSourcedRepository::with_snapshots(
+            Arc::new(CounterEventStore::new()),
+            Arc::new(InMemorySnapshotStore::new()),
+            1,
+        ))
+    }
+
+    #[test]
+    fn async_event_store_over_sync_store() {
+        // Arrange
+        let event_store = AsyncAdapter::new(CounterEventStore::new());
+        let appended =
+            event_store.append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream);
+
+        // Act
+        let result =
+            executor::spawn(appended.and_then(|_| event_store.read_events("a", 0))).wait_future();
+
+        // Assert
+        assert_eq!(
+            Ok(vec![VersionedEvent {
+                version: 1,
+                event: CounterEvent::Added(1),
+            }]),
+            result
+        );
+    }
+
+    #[test]
+    fn async_event_store_reports_concurrency_error() {
+        // Arrange
+        let event_store = AsyncAdapter::new(CounterEventStore::new());
+        executor::spawn(event_store.append_events(
+            "a",
+            vec![CounterEvent::Added(1)],
+            ExpectedVersion::NoStream,
+        ))
+        .wait_future()
+        .unwrap();
+
+        // Act
+        let result = executor::spawn(event_store.append_events(
+            "a",
+            vec![CounterEvent::Added(1)],
+            ExpectedVersion::NoStream,
+        ))
+        .wait_future();
+
+        // Assert
+        assert_eq!(
+            Err(EventStoreError::WrongExpectedVersion {
+                expected: ExpectedVersion::NoStream,
+                actual: 1,
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn async_snapshot_store_over_sync_store() {
+        // Arrange
+        let snapshot_store = AsyncAdapter::new(InMemorySnapshotStore::new());
+        let aggregate = CounterAggregate {
+            value: 3,
+            generation: 1,
+        };
+
+        // Act
+        let saved = snapshot_store.save_snapshot("a", aggregate.clone());
+        let result =
+            executor::spawn(saved.and_then(|_| snapshot_store.load_snapshot("a"))).wait_future();
+
+        // Assert
+        assert_eq!(Ok(Some(aggregate)), result);
+    }
+
+    #[test]
+    fn async_repository_load_and_save() {
+        // Arrange
+        let repository = AsyncAdapter::new(build_repository());
+
+        // Act
+        let future = repository
+            .load("a")
+            .and_then(|aggregate| repository.save("a", aggregate, vec![CounterEvent::Added(5)]));
+        let result = executor::spawn(future).wait_future().unwrap();
+
+        // Assert
+        assert_eq!(5, result.value);
+        assert_eq!(1, result.generation());
+    }
+
+    #[test]
+    fn async_command_handler_over_sync_handler() {
+        // Arrange
+        let repository = build_repository();
+        let handler = AsyncAdapter::new(AddToCounterHandler {
+            repository: repository.clone(),
+        });
+
+        // Act
+        let result =
+            executor::spawn(handler.handle(AddToCounter { id: "a", value: 2 })).wait_future();
+
+        // Assert
+        assert_eq!(Ok(()), result);
+        assert_eq!(2, repository.load("a").unwrap().value);
+    }
+
+    #[test]
+    fn blocking_adapter_round_trips_async_implementations() {
+        // Arrange
+        let repository = build_repository();
+        let event_store = BlockingAdapter::new(AsyncAdapter::new(CounterEventStore::new()));
+        let handler = BlockingAdapter::new(AsyncAdapter::new(AddToCounterHandler {
+            repository: repository.clone(),
+        }));
+
+        // Act
+        let appended =
+            event_store.append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::Any);
+        let handled = handler.handle(AddToCounter { id: "b", value: 4 });
+
+        // Assert
+        assert_eq!(Ok(1), appended);
+        assert_eq!(Ok(()), handled);
+        assert_eq!(
+            Ok(CounterAggregate {
+                value: 4,
+                generation: 1,
+            }),
+            BlockingAdapter::new(AsyncAdapter::new(repository)).load("b")
+        );
+    }
+}
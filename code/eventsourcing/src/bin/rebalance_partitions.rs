@@ -0,0 +1,31 @@
+//! Offline tool to change the number of partitions of a `PartitionedEventStore`.
+//!
+//!     rebalance_partitions <store-dir> <partitions>
+
+use eventsourcing::partitioned::rebalance;
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: rebalance_partitions <store-dir> <partitions>");
+        process::exit(2);
+    }
+
+    let partitions: usize = match args[2].parse() {
+        Ok(partitions) if partitions > 0 => partitions,
+        _ => {
+            eprintln!("partitions must be a positive number, got {:?}", args[2]);
+            process::exit(2);
+        }
+    };
+
+    match rebalance(&args[1], partitions) {
+        Ok(()) => println!("Rebalanced {} into {} partitions", args[1], partitions),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
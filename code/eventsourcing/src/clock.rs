@@ -0,0 +1,88 @@
+//! Time as seen by schedulers and process managers, injected so tests can move it by hand.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the Unix epoch.
+pub type Timestamp = u64;
+
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+impl<C> Clock for Arc<C>
+where
+    C: Clock,
+{
+    fn now(&self) -> Timestamp {
+        (**self).now()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as Timestamp)
+            .unwrap_or(0)
+    }
+}
+
+/// Clock that always tells the same time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedClock(pub Timestamp);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.0
+    }
+}
+
+/// Clock that only moves when told to.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now
+            .fetch_add(by.as_millis() as Timestamp, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// `now` moved on by `after`, saturating instead of overflowing.
+pub fn deadline(now: Timestamp, after: Duration) -> Timestamp {
+    now.saturating_add(after.as_millis() as Timestamp)
+}
+
+/// When to try again after the `attempts`th failed try: `retry_delay` after `now`, doubled for
+/// every try before it.
+pub fn backoff(now: Timestamp, retry_delay: Duration, attempts: u32) -> Timestamp {
+    let delay = 2u32
+        .checked_pow(attempts - 1)
+        .and_then(|factor| retry_delay.checked_mul(factor))
+        .unwrap_or(Duration::MAX);
+
+    deadline(now, delay)
+}
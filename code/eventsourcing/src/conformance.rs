@@ -0,0 +1,563 @@
+//! Behaviour every event store has to share, whatever keeps its events.
+//!
+//! `event_store_conformance!` runs every case against a store built by an expression, fresh
+//! for each case:
+//!
+//! ```ignore
+//! event_store_conformance!(in_memory, InMemoryEventStore::new());
+//! event_store_conformance!(
+//!     file_store,
+//!     FileEventStore::open(TempDir::new().unwrap().path().join("events.log")).unwrap()
+//! );
+//! ```
+//!
+//! Temporaries of the expression, like the `TempDir` above, live until the case is over.
+//! Stores that aren't a `GlobalEventStore` add `streams_only` to skip the `$all` cases, and
+//! stores that are an `OutboxEventStore` as well add `outbox` to run the outbox cases too.
+
+use crate::eventstore::{EventStore, ExpectedVersion, GlobalEventStore, Version};
+use crate::outbox::{Outbox, OutboxEventStore, OutboxMessage};
+use crate::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Barrier;
+use std::thread;
+
+/// Aggregate whose events the cases append.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub total: u64,
+    pub generation: u64,
+}
+
+impl Aggregate for Tally {
+    fn aggregate_type() -> &'static str {
+        "Tally"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counted(pub u64);
+
+impl Event for Counted {
+    fn event_type(&self) -> &'static str {
+        "counted"
+    }
+}
+
+impl AggregateEvent<Tally> for Counted {
+    type Error = Infallible;
+
+    fn apply_to(self, tally: &mut Tally) -> Result<(), Self::Error> {
+        tally.total += self.0;
+        Ok(())
+    }
+}
+
+fn counted(values: std::ops::Range<u64>) -> Vec<Counted> {
+    values.map(Counted).collect()
+}
+
+fn read<S>(store: &S, id: &str, since: Version) -> Vec<(Version, Counted)>
+where
+    S: EventStore<Tally, Counted>,
+{
+    store
+        .read_events(id, since)
+        .unwrap()
+        .into_iter()
+        .map(|event| (event.version, event.event))
+        .collect()
+}
+
+/// Appended events read back in order, numbered from 1.
+pub fn append_and_read<S>(store: &S)
+where
+    S: EventStore<Tally, Counted>,
+{
+    let appended = store.append_events("tally-1", counted(0..3), ExpectedVersion::NoStream);
+    assert_eq!(3, appended.unwrap(), "version after the first append");
+
+    let appended = store.append_events("tally-1", counted(3..5), ExpectedVersion::Exact(3));
+    assert_eq!(5, appended.unwrap(), "version after the second append");
+
+    assert_eq!(
+        vec![
+            (1, Counted(0)),
+            (2, Counted(1)),
+            (3, Counted(2)),
+            (4, Counted(3)),
+            (5, Counted(4)),
+        ],
+        read(store, "tally-1", 0)
+    );
+    assert_eq!(vec![(5, Counted(4))], read(store, "tally-1", 4));
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "tally-1", 5));
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "missing", 0));
+}
+
+/// Appends are refused, leaving the stream as it was, unless it is at the expected version.
+pub fn expected_version<S>(store: &S)
+where
+    S: EventStore<Tally, Counted>,
+{
+    let refused = [
+        ("tally-1", ExpectedVersion::Exact(1)),
+        ("tally-2", ExpectedVersion::NoStream),
+        ("tally-2", ExpectedVersion::Exact(1)),
+        ("tally-2", ExpectedVersion::Exact(3)),
+    ];
+    store
+        .append_events("tally-2", counted(0..2), ExpectedVersion::NoStream)
+        .unwrap();
+
+    for (id, expected) in refused.iter() {
+        assert!(
+            store.append_events(id, counted(9..10), *expected).is_err(),
+            "appending to {} expecting {:?} was accepted",
+            id,
+            expected
+        );
+    }
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "tally-1", 0));
+    assert_eq!(2, read(store, "tally-2", 0).len());
+
+    let appended = store.append_events("tally-2", counted(2..3), ExpectedVersion::Exact(2));
+    assert_eq!(3, appended.unwrap());
+    let appended = store.append_events("tally-2", counted(3..4), ExpectedVersion::Any);
+    assert_eq!(4, appended.unwrap());
+    let appended = store.append_events("tally-3", counted(0..1), ExpectedVersion::Any);
+    assert_eq!(1, appended.unwrap());
+}
+
+/// Each stream reads back only its own events, even next to streams with similar ids.
+pub fn stream_isolation<S>(store: &S)
+where
+    S: EventStore<Tally, Counted>,
+{
+    let ids = ["tally-1", "tally-10", "tally-1-0", "Tally-1"];
+    for round in 0..3 {
+        for (offset, id) in ids.iter().enumerate() {
+            let value = round * 10 + offset as u64;
+            store
+                .append_events(id, vec![Counted(value)], ExpectedVersion::Any)
+                .unwrap();
+        }
+    }
+
+    for (offset, id) in ids.iter().enumerate() {
+        let offset = offset as u64;
+        assert_eq!(
+            vec![
+                (1, Counted(offset)),
+                (2, Counted(10 + offset)),
+                (3, Counted(20 + offset)),
+            ],
+            read(store, id, 0),
+            "events of {}",
+            id
+        );
+    }
+}
+
+/// Only one of several writers expecting the same version gets to append, and writers that
+/// retry on refusal lose no events.
+pub fn concurrent_appends<S>(store: &S)
+where
+    S: EventStore<Tally, Counted> + Sync,
+{
+    const WRITERS: u64 = 4;
+    const APPENDS: u64 = 25;
+
+    let barrier = Barrier::new(WRITERS as usize);
+    let accepted: u64 = thread::scope(|scope| {
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    barrier.wait();
+                    let result = store.append_events(
+                        "tally-race",
+                        vec![Counted(writer)],
+                        ExpectedVersion::NoStream,
+                    );
+                    result.is_ok() as u64
+                })
+            })
+            .collect();
+        writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .sum()
+    });
+    assert_eq!(1, accepted, "writers creating the same stream");
+
+    thread::scope(|scope| {
+        for writer in 0..WRITERS {
+            scope.spawn(move || {
+                for append in 0..APPENDS {
+                    let value = writer * APPENDS + append;
+                    loop {
+                        let version = read(store, "tally-retry", 0).len() as Version;
+                        let expected = ExpectedVersion::from_generation(version);
+                        if store
+                            .append_events("tally-retry", vec![Counted(value)], expected)
+                            .is_ok()
+                        {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let events = read(store, "tally-retry", 0);
+    let versions: Vec<Version> = events.iter().map(|(version, _)| *version).collect();
+    let mut values: Vec<u64> = events.into_iter().map(|(_, event)| event.0).collect();
+    values.sort_unstable();
+    assert_eq!((1..=WRITERS * APPENDS).collect::<Vec<_>>(), versions);
+    assert_eq!((0..WRITERS * APPENDS).collect::<Vec<_>>(), values);
+}
+
+/// A stream of many events reads back whole and from any version.
+pub fn large_stream<S>(store: &S)
+where
+    S: EventStore<Tally, Counted>,
+{
+    const EVENTS: u64 = 10_000;
+    const BATCH: u64 = 500;
+
+    for first in (0..EVENTS).step_by(BATCH as usize) {
+        store
+            .append_events(
+                "tally-large",
+                counted(first..first + BATCH),
+                ExpectedVersion::Exact(first),
+            )
+            .unwrap();
+    }
+
+    let events = read(store, "tally-large", 0);
+    assert_eq!(EVENTS as usize, events.len());
+    assert!(events
+        .iter()
+        .enumerate()
+        .all(|(i, (version, event))| *version == i as u64 + 1 && event.0 == i as u64));
+    assert_eq!(
+        vec![(EVENTS, Counted(EVENTS - 1))],
+        read(store, "tally-large", EVENTS - 1)
+    );
+}
+
+/// A deleted stream reads as empty and refuses any further append or delete, while the streams
+/// next to it are left alone.
+pub fn delete_stream<S>(store: &S)
+where
+    S: EventStore<Tally, Counted>,
+{
+    store
+        .append_events("tally-1", counted(0..3), ExpectedVersion::NoStream)
+        .unwrap();
+    store
+        .append_events("tally-2", counted(3..4), ExpectedVersion::NoStream)
+        .unwrap();
+
+    assert!(
+        store
+            .delete_stream("tally-1", ExpectedVersion::Exact(2))
+            .is_err(),
+        "deleting at the wrong version was accepted"
+    );
+    assert_eq!(3, read(store, "tally-1", 0).len());
+
+    store
+        .delete_stream("tally-1", ExpectedVersion::Exact(3))
+        .unwrap();
+
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "tally-1", 0));
+    let refused = [
+        ExpectedVersion::Any,
+        ExpectedVersion::NoStream,
+        ExpectedVersion::Exact(3),
+    ];
+    for expected in refused.iter() {
+        assert!(
+            store
+                .append_events("tally-1", counted(9..10), *expected)
+                .is_err(),
+            "appending to a deleted stream expecting {:?} was accepted",
+            expected
+        );
+    }
+    assert!(
+        store
+            .delete_stream("tally-1", ExpectedVersion::Any)
+            .is_err(),
+        "deleting a stream twice was accepted"
+    );
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "tally-1", 0));
+    assert_eq!(vec![(1, Counted(3))], read(store, "tally-2", 0));
+
+    store
+        .delete_stream("tally-3", ExpectedVersion::NoStream)
+        .unwrap();
+    assert!(
+        store
+            .append_events("tally-3", counted(0..1), ExpectedVersion::NoStream)
+            .is_err(),
+        "appending to a stream deleted before it had events was accepted"
+    );
+}
+
+/// `$all` holds the events of every stream in commit order, at increasing positions.
+pub fn global_order<S>(store: &S)
+where
+    S: GlobalEventStore<Tally, Counted>,
+{
+    assert_eq!(0, store.head_position().unwrap(), "head of an empty store");
+    assert!(store.read_all(0, usize::MAX).unwrap().is_empty());
+
+    let appends = [("tally-1", 0..2), ("tally-2", 2..3), ("tally-1", 3..5)];
+    for (id, values) in appends.iter().cloned() {
+        store
+            .append_events(id, counted(values), ExpectedVersion::Any)
+            .unwrap();
+    }
+
+    let all = store.read_all(0, usize::MAX).unwrap();
+    let described: Vec<_> = all
+        .iter()
+        .map(|envelope| {
+            (
+                envelope.stream_id.as_str(),
+                envelope.version,
+                envelope.event.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            ("tally-1", 1, Counted(0)),
+            ("tally-1", 2, Counted(1)),
+            ("tally-2", 1, Counted(2)),
+            ("tally-1", 3, Counted(3)),
+            ("tally-1", 4, Counted(4)),
+        ],
+        described
+    );
+    assert!(
+        all.windows(2)
+            .all(|pair| pair[0].position < pair[1].position),
+        "positions increase"
+    );
+    assert!(all[0].position > 0, "positions start after 0");
+    assert_eq!(all[4].position, store.head_position().unwrap());
+
+    let page = store.read_all(all[1].position, 2).unwrap();
+    assert_eq!(all[2..4].to_vec(), page);
+    assert!(store.read_all(all[4].position, 10).unwrap().is_empty());
+
+    assert_eq!(
+        Some(all[3].position),
+        store.stream_position("tally-1", 3).unwrap()
+    );
+    assert_eq!(None, store.stream_position("tally-1", 5).unwrap());
+    assert_eq!(None, store.stream_position("missing", 1).unwrap());
+}
+
+/// Events of a deleted stream stay in `$all` at their positions, but are no longer found by
+/// stream.
+pub fn global_deletion<S>(store: &S)
+where
+    S: GlobalEventStore<Tally, Counted>,
+{
+    store
+        .append_events("tally-1", counted(0..2), ExpectedVersion::NoStream)
+        .unwrap();
+    store
+        .append_events("tally-2", counted(2..3), ExpectedVersion::NoStream)
+        .unwrap();
+    let all = store.read_all(0, usize::MAX).unwrap();
+
+    store
+        .delete_stream("tally-1", ExpectedVersion::Exact(2))
+        .unwrap();
+
+    assert_eq!(all, store.read_all(0, usize::MAX).unwrap());
+    assert_eq!(all[2].position, store.head_position().unwrap());
+    assert_eq!(None, store.stream_position("tally-1", 1).unwrap());
+    assert_eq!(
+        Some(all[2].position),
+        store.stream_position("tally-2", 1).unwrap()
+    );
+}
+
+/// Outbox messages are committed exactly when the events of their append are, and refused
+/// without any event to commit them with.
+pub fn outbox_messages<S>(store: &S)
+where
+    S: OutboxEventStore<Tally, Counted> + Outbox,
+{
+    let message = |payload: &str| OutboxMessage::new("tally", payload.to_owned());
+    let payloads = |store: &S| -> Vec<String> {
+        store
+            .due(u64::MAX, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message.payload)
+            .collect()
+    };
+
+    let appended = store.append_with_outbox(
+        "tally-1",
+        counted(0..1),
+        ExpectedVersion::NoStream,
+        vec![message("1"), message("2")],
+    );
+    assert_eq!(1, appended.unwrap());
+
+    let refused = [
+        ("tally-1", counted(1..2), ExpectedVersion::NoStream),
+        ("tally-2", Vec::new(), ExpectedVersion::Any),
+    ];
+    for (id, events, expected) in refused.iter().cloned() {
+        assert!(
+            store
+                .append_with_outbox(id, events, expected, vec![message("3")])
+                .is_err(),
+            "appending messages to {} expecting {:?} was accepted",
+            id,
+            expected
+        );
+    }
+
+    assert_eq!(vec!["1".to_owned(), "2".to_owned()], payloads(store));
+    assert_eq!(1, read(store, "tally-1", 0).len());
+    assert_eq!(Vec::<(Version, Counted)>::new(), read(store, "tally-2", 0));
+}
+
+/// Tests running every conformance case against the store `$store` builds, in module `$name`.
+#[macro_export]
+macro_rules! event_store_conformance {
+    ($name:ident, $store:expr) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::event_store_conformance!(@streams $store);
+            $crate::event_store_conformance!(@global $store);
+        }
+    };
+    ($name:ident, $store:expr, outbox) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::event_store_conformance!(@streams $store);
+            $crate::event_store_conformance!(@global $store);
+
+            #[test]
+            fn outbox_messages() {
+                $crate::conformance::outbox_messages(&$store);
+            }
+        }
+    };
+    ($name:ident, $store:expr, streams_only) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::event_store_conformance!(@streams $store);
+        }
+    };
+    (@global $store:expr) => {
+        #[test]
+        fn global_order() {
+            $crate::conformance::global_order(&$store);
+        }
+
+        #[test]
+        fn global_deletion() {
+            $crate::conformance::global_deletion(&$store);
+        }
+    };
+    (@streams $store:expr) => {
+        #[test]
+        fn append_and_read() {
+            $crate::conformance::append_and_read(&$store);
+        }
+
+        #[test]
+        fn expected_version() {
+            $crate::conformance::expected_version(&$store);
+        }
+
+        #[test]
+        fn stream_isolation() {
+            $crate::conformance::stream_isolation(&$store);
+        }
+
+        #[test]
+        fn concurrent_appends() {
+            $crate::conformance::concurrent_appends(&$store);
+        }
+
+        #[test]
+        fn large_stream() {
+            $crate::conformance::large_stream(&$store);
+        }
+
+        #[test]
+        fn delete_stream() {
+            $crate::conformance::delete_stream(&$store);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynchronous::{AsyncAdapter, BlockingAdapter};
+    use crate::eventstore::InMemoryEventStore;
+    use crate::filestore::FileEventStore;
+    use crate::group_commit::{GroupCommitConfig, GroupCommitEventStore};
+    use crate::partitioned::PartitionedEventStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    event_store_conformance!(in_memory, InMemoryEventStore::new(), outbox);
+
+    event_store_conformance!(shared, Arc::new(InMemoryEventStore::new()), outbox);
+
+    event_store_conformance!(
+        file_store,
+        FileEventStore::open(TempDir::new().unwrap().path().join("events.log")).unwrap(),
+        outbox
+    );
+
+    event_store_conformance!(
+        partitioned,
+        PartitionedEventStore::open(TempDir::new().unwrap().path(), 3).unwrap(),
+        outbox
+    );
+
+    event_store_conformance!(
+        blocking,
+        BlockingAdapter::new(AsyncAdapter::new(InMemoryEventStore::new())),
+        streams_only
+    );
+
+    event_store_conformance!(
+        group_commit,
+        GroupCommitEventStore::new(InMemoryEventStore::new(), GroupCommitConfig::default())
+            .unwrap()
+    );
+}
@@ -1,5 +1,737 @@
-pub trait EventStore {}
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::id::{Id, IdGenerator, RandomIds};
+use crate::outbox::{MessageId, Outbox, OutboxEntry, OutboxEventStore, OutboxMessage, OutboxTable};
+use crate::{Aggregate, AggregateEvent, CqrsError};
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::{error::Error, fmt, io};
 
-pub struct DummyEventStore {}
+/// Number of events in a stream. A stream that doesn't exist is at version 0.
+pub type Version = u64;
 
-impl EventStore for DummyEventStore {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    Any,
+    NoStream,
+    Exact(Version),
+}
+
+impl ExpectedVersion {
+    /// Expected version of a stream whose aggregate was rebuilt up to `generation`.
+    pub fn from_generation(generation: Version) -> ExpectedVersion {
+        match generation {
+            0 => ExpectedVersion::NoStream,
+            n => ExpectedVersion::Exact(n),
+        }
+    }
+
+    pub fn check(self, actual: Version) -> Result<(), EventStoreError> {
+        let matches = match self {
+            ExpectedVersion::Any => true,
+            ExpectedVersion::NoStream => actual == 0,
+            ExpectedVersion::Exact(expected) => actual == expected,
+        };
+
+        if matches {
+            Ok(())
+        } else {
+            Err(EventStoreError::WrongExpectedVersion {
+                expected: self,
+                actual,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedEvent<E> {
+    pub version: Version,
+    pub event: E,
+}
+
+/// Place of an event in the store wide order of all committed events, starting at 1.
+pub type Position = u64;
+
+/// Event read from the `$all` stream together with where it was stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventEnvelope<E> {
+    pub position: Position,
+    pub stream_id: String,
+    pub version: Version,
+    /// Handed out by the store's `IdGenerator` when the event was committed.
+    pub event_id: Id,
+    /// When the event was committed, by the store's `Clock`.
+    pub recorded_at: Timestamp,
+    pub event: E,
+}
+
+/// Id and commit time the store gave an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    pub(crate) event_id: Id,
+    pub(crate) recorded_at: Timestamp,
+}
+
+/// Where a store takes the ids and commit times of the events it commits from.
+pub(crate) struct Stamps {
+    pub(crate) clock: Box<dyn Clock + Send + Sync>,
+    pub(crate) ids: Box<dyn IdGenerator + Send + Sync>,
+}
+
+impl Stamps {
+    /// System time and random ids.
+    pub(crate) fn new() -> Stamps {
+        Stamps {
+            clock: Box::new(SystemClock),
+            ids: Box::new(RandomIds),
+        }
+    }
+
+    /// Stamps of `count` events committed together, which share their commit time.
+    pub(crate) fn take(&self, count: usize) -> Vec<Stamp> {
+        let recorded_at = self.clock.now();
+
+        (0..count)
+            .map(|_| Stamp {
+                event_id: self.ids.next_id(),
+                recorded_at,
+            })
+            .collect()
+    }
+}
+
+type ReadEventsResult<E, Err> = Result<Vec<VersionedEvent<E>>, Err>;
+type ReadAllResult<E, Err> = Result<Vec<EventEnvelope<E>>, Err>;
+type AppendEventsResult<Err> = Result<Version, Err>;
+
+pub trait EventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+{
+    type Error: CqrsError;
+
+    /// Events of stream `id` with a version greater than `since`.
+    fn read_events(&self, id: &str, since: Version) -> ReadEventsResult<E, Self::Error>;
+
+    /// Appends `events` to stream `id` and returns the new stream version.
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> AppendEventsResult<Self::Error>;
+
+    /// Deletes stream `id` for good: it reads as empty and refuses any further append or
+    /// delete. Its events stay in `$all`.
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error>;
+}
+
+pub struct AppendRequest<E> {
+    pub id: String,
+    pub events: Vec<E>,
+    pub expected: ExpectedVersion,
+}
+
+/// Event store that can durably commit appends to several streams at once.
+pub trait BatchEventStore<A, E>: EventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+{
+    /// Commits all requests together and returns one result per request, in the same order.
+    fn append_batch(&self, requests: Vec<AppendRequest<E>>)
+        -> Vec<AppendEventsResult<Self::Error>>;
+}
+
+/// Event store that can be read as a single `$all` stream in commit order.
+pub trait GlobalEventStore<A, E>: EventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+{
+    /// At most `limit` events of all streams with a position greater than `since`.
+    fn read_all(&self, since: Position, limit: usize) -> ReadAllResult<E, Self::Error>;
+
+    /// Position of the last event `read_all` can return, 0 when there is none.
+    fn head_position(&self) -> Result<Position, Self::Error>;
+
+    /// Position of event `version` of stream `id`, `None` when the stream has no such event.
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, Self::Error>;
+}
+
+impl<A, E, S> EventStore<A, E> for Arc<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: EventStore<A, E>,
+{
+    type Error = S::Error;
+
+    fn read_events(&self, id: &str, since: Version) -> ReadEventsResult<E, Self::Error> {
+        (**self).read_events(id, since)
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> AppendEventsResult<Self::Error> {
+        (**self).append_events(id, events, expected)
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error> {
+        (**self).delete_stream(id, expected)
+    }
+}
+
+impl<A, E, S> BatchEventStore<A, E> for Arc<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E>,
+{
+    fn append_batch(
+        &self,
+        requests: Vec<AppendRequest<E>>,
+    ) -> Vec<AppendEventsResult<Self::Error>> {
+        (**self).append_batch(requests)
+    }
+}
+
+impl<A, E, S> GlobalEventStore<A, E> for Arc<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: GlobalEventStore<A, E>,
+{
+    fn read_all(&self, since: Position, limit: usize) -> ReadAllResult<E, Self::Error> {
+        (**self).read_all(since, limit)
+    }
+
+    fn head_position(&self) -> Result<Position, Self::Error> {
+        (**self).head_position()
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, Self::Error> {
+        (**self).stream_position(id, version)
+    }
+}
+
+/// Events of every stream in the order they were committed, indexed by stream.
+pub(crate) struct EventLog<E> {
+    log: Vec<EventEnvelope<E>>,
+    streams: HashMap<String, Vec<usize>>,
+    deleted: HashSet<String>,
+}
+
+impl<E> EventLog<E> {
+    pub(crate) fn new() -> EventLog<E> {
+        EventLog {
+            log: Vec::new(),
+            streams: HashMap::new(),
+            deleted: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn stream_version(&self, id: &str) -> Version {
+        self.streams.get(id).map_or(0, Vec::len) as Version
+    }
+
+    /// Refuses changes to a deleted stream or to one not at the `expected` version, and
+    /// returns its version otherwise.
+    pub(crate) fn check(
+        &self,
+        id: &str,
+        expected: ExpectedVersion,
+    ) -> Result<Version, EventStoreError> {
+        if self.deleted.contains(id) {
+            return Err(EventStoreError::StreamDeleted(id.to_owned()));
+        }
+
+        let version = self.stream_version(id);
+        expected.check(version)?;
+
+        Ok(version)
+    }
+
+    /// Tombstones stream `id`; its events are kept for `$all`.
+    pub(crate) fn delete(&mut self, id: &str) {
+        self.streams.remove(id);
+        self.deleted.insert(id.to_owned());
+    }
+
+    pub(crate) fn last_position(&self) -> Position {
+        self.log.last().map_or(0, |envelope| envelope.position)
+    }
+
+    pub(crate) fn stream_position(&self, id: &str, version: Version) -> Option<Position> {
+        let index = self
+            .streams
+            .get(id)?
+            .get((version as usize).checked_sub(1)?)?;
+
+        Some(self.log[*index].position)
+    }
+
+    /// Adds an already validated event; positions must keep increasing.
+    pub(crate) fn push(&mut self, envelope: EventEnvelope<E>) {
+        self.streams
+            .entry(envelope.stream_id.clone())
+            .or_default()
+            .push(self.log.len());
+        self.log.push(envelope);
+    }
+
+    /// Appends `events` to stream `id` at positions following `first_position`, stamped with
+    /// one of `stamps` each.
+    pub(crate) fn append(
+        &mut self,
+        id: &str,
+        events: Vec<E>,
+        first_position: Position,
+        stamps: Vec<Stamp>,
+    ) -> Version {
+        let mut version = self.stream_version(id);
+
+        for (offset, (event, stamp)) in events.into_iter().zip(stamps).enumerate() {
+            version += 1;
+            self.push(EventEnvelope {
+                position: first_position + offset as Position,
+                stream_id: id.to_owned(),
+                version,
+                event_id: stamp.event_id,
+                recorded_at: stamp.recorded_at,
+                event,
+            });
+        }
+
+        version
+    }
+}
+
+impl<E> EventLog<E>
+where
+    E: Clone,
+{
+    pub(crate) fn read_stream(&self, id: &str, since: Version) -> Vec<VersionedEvent<E>> {
+        match self.streams.get(id) {
+            Some(indexes) => indexes
+                .iter()
+                .skip(since as usize)
+                .map(|index| VersionedEvent {
+                    version: self.log[*index].version,
+                    event: self.log[*index].event.clone(),
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn read_all(&self, since: Position, limit: usize) -> Vec<EventEnvelope<E>> {
+        let start = self
+            .log
+            .partition_point(|envelope| envelope.position <= since);
+
+        self.log[start..].iter().take(limit).cloned().collect()
+    }
+}
+
+pub struct InMemoryEventStore<A, E> {
+    events: Mutex<EventLog<E>>,
+    /// Locked after `events` when both are needed.
+    outbox: Mutex<OutboxTable>,
+    stamps: Stamps,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+impl<A, E> InMemoryEventStore<A, E> {
+    pub fn new() -> InMemoryEventStore<A, E> {
+        InMemoryEventStore {
+            events: Mutex::new(EventLog::new()),
+            outbox: Mutex::new(OutboxTable::new()),
+            stamps: Stamps::new(),
+            _aggregate: PhantomData,
+        }
+    }
+
+    /// Tells when events are committed, the system time by default.
+    pub fn with_clock<C>(mut self, clock: C) -> InMemoryEventStore<A, E>
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.stamps.clock = Box::new(clock);
+        self
+    }
+
+    /// Hands out the ids of committed events, random ones by default.
+    pub fn with_ids<G>(mut self, ids: G) -> InMemoryEventStore<A, E>
+    where
+        G: IdGenerator + Send + Sync + 'static,
+    {
+        self.stamps.ids = Box::new(ids);
+        self
+    }
+
+    /// Outbox entries whose delivery was given up, with the last delivery error.
+    pub fn dead_letters(&self) -> Vec<(OutboxEntry, String)> {
+        self.outbox.lock().unwrap().dead_letters()
+    }
+}
+
+impl<A, E> Default for InMemoryEventStore<A, E> {
+    fn default() -> Self {
+        InMemoryEventStore::new()
+    }
+}
+
+impl<A, E> EventStore<A, E> for InMemoryEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    type Error = EventStoreError;
+
+    fn read_events(&self, id: &str, since: Version) -> ReadEventsResult<E, Self::Error> {
+        let events = self.events.lock().unwrap();
+
+        Ok(events.read_stream(id, since))
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> AppendEventsResult<Self::Error> {
+        let mut log = self.events.lock().unwrap();
+
+        log.check(id, expected)?;
+
+        let first_position = log.last_position() + 1;
+
+        let stamps = self.stamps.take(events.len());
+
+        Ok(log.append(id, events, first_position, stamps))
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error> {
+        let mut log = self.events.lock().unwrap();
+
+        log.check(id, expected)?;
+        log.delete(id);
+
+        Ok(())
+    }
+}
+
+impl<A, E> BatchEventStore<A, E> for InMemoryEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    fn append_batch(
+        &self,
+        requests: Vec<AppendRequest<E>>,
+    ) -> Vec<AppendEventsResult<Self::Error>> {
+        let mut log = self.events.lock().unwrap();
+
+        requests
+            .into_iter()
+            .map(|request| {
+                log.check(&request.id, request.expected)?;
+
+                let first_position = log.last_position() + 1;
+
+                let stamps = self.stamps.take(request.events.len());
+
+                Ok(log.append(&request.id, request.events, first_position, stamps))
+            })
+            .collect()
+    }
+}
+
+impl<A, E> GlobalEventStore<A, E> for InMemoryEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    fn read_all(&self, since: Position, limit: usize) -> ReadAllResult<E, Self::Error> {
+        let events = self.events.lock().unwrap();
+
+        Ok(events.read_all(since, limit))
+    }
+
+    fn head_position(&self) -> Result<Position, Self::Error> {
+        Ok(self.events.lock().unwrap().last_position())
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, Self::Error> {
+        Ok(self.events.lock().unwrap().stream_position(id, version))
+    }
+}
+
+impl<A, E> OutboxEventStore<A, E> for InMemoryEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    fn append_with_outbox(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+        messages: Vec<OutboxMessage>,
+    ) -> AppendEventsResult<Self::Error> {
+        if events.is_empty() && !messages.is_empty() {
+            return Err(EventStoreError::MessagesWithoutEvents);
+        }
+
+        let mut log = self.events.lock().unwrap();
+
+        log.check(id, expected)?;
+
+        let first_position = log.last_position() + 1;
+        self.outbox.lock().unwrap().push(messages);
+
+        let stamps = self.stamps.take(events.len());
+
+        Ok(log.append(id, events, first_position, stamps))
+    }
+}
+
+impl<A, E> Outbox for InMemoryEventStore<A, E> {
+    type Error = EventStoreError;
+
+    fn due(&self, now: Timestamp, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        Ok(self.outbox.lock().unwrap().due(now, limit))
+    }
+
+    fn mark_sent(&self, id: MessageId) -> Result<(), Self::Error> {
+        self.outbox.lock().unwrap().mark_sent(id);
+        Ok(())
+    }
+
+    fn mark_failed(
+        &self,
+        id: MessageId,
+        error: &str,
+        retry_at: Option<Timestamp>,
+    ) -> Result<(), Self::Error> {
+        self.outbox.lock().unwrap().mark_failed(id, error, retry_at);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventStoreError {
+    WrongExpectedVersion {
+        expected: ExpectedVersion,
+        actual: Version,
+    },
+    StreamDeleted(String),
+    /// Outbox messages were to be appended without any event to commit them with.
+    MessagesWithoutEvents,
+    Io(String),
+    Codec(String),
+    PartitionCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Error for EventStoreError {}
+
+impl From<io::Error> for EventStoreError {
+    fn from(err: io::Error) -> EventStoreError {
+        EventStoreError::Io(err.to_string())
+    }
+}
+
+impl fmt::Display for EventStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventStoreError::WrongExpectedVersion { expected, actual } => write!(
+                f,
+                "EventStoreError: expected stream version {:?}, found {}",
+                expected, actual
+            ),
+            EventStoreError::StreamDeleted(id) => {
+                write!(f, "EventStoreError: stream {} was deleted", id)
+            }
+            EventStoreError::MessagesWithoutEvents => write!(
+                f,
+                "EventStoreError: outbox messages need an event to be written with"
+            ),
+            EventStoreError::Io(message) => write!(f, "EventStoreError: io: {}", message),
+            EventStoreError::Codec(message) => write!(f, "EventStoreError: codec: {}", message),
+            EventStoreError::PartitionCount { expected: 0, .. } => {
+                write!(f, "EventStoreError: a store needs at least one partition")
+            }
+            EventStoreError::PartitionCount { expected, found } => write!(
+                f,
+                "EventStoreError: store has {} partitions, not {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::eventstore::*;
+    use crate::id::SequentialIds;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+
+    type CounterEventStore = InMemoryEventStore<CounterAggregate, CounterEvent>;
+
+    #[test]
+    fn read_events_returns_only_events_of_requested_stream() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::Any)
+            .unwrap();
+        event_store
+            .append_events("b", vec![CounterEvent::Added(2)], ExpectedVersion::Any)
+            .unwrap();
+        let expected = vec![VersionedEvent {
+            version: 1,
+            event: CounterEvent::Added(1),
+        }];
+
+        // Act
+        let result = event_store.read_events("a", 0).unwrap();
+
+        // Assert
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_events_since_version() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+        let events = vec![
+            CounterEvent::Added(1),
+            CounterEvent::Added(2),
+            CounterEvent::Added(3),
+        ];
+        event_store
+            .append_events("a", events, ExpectedVersion::NoStream)
+            .unwrap();
+
+        // Act
+        let result = event_store.read_events("a", 2).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![VersionedEvent {
+                version: 3,
+                event: CounterEvent::Added(3),
+            }],
+            result
+        );
+    }
+
+    #[test]
+    fn append_fails_on_wrong_expected_version() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        let expected_error = Err(EventStoreError::WrongExpectedVersion {
+            expected: ExpectedVersion::NoStream,
+            actual: 1,
+        });
+
+        // Act
+        let result =
+            event_store.append_events("a", vec![CounterEvent::Added(2)], ExpectedVersion::NoStream);
+
+        // Assert
+        assert_eq!(expected_error, result);
+    }
+
+    #[test]
+    fn append_batch_checks_each_request_against_earlier_ones() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+        let requests = vec![
+            AppendRequest {
+                id: "a".to_owned(),
+                events: vec![CounterEvent::Added(1)],
+                expected: ExpectedVersion::NoStream,
+            },
+            AppendRequest {
+                id: "a".to_owned(),
+                events: vec![CounterEvent::Added(2)],
+                expected: ExpectedVersion::NoStream,
+            },
+        ];
+
+        // Act
+        let result = event_store.append_batch(requests);
+
+        // Assert
+        assert_eq!(
+            vec![
+                Ok(1),
+                Err(EventStoreError::WrongExpectedVersion {
+                    expected: ExpectedVersion::NoStream,
+                    actual: 1,
+                }),
+            ],
+            result
+        );
+    }
+
+    #[test]
+    fn stream_position_locates_event_in_all() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+        for id in &["a", "b", "a"] {
+            event_store
+                .append_events(id, vec![CounterEvent::Added(1)], ExpectedVersion::Any)
+                .unwrap();
+        }
+
+        // Act
+        let second_of_a = event_store.stream_position("a", 2);
+        let missing = event_store.stream_position("b", 2);
+
+        // Assert
+        assert_eq!(Ok(Some(3)), second_of_a);
+        assert_eq!(Ok(None), missing);
+    }
+
+    #[test]
+    fn committed_events_are_stamped_with_id_and_commit_time() {
+        // Arrange
+        let event_store = CounterEventStore::new()
+            .with_clock(FixedClock(1_000))
+            .with_ids(SequentialIds::new(7));
+
+        // Act
+        event_store
+            .append_events(
+                "a",
+                vec![CounterEvent::Added(1), CounterEvent::Added(2)],
+                ExpectedVersion::NoStream,
+            )
+            .unwrap();
+        let result = event_store.read_all(0, 10).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![(7, 1_000), (8, 1_000)],
+            result
+                .iter()
+                .map(|envelope| (envelope.event_id, envelope.recorded_at))
+                .collect::<Vec<_>>()
+        );
+    }
+}
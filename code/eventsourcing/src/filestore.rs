@@ -0,0 +1,989 @@
+//! Event store persisted as an append-only log of JSON lines, one event per line.
+//!
+//! The first record of an append of several events carries how many records the append
+//! wrote, so an append cut short by a crash is dropped as a whole. Outbox messages are written
+//! in the first record of their append, so they are committed exactly when its events are.
+//! Their deliveries are logged to a second file next to the events, with `.outbox` appended to
+//! its name, and deleted streams to a third one with `.deleted` appended.
+
+use crate::clock::{Clock, Timestamp};
+use crate::eventstore::{
+    AppendRequest, BatchEventStore, EventEnvelope, EventLog, EventStore, EventStoreError,
+    ExpectedVersion, GlobalEventStore, Position, Stamp, Stamps, Version, VersionedEvent,
+};
+use crate::id::{Id, IdGenerator};
+use crate::outbox::{MessageId, Outbox, OutboxEntry, OutboxEventStore, OutboxMessage, OutboxTable};
+use crate::{Aggregate, AggregateEvent};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Serialize)]
+struct RecordRef<'a, E> {
+    position: Position,
+    stream: &'a str,
+    version: Version,
+    event_id: Id,
+    recorded_at: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch: Option<usize>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    outbox: &'a [StoredMessage],
+    event: &'a E,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Record<E> {
+    pub(crate) position: Position,
+    pub(crate) stream: String,
+    pub(crate) version: Version,
+    /// Missing from logs written before events had ids, which read as 0.
+    #[serde(default)]
+    pub(crate) event_id: Id,
+    #[serde(default)]
+    pub(crate) recorded_at: Timestamp,
+    /// Records written by the append this record starts, when there are more than one.
+    #[serde(default)]
+    pub(crate) batch: Option<usize>,
+    /// Outbox messages of the append this record starts.
+    #[serde(default)]
+    pub(crate) outbox: Vec<StoredMessage>,
+    pub(crate) event: E,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredMessage {
+    id: MessageId,
+    #[serde(flatten)]
+    message: OutboxMessage,
+}
+
+pub(crate) struct FileState<E> {
+    file: File,
+    len: u64,
+    pub(crate) events: EventLog<E>,
+    pub(crate) outbox: OutboxTable,
+}
+
+/// An append with the outbox messages committed along with it.
+pub(crate) type OutboxRequest<E> = (AppendRequest<E>, Vec<OutboxMessage>);
+
+/// Hands out the positions of events and the ids of outbox messages an append writes.
+pub(crate) trait Allocator {
+    /// First of `count` consecutive positions.
+    fn positions(&mut self, count: u64) -> Position;
+    /// First of `count` consecutive message ids.
+    fn message_ids(&mut self, count: u64) -> MessageId;
+}
+
+pub struct FileEventStore<A, E> {
+    pub(crate) state: Mutex<FileState<E>>,
+    deliveries: Mutex<JsonLog>,
+    /// Locked after `state`.
+    tombstones: Mutex<JsonLog>,
+    stamps: Stamps,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+impl<A, E> FileEventStore<A, E>
+where
+    E: DeserializeOwned,
+{
+    /// Opens the log at `path`, creating it when missing.
+    ///
+    /// An append left partly written by a crash is cut off; any other damage fails the open
+    /// instead of silently dropping committed events.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileEventStore<A, E>, EventStoreError> {
+        let path = path.as_ref();
+        let mut state = FileState::open(path)?;
+
+        let (deliveries, logged) = JsonLog::open::<Delivery, _>(sidecar(path, ".outbox"))?;
+        for delivery in logged {
+            delivery.apply_to(&mut state.outbox);
+        }
+
+        let (tombstones, deleted) = JsonLog::open::<Tombstone, _>(sidecar(path, ".deleted"))?;
+        for tombstone in deleted {
+            state.events.delete(&tombstone.stream);
+        }
+
+        Ok(FileEventStore {
+            state: Mutex::new(state),
+            deliveries: Mutex::new(deliveries),
+            tombstones: Mutex::new(tombstones),
+            stamps: Stamps::new(),
+            _aggregate: PhantomData,
+        })
+    }
+
+    /// Tells when events are committed, the system time by default.
+    pub fn with_clock<C>(mut self, clock: C) -> FileEventStore<A, E>
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.stamps.clock = Box::new(clock);
+        self
+    }
+
+    /// Hands out the ids of committed events, random ones by default.
+    pub fn with_ids<G>(mut self, ids: G) -> FileEventStore<A, E>
+    where
+        G: IdGenerator + Send + Sync + 'static,
+    {
+        self.stamps.ids = Box::new(ids);
+        self
+    }
+
+    /// Outbox entries whose delivery was given up, with the last delivery error.
+    pub fn dead_letters(&self) -> Vec<(OutboxEntry, String)> {
+        self.state.lock().unwrap().outbox.dead_letters()
+    }
+}
+
+/// Path of the file kept next to the log at `path`, named after it with `suffix` appended.
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(suffix);
+    PathBuf::from(sidecar)
+}
+
+impl<E> FileState<E>
+where
+    E: DeserializeOwned,
+{
+    /// Opens the log at `path`, creating it when missing, and cuts off a torn last append.
+    pub(crate) fn open(path: &Path) -> Result<FileState<E>, EventStoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let (events, outbox, len) = recover(&contents)?;
+
+        if len < contents.len() as u64 {
+            file.set_len(len)?;
+            file.sync_data()?;
+        }
+
+        Ok(FileState {
+            file,
+            len,
+            events,
+            outbox,
+        })
+    }
+}
+
+fn recover<E>(contents: &[u8]) -> Result<(EventLog<E>, OutboxTable, u64), EventStoreError>
+where
+    E: DeserializeOwned,
+{
+    let mut events = EventLog::new();
+    let mut outbox = OutboxTable::new();
+    let (records, len) = committed_records(contents)?;
+
+    for (offset, record) in records {
+        if record.version != events.stream_version(&record.stream) + 1 {
+            return Err(corrupted(offset, "out of order stream version"));
+        }
+        if record.position <= events.last_position() {
+            return Err(corrupted(offset, "out of order position"));
+        }
+
+        for stored in record.outbox {
+            outbox.insert(stored.id, stored.message);
+        }
+        events.push(EventEnvelope {
+            position: record.position,
+            stream_id: record.stream,
+            version: record.version,
+            event_id: record.event_id,
+            recorded_at: record.recorded_at,
+            event: record.event,
+        });
+    }
+
+    Ok((events, outbox, len))
+}
+
+/// Records, each with the byte offset of its line, and the length of the log they take up.
+pub(crate) type CommittedRecords<E> = (Vec<(usize, Record<E>)>, u64);
+
+/// Records of every append written in full.
+pub(crate) fn committed_records<E>(contents: &[u8]) -> Result<CommittedRecords<E>, EventStoreError>
+where
+    E: DeserializeOwned,
+{
+    let mut committed = Vec::new();
+    let mut pending = Vec::new();
+    let mut missing = 0;
+    let mut offset = 0;
+    let mut len = 0;
+
+    let mut lines: Vec<&[u8]> = contents.split(|byte| *byte == b'\n').collect();
+    // Whatever follows the last newline was never committed.
+    lines.pop();
+
+    for line in lines {
+        let record: Record<E> =
+            serde_json::from_slice(line).map_err(|err| corrupted(offset, &err.to_string()))?;
+
+        match (record.batch, missing) {
+            (Some(0), _) => return Err(corrupted(offset, "empty batch")),
+            (Some(_), missing) if missing > 0 => {
+                return Err(corrupted(offset, "batch started inside another batch"))
+            }
+            (Some(size), _) => missing = size,
+            (None, 0) => missing = 1,
+            (None, _) => (),
+        }
+
+        pending.push((offset, record));
+        missing -= 1;
+        offset += line.len() + 1;
+
+        if missing == 0 {
+            committed.append(&mut pending);
+            len = offset;
+        }
+    }
+
+    Ok((committed, len as u64))
+}
+
+fn corrupted(offset: usize, reason: &str) -> EventStoreError {
+    EventStoreError::Codec(format!("corrupted record at byte {}: {}", offset, reason))
+}
+
+impl<E> FileState<E>
+where
+    E: Serialize,
+{
+    /// Writes every accepted request with its outbox messages with a single write and a single
+    /// fsync.
+    ///
+    /// `allocate` hands out the position of the first event of each accepted request; the
+    /// following events of that request take the positions right after it. Messages get their
+    /// ids the same way, and events their stamps from `stamps`.
+    pub(crate) fn commit(
+        &mut self,
+        requests: Vec<OutboxRequest<E>>,
+        allocate: &mut dyn Allocator,
+        stamps: &Stamps,
+    ) -> Vec<Result<Version, EventStoreError>> {
+        let mut buffer = Vec::new();
+        let mut staged: HashMap<String, Version> = HashMap::new();
+        let mut accepted = Vec::new();
+        let mut results = Vec::with_capacity(requests.len());
+
+        for (request, messages) in requests {
+            if let Err(err) = self.events.check(&request.id, ExpectedVersion::Any) {
+                results.push(Err(err));
+                continue;
+            }
+
+            let events = &self.events;
+            let current = *staged
+                .entry(request.id.clone())
+                .or_insert_with(|| events.stream_version(&request.id));
+
+            if let Err(err) = request.expected.check(current) {
+                results.push(Err(err));
+                continue;
+            }
+            if request.events.is_empty() && !messages.is_empty() {
+                results.push(Err(EventStoreError::MessagesWithoutEvents));
+                continue;
+            }
+
+            let first_position = if request.events.is_empty() {
+                0
+            } else {
+                allocate.positions(request.events.len() as u64)
+            };
+            let first_id = if messages.is_empty() {
+                0
+            } else {
+                allocate.message_ids(messages.len() as u64)
+            };
+            let outbox: Vec<_> = messages
+                .into_iter()
+                .enumerate()
+                .map(|(offset, message)| StoredMessage {
+                    id: first_id + offset as MessageId,
+                    message,
+                })
+                .collect();
+
+            let stamps = stamps.take(request.events.len());
+
+            match encode(&request, current, first_position, &stamps, &outbox) {
+                Ok(encoded) => buffer.extend(encoded),
+                Err(err) => {
+                    results.push(Err(err));
+                    continue;
+                }
+            }
+
+            let version = current + request.events.len() as Version;
+            staged.insert(request.id.clone(), version);
+            results.push(Ok(version));
+            accepted.push((request, first_position, stamps, outbox));
+        }
+
+        if let Err(err) = self.write(&buffer) {
+            return fail_all(results, err);
+        }
+
+        for (request, first_position, stamps, outbox) in accepted {
+            for stored in outbox {
+                self.outbox.insert(stored.id, stored.message);
+            }
+            self.events
+                .append(&request.id, request.events, first_position, stamps);
+        }
+
+        results
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<(), EventStoreError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let written = self
+            .file
+            .write_all(buffer)
+            .and_then(|_| self.file.sync_data());
+
+        if let Err(err) = written {
+            // Leave no partially written batch behind for the next open to pick up.
+            self.file.set_len(self.len)?;
+            return Err(err.into());
+        }
+
+        self.len += buffer.len() as u64;
+        Ok(())
+    }
+}
+
+fn encode<E>(
+    request: &AppendRequest<E>,
+    current: Version,
+    first_position: Position,
+    stamps: &[Stamp],
+    outbox: &[StoredMessage],
+) -> Result<Vec<u8>, EventStoreError>
+where
+    E: Serialize,
+{
+    let mut encoded = Vec::new();
+    let batch = request.events.len();
+
+    for (offset, (event, stamp)) in request.events.iter().zip(stamps).enumerate() {
+        let record = RecordRef {
+            position: first_position + offset as Position,
+            stream: &request.id,
+            version: current + offset as Version + 1,
+            event_id: stamp.event_id,
+            recorded_at: stamp.recorded_at,
+            batch: if offset == 0 && batch > 1 {
+                Some(batch)
+            } else {
+                None
+            },
+            outbox: if offset == 0 { outbox } else { &[] },
+            event,
+        };
+        serde_json::to_writer(&mut encoded, &record)
+            .map_err(|err| EventStoreError::Codec(err.to_string()))?;
+        encoded.push(b'\n');
+    }
+
+    Ok(encoded)
+}
+
+fn fail_all(
+    results: Vec<Result<Version, EventStoreError>>,
+    err: EventStoreError,
+) -> Vec<Result<Version, EventStoreError>> {
+    results
+        .into_iter()
+        .map(|result| result.and_then(|_| Err(err.clone())))
+        .collect()
+}
+
+/// A delivery of an outbox message, as logged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Delivery {
+    Sent(MessageId),
+    Failed {
+        id: MessageId,
+        error: String,
+        retry_at: Option<Timestamp>,
+    },
+}
+
+impl Delivery {
+    pub(crate) fn apply_to(&self, outbox: &mut OutboxTable) {
+        match self {
+            Delivery::Sent(id) => outbox.mark_sent(*id),
+            Delivery::Failed {
+                id,
+                error,
+                retry_at,
+            } => outbox.mark_failed(*id, error, *retry_at),
+        }
+    }
+}
+
+/// A deleted stream, as logged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Tombstone {
+    pub(crate) stream: String,
+}
+
+/// Append-only log of JSON lines kept next to the events, like outbox deliveries or
+/// tombstones.
+pub(crate) struct JsonLog {
+    file: File,
+    len: u64,
+}
+
+impl JsonLog {
+    /// Opens the log at `path`, creating it when missing, with the entries logged so far.
+    pub(crate) fn open<T, P>(path: P) -> Result<(JsonLog, Vec<T>), EventStoreError>
+    where
+        T: DeserializeOwned,
+        P: AsRef<Path>,
+    {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut entries = Vec::new();
+        let mut len = 0;
+        let mut lines: Vec<&[u8]> = contents.split(|byte| *byte == b'\n').collect();
+        // Whatever follows the last newline was never committed.
+        lines.pop();
+
+        for line in lines {
+            let entry =
+                serde_json::from_slice(line).map_err(|err| corrupted(len, &err.to_string()))?;
+            entries.push(entry);
+            len += line.len() + 1;
+        }
+
+        if len < contents.len() {
+            file.set_len(len as u64)?;
+            file.sync_data()?;
+        }
+
+        Ok((
+            JsonLog {
+                file,
+                len: len as u64,
+            },
+            entries,
+        ))
+    }
+
+    pub(crate) fn record<T: Serialize>(&mut self, entry: &T) -> Result<(), EventStoreError> {
+        let mut line =
+            serde_json::to_vec(entry).map_err(|err| EventStoreError::Codec(err.to_string()))?;
+        line.push(b'\n');
+
+        let written = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data());
+
+        if let Err(err) = written {
+            self.file.set_len(self.len)?;
+            return Err(err.into());
+        }
+
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Positions and message ids following the last ones of a single log.
+struct NextInLog {
+    position: Position,
+    message_id: MessageId,
+}
+
+impl Allocator for NextInLog {
+    fn positions(&mut self, count: u64) -> Position {
+        let first_position = self.position;
+        self.position += count;
+        first_position
+    }
+
+    fn message_ids(&mut self, count: u64) -> MessageId {
+        let first_id = self.message_id;
+        self.message_id += count;
+        first_id
+    }
+}
+
+impl<A, E> FileEventStore<A, E>
+where
+    E: Serialize,
+{
+    fn commit(&self, requests: Vec<OutboxRequest<E>>) -> Vec<Result<Version, EventStoreError>> {
+        let mut state = self.state.lock().unwrap();
+        let mut next = NextInLog {
+            position: state.events.last_position() + 1,
+            message_id: state.outbox.next_id(),
+        };
+
+        state.commit(requests, &mut next, &self.stamps)
+    }
+
+    fn record(&self, delivery: Delivery) -> Result<(), EventStoreError> {
+        self.deliveries.lock().unwrap().record(&delivery)?;
+        delivery.apply_to(&mut self.state.lock().unwrap().outbox);
+        Ok(())
+    }
+}
+
+impl<A, E> EventStore<A, E> for FileEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    type Error = EventStoreError;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, Self::Error> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.events.read_stream(id, since))
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, Self::Error> {
+        self.append_with_outbox(id, events, expected, Vec::new())
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.events.check(id, expected)?;
+        self.tombstones.lock().unwrap().record(&Tombstone {
+            stream: id.to_owned(),
+        })?;
+        state.events.delete(id);
+
+        Ok(())
+    }
+}
+
+impl<A, E> BatchEventStore<A, E> for FileEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    fn append_batch(&self, requests: Vec<AppendRequest<E>>) -> Vec<Result<Version, Self::Error>> {
+        self.commit(
+            requests
+                .into_iter()
+                .map(|request| (request, Vec::new()))
+                .collect(),
+        )
+    }
+}
+
+impl<A, E> GlobalEventStore<A, E> for FileEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    fn read_all(
+        &self,
+        since: Position,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<E>>, Self::Error> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.events.read_all(since, limit))
+    }
+
+    fn head_position(&self) -> Result<Position, Self::Error> {
+        Ok(self.state.lock().unwrap().events.last_position())
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, Self::Error> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .events
+            .stream_position(id, version))
+    }
+}
+
+impl<A, E> OutboxEventStore<A, E> for FileEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    fn append_with_outbox(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<Version, Self::Error> {
+        let request = AppendRequest {
+            id: id.to_owned(),
+            events,
+            expected,
+        };
+
+        self.commit(vec![(request, messages)]).remove(0)
+    }
+}
+
+impl<A, E> Outbox for FileEventStore<A, E>
+where
+    E: Serialize,
+{
+    type Error = EventStoreError;
+
+    fn due(&self, now: Timestamp, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        Ok(self.state.lock().unwrap().outbox.due(now, limit))
+    }
+
+    fn mark_sent(&self, id: MessageId) -> Result<(), Self::Error> {
+        self.record(Delivery::Sent(id))
+    }
+
+    fn mark_failed(
+        &self,
+        id: MessageId,
+        error: &str,
+        retry_at: Option<Timestamp>,
+    ) -> Result<(), Self::Error> {
+        self.record(Delivery::Failed {
+            id,
+            error: error.to_owned(),
+            retry_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::FixedClock;
+    use crate::eventstore::*;
+    use crate::filestore::FileEventStore;
+    use crate::id::SequentialIds;
+    use crate::outbox::{Outbox, OutboxEntry, OutboxEventStore, OutboxMessage};
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    type CounterFileEventStore = FileEventStore<CounterAggregate, CounterEvent>;
+
+    #[test]
+    fn appended_events_survive_reopening() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path).unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(2)], ExpectedVersion::Exact(1))
+            .unwrap();
+        drop(event_store);
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+        let result = reopened.read_events("a", 0).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                VersionedEvent {
+                    version: 1,
+                    event: CounterEvent::Added(1),
+                },
+                VersionedEvent {
+                    version: 2,
+                    event: CounterEvent::Added(2),
+                },
+            ],
+            result
+        );
+    }
+
+    #[test]
+    fn torn_last_record_is_dropped_on_open() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path).unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        drop(event_store);
+        let committed_len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"stream\":\"a\",\"vers").unwrap();
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+
+        // Assert
+        assert_eq!(1, reopened.read_events("a", 0).unwrap().len());
+        assert_eq!(committed_len, fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn partly_written_append_is_dropped_on_open() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path).unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        let committed_len = fs::metadata(&path).unwrap().len();
+        event_store
+            .append_events(
+                "a",
+                vec![
+                    CounterEvent::Added(2),
+                    CounterEvent::Added(3),
+                    CounterEvent::Added(4),
+                ],
+                ExpectedVersion::Exact(1),
+            )
+            .unwrap();
+        drop(event_store);
+        let contents = fs::read(&path).unwrap();
+        let last_line = contents[..contents.len() - 1]
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .unwrap();
+        fs::write(&path, &contents[..=last_line]).unwrap();
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+
+        // Assert
+        assert_eq!(1, reopened.read_events("a", 0).unwrap().len());
+        assert_eq!(committed_len, fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn corrupted_committed_record_fails_open() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        fs::write(&path, b"not json\n").unwrap();
+
+        // Act
+        let result = CounterFileEventStore::open(&path);
+
+        // Assert
+        match result {
+            Err(EventStoreError::Codec(_)) => (),
+            _ => panic!("Corrupted log was opened"),
+        }
+    }
+
+    #[test]
+    fn batch_reports_result_per_request() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterFileEventStore::open(dir.path().join("events.log")).unwrap();
+        let requests = vec![
+            AppendRequest {
+                id: "a".to_owned(),
+                events: vec![CounterEvent::Added(1)],
+                expected: ExpectedVersion::NoStream,
+            },
+            AppendRequest {
+                id: "a".to_owned(),
+                events: vec![CounterEvent::Added(2)],
+                expected: ExpectedVersion::NoStream,
+            },
+            AppendRequest {
+                id: "a".to_owned(),
+                events: vec![CounterEvent::Added(3)],
+                expected: ExpectedVersion::Exact(1),
+            },
+        ];
+
+        // Act
+        let result = event_store.append_batch(requests);
+
+        // Assert
+        assert_eq!(
+            vec![
+                Ok(1),
+                Err(EventStoreError::WrongExpectedVersion {
+                    expected: ExpectedVersion::NoStream,
+                    actual: 1,
+                }),
+                Ok(2),
+            ],
+            result
+        );
+        assert_eq!(2, event_store.read_events("a", 0).unwrap().len());
+    }
+
+    #[test]
+    fn outbox_messages_and_their_deliveries_survive_reopening() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path).unwrap();
+        event_store
+            .append_with_outbox(
+                "a",
+                vec![CounterEvent::Added(1), CounterEvent::Added(2)],
+                ExpectedVersion::NoStream,
+                vec![
+                    OutboxMessage::new("added", "1".to_owned()),
+                    OutboxMessage::new("added", "2".to_owned()),
+                ],
+            )
+            .unwrap();
+        event_store
+            .append_with_outbox(
+                "b",
+                vec![CounterEvent::Added(3)],
+                ExpectedVersion::NoStream,
+                vec![OutboxMessage::new("added", "3".to_owned())],
+            )
+            .unwrap();
+        event_store.mark_sent(1).unwrap();
+        event_store.mark_failed(2, "unreachable", None).unwrap();
+        drop(event_store);
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+        let due = reopened.due(0, 10);
+        reopened
+            .append_with_outbox(
+                "b",
+                vec![CounterEvent::Added(4)],
+                ExpectedVersion::Exact(1),
+                vec![OutboxMessage::new("added", "4".to_owned())],
+            )
+            .unwrap();
+
+        // Assert
+        let entry = |id, payload: &str, attempts| OutboxEntry {
+            id,
+            message: OutboxMessage::new("added", payload.to_owned()),
+            attempts,
+        };
+        assert_eq!(Ok(vec![entry(3, "3", 0)]), due);
+        assert_eq!(
+            vec![(entry(2, "2", 1), "unreachable".to_owned())],
+            reopened.dead_letters()
+        );
+        assert_eq!(
+            Ok(vec![entry(3, "3", 0), entry(4, "4", 0)]),
+            reopened.due(0, 10)
+        );
+    }
+
+    #[test]
+    fn outbox_messages_without_events_are_refused() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterFileEventStore::open(dir.path().join("events.log")).unwrap();
+
+        // Act
+        let result = event_store.append_with_outbox(
+            "a",
+            vec![],
+            ExpectedVersion::Any,
+            vec![OutboxMessage::new("added", "1".to_owned())],
+        );
+
+        // Assert
+        assert_eq!(Err(EventStoreError::MessagesWithoutEvents), result);
+        assert_eq!(Ok(vec![]), event_store.due(0, 10));
+    }
+
+    #[test]
+    fn event_ids_and_commit_times_survive_reopening() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path)
+            .unwrap()
+            .with_clock(FixedClock(1_000))
+            .with_ids(SequentialIds::new(7));
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        let appended = event_store.read_all(0, 10).unwrap();
+        drop(event_store);
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+        let result = reopened.read_all(0, 10).unwrap();
+
+        // Assert
+        assert_eq!(7, result[0].event_id);
+        assert_eq!(1_000, result[0].recorded_at);
+        assert_eq!(appended, result);
+    }
+
+    #[test]
+    fn deleted_streams_stay_deleted_after_reopening() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let event_store = CounterFileEventStore::open(&path).unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        event_store
+            .delete_stream("a", ExpectedVersion::Exact(1))
+            .unwrap();
+        drop(event_store);
+
+        // Act
+        let reopened = CounterFileEventStore::open(&path).unwrap();
+        let appended =
+            reopened.append_events("a", vec![CounterEvent::Added(2)], ExpectedVersion::Any);
+
+        // Assert
+        assert_eq!(Ok(vec![]), reopened.read_events("a", 0));
+        assert_eq!(
+            Err(EventStoreError::StreamDeleted("a".to_owned())),
+            appended
+        );
+        assert_eq!(1, reopened.read_all(0, 10).unwrap().len());
+    }
+}
@@ -0,0 +1,395 @@
+//! Gathers concurrent appends into batches so a persistent store pays for one fsync or one
+//! transaction per batch instead of one per append.
+
+use crate::eventstore::{
+    AppendRequest, BatchEventStore, EventEnvelope, EventStore, ExpectedVersion, GlobalEventStore,
+    Position, Version, VersionedEvent,
+};
+use crate::{Aggregate, AggregateEvent};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupCommitConfig {
+    /// Most appends committed together.
+    pub max_batch_size: usize,
+    /// How long the first append of a batch waits for others to join it. Appends that queued
+    /// up while the previous batch was being written join the next batch without waiting.
+    pub max_wait: Duration,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        GroupCommitConfig {
+            max_batch_size: 128,
+            max_wait: Duration::from_millis(0),
+        }
+    }
+}
+
+struct PendingAppend<E, Err> {
+    request: AppendRequest<E>,
+    reply: Sender<Result<Version, Err>>,
+}
+
+/// Wraps a `BatchEventStore` and funnels every append through a single writer thread.
+///
+/// Reads and deletes go straight to the wrapped store.
+pub struct GroupCommitEventStore<A, E, S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E>,
+{
+    store: Arc<S>,
+    sender: Option<Sender<PendingAppend<E, S::Error>>>,
+    writer: Option<JoinHandle<()>>,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+impl<A, E, S> GroupCommitEventStore<A, E, S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Send + 'static,
+    S: BatchEventStore<A, E> + Send + Sync + 'static,
+    S::Error: From<io::Error>,
+{
+    pub fn new(
+        store: S,
+        config: GroupCommitConfig,
+    ) -> Result<GroupCommitEventStore<A, E, S>, S::Error> {
+        let store = Arc::new(store);
+        let (sender, receiver) = mpsc::channel();
+
+        let writer_store = Arc::clone(&store);
+        let writer = thread::Builder::new()
+            .name("group-commit".to_owned())
+            .spawn(move || run_writer(&*writer_store, &receiver, config))?;
+
+        Ok(GroupCommitEventStore {
+            store,
+            sender: Some(sender),
+            writer: Some(writer),
+            _aggregate: PhantomData,
+        })
+    }
+}
+
+/// Error of an append the writer thread can no longer take, e.g. after the wrapped store
+/// panicked.
+fn writer_stopped<Err>() -> Err
+where
+    Err: From<io::Error>,
+{
+    io::Error::new(io::ErrorKind::BrokenPipe, "group commit writer stopped").into()
+}
+
+fn run_writer<A, E, S>(
+    store: &S,
+    receiver: &Receiver<PendingAppend<E, S::Error>>,
+    config: GroupCommitConfig,
+) where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E>,
+{
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + config.max_wait;
+        let mut batch = vec![first];
+
+        while batch.len() < config.max_batch_size {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(pending) => batch.push(pending),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let (requests, replies): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.request, pending.reply))
+            .unzip();
+
+        for (reply, result) in replies.into_iter().zip(store.append_batch(requests)) {
+            // The caller may have gone away; its append is committed regardless.
+            let _ = reply.send(result);
+        }
+    }
+}
+
+impl<A, E, S> Drop for GroupCommitEventStore<A, E, S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E>,
+{
+    fn drop(&mut self) {
+        // Closing the channel lets the writer finish the batches already queued and exit.
+        self.sender.take();
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl<A, E, S> EventStore<A, E> for GroupCommitEventStore<A, E, S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E>,
+    S::Error: From<io::Error>,
+{
+    type Error = S::Error;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, S::Error> {
+        self.store.read_events(id, since)
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, S::Error> {
+        let (reply, result) = mpsc::channel();
+        let pending = PendingAppend {
+            request: AppendRequest {
+                id: id.to_owned(),
+                events,
+                expected,
+            },
+            reply,
+        };
+
+        let sender = self.sender.as_ref().ok_or_else(writer_stopped)?;
+        sender.send(pending).map_err(|_| writer_stopped())?;
+
+        result.recv().unwrap_or_else(|_| Err(writer_stopped()))
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), S::Error> {
+        self.store.delete_stream(id, expected)
+    }
+}
+
+impl<A, E, S> GlobalEventStore<A, E> for GroupCommitEventStore<A, E, S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: BatchEventStore<A, E> + GlobalEventStore<A, E>,
+    S::Error: From<io::Error>,
+{
+    fn read_all(&self, since: Position, limit: usize) -> Result<Vec<EventEnvelope<E>>, S::Error> {
+        self.store.read_all(since, limit)
+    }
+
+    fn head_position(&self) -> Result<Position, S::Error> {
+        self.store.head_position()
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, S::Error> {
+        self.store.stream_position(id, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eventstore::*;
+    use crate::group_commit::*;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use std::sync::Barrier;
+
+    type CounterEventStore = InMemoryEventStore<CounterAggregate, CounterEvent>;
+
+    /// Records the size of every batch it commits, and panics on appends to stream `crash`.
+    struct RecordingEventStore {
+        inner: CounterEventStore,
+        batches: std::sync::Mutex<Vec<usize>>,
+    }
+
+    impl EventStore<CounterAggregate, CounterEvent> for RecordingEventStore {
+        type Error = EventStoreError;
+
+        fn read_events(
+            &self,
+            id: &str,
+            since: Version,
+        ) -> Result<Vec<VersionedEvent<CounterEvent>>, Self::Error> {
+            self.inner.read_events(id, since)
+        }
+
+        fn append_events(
+            &self,
+            id: &str,
+            events: Vec<CounterEvent>,
+            expected: ExpectedVersion,
+        ) -> Result<Version, Self::Error> {
+            self.inner.append_events(id, events, expected)
+        }
+
+        fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error> {
+            self.inner.delete_stream(id, expected)
+        }
+    }
+
+    impl BatchEventStore<CounterAggregate, CounterEvent> for RecordingEventStore {
+        fn append_batch(
+            &self,
+            requests: Vec<AppendRequest<CounterEvent>>,
+        ) -> Vec<Result<Version, Self::Error>> {
+            if requests.iter().any(|request| request.id == "crash") {
+                panic!("store crashed");
+            }
+            self.batches.lock().unwrap().push(requests.len());
+            self.inner.append_batch(requests)
+        }
+    }
+
+    fn recording_store(
+        config: GroupCommitConfig,
+    ) -> Arc<GroupCommitEventStore<CounterAggregate, CounterEvent, Arc<RecordingEventStore>>> {
+        let store = RecordingEventStore {
+            inner: CounterEventStore::new(),
+            batches: std::sync::Mutex::new(Vec::new()),
+        };
+        Arc::new(GroupCommitEventStore::new(Arc::new(store), config).unwrap())
+    }
+
+    #[test]
+    fn append_and_read_through_group_commit() {
+        // Arrange
+        let event_store =
+            GroupCommitEventStore::new(CounterEventStore::new(), GroupCommitConfig::default())
+                .unwrap();
+
+        // Act
+        let appended =
+            event_store.append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream);
+        let result = event_store.read_events("a", 0);
+
+        // Assert
+        assert_eq!(Ok(1), appended);
+        assert_eq!(
+            Ok(vec![VersionedEvent {
+                version: 1,
+                event: CounterEvent::Added(1),
+            }]),
+            result
+        );
+    }
+
+    #[test]
+    fn concurrent_appends_are_committed_in_batches() {
+        // Arrange
+        let writers = 8;
+        let config = GroupCommitConfig {
+            max_batch_size: writers,
+            max_wait: Duration::from_secs(5),
+        };
+        let event_store = recording_store(config);
+        let barrier = Arc::new(Barrier::new(writers));
+
+        // Act
+        let handles: Vec<_> = (0..writers)
+            .map(|writer| {
+                let event_store = Arc::clone(&event_store);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    event_store.append_events(
+                        &writer.to_string(),
+                        vec![CounterEvent::Added(1)],
+                        ExpectedVersion::NoStream,
+                    )
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Assert
+        assert!(results.iter().all(|result| *result == Ok(1)));
+        assert_eq!(vec![writers], *event_store.store.batches.lock().unwrap());
+    }
+
+    #[test]
+    fn every_caller_sees_its_own_concurrency_error() {
+        // Arrange
+        let writers = 4;
+        let config = GroupCommitConfig {
+            max_batch_size: writers,
+            max_wait: Duration::from_secs(5),
+        };
+        let event_store = recording_store(config);
+        let barrier = Arc::new(Barrier::new(writers));
+
+        // Act
+        let handles: Vec<_> = (0..writers)
+            .map(|_| {
+                let event_store = Arc::clone(&event_store);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    event_store.append_events(
+                        "a",
+                        vec![CounterEvent::Added(1)],
+                        ExpectedVersion::NoStream,
+                    )
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Assert
+        assert_eq!(1, results.iter().filter(|result| result.is_ok()).count());
+        assert!(results
+            .iter()
+            .filter(|result| result.is_err())
+            .all(|result| *result
+                == Err(EventStoreError::WrongExpectedVersion {
+                    expected: ExpectedVersion::NoStream,
+                    actual: 1,
+                })));
+    }
+
+    #[test]
+    fn batch_is_cut_at_max_batch_size() {
+        // Arrange
+        let config = GroupCommitConfig {
+            max_batch_size: 1,
+            max_wait: Duration::from_secs(5),
+        };
+        let event_store = recording_store(config);
+
+        // Act
+        for _ in 0..3 {
+            event_store
+                .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::Any)
+                .unwrap();
+        }
+
+        // Assert
+        assert_eq!(vec![1, 1, 1], *event_store.store.batches.lock().unwrap());
+    }
+
+    #[test]
+    fn appends_fail_once_the_writer_stopped() {
+        // Arrange
+        let event_store = recording_store(GroupCommitConfig::default());
+
+        // Act
+        let crashed =
+            event_store.append_events("crash", vec![CounterEvent::Added(1)], ExpectedVersion::Any);
+        let after =
+            event_store.append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::Any);
+
+        // Assert
+        let stopped = EventStoreError::Io("group commit writer stopped".to_owned());
+        assert_eq!(Err(stopped.clone()), crashed);
+        assert_eq!(Err(stopped), after);
+    }
+}
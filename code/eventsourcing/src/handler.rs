@@ -0,0 +1,29 @@
+use crate::CqrsError;
+use std::sync::Arc;
+
+pub trait CommandHandler<C> {
+    type Error: CqrsError;
+
+    fn handle(&self, command: C) -> Result<(), Self::Error>;
+
+    /// Whether `error` may go away when the command is sent again, like an I/O error or a
+    /// race lost to another writer, instead of the command being refused.
+    fn is_transient(_error: &Self::Error) -> bool {
+        false
+    }
+}
+
+impl<C, H> CommandHandler<C> for Arc<H>
+where
+    H: CommandHandler<C>,
+{
+    type Error = H::Error;
+
+    fn handle(&self, command: C) -> Result<(), Self::Error> {
+        (**self).handle(command)
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        H::is_transient(error)
+    }
+}
@@ -0,0 +1,89 @@
+//! Ids handed to new events, messages and aggregates, injected so tests can predict them.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub type Id = u64;
+
+pub trait IdGenerator {
+    fn next_id(&self) -> Id;
+}
+
+impl<G> IdGenerator for Arc<G>
+where
+    G: IdGenerator,
+{
+    fn next_id(&self) -> Id {
+        (**self).next_id()
+    }
+}
+
+/// Random ids, unlikely to repeat across processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> Id {
+        // Every `RandomState` is seeded with fresh random keys.
+        RandomState::new().build_hasher().finish()
+    }
+}
+
+/// Ids counting up from the first one.
+#[derive(Debug, Default)]
+pub struct SequentialIds {
+    next: AtomicU64,
+}
+
+impl SequentialIds {
+    pub fn new(first: Id) -> SequentialIds {
+        SequentialIds {
+            next: AtomicU64::new(first),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> Id {
+        self.next.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// The same id every time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedId(pub Id);
+
+impl IdGenerator for FixedId {
+    fn next_id(&self) -> Id {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::id::*;
+
+    #[test]
+    fn sequential_ids_count_up_from_first() {
+        // Arrange
+        let ids = Arc::new(SequentialIds::new(7));
+
+        // Act
+        let taken: Vec<Id> = (0..3).map(|_| ids.next_id()).collect();
+
+        // Assert
+        assert_eq!(vec![7, 8, 9], taken);
+    }
+
+    #[test]
+    fn random_ids_differ() {
+        // Act
+        let first = RandomIds.next_id();
+        let second = RandomIds.next_id();
+
+        // Assert
+        assert_ne!(first, second);
+    }
+}
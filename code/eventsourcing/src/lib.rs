@@ -1,9 +1,35 @@
+pub mod asynchronous;
+pub mod clock;
+#[cfg(any(test, feature = "testing"))]
+pub mod conformance;
 pub mod eventstore;
+pub mod filestore;
+pub mod group_commit;
+pub mod handler;
+pub mod id;
+pub mod outbox;
+pub mod partitioned;
+pub mod process;
+pub mod projection;
+pub mod repository;
+pub mod scheduler;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+#[cfg(test)]
+mod test_helpers;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod webhook;
 
+#[cfg(feature = "derive")]
+pub use eventsourcing_derive::{Aggregate, AggregateEvent, Event};
 use std::fmt;
 
 pub trait Aggregate: Default {
     fn aggregate_type() -> &'static str;
+    fn generation(&self) -> u64;
     fn increment_generation(&mut self);
 
     fn execute<C>(&self, command: C) -> Result<C::Events, C::Error>
@@ -25,6 +51,11 @@ pub trait Aggregate: Default {
 
 pub trait Event {
     fn event_type(&self) -> &'static str;
+
+    /// Version of the event's shape, raised when it changes in a way old readers can't follow.
+    fn event_version(&self) -> u32 {
+        1
+    }
 }
 
 pub trait AggregateEvent<A: Aggregate>: Event {
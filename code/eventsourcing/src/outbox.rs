@@ -0,0 +1,600 @@
+//! Integration messages for outside consumers, stored together with the events they describe.
+//!
+//! An `OutboxEventStore` commits outbox messages in the same append as the events, so a message
+//! exists exactly when its events do. An `OutboxRelay` delivers pending messages through a
+//! `Transport` at least once: a crash between delivering and marking a message sent delivers it
+//! again, so consumers should deduplicate by message id.
+
+use crate::clock::{self, Clock, SystemClock, Timestamp};
+use crate::eventstore::{
+    EventEnvelope, EventStore, ExpectedVersion, GlobalEventStore, Position, Version, VersionedEvent,
+};
+use crate::{Aggregate, AggregateEvent, CqrsError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type MessageId = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub topic: String,
+    pub payload: String,
+}
+
+impl OutboxMessage {
+    pub fn new(topic: &str, payload: String) -> OutboxMessage {
+        OutboxMessage {
+            topic: topic.to_owned(),
+            payload,
+        }
+    }
+}
+
+/// A message waiting in the outbox, with the number of failed deliveries so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub id: MessageId,
+    pub message: OutboxMessage,
+    pub attempts: u32,
+}
+
+pub trait Outbox {
+    type Error: CqrsError;
+
+    /// At most `limit` unsent entries due for a delivery at `now`, oldest first.
+    fn due(&self, now: Timestamp, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error>;
+
+    fn mark_sent(&self, id: MessageId) -> Result<(), Self::Error>;
+
+    /// Records a failed delivery, to be tried again at `retry_at` or never when it is `None`.
+    fn mark_failed(
+        &self,
+        id: MessageId,
+        error: &str,
+        retry_at: Option<Timestamp>,
+    ) -> Result<(), Self::Error>;
+}
+
+/// Event store that commits outbox messages atomically with the events.
+pub trait OutboxEventStore<A, E>: EventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+{
+    /// Appends `events` to stream `id` and `messages` to the outbox, or neither of them.
+    /// Messages are refused when there are no events to commit them with.
+    fn append_with_outbox(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<Version, Self::Error>;
+}
+
+impl<O> Outbox for Arc<O>
+where
+    O: Outbox,
+{
+    type Error = O::Error;
+
+    fn due(&self, now: Timestamp, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        (**self).due(now, limit)
+    }
+
+    fn mark_sent(&self, id: MessageId) -> Result<(), Self::Error> {
+        (**self).mark_sent(id)
+    }
+
+    fn mark_failed(
+        &self,
+        id: MessageId,
+        error: &str,
+        retry_at: Option<Timestamp>,
+    ) -> Result<(), Self::Error> {
+        (**self).mark_failed(id, error, retry_at)
+    }
+}
+
+impl<A, E, S> OutboxEventStore<A, E> for Arc<S>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    S: OutboxEventStore<A, E>,
+{
+    fn append_with_outbox(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<Version, Self::Error> {
+        (**self).append_with_outbox(id, events, expected, messages)
+    }
+}
+
+/// Turns a stored event into the message outside consumers get, if any.
+pub trait OutboxMapper<E> {
+    fn message(&self, stream_id: &str, event: &E) -> Option<OutboxMessage>;
+}
+
+impl<E, F> OutboxMapper<E> for F
+where
+    F: Fn(&str, &E) -> Option<OutboxMessage>,
+{
+    fn message(&self, stream_id: &str, event: &E) -> Option<OutboxMessage> {
+        self(stream_id, event)
+    }
+}
+
+/// Event store whose appends also put the messages `mapper` makes of the events into the
+/// outbox, so a repository on top of it publishes without knowing about the outbox.
+pub struct OutboxWriter<ES, M> {
+    event_store: ES,
+    mapper: M,
+}
+
+impl<ES, M> OutboxWriter<ES, M> {
+    pub fn new(event_store: ES, mapper: M) -> OutboxWriter<ES, M> {
+        OutboxWriter {
+            event_store,
+            mapper,
+        }
+    }
+}
+
+impl<A, E, ES, M> EventStore<A, E> for OutboxWriter<ES, M>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    ES: OutboxEventStore<A, E>,
+    M: OutboxMapper<E>,
+{
+    type Error = ES::Error;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, ES::Error> {
+        self.event_store.read_events(id, since)
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, ES::Error> {
+        let messages = events
+            .iter()
+            .filter_map(|event| self.mapper.message(id, event))
+            .collect();
+
+        self.event_store
+            .append_with_outbox(id, events, expected, messages)
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), ES::Error> {
+        self.event_store.delete_stream(id, expected)
+    }
+}
+
+impl<A, E, ES, M> GlobalEventStore<A, E> for OutboxWriter<ES, M>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    ES: OutboxEventStore<A, E> + GlobalEventStore<A, E>,
+    M: OutboxMapper<E>,
+{
+    fn read_all(&self, since: Position, limit: usize) -> Result<Vec<EventEnvelope<E>>, ES::Error> {
+        self.event_store.read_all(since, limit)
+    }
+
+    fn head_position(&self) -> Result<Position, ES::Error> {
+        self.event_store.head_position()
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, ES::Error> {
+        self.event_store.stream_position(id, version)
+    }
+}
+
+struct StoredEntry {
+    entry: OutboxEntry,
+    /// `None` once delivery was given up.
+    retry_at: Option<Timestamp>,
+    last_error: Option<String>,
+}
+
+/// Unsent outbox entries of an event store, kept in memory. Sent entries are dropped.
+pub(crate) struct OutboxTable {
+    entries: BTreeMap<MessageId, StoredEntry>,
+    next_id: MessageId,
+}
+
+impl OutboxTable {
+    pub(crate) fn new() -> OutboxTable {
+        OutboxTable {
+            entries: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub(crate) fn push(&mut self, messages: Vec<OutboxMessage>) {
+        for message in messages {
+            self.insert(self.next_id, message);
+        }
+    }
+
+    /// Id the next message pushed gets.
+    pub(crate) fn next_id(&self) -> MessageId {
+        self.next_id
+    }
+
+    /// Adds `message` under an id handed out by the store.
+    pub(crate) fn insert(&mut self, id: MessageId, message: OutboxMessage) {
+        self.next_id = self.next_id.max(id + 1);
+        self.entries.insert(
+            id,
+            StoredEntry {
+                entry: OutboxEntry {
+                    id,
+                    message,
+                    attempts: 0,
+                },
+                retry_at: Some(0),
+                last_error: None,
+            },
+        );
+    }
+
+    pub(crate) fn due(&self, now: Timestamp, limit: usize) -> Vec<OutboxEntry> {
+        self.entries
+            .values()
+            .filter(|stored| stored.retry_at.is_some_and(|retry_at| retry_at <= now))
+            .take(limit)
+            .map(|stored| stored.entry.clone())
+            .collect()
+    }
+
+    pub(crate) fn mark_sent(&mut self, id: MessageId) {
+        self.entries.remove(&id);
+    }
+
+    pub(crate) fn mark_failed(&mut self, id: MessageId, error: &str, retry_at: Option<Timestamp>) {
+        if let Some(stored) = self.entries.get_mut(&id) {
+            stored.entry.attempts += 1;
+            stored.retry_at = retry_at;
+            stored.last_error = Some(error.to_owned());
+        }
+    }
+
+    /// Entries whose delivery was given up, with the last error.
+    pub(crate) fn dead_letters(&self) -> Vec<(OutboxEntry, String)> {
+        self.entries
+            .values()
+            .filter(|stored| stored.retry_at.is_none())
+            .map(|stored| {
+                (
+                    stored.entry.clone(),
+                    stored.last_error.clone().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+}
+
+pub trait Transport {
+    type Error: CqrsError;
+
+    /// Delivers `entry` to its consumers, who may get it more than once.
+    fn send(&self, entry: &OutboxEntry) -> Result<(), Self::Error>;
+}
+
+impl<T> Transport for Arc<T>
+where
+    T: Transport,
+{
+    type Error = T::Error;
+
+    fn send(&self, entry: &OutboxEntry) -> Result<(), Self::Error> {
+        (**self).send(entry)
+    }
+}
+
+/// Transport keeping every delivered entry in memory, for tests.
+#[derive(Debug, Default)]
+pub struct InProcessTransport {
+    delivered: Mutex<Vec<OutboxEntry>>,
+}
+
+impl InProcessTransport {
+    pub fn new() -> InProcessTransport {
+        InProcessTransport::default()
+    }
+
+    pub fn delivered(&self) -> Vec<OutboxEntry> {
+        self.delivered.lock().unwrap().clone()
+    }
+}
+
+impl Transport for InProcessTransport {
+    type Error = Infallible;
+
+    fn send(&self, entry: &OutboxEntry) -> Result<(), Infallible> {
+        self.delivered.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+}
+
+/// Delivers outbox entries through a transport, retrying failed deliveries with a delay that
+/// doubles with every attempt.
+pub struct OutboxRelay<O, T> {
+    outbox: O,
+    transport: T,
+    batch_size: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl<O, T> OutboxRelay<O, T>
+where
+    O: Outbox,
+    T: Transport,
+{
+    pub fn new(outbox: O, transport: T) -> OutboxRelay<O, T> {
+        OutboxRelay {
+            outbox,
+            transport,
+            batch_size: 100,
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(1),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> OutboxRelay<O, T> {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Number of failed deliveries after which an entry is given up.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> OutboxRelay<O, T> {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before the first retry.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> OutboxRelay<O, T> {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn with_clock<C>(mut self, clock: C) -> OutboxRelay<O, T>
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Delivers one batch of due entries and returns how many were sent.
+    pub fn run_once(&self) -> Result<usize, O::Error> {
+        let now = self.clock.now();
+        let mut sent = 0;
+
+        for entry in self.outbox.due(now, self.batch_size)? {
+            match self.transport.send(&entry) {
+                Ok(()) => {
+                    self.outbox.mark_sent(entry.id)?;
+                    sent += 1;
+                }
+                Err(err) => {
+                    let retry_at = self.retry_at(now, entry.attempts + 1);
+                    self.outbox
+                        .mark_failed(entry.id, &err.to_string(), retry_at)?;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    fn retry_at(&self, now: Timestamp, attempts: u32) -> Option<Timestamp> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+
+        Some(clock::backoff(now, self.retry_delay, attempts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::eventstore::{EventStoreError, InMemoryEventStore};
+    use crate::outbox::*;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use std::fmt;
+
+    type CounterEventStore = InMemoryEventStore<CounterAggregate, CounterEvent>;
+    type CounterMapper = fn(&str, &CounterEvent) -> Option<OutboxMessage>;
+
+    const START: Timestamp = 10_000;
+
+    fn added(stream_id: &str, event: &CounterEvent) -> Option<OutboxMessage> {
+        let CounterEvent::Added(value) = event;
+        if *value == 0 {
+            return None;
+        }
+        Some(OutboxMessage::new(
+            "counter.added",
+            format!("{}:{}", stream_id, value),
+        ))
+    }
+
+    #[derive(Debug)]
+    struct Unreachable;
+
+    impl fmt::Display for Unreachable {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "unreachable")
+        }
+    }
+
+    /// Fails the first `failures` deliveries, then hands entries to an in-process transport.
+    struct Flaky {
+        failures: Mutex<u32>,
+        inner: InProcessTransport,
+    }
+
+    impl Flaky {
+        fn new(failures: u32) -> Flaky {
+            Flaky {
+                failures: Mutex::new(failures),
+                inner: InProcessTransport::new(),
+            }
+        }
+    }
+
+    impl Transport for Flaky {
+        type Error = Unreachable;
+
+        fn send(&self, entry: &OutboxEntry) -> Result<(), Unreachable> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Unreachable);
+            }
+            self.inner
+                .send(entry)
+                .unwrap_or_else(|never| match never {});
+            Ok(())
+        }
+    }
+
+    fn writer(
+        event_store: &Arc<CounterEventStore>,
+    ) -> OutboxWriter<Arc<CounterEventStore>, CounterMapper> {
+        OutboxWriter::new(event_store.clone(), added)
+    }
+
+    #[test]
+    fn appended_events_are_relayed_once() {
+        // Arrange
+        let event_store = Arc::new(CounterEventStore::new());
+        writer(&event_store)
+            .append_events(
+                "a",
+                vec![CounterEvent::Added(1), CounterEvent::Added(0)],
+                ExpectedVersion::NoStream,
+            )
+            .unwrap();
+        let transport = Arc::new(InProcessTransport::new());
+        let relay = OutboxRelay::new(event_store.clone(), transport.clone());
+
+        // Act
+        let first = relay.run_once();
+        let second = relay.run_once();
+
+        // Assert
+        assert_eq!(Ok(1), first);
+        assert_eq!(Ok(0), second);
+        assert_eq!(
+            vec![OutboxEntry {
+                id: 1,
+                message: OutboxMessage::new("counter.added", "a:1".to_owned()),
+                attempts: 0,
+            }],
+            transport.delivered()
+        );
+    }
+
+    #[test]
+    fn rejected_append_leaves_no_message() {
+        // Arrange
+        let event_store = Arc::new(CounterEventStore::new());
+        let writer = writer(&event_store);
+        writer
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+
+        // Act
+        let result =
+            writer.append_events("a", vec![CounterEvent::Added(2)], ExpectedVersion::NoStream);
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(1, event_store.due(START, 10).unwrap().len());
+    }
+
+    #[test]
+    fn messages_without_events_are_refused() {
+        // Arrange
+        let event_store = CounterEventStore::new();
+
+        // Act
+        let result = event_store.append_with_outbox(
+            "a",
+            vec![],
+            ExpectedVersion::Any,
+            vec![OutboxMessage::new("counter.added", "a:1".to_owned())],
+        );
+
+        // Assert
+        assert_eq!(Err(EventStoreError::MessagesWithoutEvents), result);
+        assert_eq!(Ok(vec![]), event_store.due(START, 10));
+    }
+
+    #[test]
+    fn failed_delivery_is_retried_with_growing_delay() {
+        // Arrange
+        let event_store = Arc::new(CounterEventStore::new());
+        writer(&event_store)
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        let clock = Arc::new(ManualClock::new(START));
+        let transport = Arc::new(Flaky::new(2));
+        let relay = OutboxRelay::new(event_store.clone(), transport.clone())
+            .with_retry_delay(Duration::from_secs(1))
+            .with_clock(clock.clone());
+
+        // Act
+        let results: Vec<_> = [0, 999, 1000, 2999, 3000]
+            .iter()
+            .map(|elapsed| {
+                clock.set(START + elapsed);
+                relay.run_once().unwrap()
+            })
+            .collect();
+
+        // Assert
+        assert_eq!(vec![0, 0, 0, 0, 1], results);
+        assert_eq!(1, transport.inner.delivered().len());
+        assert_eq!(2, transport.inner.delivered()[0].attempts);
+    }
+
+    #[test]
+    fn delivery_is_given_up_after_max_attempts() {
+        // Arrange
+        let event_store = Arc::new(CounterEventStore::new());
+        writer(&event_store)
+            .append_events("a", vec![CounterEvent::Added(1)], ExpectedVersion::NoStream)
+            .unwrap();
+        let relay = OutboxRelay::new(event_store.clone(), Flaky::new(u32::MAX))
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::from_millis(0));
+
+        // Act
+        relay.run_once().unwrap();
+        relay.run_once().unwrap();
+
+        // Assert
+        assert_eq!(Ok(vec![]), event_store.due(u64::MAX, 10));
+        let dead_letters = event_store.dead_letters();
+        assert_eq!(1, dead_letters.len());
+        assert_eq!(2, dead_letters[0].0.attempts);
+        assert_eq!("unreachable", dead_letters[0].1);
+    }
+}
@@ -0,0 +1,873 @@
+//! Event store that spreads streams over several file backed partitions.
+//!
+//! A stream always lives in the partition picked by hashing its id, so per stream ordering is
+//! kept by that partition. Positions are handed out by one sequencer shared by all partitions
+//! and `$all` is read by merging the partitions on position. Outbox messages are written with
+//! their events in the partition of the stream. Their deliveries, and the streams deleted, are
+//! logged to one file each for the whole store.
+
+use crate::clock::{Clock, Timestamp};
+use crate::eventstore::{
+    AppendRequest, BatchEventStore, EventEnvelope, EventStore, EventStoreError, ExpectedVersion,
+    GlobalEventStore, Position, Stamps, Version, VersionedEvent,
+};
+use crate::filestore::{
+    committed_records, Allocator, Delivery, FileState, JsonLog, OutboxRequest, Tombstone,
+};
+use crate::id::IdGenerator;
+use crate::outbox::{MessageId, Outbox, OutboxEntry, OutboxEventStore, OutboxMessage};
+use crate::{Aggregate, AggregateEvent};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const PARTITIONS_FILE: &str = "partitions";
+const DELIVERIES_FILE: &str = "outbox.log";
+const TOMBSTONES_FILE: &str = "deleted.log";
+const REBALANCE_DIR: &str = "rebalance.tmp";
+
+/// Partition of stream `id` when the store is split into `partitions`.
+///
+/// Uses FNV-1a so the result stays the same across processes and Rust versions. `partitions`
+/// must not be 0.
+pub fn partition_for(id: &str, partitions: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in id.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % partitions as u64) as usize
+}
+
+/// Hands out positions and message ids, and remembers which positions are still being written.
+struct Sequencer {
+    next: Position,
+    in_flight: BTreeSet<Position>,
+    next_message_id: MessageId,
+}
+
+impl Sequencer {
+    fn allocate(&mut self, count: u64) -> Position {
+        let first_position = self.next;
+        self.next += count;
+        self.in_flight.insert(first_position);
+        first_position
+    }
+
+    fn allocate_message_ids(&mut self, count: u64) -> MessageId {
+        let first_id = self.next_message_id;
+        self.next_message_id += count;
+        first_id
+    }
+
+    fn release(&mut self, first_positions: &[Position]) {
+        for first_position in first_positions {
+            self.in_flight.remove(first_position);
+        }
+    }
+
+    /// Highest position below which every allocated position has been written or abandoned.
+    fn watermark(&self) -> Position {
+        match self.in_flight.iter().next() {
+            Some(first_in_flight) => first_in_flight - 1,
+            None => self.next - 1,
+        }
+    }
+}
+
+/// What one partition allocated from the sequencer while committing.
+struct Allocation<'a> {
+    sequencer: &'a Mutex<Sequencer>,
+    positions: Vec<Position>,
+}
+
+impl Allocator for Allocation<'_> {
+    fn positions(&mut self, count: u64) -> Position {
+        let first_position = self.sequencer.lock().unwrap().allocate(count);
+        self.positions.push(first_position);
+        first_position
+    }
+
+    fn message_ids(&mut self, count: u64) -> MessageId {
+        self.sequencer.lock().unwrap().allocate_message_ids(count)
+    }
+}
+
+pub struct PartitionedEventStore<A, E> {
+    partitions: Vec<Mutex<FileState<E>>>,
+    deliveries: Mutex<JsonLog>,
+    /// Locked after a partition.
+    tombstones: Mutex<JsonLog>,
+    sequencer: Mutex<Sequencer>,
+    stamps: Stamps,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+impl<A, E> PartitionedEventStore<A, E>
+where
+    E: DeserializeOwned,
+{
+    /// Opens a store kept in `dir` as `partitions` log files, creating it when missing.
+    ///
+    /// An existing store split into a different number of partitions has to be rebalanced
+    /// with `rebalance` first. A rebalance cut short by a crash is finished or rolled back.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        partitions: usize,
+    ) -> Result<PartitionedEventStore<A, E>, EventStoreError> {
+        let dir = dir.as_ref();
+        check_partitions(dir, partitions)?;
+        fs::create_dir_all(dir)?;
+        recover_rebalance(dir)?;
+
+        match read_partition_count(dir)? {
+            Some(found) if found != partitions => {
+                return Err(EventStoreError::PartitionCount {
+                    expected: partitions,
+                    found,
+                });
+            }
+            Some(_) => (),
+            None => write_partition_count(dir, partitions)?,
+        }
+
+        let mut partitions = (0..partitions)
+            .map(|partition| FileState::open(&partition_path(dir, partition)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (deliveries, logged) = JsonLog::open::<Delivery, _>(dir.join(DELIVERIES_FILE))?;
+        for delivery in logged {
+            for partition in &mut partitions {
+                delivery.apply_to(&mut partition.outbox);
+            }
+        }
+
+        let (tombstones, deleted) = JsonLog::open::<Tombstone, _>(dir.join(TOMBSTONES_FILE))?;
+        for tombstone in deleted {
+            let partition = partition_for(&tombstone.stream, partitions.len());
+            partitions[partition].events.delete(&tombstone.stream);
+        }
+
+        let last_position = partitions
+            .iter()
+            .map(|partition| partition.events.last_position())
+            .max()
+            .unwrap_or(0);
+        let next_message_id = partitions
+            .iter()
+            .map(|partition| partition.outbox.next_id())
+            .max()
+            .unwrap_or(1);
+
+        Ok(PartitionedEventStore {
+            partitions: partitions.into_iter().map(Mutex::new).collect(),
+            deliveries: Mutex::new(deliveries),
+            tombstones: Mutex::new(tombstones),
+            sequencer: Mutex::new(Sequencer {
+                next: last_position + 1,
+                in_flight: BTreeSet::new(),
+                next_message_id,
+            }),
+            stamps: Stamps::new(),
+            _aggregate: PhantomData,
+        })
+    }
+
+    /// Tells when events are committed, the system time by default.
+    pub fn with_clock<C>(mut self, clock: C) -> PartitionedEventStore<A, E>
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.stamps.clock = Box::new(clock);
+        self
+    }
+
+    /// Hands out the ids of committed events, random ones by default.
+    pub fn with_ids<G>(mut self, ids: G) -> PartitionedEventStore<A, E>
+    where
+        G: IdGenerator + Send + Sync + 'static,
+    {
+        self.stamps.ids = Box::new(ids);
+        self
+    }
+
+    /// Outbox entries whose delivery was given up, with the last delivery error.
+    pub fn dead_letters(&self) -> Vec<(OutboxEntry, String)> {
+        let mut dead_letters: Vec<_> = self
+            .partitions
+            .iter()
+            .flat_map(|partition| partition.lock().unwrap().outbox.dead_letters())
+            .collect();
+        dead_letters.sort_by_key(|(entry, _)| entry.id);
+
+        dead_letters
+    }
+}
+
+impl<A, E> PartitionedEventStore<A, E>
+where
+    E: Serialize,
+{
+    fn commit(
+        &self,
+        partition: usize,
+        requests: Vec<OutboxRequest<E>>,
+    ) -> Vec<Result<Version, EventStoreError>> {
+        let mut state = self.partitions[partition].lock().unwrap();
+        let mut allocation = Allocation {
+            sequencer: &self.sequencer,
+            positions: Vec::new(),
+        };
+
+        // Positions are allocated while the partition is locked, so within a partition they
+        // are written in increasing order.
+        let results = state.commit(requests, &mut allocation, &self.stamps);
+
+        self.sequencer
+            .lock()
+            .unwrap()
+            .release(&allocation.positions);
+
+        results
+    }
+
+    fn partition(&self, id: &str) -> usize {
+        partition_for(id, self.partitions.len())
+    }
+
+    fn record(&self, delivery: Delivery) -> Result<(), EventStoreError> {
+        self.deliveries.lock().unwrap().record(&delivery)?;
+        for partition in &self.partitions {
+            delivery.apply_to(&mut partition.lock().unwrap().outbox);
+        }
+        Ok(())
+    }
+}
+
+impl<A, E> EventStore<A, E> for PartitionedEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    type Error = EventStoreError;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, Self::Error> {
+        let state = self.partitions[self.partition(id)].lock().unwrap();
+
+        Ok(state.events.read_stream(id, since))
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, Self::Error> {
+        self.append_with_outbox(id, events, expected, Vec::new())
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), Self::Error> {
+        let mut state = self.partitions[self.partition(id)].lock().unwrap();
+
+        state.events.check(id, expected)?;
+        self.tombstones.lock().unwrap().record(&Tombstone {
+            stream: id.to_owned(),
+        })?;
+        state.events.delete(id);
+
+        Ok(())
+    }
+}
+
+impl<A, E> BatchEventStore<A, E> for PartitionedEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    /// Commits the requests of every partition as one batch of that partition.
+    fn append_batch(&self, requests: Vec<AppendRequest<E>>) -> Vec<Result<Version, Self::Error>> {
+        let count = requests.len();
+        let mut by_partition: Vec<Vec<(usize, OutboxRequest<E>)>> =
+            (0..self.partitions.len()).map(|_| Vec::new()).collect();
+
+        for (index, request) in requests.into_iter().enumerate() {
+            by_partition[self.partition(&request.id)].push((index, (request, Vec::new())));
+        }
+
+        let mut results: Vec<Option<Result<Version, EventStoreError>>> =
+            (0..count).map(|_| None).collect();
+
+        for (partition, requests) in by_partition.into_iter().enumerate() {
+            if requests.is_empty() {
+                continue;
+            }
+
+            let (indexes, requests): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+
+            for (index, result) in indexes.into_iter().zip(self.commit(partition, requests)) {
+                results[index] = Some(result);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every request belongs to a partition"))
+            .collect()
+    }
+}
+
+impl<A, E> GlobalEventStore<A, E> for PartitionedEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    /// Merges the partitions on position, holding back events that follow a position that is
+    /// still being written so a reader never skips over it.
+    fn read_all(
+        &self,
+        since: Position,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<E>>, Self::Error> {
+        let watermark = self.sequencer.lock().unwrap().watermark();
+
+        let mut events = Vec::new();
+        for partition in &self.partitions {
+            let read = partition.lock().unwrap().events.read_all(since, limit);
+            events.extend(
+                read.into_iter()
+                    .take_while(|envelope| envelope.position <= watermark),
+            );
+        }
+
+        events.sort_by_key(|envelope| envelope.position);
+        events.truncate(limit);
+
+        Ok(events)
+    }
+
+    fn head_position(&self) -> Result<Position, Self::Error> {
+        Ok(self.sequencer.lock().unwrap().watermark())
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, Self::Error> {
+        let state = self.partitions[self.partition(id)].lock().unwrap();
+
+        Ok(state.events.stream_position(id, version))
+    }
+}
+
+impl<A, E> OutboxEventStore<A, E> for PartitionedEventStore<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Serialize,
+{
+    fn append_with_outbox(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<Version, Self::Error> {
+        let request = AppendRequest {
+            id: id.to_owned(),
+            events,
+            expected,
+        };
+
+        self.commit(self.partition(id), vec![(request, messages)])
+            .remove(0)
+    }
+}
+
+impl<A, E> Outbox for PartitionedEventStore<A, E>
+where
+    E: Serialize,
+{
+    type Error = EventStoreError;
+
+    /// Merges the due entries of the partitions on message id.
+    fn due(&self, now: Timestamp, limit: usize) -> Result<Vec<OutboxEntry>, Self::Error> {
+        let mut due: Vec<_> = self
+            .partitions
+            .iter()
+            .flat_map(|partition| partition.lock().unwrap().outbox.due(now, limit))
+            .collect();
+        due.sort_by_key(|entry| entry.id);
+        due.truncate(limit);
+
+        Ok(due)
+    }
+
+    fn mark_sent(&self, id: MessageId) -> Result<(), Self::Error> {
+        self.record(Delivery::Sent(id))
+    }
+
+    fn mark_failed(
+        &self,
+        id: MessageId,
+        error: &str,
+        retry_at: Option<Timestamp>,
+    ) -> Result<(), Self::Error> {
+        self.record(Delivery::Failed {
+            id,
+            error: error.to_owned(),
+            retry_at,
+        })
+    }
+}
+
+/// Redistributes the streams of the store in `dir` over `partitions` log files.
+///
+/// Records are moved as they are, keeping their positions, so readers of `$all` can carry on
+/// from the position they reached. The store must not be open while this runs.
+///
+/// The new partitions are staged next to the old ones before any of these is touched. A
+/// rebalance cut short by a crash is finished by the next `open` or `rebalance` once every
+/// partition was staged, and rolled back otherwise.
+pub fn rebalance<P: AsRef<Path>>(dir: P, partitions: usize) -> Result<(), EventStoreError> {
+    let dir = dir.as_ref();
+    check_partitions(dir, partitions)?;
+    recover_rebalance(dir)?;
+    stage(dir, partitions)?;
+    switch(dir, partitions)
+}
+
+/// Writes the records of the store in `dir` split into `partitions` to the staging directory,
+/// with the partition count last to mark the staged partitions complete.
+fn stage(dir: &Path, partitions: usize) -> Result<(), EventStoreError> {
+    let current = read_partition_count(dir)?.unwrap_or(0);
+
+    let mut records = Vec::new();
+    for partition in 0..current {
+        let contents = fs::read(partition_path(dir, partition))?;
+        let (committed, _) = committed_records::<IgnoredAny>(&contents)?;
+        for (offset, record) in committed {
+            let end = offset + contents[offset..].iter().position(|b| *b == b'\n').unwrap();
+            records.push((
+                record.position,
+                record.stream,
+                contents[offset..=end].to_vec(),
+            ));
+        }
+    }
+    // Records of one append keep their consecutive positions, so they stay together.
+    records.sort_by_key(|(position, _, _)| *position);
+
+    let mut buffers: Vec<Vec<u8>> = (0..partitions).map(|_| Vec::new()).collect();
+    for (_, stream, line) in records {
+        buffers[partition_for(&stream, partitions)].extend(line);
+    }
+
+    let staging = dir.join(REBALANCE_DIR);
+    fs::create_dir_all(&staging)?;
+    for (partition, buffer) in buffers.iter().enumerate() {
+        let mut file = File::create(partition_path(&staging, partition))?;
+        file.write_all(buffer)?;
+        file.sync_all()?;
+    }
+
+    write_partition_count(&staging, partitions)
+}
+
+/// Moves the staged partitions in place of the old ones, replacing the partition count last.
+///
+/// Every step can be repeated, so a switch cut short is finished by running it again.
+fn switch(dir: &Path, partitions: usize) -> Result<(), EventStoreError> {
+    let staging = dir.join(REBALANCE_DIR);
+    let current = read_partition_count(dir)?.unwrap_or(0);
+
+    for partition in 0..partitions {
+        let staged = partition_path(&staging, partition);
+        if staged.exists() {
+            fs::rename(staged, partition_path(dir, partition))?;
+        }
+    }
+    for partition in partitions..current {
+        let path = partition_path(dir, partition);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    sync_dir(dir)?;
+
+    fs::rename(staging.join(PARTITIONS_FILE), dir.join(PARTITIONS_FILE))?;
+    sync_dir(dir)?;
+    fs::remove_dir_all(&staging)?;
+
+    Ok(())
+}
+
+/// Finishes a rebalance of the store in `dir` that staged every partition before it was cut
+/// short, and drops the staged partitions of one that didn't.
+fn recover_rebalance(dir: &Path) -> Result<(), EventStoreError> {
+    let staging = dir.join(REBALANCE_DIR);
+    if !staging.exists() {
+        return Ok(());
+    }
+
+    match read_partition_count(&staging)? {
+        Some(partitions) => switch(dir, partitions),
+        None => Ok(fs::remove_dir_all(&staging)?),
+    }
+}
+
+/// Refuses to split the store in `dir` into no partitions at all.
+fn check_partitions(dir: &Path, partitions: usize) -> Result<(), EventStoreError> {
+    if partitions > 0 {
+        return Ok(());
+    }
+
+    Err(EventStoreError::PartitionCount {
+        expected: partitions,
+        found: read_partition_count(dir)?.unwrap_or(0),
+    })
+}
+
+fn partition_path(dir: &Path, partition: usize) -> PathBuf {
+    dir.join(format!("partition-{}.log", partition))
+}
+
+fn read_partition_count(dir: &Path) -> Result<Option<usize>, EventStoreError> {
+    let path = dir.join(PARTITIONS_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(path)?;
+    contents
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| EventStoreError::Codec(format!("invalid partition count {:?}", contents)))
+}
+
+/// Replaces the partition count in `dir` in one step, so it is never found half written.
+fn write_partition_count(dir: &Path, partitions: usize) -> Result<(), EventStoreError> {
+    let temporary = dir.join(format!("{}.tmp", PARTITIONS_FILE));
+    let mut file = File::create(&temporary)?;
+    writeln!(file, "{}", partitions)?;
+    file.sync_all()?;
+    fs::rename(temporary, dir.join(PARTITIONS_FILE))?;
+    sync_dir(dir)
+}
+
+fn sync_dir(dir: &Path) -> Result<(), EventStoreError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eventstore::*;
+    use crate::outbox::{Outbox, OutboxEventStore, OutboxMessage};
+    use crate::partitioned::*;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use std::fs;
+    use tempfile::TempDir;
+
+    type CounterPartitionedEventStore = PartitionedEventStore<CounterAggregate, CounterEvent>;
+
+    fn append_to_streams(event_store: &CounterPartitionedEventStore, ids: &[&str]) {
+        for (value, id) in ids.iter().enumerate() {
+            event_store
+                .append_events(
+                    id,
+                    vec![CounterEvent::Added(value as u64)],
+                    ExpectedVersion::Any,
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn partition_for_is_stable() {
+        assert_eq!(partition_for("123", 4), partition_for("123", 4));
+        assert!(partition_for("123", 4) < 4);
+        assert_eq!(0, partition_for("123", 1));
+    }
+
+    #[test]
+    fn streams_keep_their_order_across_partitions() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+
+        // Act
+        append_to_streams(&event_store, &["1", "2", "3", "1", "2", "1"]);
+
+        // Assert
+        assert_eq!(
+            vec![
+                VersionedEvent {
+                    version: 1,
+                    event: CounterEvent::Added(0),
+                },
+                VersionedEvent {
+                    version: 2,
+                    event: CounterEvent::Added(3),
+                },
+                VersionedEvent {
+                    version: 3,
+                    event: CounterEvent::Added(5),
+                },
+            ],
+            event_store.read_events("1", 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn read_all_merges_partitions_in_commit_order() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 3).unwrap();
+        append_to_streams(&event_store, &["1", "2", "3", "4", "5", "6"]);
+        drop(event_store);
+
+        // Act
+        let reopened = CounterPartitionedEventStore::open(dir.path(), 3).unwrap();
+        let result = reopened.read_all(0, 100).unwrap();
+
+        // Assert
+        let streams: Vec<_> = result.iter().map(|e| e.stream_id.as_str()).collect();
+        let positions: Vec<_> = result.iter().map(|e| e.position).collect();
+        assert_eq!(vec!["1", "2", "3", "4", "5", "6"], streams);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], positions);
+        assert_eq!(4, reopened.read_all(2, 2).unwrap()[1].position);
+    }
+
+    #[test]
+    fn read_all_stops_before_positions_still_being_written() {
+        // Arrange
+        let mut sequencer = Sequencer {
+            next: 1,
+            in_flight: BTreeSet::new(),
+            next_message_id: 1,
+        };
+        let first = sequencer.allocate(2);
+        let second = sequencer.allocate(1);
+
+        // Act
+        sequencer.release(&[second]);
+        let while_first_in_flight = sequencer.watermark();
+        sequencer.release(&[first]);
+
+        // Assert
+        assert_eq!(0, while_first_in_flight);
+        assert_eq!(3, sequencer.watermark());
+    }
+
+    #[test]
+    fn opening_with_another_partition_count_fails() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        CounterPartitionedEventStore::open(dir.path(), 2).unwrap();
+
+        // Act
+        let result = CounterPartitionedEventStore::open(dir.path(), 3);
+
+        // Assert
+        match result {
+            Err(EventStoreError::PartitionCount {
+                expected: 3,
+                found: 2,
+            }) => (),
+            _ => panic!("Store opened with wrong partition count"),
+        }
+    }
+
+    #[test]
+    fn rebalance_keeps_streams_and_global_order() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let ids = ["1", "2", "3", "4", "1", "5", "2", "6", "7", "1"];
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 2).unwrap();
+        append_to_streams(&event_store, &ids);
+        let all_before = event_store.read_all(0, 100).unwrap();
+        let stream_before = event_store.read_events("1", 0).unwrap();
+        drop(event_store);
+
+        // Act
+        rebalance(dir.path(), 5).unwrap();
+        let rebalanced = CounterPartitionedEventStore::open(dir.path(), 5).unwrap();
+
+        // Assert
+        assert_eq!(all_before, rebalanced.read_all(0, 100).unwrap());
+        assert_eq!(stream_before, rebalanced.read_events("1", 0).unwrap());
+        assert_eq!(
+            Ok(4),
+            rebalanced.append_events("1", vec![CounterEvent::Added(9)], ExpectedVersion::Exact(3))
+        );
+        assert_eq!(11, rebalanced.read_all(10, 1).unwrap()[0].position);
+    }
+
+    #[test]
+    fn rebalance_into_fewer_partitions_keeps_streams_and_global_order() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let ids = ["1", "2", "3", "4", "1", "5", "2", "6", "7", "1"];
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 5).unwrap();
+        append_to_streams(&event_store, &ids);
+        event_store
+            .append_events(
+                "3",
+                vec![CounterEvent::Added(7), CounterEvent::Added(8)],
+                ExpectedVersion::Exact(1),
+            )
+            .unwrap();
+        let all_before = event_store.read_all(0, 100).unwrap();
+        drop(event_store);
+
+        // Act
+        rebalance(dir.path(), 2).unwrap();
+        let rebalanced = CounterPartitionedEventStore::open(dir.path(), 2).unwrap();
+
+        // Assert
+        assert_eq!(all_before, rebalanced.read_all(0, 100).unwrap());
+        assert_eq!(3, rebalanced.read_events("3", 0).unwrap().len());
+        assert!(!partition_path(dir.path(), 2).exists());
+        assert!(!dir.path().join(REBALANCE_DIR).exists());
+    }
+
+    #[test]
+    fn rebalance_cut_short_after_staging_is_finished_on_open() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+        append_to_streams(&event_store, &["1", "2", "3", "4", "5", "1"]);
+        let all_before = event_store.read_all(0, 100).unwrap();
+        drop(event_store);
+        stage(dir.path(), 3).unwrap();
+        let staging = dir.path().join(REBALANCE_DIR);
+        fs::rename(partition_path(&staging, 0), partition_path(dir.path(), 0)).unwrap();
+        fs::remove_file(partition_path(dir.path(), 3)).unwrap();
+
+        // Act
+        let reopened = CounterPartitionedEventStore::open(dir.path(), 3).unwrap();
+
+        // Assert
+        assert_eq!(all_before, reopened.read_all(0, 100).unwrap());
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn rebalance_cut_short_while_staging_is_rolled_back_on_open() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+        append_to_streams(&event_store, &["1", "2", "3", "4", "5", "1"]);
+        let all_before = event_store.read_all(0, 100).unwrap();
+        drop(event_store);
+        stage(dir.path(), 3).unwrap();
+        let staging = dir.path().join(REBALANCE_DIR);
+        fs::remove_file(staging.join(PARTITIONS_FILE)).unwrap();
+
+        // Act
+        let reopened = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+
+        // Assert
+        assert_eq!(all_before, reopened.read_all(0, 100).unwrap());
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn zero_partitions_are_refused() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        CounterPartitionedEventStore::open(dir.path(), 2).unwrap();
+        let new_dir = dir.path().join("new");
+
+        // Act
+        let opened = CounterPartitionedEventStore::open(&new_dir, 0);
+        let rebalanced = rebalance(dir.path(), 0);
+
+        // Assert
+        match opened {
+            Err(EventStoreError::PartitionCount {
+                expected: 0,
+                found: 0,
+            }) => (),
+            _ => panic!("Store opened without partitions"),
+        }
+        assert_eq!(
+            Err(EventStoreError::PartitionCount {
+                expected: 0,
+                found: 2,
+            }),
+            rebalanced
+        );
+        assert!(!new_dir.exists());
+        assert_eq!(Some(2), read_partition_count(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn outbox_messages_survive_reopening_and_rebalance() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+        for id in &["1", "2", "3", "4", "5"] {
+            event_store
+                .append_with_outbox(
+                    id,
+                    vec![CounterEvent::Added(1)],
+                    ExpectedVersion::NoStream,
+                    vec![OutboxMessage::new("added", id.to_string())],
+                )
+                .unwrap();
+        }
+        event_store.mark_sent(2).unwrap();
+        drop(event_store);
+        rebalance(dir.path(), 3).unwrap();
+
+        // Act
+        let reopened = CounterPartitionedEventStore::open(dir.path(), 3).unwrap();
+        reopened
+            .append_with_outbox(
+                "6",
+                vec![CounterEvent::Added(1)],
+                ExpectedVersion::NoStream,
+                vec![OutboxMessage::new("added", "6".to_owned())],
+            )
+            .unwrap();
+        let due = reopened.due(0, 10).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![(1, "1"), (3, "3"), (4, "4"), (5, "5"), (6, "6")],
+            due.iter()
+                .map(|entry| (entry.id, entry.message.payload.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn deleted_streams_stay_deleted_after_rebalance() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let event_store = CounterPartitionedEventStore::open(dir.path(), 4).unwrap();
+        append_to_streams(&event_store, &["1", "2", "3"]);
+        event_store
+            .delete_stream("2", ExpectedVersion::Any)
+            .unwrap();
+        drop(event_store);
+        rebalance(dir.path(), 3).unwrap();
+
+        // Act
+        let reopened = CounterPartitionedEventStore::open(dir.path(), 3).unwrap();
+        let appended =
+            reopened.append_events("2", vec![CounterEvent::Added(1)], ExpectedVersion::Any);
+
+        // Assert
+        assert_eq!(Ok(vec![]), reopened.read_events("2", 0));
+        assert_eq!(
+            Err(EventStoreError::StreamDeleted("2".to_owned())),
+            appended
+        );
+        assert_eq!(1, reopened.read_events("1", 0).unwrap().len());
+        assert_eq!(1, reopened.read_events("3", 0).unwrap().len());
+    }
+}
@@ -0,0 +1,1235 @@
+//! Process managers that coordinate several aggregates.
+//!
+//! A process manager reacts to events of `$all`. Its correlation id routes every event to one
+//! instance, whose state is event-sourced in a stream of its own. Everything an instance decides
+//! is stored in that stream before a command is dispatched, so after a crash instances are
+//! rebuilt from their streams and carry on where they stopped: commands that were recorded but
+//! not dispatched yet are sent again and timeouts fire at their recorded deadlines.
+//!
+//! A command the dispatcher refuses is compensated for. One that fails for a reason that may
+//! go away is sent again by `retry_commands` with a delay that doubles with every attempt, and
+//! compensated for once it ran out of attempts. An instance whose compensating command is
+//! refused as well ends as failed.
+
+use crate::clock::{self, Clock, SystemClock, Timestamp};
+use crate::eventstore::{EventEnvelope, EventStore, ExpectedVersion, GlobalEventStore, Position};
+use crate::handler::CommandHandler;
+use crate::projection::Subscription;
+use crate::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{error::Error, fmt};
+
+/// What an instance does in reaction to an event, a timeout or a refused command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step<Ev, C> {
+    /// Changes the state of the instance.
+    Record(Ev),
+    /// Dispatches a command once the steps are stored.
+    Issue(C),
+    /// Calls `ProcessManager::timeout` with `name` once `after` passed. Replaces a timeout of
+    /// the same name that did not fire yet.
+    ScheduleTimeout {
+        name: String,
+        after: Duration,
+    },
+    CancelTimeout(String),
+    /// Ends the instance. It ignores events and timeouts from then on.
+    Complete,
+}
+
+pub trait ProcessManager<E> {
+    /// State of one instance.
+    type State: Aggregate;
+    type Event: AggregateEvent<Self::State> + Clone;
+    type Command: Clone;
+
+    /// Category of the streams instances are stored in, `{name}-{correlation id}`.
+    fn name(&self) -> &str;
+
+    /// Correlation id of the instance `envelope` belongs to, `None` when no instance cares.
+    fn correlate(&self, envelope: &EventEnvelope<E>) -> Option<String>;
+
+    /// An instance that takes no steps on its first event is not started.
+    fn handle(
+        &self,
+        state: &Self::State,
+        envelope: &EventEnvelope<E>,
+    ) -> Vec<Step<Self::Event, Self::Command>>;
+
+    fn timeout(&self, state: &Self::State, name: &str) -> Vec<Step<Self::Event, Self::Command>>;
+
+    /// Steps to take after the dispatcher refused `command`, usually commands undoing what
+    /// earlier steps did. Commands issued here are compensating commands.
+    fn compensate(
+        &self,
+        state: &Self::State,
+        command: &Self::Command,
+        error: &str,
+    ) -> Vec<Step<Self::Event, Self::Command>>;
+}
+
+/// What is stored in the stream of an instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessEvent<Ev, C> {
+    /// The instance took steps on the event of `$all` at this position.
+    Handled(Position),
+    Recorded(Ev),
+    CommandIssued(C),
+    /// Issued while compensating for a refused command.
+    CompensationIssued(C),
+    /// Number of the issued command, counting from 0.
+    CommandDispatched(usize),
+    /// The command failed for a reason that may go away and is sent again at `retry_at`.
+    CommandDelayed {
+        command: usize,
+        error: String,
+        retry_at: Timestamp,
+    },
+    CommandFailed {
+        command: usize,
+        error: String,
+    },
+    TimeoutScheduled {
+        name: String,
+        deadline: Timestamp,
+    },
+    TimeoutCancelled(String),
+    TimeoutFired(String),
+    Completed,
+    /// Ends the instance after a compensating command was refused.
+    Failed(String),
+}
+
+impl<Ev, C> Event for ProcessEvent<Ev, C>
+where
+    Ev: Event,
+{
+    fn event_type(&self) -> &'static str {
+        match self {
+            ProcessEvent::Handled(_) => "handled",
+            ProcessEvent::Recorded(event) => event.event_type(),
+            ProcessEvent::CommandIssued(_) => "command_issued",
+            ProcessEvent::CompensationIssued(_) => "compensation_issued",
+            ProcessEvent::CommandDispatched(_) => "command_dispatched",
+            ProcessEvent::CommandDelayed { .. } => "command_delayed",
+            ProcessEvent::CommandFailed { .. } => "command_failed",
+            ProcessEvent::TimeoutScheduled { .. } => "timeout_scheduled",
+            ProcessEvent::TimeoutCancelled(_) => "timeout_cancelled",
+            ProcessEvent::TimeoutFired(_) => "timeout_fired",
+            ProcessEvent::Completed => "completed",
+            ProcessEvent::Failed(_) => "failed",
+        }
+    }
+}
+
+impl<S, Ev, C> AggregateEvent<ProcessInstance<S, C>> for ProcessEvent<Ev, C>
+where
+    S: Aggregate,
+    Ev: AggregateEvent<S>,
+{
+    type Error = Ev::Error;
+
+    fn apply_to(self, instance: &mut ProcessInstance<S, C>) -> Result<(), Self::Error> {
+        match self {
+            ProcessEvent::Handled(position) => instance.position = position,
+            ProcessEvent::Recorded(event) => instance.state.apply(event)?,
+            ProcessEvent::CommandIssued(command) => {
+                instance.commands.push((command, CommandStatus::Pending))
+            }
+            ProcessEvent::CompensationIssued(command) => {
+                instance.compensations.insert(instance.commands.len());
+                instance.commands.push((command, CommandStatus::Pending))
+            }
+            ProcessEvent::CommandDispatched(number) => {
+                instance.set_command_status(number, CommandStatus::Dispatched)
+            }
+            ProcessEvent::CommandDelayed {
+                command, retry_at, ..
+            } => {
+                let attempts = instance.attempts(command) + 1;
+                instance.set_command_status(command, CommandStatus::Delayed { attempts, retry_at })
+            }
+            ProcessEvent::CommandFailed { command, .. } => {
+                instance.set_command_status(command, CommandStatus::Failed)
+            }
+            ProcessEvent::TimeoutScheduled { name, deadline } => {
+                instance.timeouts.insert(name, deadline);
+            }
+            ProcessEvent::TimeoutCancelled(name) | ProcessEvent::TimeoutFired(name) => {
+                instance.timeouts.remove(&name);
+            }
+            ProcessEvent::Completed => {
+                instance.completed = true;
+                instance.timeouts.clear();
+            }
+            ProcessEvent::Failed(_) => {
+                instance.completed = true;
+                instance.failed = true;
+                instance.timeouts.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandStatus {
+    Pending,
+    Dispatched,
+    /// Failed `attempts` times for a reason that may go away; sent again at `retry_at`.
+    Delayed {
+        attempts: u32,
+        retry_at: Timestamp,
+    },
+    Failed,
+}
+
+/// One run of a process, rebuilt from its stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInstance<S, C> {
+    pub state: S,
+    /// Position of the last event of `$all` the instance took steps on.
+    pub position: Position,
+    /// Issued commands, in the order they are dispatched in.
+    pub commands: Vec<(C, CommandStatus)>,
+    /// Deadlines of the timeouts that did not fire yet, by name.
+    pub timeouts: BTreeMap<String, Timestamp>,
+    pub completed: bool,
+    /// Ended because a compensating command was refused.
+    pub failed: bool,
+    /// Numbers of the commands issued while compensating.
+    compensations: BTreeSet<usize>,
+    generation: u64,
+}
+
+impl<S, C> ProcessInstance<S, C> {
+    fn set_command_status(&mut self, number: usize, status: CommandStatus) {
+        if let Some(command) = self.commands.get_mut(number) {
+            command.1 = status;
+        }
+    }
+
+    fn attempts(&self, number: usize) -> u32 {
+        match self.commands.get(number) {
+            Some((_, CommandStatus::Delayed { attempts, .. })) => *attempts,
+            _ => 0,
+        }
+    }
+
+    /// First command not sent yet, unless it waits for a retry that is not due at `now`.
+    fn next_command(&self, now: Timestamp) -> Option<(usize, &C)> {
+        if self.failed {
+            return None;
+        }
+
+        let number = self.commands.iter().position(|(_, status)| {
+            matches!(
+                status,
+                CommandStatus::Pending | CommandStatus::Delayed { .. }
+            )
+        })?;
+
+        match self.commands[number].1 {
+            CommandStatus::Delayed { retry_at, .. } if retry_at > now => None,
+            _ => Some((number, &self.commands[number].0)),
+        }
+    }
+
+    /// Whether the instance still takes steps or has a command waiting for a retry.
+    fn is_active(&self) -> bool {
+        let delayed = self
+            .commands
+            .iter()
+            .any(|(_, status)| matches!(status, CommandStatus::Delayed { .. }));
+
+        !self.failed && (!self.completed || delayed)
+    }
+
+    /// Timeout with the earliest deadline that passed at `now`.
+    fn due_timeout(&self, now: Timestamp) -> Option<String> {
+        self.timeouts
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .min_by_key(|(name, deadline)| (**deadline, name.as_str()))
+            .map(|(name, _)| name.clone())
+    }
+}
+
+impl<S, C> Default for ProcessInstance<S, C>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        ProcessInstance {
+            state: S::default(),
+            position: 0,
+            commands: Vec::new(),
+            timeouts: BTreeMap::new(),
+            completed: false,
+            failed: false,
+            compensations: BTreeSet::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl<S, C> Aggregate for ProcessInstance<S, C>
+where
+    S: Aggregate,
+{
+    fn aggregate_type() -> &'static str {
+        "ProcessInstance"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+pub type ProcessInstanceOf<E, P> =
+    ProcessInstance<<P as ProcessManager<E>>::State, <P as ProcessManager<E>>::Command>;
+pub type ProcessEventOf<E, P> =
+    ProcessEvent<<P as ProcessManager<E>>::Event, <P as ProcessManager<E>>::Command>;
+
+pub type ProcessRunnerError<A, E, ES, PS, P> = ProcessError<
+    <ES as EventStore<A, E>>::Error,
+    <PS as EventStore<ProcessInstanceOf<E, P>, ProcessEventOf<E, P>>>::Error,
+    <<P as ProcessManager<E>>::Event as AggregateEvent<<P as ProcessManager<E>>::State>>::Error,
+>;
+
+type ProcessResult<T, A, E, ES, PS, P> = Result<T, ProcessRunnerError<A, E, ES, PS, P>>;
+
+struct Progress {
+    /// Position of the last event of `$all` looked at.
+    position: Position,
+    /// Correlation ids of the instances that did not complete.
+    active: BTreeSet<String>,
+}
+
+/// Feeds a process manager the events of `$all`, stores what its instances decide and
+/// dispatches their commands.
+pub struct ProcessManagerRunner<A, E, ES, PS, P, D> {
+    event_store: ES,
+    process_store: PS,
+    process: P,
+    dispatcher: D,
+    /// Held while instances are changed, so only one caller changes them at a time.
+    progress: Mutex<Progress>,
+    clock: Box<dyn Clock + Send + Sync>,
+    batch_size: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+    _types: PhantomData<fn() -> (A, E)>,
+}
+
+impl<A, E, ES, PS, P, D> ProcessManagerRunner<A, E, ES, PS, P, D>
+where
+    A: Aggregate,
+    E: AggregateEvent<A>,
+    ES: GlobalEventStore<A, E>,
+    PS: GlobalEventStore<ProcessInstanceOf<E, P>, ProcessEventOf<E, P>>,
+    P: ProcessManager<E>,
+    D: CommandHandler<P::Command>,
+{
+    pub fn new(
+        event_store: ES,
+        process_store: PS,
+        process: P,
+        dispatcher: D,
+    ) -> ProcessManagerRunner<A, E, ES, PS, P, D> {
+        ProcessManagerRunner {
+            event_store,
+            process_store,
+            process,
+            dispatcher,
+            progress: Mutex::new(Progress {
+                position: 0,
+                active: BTreeSet::new(),
+            }),
+            clock: Box::new(SystemClock),
+            batch_size: 500,
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(1),
+            _types: PhantomData,
+        }
+    }
+
+    /// Most events read from `$all` at once.
+    ///
+    /// Panics when `batch_size` is 0, as the runner would never move on.
+    pub fn with_batch_size(
+        mut self,
+        batch_size: usize,
+    ) -> ProcessManagerRunner<A, E, ES, PS, P, D> {
+        assert!(
+            batch_size > 0,
+            "process manager batch size must be positive"
+        );
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Number of transient failures after which a command counts as refused.
+    pub fn with_max_attempts(
+        mut self,
+        max_attempts: u32,
+    ) -> ProcessManagerRunner<A, E, ES, PS, P, D> {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before a command that failed for a transient reason is sent the first time again.
+    pub fn with_retry_delay(
+        mut self,
+        retry_delay: Duration,
+    ) -> ProcessManagerRunner<A, E, ES, PS, P, D> {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Where deadlines of timeouts are measured from, the system time by default.
+    pub fn with_clock<C>(mut self, clock: C) -> ProcessManagerRunner<A, E, ES, PS, P, D>
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn process(&self) -> &P {
+        &self.process
+    }
+
+    /// Position of the last event of `$all` the runner looked at.
+    pub fn position(&self) -> Position {
+        self.progress.lock().unwrap().position
+    }
+
+    pub fn instance(
+        &self,
+        correlation_id: &str,
+    ) -> ProcessResult<ProcessInstanceOf<E, P>, A, E, ES, PS, P> {
+        self.load(correlation_id)
+    }
+
+    /// Rebuilds every instance from the process store after a restart, continues after the last
+    /// event an instance took steps on and dispatches the commands instances recorded but did not
+    /// get to dispatch. Those may have reached the dispatcher before the crash, so commands are
+    /// delivered at least once and have to be idempotent. Returns how many instances are active.
+    pub fn recover(&self) -> ProcessResult<usize, A, E, ES, PS, P> {
+        let mut progress = self.progress.lock().unwrap();
+        let category = Subscription::Category(self.process.name().to_owned());
+        let prefix_len = self.process.name().len() + 1;
+        let mut correlation_ids = BTreeSet::new();
+        let mut position = 0;
+
+        loop {
+            let events = self
+                .process_store
+                .read_all(position, self.batch_size)
+                .map_err(ProcessError::ProcessStore)?;
+            let last = match events.last() {
+                Some(envelope) => envelope.position,
+                None => break,
+            };
+            for envelope in events {
+                if category.matches(&envelope.stream_id) {
+                    correlation_ids.insert(envelope.stream_id[prefix_len..].to_owned());
+                }
+            }
+            position = last;
+        }
+
+        for id in correlation_ids {
+            let instance = self.load(&id)?;
+            progress.position = progress.position.max(instance.position);
+            self.settle(&mut progress.active, &id, instance)?;
+        }
+
+        Ok(progress.active.len())
+    }
+
+    /// Hands every event after the runner's position to the instance it is correlated with and
+    /// returns how many events instances took steps on.
+    pub fn run_once(&self) -> ProcessResult<usize, A, E, ES, PS, P> {
+        let mut progress = self.progress.lock().unwrap();
+        let mut handled = 0;
+
+        loop {
+            let events = self
+                .event_store
+                .read_all(progress.position, self.batch_size)
+                .map_err(ProcessError::EventStore)?;
+            if events.is_empty() {
+                break;
+            }
+
+            for envelope in events {
+                if let Some(id) = self.process.correlate(&envelope) {
+                    if self.handle_event(&mut progress.active, &id, &envelope)? {
+                        handled += 1;
+                    }
+                }
+                progress.position = envelope.position;
+            }
+        }
+
+        Ok(handled)
+    }
+
+    /// Fires the timeouts of active instances whose deadline passed, earliest first, and returns
+    /// how many fired.
+    pub fn fire_timeouts(&self) -> ProcessResult<usize, A, E, ES, PS, P> {
+        let mut progress = self.progress.lock().unwrap();
+        let now = self.clock.now();
+        let mut fired = 0;
+
+        let ids: Vec<String> = progress.active.iter().cloned().collect();
+        for id in ids {
+            let mut instance = self.load(&id)?;
+
+            while let Some(name) = instance.due_timeout(now) {
+                let steps = self.process.timeout(&instance.state, &name);
+                let mut events = vec![ProcessEvent::TimeoutFired(name)];
+                events.extend(self.record(steps, false));
+                instance = self.save(&id, instance, events)?;
+                instance = self.dispatch(&id, instance)?;
+                fired += 1;
+            }
+
+            if !instance.is_active() {
+                progress.active.remove(&id);
+            }
+        }
+
+        Ok(fired)
+    }
+
+    /// Sends again the commands of active instances whose retry is due and returns how many
+    /// were sent.
+    pub fn retry_commands(&self) -> ProcessResult<usize, A, E, ES, PS, P> {
+        let mut progress = self.progress.lock().unwrap();
+        let now = self.clock.now();
+        let mut retried = 0;
+
+        let ids: Vec<String> = progress.active.iter().cloned().collect();
+        for id in ids {
+            let instance = self.load(&id)?;
+            let due = match instance.next_command(now) {
+                Some((number, _)) => instance.attempts(number) > 0,
+                None => false,
+            };
+
+            if due {
+                self.settle(&mut progress.active, &id, instance)?;
+                retried += 1;
+            }
+        }
+
+        Ok(retried)
+    }
+
+    fn handle_event(
+        &self,
+        active: &mut BTreeSet<String>,
+        id: &str,
+        envelope: &EventEnvelope<E>,
+    ) -> ProcessResult<bool, A, E, ES, PS, P> {
+        let instance = self.load(id)?;
+        if instance.completed || envelope.position <= instance.position {
+            return Ok(false);
+        }
+
+        let steps = self.process.handle(&instance.state, envelope);
+        if steps.is_empty() {
+            return Ok(false);
+        }
+
+        let mut events = vec![ProcessEvent::Handled(envelope.position)];
+        events.extend(self.record(steps, false));
+        let instance = self.save(id, instance, events)?;
+        self.settle(active, id, instance)?;
+
+        Ok(true)
+    }
+
+    /// Dispatches the pending commands of the instance and tracks whether it is still active.
+    fn settle(
+        &self,
+        active: &mut BTreeSet<String>,
+        id: &str,
+        instance: ProcessInstanceOf<E, P>,
+    ) -> ProcessResult<(), A, E, ES, PS, P> {
+        let instance = self.dispatch(id, instance)?;
+
+        if !instance.is_active() {
+            active.remove(id);
+        } else {
+            active.insert(id.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Sends the pending commands in the order they were issued, stopping at one that waits
+    /// for a retry. A command failing for a transient reason is delayed until it ran out of
+    /// attempts; a refused one is recorded as failed together with the steps compensating for
+    /// it, or ends the instance when it was a compensating command itself.
+    fn dispatch(
+        &self,
+        id: &str,
+        mut instance: ProcessInstanceOf<E, P>,
+    ) -> ProcessResult<ProcessInstanceOf<E, P>, A, E, ES, PS, P> {
+        let now = self.clock.now();
+
+        while let Some((number, command)) = instance
+            .next_command(now)
+            .map(|(number, command)| (number, command.clone()))
+        {
+            let attempts = instance.attempts(number) + 1;
+            let events = match self.dispatcher.handle(command.clone()) {
+                Ok(()) => vec![ProcessEvent::CommandDispatched(number)],
+                Err(err) if D::is_transient(&err) && attempts < self.max_attempts => {
+                    let delayed = ProcessEvent::CommandDelayed {
+                        command: number,
+                        error: err.to_string(),
+                        retry_at: self.retry_at(now, attempts),
+                    };
+                    // Later commands wait for this one, which `retry_commands` sends again.
+                    return self.save(id, instance, vec![delayed]);
+                }
+                Err(err) => self.refused(&instance, number, &command, err.to_string()),
+            };
+            instance = self.save(id, instance, events)?;
+        }
+
+        Ok(instance)
+    }
+
+    fn refused(
+        &self,
+        instance: &ProcessInstanceOf<E, P>,
+        number: usize,
+        command: &P::Command,
+        error: String,
+    ) -> Vec<ProcessEventOf<E, P>> {
+        let mut events = vec![ProcessEvent::CommandFailed {
+            command: number,
+            error: error.clone(),
+        }];
+
+        if instance.compensations.contains(&number) {
+            events.push(ProcessEvent::Failed(error));
+        } else {
+            let steps = self.process.compensate(&instance.state, command, &error);
+            events.extend(self.record(steps, true));
+        }
+
+        events
+    }
+
+    fn retry_at(&self, now: Timestamp, attempts: u32) -> Timestamp {
+        clock::backoff(now, self.retry_delay, attempts)
+    }
+
+    fn record(
+        &self,
+        steps: Vec<Step<P::Event, P::Command>>,
+        compensating: bool,
+    ) -> Vec<ProcessEventOf<E, P>> {
+        let now = self.clock.now();
+
+        steps
+            .into_iter()
+            .map(|step| match step {
+                Step::Record(event) => ProcessEvent::Recorded(event),
+                Step::Issue(command) if compensating => ProcessEvent::CompensationIssued(command),
+                Step::Issue(command) => ProcessEvent::CommandIssued(command),
+                Step::ScheduleTimeout { name, after } => ProcessEvent::TimeoutScheduled {
+                    name,
+                    deadline: clock::deadline(now, after),
+                },
+                Step::CancelTimeout(name) => ProcessEvent::TimeoutCancelled(name),
+                Step::Complete => ProcessEvent::Completed,
+            })
+            .collect()
+    }
+
+    fn stream_id(&self, correlation_id: &str) -> String {
+        format!("{}-{}", self.process.name(), correlation_id)
+    }
+
+    fn load(
+        &self,
+        correlation_id: &str,
+    ) -> ProcessResult<ProcessInstanceOf<E, P>, A, E, ES, PS, P> {
+        let events = self
+            .process_store
+            .read_events(&self.stream_id(correlation_id), 0)
+            .map_err(ProcessError::ProcessStore)?;
+
+        let mut instance = ProcessInstance::default();
+        for versioned in events {
+            instance
+                .apply(versioned.event)
+                .map_err(ProcessError::Apply)?;
+        }
+
+        Ok(instance)
+    }
+
+    fn save(
+        &self,
+        correlation_id: &str,
+        mut instance: ProcessInstanceOf<E, P>,
+        events: Vec<ProcessEventOf<E, P>>,
+    ) -> ProcessResult<ProcessInstanceOf<E, P>, A, E, ES, PS, P> {
+        let previous_generation = instance.generation();
+
+        for event in events.iter().cloned() {
+            instance.apply(event).map_err(ProcessError::Apply)?;
+        }
+
+        self.process_store
+            .append_events(
+                &self.stream_id(correlation_id),
+                events,
+                ExpectedVersion::from_generation(previous_generation),
+            )
+            .map_err(ProcessError::ProcessStore)?;
+
+        Ok(instance)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessError<ES, PS, AE> {
+    EventStore(ES),
+    ProcessStore(PS),
+    /// A stored event of an instance could not be applied to its state.
+    Apply(AE),
+}
+
+impl<ES, PS, AE> Error for ProcessError<ES, PS, AE>
+where
+    ES: fmt::Debug + fmt::Display,
+    PS: fmt::Debug + fmt::Display,
+    AE: fmt::Debug + fmt::Display,
+{
+}
+
+impl<ES, PS, AE> fmt::Display for ProcessError<ES, PS, AE>
+where
+    ES: fmt::Display,
+    PS: fmt::Display,
+    AE: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::EventStore(err) => write!(f, "ProcessError: {}", err),
+            ProcessError::ProcessStore(err) => write!(f, "ProcessError: process store: {}", err),
+            ProcessError::Apply(err) => write!(f, "ProcessError: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::eventstore::{EventStore, ExpectedVersion, InMemoryEventStore};
+    use crate::handler::CommandHandler;
+    use crate::process::*;
+    use crate::test_helpers::{CounterAggregate, CounterError, CounterEvent};
+    use std::sync::Arc;
+
+    type CounterEventStore = Arc<InMemoryEventStore<CounterAggregate, CounterEvent>>;
+    type FulfilmentStore = Arc<
+        InMemoryEventStore<
+            ProcessInstanceOf<CounterEvent, Fulfilment>,
+            ProcessEventOf<CounterEvent, Fulfilment>,
+        >,
+    >;
+    type FulfilmentRunner = ProcessManagerRunner<
+        CounterAggregate,
+        CounterEvent,
+        CounterEventStore,
+        FulfilmentStore,
+        Fulfilment,
+        Arc<Warehouse>,
+    >;
+
+    const START: Timestamp = 10_000;
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    struct Order {
+        reserved: Option<(String, u64)>,
+        generation: u64,
+    }
+
+    impl Aggregate for Order {
+        fn aggregate_type() -> &'static str {
+            "Order"
+        }
+
+        fn generation(&self) -> u64 {
+            self.generation
+        }
+
+        fn increment_generation(&mut self) {
+            self.generation += 1;
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Reserving(String, u64);
+
+    impl Event for Reserving {
+        fn event_type(&self) -> &'static str {
+            "reserving"
+        }
+    }
+
+    impl AggregateEvent<Order> for Reserving {
+        type Error = CounterError;
+
+        fn apply_to(self, order: &mut Order) -> Result<(), Self::Error> {
+            order.reserved = Some((self.0, self.1));
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum WarehouseCommand {
+        Reserve(String, u64),
+        Ship(String),
+        Release(String, u64),
+    }
+
+    /// Reserves stock for an order on its first event and ships it on the next one. Releases
+    /// the stock when payment takes too long or shipping is refused.
+    struct Fulfilment;
+
+    impl Fulfilment {
+        fn release(order: &Order) -> Vec<Step<Reserving, WarehouseCommand>> {
+            let (id, amount) = order.reserved.clone().unwrap();
+            vec![
+                Step::Issue(WarehouseCommand::Release(id, amount)),
+                Step::Complete,
+            ]
+        }
+    }
+
+    impl ProcessManager<CounterEvent> for Fulfilment {
+        type State = Order;
+        type Event = Reserving;
+        type Command = WarehouseCommand;
+
+        fn name(&self) -> &str {
+            "fulfilment"
+        }
+
+        fn correlate(&self, envelope: &EventEnvelope<CounterEvent>) -> Option<String> {
+            if envelope.stream_id.starts_with("order-") {
+                Some(envelope.stream_id["order-".len()..].to_owned())
+            } else {
+                None
+            }
+        }
+
+        fn handle(
+            &self,
+            order: &Order,
+            envelope: &EventEnvelope<CounterEvent>,
+        ) -> Vec<Step<Reserving, WarehouseCommand>> {
+            let id = self.correlate(envelope).unwrap();
+            let CounterEvent::Added(amount) = envelope.event;
+
+            match order.reserved {
+                None => vec![
+                    Step::Record(Reserving(id.clone(), amount)),
+                    Step::Issue(WarehouseCommand::Reserve(id, amount)),
+                    Step::ScheduleTimeout {
+                        name: "payment".to_owned(),
+                        after: Duration::from_secs(1),
+                    },
+                ],
+                Some(_) => vec![
+                    Step::CancelTimeout("payment".to_owned()),
+                    Step::Issue(WarehouseCommand::Ship(id)),
+                    Step::Complete,
+                ],
+            }
+        }
+
+        fn timeout(&self, order: &Order, _name: &str) -> Vec<Step<Reserving, WarehouseCommand>> {
+            Fulfilment::release(order)
+        }
+
+        fn compensate(
+            &self,
+            order: &Order,
+            command: &WarehouseCommand,
+            _error: &str,
+        ) -> Vec<Step<Reserving, WarehouseCommand>> {
+            match command {
+                WarehouseCommand::Reserve(..) => vec![Step::Complete],
+                _ => Fulfilment::release(order),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Warehouse {
+        received: Mutex<Vec<WarehouseCommand>>,
+        refuse_shipping: bool,
+        refuse_release: bool,
+        /// Number of commands still to fail as unavailable before any gets through.
+        unavailable: Mutex<u32>,
+    }
+
+    impl Warehouse {
+        fn received(&self) -> Vec<WarehouseCommand> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    impl CommandHandler<WarehouseCommand> for Warehouse {
+        type Error = String;
+
+        fn handle(&self, command: WarehouseCommand) -> Result<(), String> {
+            let mut unavailable = self.unavailable.lock().unwrap();
+            if *unavailable > 0 {
+                *unavailable -= 1;
+                return Err("unavailable".to_owned());
+            }
+
+            let refused = match command {
+                WarehouseCommand::Ship(_) => self.refuse_shipping,
+                WarehouseCommand::Release(..) => self.refuse_release,
+                WarehouseCommand::Reserve(..) => false,
+            };
+            self.received.lock().unwrap().push(command);
+
+            if refused {
+                Err("out of stock".to_owned())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn is_transient(error: &String) -> bool {
+            error == "unavailable"
+        }
+    }
+
+    struct Fixture {
+        event_store: CounterEventStore,
+        process_store: FulfilmentStore,
+        warehouse: Arc<Warehouse>,
+        clock: Arc<ManualClock>,
+    }
+
+    impl Fixture {
+        fn new(warehouse: Warehouse) -> Fixture {
+            Fixture {
+                event_store: Arc::new(InMemoryEventStore::new()),
+                process_store: Arc::new(InMemoryEventStore::new()),
+                warehouse: Arc::new(warehouse),
+                clock: Arc::new(ManualClock::new(START)),
+            }
+        }
+
+        fn add(&self, id: &str, value: u64) {
+            self.event_store
+                .append_events(id, vec![CounterEvent::Added(value)], ExpectedVersion::Any)
+                .unwrap();
+        }
+
+        fn runner(&self) -> FulfilmentRunner {
+            ProcessManagerRunner::new(
+                self.event_store.clone(),
+                self.process_store.clone(),
+                Fulfilment,
+                self.warehouse.clone(),
+            )
+            .with_clock(self.clock.clone())
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "process manager batch size must be positive")]
+    fn zero_batch_size_is_refused() {
+        Fixture::new(Warehouse::default())
+            .runner()
+            .with_batch_size(0);
+    }
+
+    #[test]
+    fn first_event_starts_instance_and_dispatches_its_command() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse::default());
+        fixture.add("other-1", 3);
+        fixture.add("order-1", 5);
+        let runner = fixture.runner();
+
+        // Act
+        let result = runner.run_once();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        assert_eq!(
+            vec![WarehouseCommand::Reserve("1".to_owned(), 5)],
+            fixture.warehouse.received()
+        );
+        let recorded: Vec<_> = fixture
+            .process_store
+            .read_events("fulfilment-1", 0)
+            .unwrap()
+            .into_iter()
+            .map(|versioned| versioned.event)
+            .collect();
+        assert_eq!(
+            vec![
+                ProcessEvent::Handled(2),
+                ProcessEvent::Recorded(Reserving("1".to_owned(), 5)),
+                ProcessEvent::CommandIssued(WarehouseCommand::Reserve("1".to_owned(), 5)),
+                ProcessEvent::TimeoutScheduled {
+                    name: "payment".to_owned(),
+                    deadline: START + 1000,
+                },
+                ProcessEvent::CommandDispatched(0),
+            ],
+            recorded
+        );
+        assert_eq!(2, runner.position());
+    }
+
+    #[test]
+    fn completed_instance_ignores_its_cancelled_timeout() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse::default());
+        fixture.add("order-1", 5);
+        fixture.add("order-1", 0);
+        let runner = fixture.runner();
+        runner.run_once().unwrap();
+        fixture.clock.set(START + 5000);
+
+        // Act
+        let result = runner.fire_timeouts();
+
+        // Assert
+        assert_eq!(Ok(0), result);
+        assert_eq!(
+            vec![
+                WarehouseCommand::Reserve("1".to_owned(), 5),
+                WarehouseCommand::Ship("1".to_owned()),
+            ],
+            fixture.warehouse.received()
+        );
+        assert!(runner.instance("1").unwrap().completed);
+    }
+
+    #[test]
+    fn timeout_fires_once_deadline_passed() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse::default());
+        fixture.add("order-1", 5);
+        let runner = fixture.runner();
+        runner.run_once().unwrap();
+
+        // Act
+        fixture.clock.set(START + 999);
+        let early = runner.fire_timeouts();
+        fixture.clock.set(START + 1000);
+        let due = runner.fire_timeouts();
+
+        // Assert
+        assert_eq!(Ok(0), early);
+        assert_eq!(Ok(1), due);
+        assert_eq!(
+            vec![
+                WarehouseCommand::Reserve("1".to_owned(), 5),
+                WarehouseCommand::Release("1".to_owned(), 5),
+            ],
+            fixture.warehouse.received()
+        );
+        let instance = runner.instance("1").unwrap();
+        assert!(instance.completed);
+        assert!(instance.timeouts.is_empty());
+    }
+
+    #[test]
+    fn refused_command_is_compensated() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse {
+            refuse_shipping: true,
+            ..Warehouse::default()
+        });
+        fixture.add("order-1", 5);
+        fixture.add("order-1", 0);
+        let runner = fixture.runner();
+
+        // Act
+        let result = runner.run_once();
+
+        // Assert
+        assert_eq!(Ok(2), result);
+        let instance = runner.instance("1").unwrap();
+        assert_eq!(
+            vec![
+                (
+                    WarehouseCommand::Reserve("1".to_owned(), 5),
+                    CommandStatus::Dispatched
+                ),
+                (
+                    WarehouseCommand::Ship("1".to_owned()),
+                    CommandStatus::Failed
+                ),
+                (
+                    WarehouseCommand::Release("1".to_owned(), 5),
+                    CommandStatus::Dispatched
+                ),
+            ],
+            instance.commands
+        );
+        assert!(instance.completed);
+    }
+
+    #[test]
+    fn recovery_dispatches_commands_recorded_before_crash() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse::default());
+        fixture.add("order-1", 5);
+        fixture
+            .process_store
+            .append_events(
+                "fulfilment-1",
+                vec![
+                    ProcessEvent::Handled(1),
+                    ProcessEvent::Recorded(Reserving("1".to_owned(), 5)),
+                    ProcessEvent::CommandIssued(WarehouseCommand::Reserve("1".to_owned(), 5)),
+                    ProcessEvent::TimeoutScheduled {
+                        name: "payment".to_owned(),
+                        deadline: START + 1000,
+                    },
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .unwrap();
+        let runner = fixture.runner();
+
+        // Act
+        let active = runner.recover();
+        let handled = runner.run_once();
+        fixture.clock.set(START + 1000);
+        let fired = runner.fire_timeouts();
+
+        // Assert
+        assert_eq!(Ok(1), active);
+        assert_eq!(Ok(0), handled);
+        assert_eq!(Ok(1), fired);
+        assert_eq!(
+            vec![
+                WarehouseCommand::Reserve("1".to_owned(), 5),
+                WarehouseCommand::Release("1".to_owned(), 5),
+            ],
+            fixture.warehouse.received()
+        );
+    }
+
+    #[test]
+    fn transient_failure_is_retried_with_growing_delay() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse {
+            unavailable: Mutex::new(2),
+            ..Warehouse::default()
+        });
+        fixture.add("order-1", 5);
+        let runner = fixture.runner().with_retry_delay(Duration::from_secs(1));
+        runner.run_once().unwrap();
+
+        // Act
+        let early = runner.retry_commands();
+        fixture.clock.set(START + 1000);
+        let first = runner.retry_commands();
+        fixture.clock.set(START + 2999);
+        let before_second = runner.retry_commands();
+        fixture.clock.set(START + 3000);
+        let second = runner.retry_commands();
+
+        // Assert
+        assert_eq!(
+            vec![Ok(0), Ok(1), Ok(0), Ok(1)],
+            vec![early, first, before_second, second]
+        );
+        assert_eq!(
+            vec![WarehouseCommand::Reserve("1".to_owned(), 5)],
+            fixture.warehouse.received()
+        );
+        assert_eq!(
+            CommandStatus::Dispatched,
+            runner.instance("1").unwrap().commands[0].1
+        );
+    }
+
+    #[test]
+    fn command_out_of_attempts_is_compensated() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse {
+            unavailable: Mutex::new(2),
+            ..Warehouse::default()
+        });
+        fixture.add("order-1", 5);
+        let runner = fixture
+            .runner()
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::from_millis(0));
+        runner.run_once().unwrap();
+
+        // Act
+        let result = runner.retry_commands();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        let instance = runner.instance("1").unwrap();
+        assert_eq!(
+            vec![(
+                WarehouseCommand::Reserve("1".to_owned(), 5),
+                CommandStatus::Failed
+            )],
+            instance.commands
+        );
+        assert!(instance.completed);
+        assert!(!instance.failed);
+    }
+
+    #[test]
+    fn refused_compensating_command_fails_instance() {
+        // Arrange
+        let fixture = Fixture::new(Warehouse {
+            refuse_shipping: true,
+            refuse_release: true,
+            ..Warehouse::default()
+        });
+        fixture.add("order-1", 5);
+        fixture.add("order-1", 0);
+        let runner = fixture.runner();
+
+        // Act
+        runner.run_once().unwrap();
+        let active = runner.recover();
+
+        // Assert
+        let instance = runner.instance("1").unwrap();
+        assert_eq!(CommandStatus::Failed, instance.commands[2].1);
+        assert!(instance.failed);
+        assert_eq!(Ok(0), active);
+    }
+}
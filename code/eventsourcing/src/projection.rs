@@ -0,0 +1,1074 @@
+//! Query side: projections that build read models from the `$all` stream.
+//!
+//! A `ProjectionRunner` reads events after the projection's checkpoint, hands them to the
+//! projection and stores the new checkpoint in the same read model transaction as the writes
+//! the projection made, so a restart never applies an event twice or skips one.
+
+use crate::eventstore::{EventEnvelope, GlobalEventStore, Position};
+use crate::partitioned::partition_for;
+use crate::{Aggregate, AggregateEvent};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{error::Error, fmt};
+
+/// Which events of `$all` a projection is interested in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subscription {
+    All,
+    /// Streams whose id starts with the category followed by `-`, e.g. `BankAccount-123`.
+    Category(String),
+}
+
+impl Subscription {
+    pub fn matches(&self, stream_id: &str) -> bool {
+        match self {
+            Subscription::All => true,
+            Subscription::Category(category) => {
+                stream_id.len() > category.len()
+                    && stream_id.starts_with(category.as_str())
+                    && stream_id.as_bytes()[category.len()] == b'-'
+            }
+        }
+    }
+}
+
+/// Storage of read models together with the checkpoints of the projections writing them.
+pub trait ReadModelStore {
+    /// What a projection writes through while a transaction is open.
+    type Tx;
+
+    /// Position of the last event handled by `projection`, 0 when it handled none.
+    fn checkpoint(&self, projection: &str) -> Result<Position, ReadModelError>;
+
+    /// Runs `write` and moves the checkpoint of `projection` to `position` in one transaction.
+    /// Nothing `write` did is kept when it fails.
+    fn commit<F>(
+        &self,
+        projection: &str,
+        position: Position,
+        write: F,
+    ) -> Result<(), ReadModelError>
+    where
+        F: FnOnce(&mut Self::Tx) -> Result<(), ReadModelError>;
+}
+
+pub trait Projection<E, S>
+where
+    S: ReadModelStore,
+{
+    /// Identifies the checkpoint of this projection in the read model store.
+    fn name(&self) -> &str;
+
+    fn subscription(&self) -> Subscription {
+        Subscription::All
+    }
+
+    fn handle(&self, tx: &mut S::Tx, envelope: &EventEnvelope<E>) -> Result<(), ReadModelError>;
+
+    /// Removes everything this projection wrote so it can be rebuilt from the start.
+    fn reset(&self, tx: &mut S::Tx) -> Result<(), ReadModelError>;
+}
+
+/// Read models kept in memory as a single state value.
+pub struct InMemoryReadModelStore<S> {
+    inner: Mutex<(S, HashMap<String, Position>)>,
+}
+
+impl<S> InMemoryReadModelStore<S>
+where
+    S: Default,
+{
+    pub fn new() -> InMemoryReadModelStore<S> {
+        InMemoryReadModelStore {
+            inner: Mutex::new((S::default(), HashMap::new())),
+        }
+    }
+}
+
+impl<S> Default for InMemoryReadModelStore<S>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        InMemoryReadModelStore::new()
+    }
+}
+
+impl<S> InMemoryReadModelStore<S> {
+    pub fn read<T, F>(&self, query: F) -> T
+    where
+        F: FnOnce(&S) -> T,
+    {
+        let inner = self.inner.lock().unwrap();
+
+        query(&inner.0)
+    }
+}
+
+impl<S> ReadModelStore for InMemoryReadModelStore<S>
+where
+    S: Clone,
+{
+    type Tx = S;
+
+    fn checkpoint(&self, projection: &str) -> Result<Position, ReadModelError> {
+        let inner = self.inner.lock().unwrap();
+
+        Ok(inner.1.get(projection).cloned().unwrap_or(0))
+    }
+
+    fn commit<F>(
+        &self,
+        projection: &str,
+        position: Position,
+        write: F,
+    ) -> Result<(), ReadModelError>
+    where
+        F: FnOnce(&mut S) -> Result<(), ReadModelError>,
+    {
+        let mut inner = self.inner.lock().unwrap();
+
+        // Writes go to a copy that only replaces the read model when all of them succeeded.
+        let mut state = inner.0.clone();
+        write(&mut state)?;
+
+        inner.0 = state;
+        inner.1.insert(projection.to_owned(), position);
+
+        Ok(())
+    }
+}
+
+pub struct ProjectionRunner<A, E, ES, S, P> {
+    event_store: ES,
+    store: RwLock<Arc<S>>,
+    /// Held while the live read model is written to or replaced.
+    live_writes: Mutex<()>,
+    /// Signalled whenever the live read model moved on.
+    advanced: Condvar,
+    advanced_lock: Mutex<()>,
+    projection: P,
+    batch_size: usize,
+    workers: usize,
+    paused: AtomicBool,
+    _types: PhantomData<fn() -> (A, E)>,
+}
+
+impl<A, E, ES, S, P> ProjectionRunner<A, E, ES, S, P>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Sync,
+    ES: GlobalEventStore<A, E>,
+    S: ReadModelStore + Sync,
+    P: Projection<E, S> + Sync,
+{
+    pub fn new(event_store: ES, store: Arc<S>, projection: P) -> ProjectionRunner<A, E, ES, S, P> {
+        ProjectionRunner {
+            event_store,
+            store: RwLock::new(store),
+            live_writes: Mutex::new(()),
+            advanced: Condvar::new(),
+            advanced_lock: Mutex::new(()),
+            projection,
+            batch_size: 500,
+            workers: 1,
+            paused: AtomicBool::new(false),
+            _types: PhantomData,
+        }
+    }
+
+    /// Most events handled in one read model transaction.
+    ///
+    /// Panics when `batch_size` is 0, as the runner would never move on.
+    pub fn with_batch_size(mut self, batch_size: usize) -> ProjectionRunner<A, E, ES, S, P> {
+        assert!(batch_size > 0, "projection batch size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Splits every batch by stream id across `workers` threads. Each worker commits its share
+    /// with its own checkpoint, so events of a stream stay in order and the projection's
+    /// position is the lowest one every worker has finished.
+    ///
+    /// Only pays off when the read model store lets commits of different workers run
+    /// concurrently. A projection replayed in parallel has to keep the same number of workers.
+    pub fn with_workers(mut self, workers: usize) -> ProjectionRunner<A, E, ES, S, P> {
+        self.workers = workers.max(1);
+        self
+    }
+
+    pub fn projection(&self) -> &P {
+        &self.projection
+    }
+
+    /// The read model queries should be served from. A rebuild replaces it, so ask for it per
+    /// query instead of holding on to it.
+    pub fn read_model(&self) -> Arc<S> {
+        Arc::clone(&self.store.read().unwrap())
+    }
+
+    pub fn position(&self) -> Result<Position, ProjectionError<ES::Error>> {
+        Ok(self.position_in(&*self.read_model())?)
+    }
+
+    fn position_in(&self, store: &S) -> Result<Position, ReadModelError> {
+        let starts = self.worker_checkpoints(store)?;
+
+        Ok(starts.into_iter().min().unwrap_or(0))
+    }
+
+    /// Where every worker continues from in `store`. Worker checkpoints only exist once
+    /// a parallel replay committed; until then workers start at the projection's checkpoint.
+    fn worker_checkpoints(&self, store: &S) -> Result<Vec<Position>, ReadModelError> {
+        let name = self.projection.name();
+        let checkpoint = store.checkpoint(name)?;
+
+        if self.workers == 1 {
+            return Ok(vec![checkpoint]);
+        }
+
+        (0..self.workers)
+            .map(|worker| {
+                let worker_checkpoint = store.checkpoint(&worker_name(name, worker))?;
+                Ok(worker_checkpoint.max(checkpoint))
+            })
+            .collect()
+    }
+
+    /// Feeds the projection everything after its checkpoint and returns how many events it
+    /// handled. Does nothing while paused.
+    pub fn run_once(&self) -> Result<usize, ProjectionError<ES::Error>> {
+        let _live_writes = self.live_writes.lock().unwrap();
+        let store = self.read_model();
+        let mut handled = 0;
+
+        while !self.is_paused() {
+            match self.run_batch(&store)? {
+                Some(count) => handled += count,
+                None => break,
+            }
+            self.notify_advanced();
+        }
+
+        Ok(handled)
+    }
+
+    /// Blocks until the projection handled every event up to `min_position`, e.g. the position
+    /// a command returned, so a query made after the command sees its effects.
+    pub fn wait_for(
+        &self,
+        min_position: Position,
+        timeout: Duration,
+    ) -> Result<Position, ProjectionError<ES::Error>> {
+        let deadline = Instant::now() + timeout;
+        let mut advanced = self.advanced_lock.lock().unwrap();
+
+        loop {
+            let position = self.position()?;
+            if position >= min_position {
+                return Ok(position);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(ProjectionError::Timeout {
+                    position,
+                    min_position,
+                });
+            }
+
+            advanced = self.advanced.wait_timeout(advanced, remaining).unwrap().0;
+        }
+    }
+
+    /// How far the projection is behind the head of `$all`.
+    pub fn lag(&self) -> Result<ProjectionLag, ProjectionError<ES::Error>> {
+        let head = self
+            .event_store
+            .head_position()
+            .map_err(ProjectionError::EventStore)?;
+        let position = self.position()?;
+
+        Ok(ProjectionLag {
+            projection: self.projection.name().to_owned(),
+            position,
+            head,
+            behind: head.saturating_sub(position),
+        })
+    }
+
+    fn notify_advanced(&self) {
+        let _advanced = self.advanced_lock.lock().unwrap();
+        self.advanced.notify_all();
+    }
+
+    /// Handles the next batch after the checkpoint in `store`; `None` once it is caught up.
+    fn run_batch(&self, store: &S) -> Result<Option<usize>, ProjectionError<ES::Error>> {
+        let name = self.projection.name();
+        let subscription = self.projection.subscription();
+
+        let starts = self.worker_checkpoints(store)?;
+        let since = starts.iter().cloned().min().unwrap_or(0);
+        let events = self
+            .event_store
+            .read_all(since, self.batch_size)
+            .map_err(ProjectionError::EventStore)?;
+
+        let last_position = match events.last() {
+            Some(envelope) => envelope.position,
+            None => return Ok(None),
+        };
+
+        let projection = &self.projection;
+
+        if self.workers == 1 {
+            store.commit(name, last_position, |tx| {
+                events
+                    .iter()
+                    .filter(|envelope| subscription.matches(&envelope.stream_id))
+                    .try_for_each(|envelope| projection.handle(tx, envelope))
+            })?;
+
+            return Ok(Some(events.len()));
+        }
+
+        let mut shares: Vec<Vec<&EventEnvelope<E>>> = vec![Vec::new(); self.workers];
+        for envelope in events
+            .iter()
+            .filter(|envelope| subscription.matches(&envelope.stream_id))
+        {
+            let worker = partition_for(&envelope.stream_id, self.workers);
+            // A worker that got further before an earlier batch failed already handled it.
+            if envelope.position > starts[worker] {
+                shares[worker].push(envelope);
+            }
+        }
+
+        // Every worker commits, even without events of its own, so its checkpoint keeps up.
+        thread::scope(|scope| {
+            let commits: Vec<_> = shares
+                .into_iter()
+                .enumerate()
+                .map(|(worker, share)| {
+                    scope.spawn(move || {
+                        store.commit(&worker_name(name, worker), last_position, |tx| {
+                            share
+                                .into_iter()
+                                .try_for_each(|envelope| projection.handle(tx, envelope))
+                        })
+                    })
+                })
+                .collect();
+
+            commits
+                .into_iter()
+                .map(|commit| commit.join().expect("projection worker panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        Ok(Some(events.len()))
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Clears the read model and the checkpoint; the next run rebuilds from the first event.
+    pub fn reset(&self) -> Result<(), ProjectionError<ES::Error>> {
+        let _live_writes = self.live_writes.lock().unwrap();
+
+        Ok(self.clear(&*self.read_model())?)
+    }
+
+    fn clear(&self, store: &S) -> Result<(), ReadModelError> {
+        let projection = &self.projection;
+        let name = projection.name();
+
+        // Worker checkpoints go first; workers never start below the projection's checkpoint,
+        // so stopping halfway leaves the read model consistent.
+        if self.workers > 1 {
+            for worker in 0..self.workers {
+                store.commit(&worker_name(name, worker), 0, |_| Ok(()))?;
+            }
+        }
+
+        store.commit(name, 0, |tx| projection.reset(tx))
+    }
+
+    /// Rebuilds the read model from the first event into `shadow` while the live one keeps
+    /// serving queries and following new events, then switches to `shadow` and clears the
+    /// old read model.
+    ///
+    /// Live updates are only held back for the last few events appended while the shadow was
+    /// catching up. `progress` is called after every batch.
+    pub fn rebuild<F>(
+        &self,
+        shadow: Arc<S>,
+        mut progress: F,
+    ) -> Result<(), ProjectionError<ES::Error>>
+    where
+        F: FnMut(&RebuildProgress),
+    {
+        let started = Instant::now();
+        let mut handled = 0;
+
+        self.clear(&shadow)?;
+
+        loop {
+            let target = self
+                .event_store
+                .head_position()
+                .map_err(ProjectionError::EventStore)?;
+
+            match self.run_batch(&shadow)? {
+                Some(count) => handled += count,
+                None => break,
+            }
+
+            progress(&RebuildProgress {
+                position: self.position_in(&shadow)?,
+                target,
+                handled,
+                elapsed: started.elapsed(),
+            });
+        }
+
+        let _live_writes = self.live_writes.lock().unwrap();
+
+        while let Some(count) = self.run_batch(&shadow)? {
+            handled += count;
+        }
+
+        let position = self.position_in(&shadow)?;
+        let old = mem::replace(&mut *self.store.write().unwrap(), shadow);
+        self.notify_advanced();
+        self.clear(&old)?;
+
+        progress(&RebuildProgress {
+            position,
+            target: position,
+            handled,
+            elapsed: started.elapsed(),
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectionLag {
+    pub projection: String,
+    pub position: Position,
+    pub head: Position,
+    /// Positions between the projection's checkpoint and the head.
+    pub behind: u64,
+}
+
+/// How far a rebuild has come.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebuildProgress {
+    /// Checkpoint of the read model being rebuilt.
+    pub position: Position,
+    /// Head of `$all` when the last batch was read; it moves while new events are appended.
+    pub target: Position,
+    pub handled: usize,
+    pub elapsed: Duration,
+}
+
+impl RebuildProgress {
+    pub fn percent(&self) -> f64 {
+        if self.target == 0 {
+            return 100.0;
+        }
+
+        (self.position as f64 / self.target as f64 * 100.0).min(100.0)
+    }
+
+    /// Time left at the rate seen so far; `None` before the first event was handled.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.position == 0 {
+            return None;
+        }
+
+        let remaining = self.target.saturating_sub(self.position);
+
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.position as f64),
+        )
+    }
+}
+
+impl fmt::Display for RebuildProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} ({:.1}%)",
+            self.position,
+            self.target,
+            self.percent()
+        )?;
+
+        match self.eta() {
+            Some(eta) => write!(f, ", eta {}s", eta.as_secs()),
+            None => write!(f, ", eta unknown"),
+        }
+    }
+}
+
+impl<A, E, ES, S, P> ProjectionRunner<A, E, ES, S, P>
+where
+    A: Aggregate + 'static,
+    E: AggregateEvent<A> + Sync + 'static,
+    ES: GlobalEventStore<A, E> + Send + Sync + 'static,
+    S: ReadModelStore + Send + Sync + 'static,
+    P: Projection<E, S> + Send + Sync + 'static,
+{
+    /// Keeps running the projection on a background thread, polling for new events.
+    ///
+    /// A failed run is tried again after `poll_interval`; the error it ran into stays
+    /// available from `RunningProjection::last_error` until a run succeeds.
+    pub fn spawn(runner: Arc<Self>, poll_interval: Duration) -> RunningProjection<ES::Error> {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let last_error = Arc::new(Mutex::new(None));
+        let thread_stopped = Arc::clone(&stopped);
+        let thread_last_error = Arc::clone(&last_error);
+
+        let handle = thread::spawn(move || {
+            while !thread_stopped.load(Ordering::SeqCst) {
+                *thread_last_error.lock().unwrap() = runner.run_once().err();
+                thread::sleep(poll_interval);
+            }
+        });
+
+        RunningProjection {
+            stopped,
+            last_error,
+            handle: Some(handle),
+        }
+    }
+}
+
+pub struct RunningProjection<ES> {
+    stopped: Arc<AtomicBool>,
+    last_error: Arc<Mutex<Option<ProjectionError<ES>>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<ES> RunningProjection<ES> {
+    pub fn stop(mut self) {
+        self.stop_thread();
+    }
+
+    /// Error of the latest run, if it failed.
+    pub fn last_error(&self) -> Option<ProjectionError<ES>>
+    where
+        ES: Clone,
+    {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    fn stop_thread(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<ES> Drop for RunningProjection<ES> {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+fn worker_name(projection: &str, worker: usize) -> String {
+    format!("{}#worker-{}", projection, worker)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadModelError {
+    Storage(String),
+    Projection(String),
+}
+
+impl Error for ReadModelError {}
+
+impl fmt::Display for ReadModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadModelError::Storage(message) => write!(f, "ReadModelError: storage: {}", message),
+            ReadModelError::Projection(message) => {
+                write!(f, "ReadModelError: projection: {}", message)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectionError<ES> {
+    EventStore(ES),
+    ReadModel(ReadModelError),
+    /// The projection did not reach `min_position` in time.
+    Timeout {
+        position: Position,
+        min_position: Position,
+    },
+}
+
+impl<ES> From<ReadModelError> for ProjectionError<ES> {
+    fn from(err: ReadModelError) -> ProjectionError<ES> {
+        ProjectionError::ReadModel(err)
+    }
+}
+
+impl<ES> Error for ProjectionError<ES> where ES: fmt::Debug + fmt::Display {}
+
+impl<ES> fmt::Display for ProjectionError<ES>
+where
+    ES: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectionError::EventStore(err) => write!(f, "ProjectionError: {}", err),
+            ProjectionError::ReadModel(err) => write!(f, "ProjectionError: {}", err),
+            ProjectionError::Timeout {
+                position,
+                min_position,
+            } => write!(
+                f,
+                "ProjectionError: waited for position {} but reached only {}",
+                min_position, position
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eventstore::{EventStore, ExpectedVersion, InMemoryEventStore};
+    use crate::projection::*;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+
+    type CounterEventStore = Arc<InMemoryEventStore<CounterAggregate, CounterEvent>>;
+    type TotalsStore = InMemoryReadModelStore<HashMap<String, u64>>;
+
+    /// Sums the values added to every stream.
+    struct Totals {
+        subscription: Subscription,
+        fail_on: Option<u64>,
+    }
+
+    impl Projection<CounterEvent, TotalsStore> for Totals {
+        fn name(&self) -> &str {
+            "totals"
+        }
+
+        fn subscription(&self) -> Subscription {
+            self.subscription.clone()
+        }
+
+        fn handle(
+            &self,
+            totals: &mut HashMap<String, u64>,
+            envelope: &EventEnvelope<CounterEvent>,
+        ) -> Result<(), ReadModelError> {
+            let CounterEvent::Added(value) = envelope.event;
+            if Some(value) == self.fail_on {
+                return Err(ReadModelError::Projection("refused".to_owned()));
+            }
+            *totals.entry(envelope.stream_id.clone()).or_insert(0) += value;
+            Ok(())
+        }
+
+        fn reset(&self, totals: &mut HashMap<String, u64>) -> Result<(), ReadModelError> {
+            totals.clear();
+            Ok(())
+        }
+    }
+
+    fn build_runner(
+        events: &[(&str, u64)],
+        projection: Totals,
+    ) -> (
+        CounterEventStore,
+        Arc<TotalsStore>,
+        ProjectionRunner<CounterAggregate, CounterEvent, CounterEventStore, TotalsStore, Totals>,
+    ) {
+        let event_store = Arc::new(InMemoryEventStore::new());
+        for (id, value) in events {
+            event_store
+                .append_events(id, vec![CounterEvent::Added(*value)], ExpectedVersion::Any)
+                .unwrap();
+        }
+        let store = Arc::new(TotalsStore::new());
+        let runner = ProjectionRunner::new(event_store.clone(), store.clone(), projection)
+            .with_batch_size(2);
+
+        (event_store, store, runner)
+    }
+
+    fn totals() -> Totals {
+        Totals {
+            subscription: Subscription::All,
+            fail_on: None,
+        }
+    }
+
+    #[test]
+    fn runner_feeds_all_events_and_stores_checkpoint() {
+        // Arrange
+        let (_, store, runner) = build_runner(&[("a", 1), ("b", 2), ("a", 3)], totals());
+
+        // Act
+        let handled = runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(3, handled);
+        assert_eq!(Ok(3), runner.position());
+        assert_eq!(Some(4), store.read(|totals| totals.get("a").cloned()));
+        assert_eq!(Some(2), store.read(|totals| totals.get("b").cloned()));
+    }
+
+    #[test]
+    fn runner_continues_from_checkpoint() {
+        // Arrange
+        let (event_store, store, runner) = build_runner(&[("a", 1)], totals());
+        runner.run_once().unwrap();
+        event_store
+            .append_events("a", vec![CounterEvent::Added(5)], ExpectedVersion::Any)
+            .unwrap();
+
+        // Act
+        let handled = runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(1, handled);
+        assert_eq!(Some(6), store.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn failed_batch_keeps_neither_writes_nor_checkpoint() {
+        // Arrange
+        let projection = Totals {
+            subscription: Subscription::All,
+            fail_on: Some(3),
+        };
+        let (_, store, runner) = build_runner(&[("a", 1), ("a", 2), ("a", 3)], projection);
+
+        // Act
+        let result = runner.run_once();
+
+        // Assert
+        assert_eq!(
+            Err(ProjectionError::ReadModel(ReadModelError::Projection(
+                "refused".to_owned()
+            ))),
+            result
+        );
+        assert_eq!(Ok(2), runner.position());
+        assert_eq!(Some(3), store.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn paused_runner_handles_nothing_until_resumed() {
+        // Arrange
+        let (_, _, runner) = build_runner(&[("a", 1)], totals());
+        runner.pause();
+
+        // Act
+        let while_paused = runner.run_once().unwrap();
+        runner.resume();
+        let after_resume = runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(0, while_paused);
+        assert_eq!(1, after_resume);
+    }
+
+    #[test]
+    fn reset_rebuilds_read_model_from_first_event() {
+        // Arrange
+        let (_, store, runner) = build_runner(&[("a", 1), ("a", 2)], totals());
+        runner.run_once().unwrap();
+
+        // Act
+        runner.reset().unwrap();
+        let after_reset = store.read(|totals| totals.len());
+        let handled = runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(0, after_reset);
+        assert_eq!(2, handled);
+        assert_eq!(Some(3), store.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn category_subscription_skips_other_streams_but_moves_checkpoint() {
+        // Arrange
+        let projection = Totals {
+            subscription: Subscription::Category("Counter".to_owned()),
+            fail_on: None,
+        };
+        let (_, store, runner) = build_runner(
+            &[("Counter-1", 1), ("Other-1", 2), ("Counterfeit-1", 4)],
+            projection,
+        );
+
+        // Act
+        runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(Ok(3), runner.position());
+        assert_eq!(1, store.read(|totals| totals.len()));
+        assert_eq!(
+            Some(1),
+            store.read(|totals| totals.get("Counter-1").cloned())
+        );
+    }
+
+    #[test]
+    fn spawned_runner_catches_up_in_background() {
+        // Arrange
+        let (_, store, runner) = build_runner(&[("a", 1), ("a", 2)], totals());
+        let runner = Arc::new(runner);
+
+        // Act
+        let running = ProjectionRunner::spawn(runner.clone(), Duration::from_millis(1));
+        while runner.position() != Ok(2) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        running.stop();
+
+        // Assert
+        assert_eq!(Some(3), store.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn spawned_runner_reports_its_latest_error() {
+        // Arrange
+        let projection = Totals {
+            subscription: Subscription::All,
+            fail_on: Some(2),
+        };
+        let (_, _, runner) = build_runner(&[("a", 1), ("a", 2)], projection);
+
+        // Act
+        let running = ProjectionRunner::spawn(Arc::new(runner), Duration::from_millis(1));
+        while running.last_error().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Assert
+        assert_eq!(
+            Some(ProjectionError::ReadModel(ReadModelError::Projection(
+                "refused".to_owned()
+            ))),
+            running.last_error()
+        );
+        running.stop();
+    }
+
+    #[test]
+    #[should_panic(expected = "projection batch size must be positive")]
+    fn zero_batch_size_is_refused() {
+        build_runner(&[], totals()).2.with_batch_size(0);
+    }
+
+    #[test]
+    fn rebuild_switches_to_shadow_and_clears_old_read_model() {
+        // Arrange
+        let (_, old, runner) = build_runner(&[("a", 1), ("b", 2), ("a", 3)], totals());
+        runner.run_once().unwrap();
+        let shadow = Arc::new(TotalsStore::new());
+        let mut reports = Vec::new();
+
+        // Act
+        runner
+            .rebuild(shadow.clone(), |progress| reports.push(*progress))
+            .unwrap();
+
+        // Assert
+        assert!(Arc::ptr_eq(&shadow, &runner.read_model()));
+        assert_eq!(Some(4), shadow.read(|totals| totals.get("a").cloned()));
+        assert_eq!(Ok(3), runner.position());
+        assert_eq!(0, old.read(|totals| totals.len()));
+        assert_eq!(Ok(0), old.checkpoint("totals"));
+        let positions: Vec<_> = reports.iter().map(|p| p.position).collect();
+        assert_eq!(vec![2, 3, 3], positions);
+        assert_eq!(100.0, reports.last().unwrap().percent());
+    }
+
+    #[test]
+    fn rebuild_picks_up_events_appended_while_it_runs() {
+        // Arrange
+        let (event_store, _, runner) = build_runner(&[("a", 1), ("a", 2)], totals());
+        let shadow = Arc::new(TotalsStore::new());
+        let mut appended = false;
+
+        // Act
+        runner
+            .rebuild(shadow.clone(), |_| {
+                if !appended {
+                    appended = true;
+                    event_store
+                        .append_events("a", vec![CounterEvent::Added(10)], ExpectedVersion::Any)
+                        .unwrap();
+                }
+            })
+            .unwrap();
+        let after_switch = runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(0, after_switch);
+        assert_eq!(Some(13), shadow.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn progress_estimates_remaining_time_from_rate_so_far() {
+        // Arrange
+        let progress = RebuildProgress {
+            position: 250,
+            target: 1000,
+            handled: 250,
+            elapsed: Duration::from_secs(10),
+        };
+
+        // Act
+        let eta = progress.eta();
+
+        // Assert
+        assert_eq!(Some(Duration::from_secs(30)), eta);
+        assert_eq!("250/1000 (25.0%), eta 30s", progress.to_string());
+    }
+
+    #[test]
+    fn parallel_replay_matches_sequential_replay() {
+        // Arrange
+        let events: Vec<_> = (1..=30)
+            .map(|value| (["a", "b", "c", "d", "e"][value as usize % 5], value))
+            .collect();
+        let (_, sequential, runner) = build_runner(&events, totals());
+        runner.run_once().unwrap();
+        let (_, parallel, runner) = build_runner(&events, totals());
+        let runner = runner.with_workers(3);
+
+        // Act
+        runner.run_once().unwrap();
+
+        // Assert
+        assert_eq!(Ok(30), runner.position());
+        assert_eq!(
+            sequential.read(|totals| totals.clone()),
+            parallel.read(|totals| totals.clone())
+        );
+    }
+
+    #[test]
+    fn parallel_checkpoint_is_lowest_position_every_worker_finished() {
+        // Arrange
+        let event_store: CounterEventStore = Arc::new(InMemoryEventStore::new());
+        for (id, value) in &[("a", 1), ("b", 2), ("a", 3), ("b", 4)] {
+            event_store
+                .append_events(id, vec![CounterEvent::Added(*value)], ExpectedVersion::Any)
+                .unwrap();
+        }
+        let store = Arc::new(TotalsStore::new());
+        let failing = Totals {
+            subscription: Subscription::All,
+            fail_on: Some(3),
+        };
+        let runner = ProjectionRunner::new(event_store.clone(), store.clone(), failing)
+            .with_batch_size(2)
+            .with_workers(2);
+
+        // Act
+        let failed = runner.run_once();
+        let position_after_failure = runner.position();
+        let runner = ProjectionRunner::new(event_store, store.clone(), totals())
+            .with_batch_size(2)
+            .with_workers(2);
+        runner.run_once().unwrap();
+
+        // Assert
+        assert!(failed.is_err());
+        assert_eq!(Ok(2), position_after_failure);
+        assert_eq!(Some(4), store.read(|totals| totals.get("a").cloned()));
+        assert_eq!(Some(6), store.read(|totals| totals.get("b").cloned()));
+        assert_eq!(Ok(4), runner.position());
+    }
+
+    #[test]
+    fn wait_for_returns_once_projection_reached_position() {
+        // Arrange
+        let (event_store, store, runner) = build_runner(&[("a", 1)], totals());
+        let runner = Arc::new(runner);
+        let _running = ProjectionRunner::spawn(runner.clone(), Duration::from_millis(1));
+        let version = event_store
+            .append_events("a", vec![CounterEvent::Added(5)], ExpectedVersion::Any)
+            .unwrap();
+        let min_position = event_store.stream_position("a", version).unwrap().unwrap();
+
+        // Act
+        let result = runner.wait_for(min_position, Duration::from_secs(5));
+
+        // Assert
+        assert!(result.unwrap() >= min_position);
+        assert_eq!(Some(6), store.read(|totals| totals.get("a").cloned()));
+    }
+
+    #[test]
+    fn wait_for_times_out_when_projection_stays_behind() {
+        // Arrange
+        let (_, _, runner) = build_runner(&[("a", 1), ("a", 2)], totals());
+
+        // Act
+        let result = runner.wait_for(2, Duration::from_millis(10));
+
+        // Assert
+        assert_eq!(
+            Err(ProjectionError::Timeout {
+                position: 0,
+                min_position: 2,
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn lag_is_distance_to_head_of_all() {
+        // Arrange
+        let (event_store, _, runner) = build_runner(&[("a", 1), ("a", 2)], totals());
+        runner.run_once().unwrap();
+        event_store
+            .append_events("b", vec![CounterEvent::Added(1)], ExpectedVersion::Any)
+            .unwrap();
+
+        // Act
+        let lag = runner.lag().unwrap();
+
+        // Assert
+        assert_eq!(
+            ProjectionLag {
+                projection: "totals".to_owned(),
+                position: 2,
+                head: 3,
+                behind: 1,
+            },
+            lag
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::eventstore::{EventStore, ExpectedVersion};
+use crate::snapshot::{NoSnapshots, SnapshotStore};
+use crate::{Aggregate, AggregateEvent, CqrsError};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::{error::Error, fmt};
+
+pub trait Repository<A>
+where
+    A: Aggregate,
+{
+    type Event: AggregateEvent<A>;
+    type Error: CqrsError;
+
+    fn load(&self, id: &str) -> Result<A, Self::Error>;
+
+    /// Stores `events` recorded on `aggregate` and returns the aggregate with them applied.
+    fn save(&self, id: &str, aggregate: A, events: Vec<Self::Event>) -> Result<A, Self::Error>;
+}
+
+impl<A, R> Repository<A> for Arc<R>
+where
+    A: Aggregate,
+    R: Repository<A>,
+{
+    type Event = R::Event;
+    type Error = R::Error;
+
+    fn load(&self, id: &str) -> Result<A, Self::Error> {
+        (**self).load(id)
+    }
+
+    fn save(&self, id: &str, aggregate: A, events: Vec<Self::Event>) -> Result<A, Self::Error> {
+        (**self).save(id, aggregate, events)
+    }
+}
+
+pub struct EventSourcedRepository<A, E, ES, SS = NoSnapshots> {
+    event_store: ES,
+    snapshot_store: SS,
+    snapshot_every: u64,
+    _types: PhantomData<fn() -> (A, E)>,
+}
+
+impl<A, E, ES> EventSourcedRepository<A, E, ES, NoSnapshots> {
+    pub fn new(event_store: ES) -> EventSourcedRepository<A, E, ES, NoSnapshots> {
+        EventSourcedRepository {
+            event_store,
+            snapshot_store: NoSnapshots,
+            snapshot_every: 0,
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<A, E, ES, SS> EventSourcedRepository<A, E, ES, SS> {
+    /// Repository that stores a snapshot every `snapshot_every` events.
+    pub fn with_snapshots(
+        event_store: ES,
+        snapshot_store: SS,
+        snapshot_every: u64,
+    ) -> EventSourcedRepository<A, E, ES, SS> {
+        EventSourcedRepository {
+            event_store,
+            snapshot_store,
+            snapshot_every,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn event_store(&self) -> &ES {
+        &self.event_store
+    }
+
+    fn snapshot_due(&self, previous_generation: u64, generation: u64) -> bool {
+        self.snapshot_every > 0
+            && generation / self.snapshot_every > previous_generation / self.snapshot_every
+    }
+}
+
+impl<A, E, ES, SS> Repository<A> for EventSourcedRepository<A, E, ES, SS>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+    ES: EventStore<A, E>,
+    SS: SnapshotStore<A>,
+{
+    type Event = E;
+    type Error = RepositoryError<ES::Error, SS::Error, E::Error>;
+
+    fn load(&self, id: &str) -> Result<A, Self::Error> {
+        let mut aggregate = self
+            .snapshot_store
+            .load_snapshot(id)
+            .map_err(RepositoryError::SnapshotStore)?
+            .unwrap_or_default();
+
+        let events = self
+            .event_store
+            .read_events(id, aggregate.generation())
+            .map_err(RepositoryError::EventStore)?;
+
+        for versioned in events {
+            aggregate
+                .apply(versioned.event)
+                .map_err(RepositoryError::Apply)?;
+        }
+
+        Ok(aggregate)
+    }
+
+    fn save(&self, id: &str, mut aggregate: A, events: Vec<E>) -> Result<A, Self::Error> {
+        let previous_generation = aggregate.generation();
+
+        for event in events.iter().cloned() {
+            aggregate.apply(event).map_err(RepositoryError::Apply)?;
+        }
+
+        self.event_store
+            .append_events(
+                id,
+                events,
+                ExpectedVersion::from_generation(previous_generation),
+            )
+            .map_err(RepositoryError::EventStore)?;
+
+        if self.snapshot_due(previous_generation, aggregate.generation()) {
+            self.snapshot_store
+                .save_snapshot(id, &aggregate)
+                .map_err(RepositoryError::SnapshotStore)?;
+        }
+
+        Ok(aggregate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepositoryError<ES, SS, AE> {
+    EventStore(ES),
+    SnapshotStore(SS),
+    Apply(AE),
+}
+
+impl<ES, SS, AE> Error for RepositoryError<ES, SS, AE>
+where
+    ES: fmt::Debug + fmt::Display,
+    SS: fmt::Debug + fmt::Display,
+    AE: fmt::Debug + fmt::Display,
+{
+}
+
+impl<ES, SS, AE> fmt::Display for RepositoryError<ES, SS, AE>
+where
+    ES: fmt::Display,
+    SS: fmt::Display,
+    AE: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepositoryError::EventStore(err) => write!(f, "RepositoryError: {}", err),
+            RepositoryError::SnapshotStore(err) => write!(f, "RepositoryError: {}", err),
+            RepositoryError::Apply(err) => write!(f, "RepositoryError: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eventstore::{EventStore, EventStoreError, ExpectedVersion, InMemoryEventStore};
+    use crate::repository::*;
+    use crate::snapshot::{InMemorySnapshotStore, SnapshotStore};
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use std::sync::Arc;
+
+    #[test]
+    fn load_applies_stored_events() {
+        // Arrange
+        let event_store = Arc::new(InMemoryEventStore::new());
+        event_store
+            .append_events(
+                "a",
+                vec![CounterEvent::Added(2), CounterEvent::Added(3)],
+                ExpectedVersion::NoStream,
+            )
+            .unwrap();
+        let repository = EventSourcedRepository::new(event_store);
+        let expected = CounterAggregate {
+            value: 5,
+            generation: 2,
+        };
+
+        // Act
+        let result = repository.load("a");
+
+        // Assert
+        assert_eq!(Ok(expected), result);
+    }
+
+    #[test]
+    fn save_appends_events_and_returns_updated_aggregate() {
+        // Arrange
+        let event_store = Arc::new(InMemoryEventStore::new());
+        let repository = EventSourcedRepository::new(event_store.clone());
+        let aggregate = repository.load("a").unwrap();
+
+        // Act
+        let result = repository.save("a", aggregate, vec![CounterEvent::Added(4)]);
+
+        // Assert
+        assert_eq!(
+            Ok(CounterAggregate {
+                value: 4,
+                generation: 1,
+            }),
+            result
+        );
+        assert_eq!(1, event_store.read_events("a", 0).unwrap().len());
+    }
+
+    #[test]
+    fn save_rejects_stale_aggregate() {
+        // Arrange
+        let event_store = Arc::new(InMemoryEventStore::new());
+        let repository = EventSourcedRepository::new(event_store);
+        let stale: CounterAggregate = repository.load("a").unwrap();
+        repository
+            .save("a", stale.clone(), vec![CounterEvent::Added(1)])
+            .unwrap();
+
+        // Act
+        let result = repository.save("a", stale, vec![CounterEvent::Added(1)]);
+
+        // Assert
+        assert_eq!(
+            Err(RepositoryError::EventStore(
+                EventStoreError::WrongExpectedVersion {
+                    expected: ExpectedVersion::NoStream,
+                    actual: 1,
+                }
+            )),
+            result
+        );
+    }
+
+    #[test]
+    fn load_starts_from_latest_snapshot() {
+        // Arrange
+        let event_store = Arc::new(InMemoryEventStore::new());
+        let snapshot_store = Arc::new(InMemorySnapshotStore::new());
+        let repository =
+            EventSourcedRepository::with_snapshots(event_store.clone(), snapshot_store.clone(), 2);
+        let aggregate = repository.load("a").unwrap();
+        let aggregate = repository
+            .save(
+                "a",
+                aggregate,
+                vec![CounterEvent::Added(1), CounterEvent::Added(2)],
+            )
+            .unwrap();
+        repository
+            .save("a", aggregate, vec![CounterEvent::Added(3)])
+            .unwrap();
+
+        // Act
+        let snapshot = snapshot_store.load_snapshot("a").unwrap();
+        let result = repository.load("a");
+
+        // Assert
+        assert_eq!(
+            Some(CounterAggregate {
+                value: 3,
+                generation: 2,
+            }),
+            snapshot
+        );
+        assert_eq!(
+            Ok(CounterAggregate {
+                value: 6,
+                generation: 3,
+            }),
+            result
+        );
+    }
+}
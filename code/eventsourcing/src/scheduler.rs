@@ -0,0 +1,838 @@
+//! Commands deferred to a point in time, e.g. expiring holds or monthly fees.
+//!
+//! A `Scheduler` keeps the commands waiting to be dispatched as events of a single stream, so
+//! they survive restarts. `run_due` dispatches whatever is due by the scheduler's clock and is
+//! meant to be called periodically. A dispatch is recorded once the dispatcher accepted the
+//! command, so a crash in between sends the command again on the next run. A command that failed
+//! for a reason that may go away is tried again with a growing delay. With a snapshot store, the
+//! scheduler saves its schedule every so many events, so opening it does not replay the whole
+//! stream.
+
+use crate::clock::{self, Clock, SystemClock, Timestamp};
+use crate::eventstore::{EventStore, ExpectedVersion};
+use crate::handler::CommandHandler;
+use crate::snapshot::{NoSnapshots, SnapshotStore, SnapshotStoreError};
+use crate::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledCommand<C> {
+    pub key: String,
+    pub due: Timestamp,
+    pub command: C,
+}
+
+/// What is stored in the stream of a scheduler.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScheduleEvent<C> {
+    /// Replaces a pending command with the same key.
+    Scheduled {
+        key: String,
+        due: Timestamp,
+        command: C,
+    },
+    Rescheduled {
+        key: String,
+        due: Timestamp,
+    },
+    Cancelled(String),
+    Dispatched(String),
+    /// The dispatch failed for a reason that may go away and is tried again at `retry_at`.
+    Delayed {
+        key: String,
+        error: String,
+        retry_at: Timestamp,
+    },
+    /// The dispatcher refused the command, or it failed too often, and it is not tried again.
+    Failed {
+        key: String,
+        error: String,
+    },
+}
+
+impl<C> Event for ScheduleEvent<C> {
+    fn event_type(&self) -> &'static str {
+        match self {
+            ScheduleEvent::Scheduled { .. } => "scheduled",
+            ScheduleEvent::Rescheduled { .. } => "rescheduled",
+            ScheduleEvent::Cancelled(_) => "cancelled",
+            ScheduleEvent::Dispatched(_) => "dispatched",
+            ScheduleEvent::Delayed { .. } => "delayed",
+            ScheduleEvent::Failed { .. } => "failed",
+        }
+    }
+}
+
+impl<C> AggregateEvent<Schedule<C>> for ScheduleEvent<C> {
+    type Error = Infallible;
+
+    fn apply_to(self, schedule: &mut Schedule<C>) -> Result<(), Self::Error> {
+        let revision = schedule.generation;
+        match self {
+            ScheduleEvent::Scheduled { key, due, command } => {
+                let scheduled = ScheduledCommand {
+                    key: key.clone(),
+                    due,
+                    command,
+                };
+                schedule.pending.insert(
+                    key,
+                    PendingCommand {
+                        scheduled,
+                        attempts: 0,
+                        revision,
+                    },
+                );
+            }
+            ScheduleEvent::Rescheduled { key, due } => {
+                if let Some(pending) = schedule.pending.get_mut(&key) {
+                    pending.scheduled.due = due;
+                    pending.revision = revision;
+                }
+            }
+            ScheduleEvent::Delayed { key, retry_at, .. } => {
+                if let Some(pending) = schedule.pending.get_mut(&key) {
+                    pending.scheduled.due = retry_at;
+                    pending.attempts += 1;
+                }
+            }
+            ScheduleEvent::Cancelled(key)
+            | ScheduleEvent::Dispatched(key)
+            | ScheduleEvent::Failed { key, .. } => {
+                schedule.pending.remove(&key);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PendingCommand<C> {
+    scheduled: ScheduledCommand<C>,
+    /// Failed dispatches so far.
+    attempts: u32,
+    /// Generation of the schedule when the command was last scheduled or moved.
+    revision: u64,
+}
+
+/// Commands waiting to be dispatched, by key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule<C> {
+    pending: BTreeMap<String, PendingCommand<C>>,
+    generation: u64,
+}
+
+impl<C> Schedule<C>
+where
+    C: Clone,
+{
+    /// Commands whose due time passed at `now`, earliest first, with their revisions.
+    fn due(&self, now: Timestamp) -> Vec<(ScheduledCommand<C>, u64)> {
+        let mut due: Vec<_> = self
+            .pending
+            .values()
+            .filter(|pending| pending.scheduled.due <= now)
+            .map(|pending| (pending.scheduled.clone(), pending.revision))
+            .collect();
+        due.sort_by(|(a, _), (b, _)| (a.due, &a.key).cmp(&(b.due, &b.key)));
+
+        due
+    }
+}
+
+impl<C> Default for Schedule<C> {
+    fn default() -> Self {
+        Schedule {
+            pending: BTreeMap::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl<C> Aggregate for Schedule<C> {
+    fn aggregate_type() -> &'static str {
+        "Schedule"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+/// Dispatches commands once they are due, keeping the ones still waiting in an event store.
+pub struct Scheduler<ES, C, D, SS = NoSnapshots> {
+    event_store: ES,
+    snapshot_store: SS,
+    snapshot_every: u64,
+    stream_id: String,
+    dispatcher: D,
+    clock: Box<dyn Clock + Send + Sync>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    /// Held while the schedule is changed, so its stream has a single writer.
+    schedule: Mutex<Schedule<C>>,
+    /// Held by `run_due`, so a due command is handed to the dispatcher by one caller at a time.
+    running: Mutex<()>,
+}
+
+impl<ES, C, D> Scheduler<ES, C, D, NoSnapshots>
+where
+    ES: EventStore<Schedule<C>, ScheduleEvent<C>>,
+    C: Clone,
+    D: CommandHandler<C>,
+{
+    /// Scheduler keeping its commands in stream `stream_id`, starting with those already there.
+    pub fn open(
+        event_store: ES,
+        stream_id: &str,
+        dispatcher: D,
+    ) -> Result<Self, SchedulerError<ES::Error, SnapshotStoreError>> {
+        Scheduler::open_with_snapshots(event_store, NoSnapshots, 0, stream_id, dispatcher)
+    }
+}
+
+impl<ES, C, D, SS> Scheduler<ES, C, D, SS>
+where
+    ES: EventStore<Schedule<C>, ScheduleEvent<C>>,
+    C: Clone,
+    D: CommandHandler<C>,
+    SS: SnapshotStore<Schedule<C>>,
+{
+    /// Scheduler that starts from its latest snapshot and saves one every `snapshot_every`
+    /// events.
+    pub fn open_with_snapshots(
+        event_store: ES,
+        snapshot_store: SS,
+        snapshot_every: u64,
+        stream_id: &str,
+        dispatcher: D,
+    ) -> Result<Self, SchedulerError<ES::Error, SS::Error>> {
+        let mut schedule = snapshot_store
+            .load_snapshot(stream_id)
+            .map_err(SchedulerError::SnapshotStore)?
+            .unwrap_or_default();
+        let events = event_store
+            .read_events(stream_id, schedule.generation())
+            .map_err(SchedulerError::EventStore)?;
+        for versioned in events {
+            apply(&mut schedule, versioned.event);
+        }
+
+        Ok(Scheduler {
+            event_store,
+            snapshot_store,
+            snapshot_every,
+            stream_id: stream_id.to_owned(),
+            dispatcher,
+            clock: Box::new(SystemClock),
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(1),
+            schedule: Mutex::new(schedule),
+            running: Mutex::new(()),
+        })
+    }
+
+    /// Decides when commands are due, the system time by default.
+    pub fn with_clock<K>(mut self, clock: K) -> Scheduler<ES, C, D, SS>
+    where
+        K: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Dispatches after which a command that keeps failing is given up on.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Scheduler<ES, C, D, SS> {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before the first retry.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Scheduler<ES, C, D, SS> {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Dispatches `command` at `due`, replacing a pending command with the same key.
+    pub fn schedule(
+        &self,
+        key: &str,
+        due: Timestamp,
+        command: C,
+    ) -> Result<(), SchedulerError<ES::Error, SS::Error>> {
+        let mut schedule = self.schedule.lock().unwrap();
+
+        self.record(
+            &mut schedule,
+            ScheduleEvent::Scheduled {
+                key: key.to_owned(),
+                due,
+                command,
+            },
+        )
+    }
+
+    /// Dispatches `command` once `after` passed from now.
+    pub fn schedule_in(
+        &self,
+        key: &str,
+        after: Duration,
+        command: C,
+    ) -> Result<(), SchedulerError<ES::Error, SS::Error>> {
+        self.schedule(key, clock::deadline(self.clock.now(), after), command)
+    }
+
+    /// Moves the pending command with `key` to `due`. Returns whether there was one.
+    pub fn reschedule(
+        &self,
+        key: &str,
+        due: Timestamp,
+    ) -> Result<bool, SchedulerError<ES::Error, SS::Error>> {
+        let mut schedule = self.schedule.lock().unwrap();
+        if !schedule.pending.contains_key(key) {
+            return Ok(false);
+        }
+
+        self.record(
+            &mut schedule,
+            ScheduleEvent::Rescheduled {
+                key: key.to_owned(),
+                due,
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// Drops the pending command with `key`. Returns whether there was one.
+    pub fn cancel(&self, key: &str) -> Result<bool, SchedulerError<ES::Error, SS::Error>> {
+        let mut schedule = self.schedule.lock().unwrap();
+        if !schedule.pending.contains_key(key) {
+            return Ok(false);
+        }
+
+        self.record(&mut schedule, ScheduleEvent::Cancelled(key.to_owned()))?;
+        Ok(true)
+    }
+
+    /// Commands waiting to be dispatched, earliest first.
+    pub fn pending(&self) -> Vec<ScheduledCommand<C>> {
+        let schedule = self.schedule.lock().unwrap();
+        let mut pending: Vec<_> = schedule
+            .pending
+            .values()
+            .map(|pending| pending.scheduled.clone())
+            .collect();
+        pending.sort_by(|a, b| (a.due, &a.key).cmp(&(b.due, &b.key)));
+
+        pending
+    }
+
+    /// Dispatches every command that is due, earliest first, and returns how many it handed to
+    /// the dispatcher. The dispatcher may change the schedule itself; a command it moved,
+    /// replaced or cancelled meanwhile keeps that change instead of its outcome.
+    pub fn run_due(&self) -> Result<usize, SchedulerError<ES::Error, SS::Error>> {
+        let _running = self.running.lock().unwrap();
+        let now = self.clock.now();
+        let due = self.schedule.lock().unwrap().due(now);
+        let mut dispatched = 0;
+
+        for (scheduled, revision) in due {
+            let result = self.dispatcher.handle(scheduled.command);
+            dispatched += 1;
+
+            let mut schedule = self.schedule.lock().unwrap();
+            let attempts = match schedule.pending.get(&scheduled.key) {
+                Some(pending) if pending.revision == revision => pending.attempts + 1,
+                _ => continue,
+            };
+            let key = scheduled.key;
+            let event = match result {
+                Ok(()) => ScheduleEvent::Dispatched(key),
+                Err(err) => match self.retry_at(now, attempts, &err) {
+                    Some(retry_at) => ScheduleEvent::Delayed {
+                        key,
+                        error: err.to_string(),
+                        retry_at,
+                    },
+                    None => ScheduleEvent::Failed {
+                        key,
+                        error: err.to_string(),
+                    },
+                },
+            };
+            self.record(&mut schedule, event)?;
+        }
+
+        Ok(dispatched)
+    }
+
+    /// When a command that failed its `attempts`th dispatch with `error` is tried again, if at
+    /// all.
+    fn retry_at(&self, now: Timestamp, attempts: u32, error: &D::Error) -> Option<Timestamp> {
+        if !D::is_transient(error) || attempts >= self.max_attempts {
+            return None;
+        }
+
+        Some(clock::backoff(now, self.retry_delay, attempts))
+    }
+
+    fn record(
+        &self,
+        schedule: &mut Schedule<C>,
+        event: ScheduleEvent<C>,
+    ) -> Result<(), SchedulerError<ES::Error, SS::Error>> {
+        let previous_generation = schedule.generation();
+        self.event_store
+            .append_events(
+                &self.stream_id,
+                vec![event.clone()],
+                ExpectedVersion::from_generation(previous_generation),
+            )
+            .map_err(SchedulerError::EventStore)?;
+        apply(schedule, event);
+
+        if self.snapshot_every > 0 && schedule.generation().is_multiple_of(self.snapshot_every) {
+            self.snapshot_store
+                .save_snapshot(&self.stream_id, schedule)
+                .map_err(SchedulerError::SnapshotStore)?;
+        }
+        Ok(())
+    }
+}
+
+fn apply<C>(schedule: &mut Schedule<C>, event: ScheduleEvent<C>) {
+    schedule.apply(event).unwrap_or_else(|never| match never {})
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchedulerError<ES, SS> {
+    EventStore(ES),
+    SnapshotStore(SS),
+}
+
+impl<ES, SS> Error for SchedulerError<ES, SS>
+where
+    ES: fmt::Debug + fmt::Display,
+    SS: fmt::Debug + fmt::Display,
+{
+}
+
+impl<ES, SS> fmt::Display for SchedulerError<ES, SS>
+where
+    ES: fmt::Display,
+    SS: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchedulerError::EventStore(err) => write!(f, "SchedulerError: {}", err),
+            SchedulerError::SnapshotStore(err) => write!(f, "SchedulerError: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::eventstore::{EventStoreError, InMemoryEventStore};
+    use crate::scheduler::*;
+    use crate::snapshot::InMemorySnapshotStore;
+    use std::sync::{Arc, Weak};
+
+    type ScheduleStore = Arc<InMemoryEventStore<Schedule<String>, ScheduleEvent<String>>>;
+
+    const START: Timestamp = 10_000;
+
+    /// Takes every command except "refused", once it is no longer unavailable.
+    #[derive(Default)]
+    struct Mailbox {
+        received: Mutex<Vec<String>>,
+        /// Dispatches still to fail with "unavailable".
+        unavailable: Mutex<u32>,
+    }
+
+    impl Mailbox {
+        fn received(&self) -> Vec<String> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    impl CommandHandler<String> for Mailbox {
+        type Error = String;
+
+        fn handle(&self, command: String) -> Result<(), String> {
+            let mut unavailable = self.unavailable.lock().unwrap();
+            if *unavailable > 0 {
+                *unavailable -= 1;
+                return Err("unavailable".to_owned());
+            }
+            if command == "refused" {
+                return Err("no thanks".to_owned());
+            }
+            self.received.lock().unwrap().push(command);
+            Ok(())
+        }
+
+        fn is_transient(error: &String) -> bool {
+            error == "unavailable"
+        }
+    }
+
+    /// Schedules every command it takes again one second later.
+    #[derive(Default)]
+    struct Recurring {
+        scheduler: Mutex<Weak<Scheduler<ScheduleStore, String, Arc<Recurring>>>>,
+    }
+
+    impl CommandHandler<String> for Recurring {
+        type Error = SchedulerError<EventStoreError, SnapshotStoreError>;
+
+        fn handle(&self, command: String) -> Result<(), Self::Error> {
+            let scheduler = self.scheduler.lock().unwrap().upgrade().unwrap();
+            scheduler.schedule_in("tick", Duration::from_secs(1), command)
+        }
+    }
+
+    struct Fixture {
+        event_store: ScheduleStore,
+        mailbox: Arc<Mailbox>,
+        clock: Arc<ManualClock>,
+    }
+
+    impl Fixture {
+        fn new() -> Fixture {
+            Fixture {
+                event_store: Arc::new(InMemoryEventStore::new()),
+                mailbox: Arc::new(Mailbox::default()),
+                clock: Arc::new(ManualClock::new(START)),
+            }
+        }
+
+        fn scheduler(&self) -> Scheduler<ScheduleStore, String, Arc<Mailbox>> {
+            Scheduler::open(
+                self.event_store.clone(),
+                "Scheduler-fees",
+                self.mailbox.clone(),
+            )
+            .unwrap()
+            .with_clock(self.clock.clone())
+        }
+
+        fn events(&self) -> Vec<ScheduleEvent<String>> {
+            self.event_store
+                .read_events("Scheduler-fees", 0)
+                .unwrap()
+                .into_iter()
+                .map(|versioned| versioned.event)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn command_is_dispatched_once_due() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule_in("fee-1", Duration::from_secs(1), "charge fee".to_owned())
+            .unwrap();
+
+        // Act
+        fixture.clock.set(START + 999);
+        let early = scheduler.run_due();
+        fixture.clock.set(START + 1000);
+        let due = scheduler.run_due();
+        let again = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(0), early);
+        assert_eq!(Ok(1), due);
+        assert_eq!(Ok(0), again);
+        assert_eq!(vec!["charge fee".to_owned()], fixture.mailbox.received());
+    }
+
+    #[test]
+    fn due_commands_are_dispatched_earliest_first() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("b", START + 20, "second".to_owned())
+            .unwrap();
+        scheduler
+            .schedule("a", START + 30, "third".to_owned())
+            .unwrap();
+        scheduler
+            .schedule("c", START + 10, "first".to_owned())
+            .unwrap();
+        fixture.clock.advance(Duration::from_secs(1));
+
+        // Act
+        let result = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(3), result);
+        assert_eq!(
+            vec!["first".to_owned(), "second".to_owned(), "third".to_owned()],
+            fixture.mailbox.received()
+        );
+    }
+
+    #[test]
+    fn rescheduled_command_waits_for_new_due_time() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("hold-1", START + 1000, "release hold".to_owned())
+            .unwrap();
+
+        // Act
+        let moved = scheduler.reschedule("hold-1", START + 2000);
+        let unknown = scheduler.reschedule("hold-2", START + 2000);
+        fixture.clock.set(START + 1500);
+        let early = scheduler.run_due();
+        fixture.clock.set(START + 2000);
+        let due = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(true), moved);
+        assert_eq!(Ok(false), unknown);
+        assert_eq!(Ok(0), early);
+        assert_eq!(Ok(1), due);
+    }
+
+    #[test]
+    fn cancelled_command_is_not_dispatched() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("hold-1", START + 1000, "release hold".to_owned())
+            .unwrap();
+
+        // Act
+        let cancelled = scheduler.cancel("hold-1");
+        let again = scheduler.cancel("hold-1");
+        fixture.clock.set(START + 1000);
+        let dispatched = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(true), cancelled);
+        assert_eq!(Ok(false), again);
+        assert_eq!(Ok(0), dispatched);
+        assert!(scheduler.pending().is_empty());
+    }
+
+    #[test]
+    fn scheduling_same_key_replaces_pending_command() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("fee-1", START + 1000, "charge 5".to_owned())
+            .unwrap();
+
+        // Act
+        scheduler
+            .schedule("fee-1", START + 500, "charge 7".to_owned())
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![ScheduledCommand {
+                key: "fee-1".to_owned(),
+                due: START + 500,
+                command: "charge 7".to_owned(),
+            }],
+            scheduler.pending()
+        );
+    }
+
+    #[test]
+    fn pending_commands_survive_restart() {
+        // Arrange
+        let fixture = Fixture::new();
+        fixture
+            .scheduler()
+            .schedule("fee-1", START + 1000, "charge fee".to_owned())
+            .unwrap();
+        fixture.clock.set(START + 1000);
+
+        // Act
+        let result = fixture.scheduler().run_due();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        assert_eq!(vec!["charge fee".to_owned()], fixture.mailbox.received());
+        assert!(fixture.scheduler().pending().is_empty());
+    }
+
+    #[test]
+    fn refused_command_is_not_tried_again() {
+        // Arrange
+        let fixture = Fixture::new();
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("fee-1", START, "refused".to_owned())
+            .unwrap();
+
+        // Act
+        let first = scheduler.run_due();
+        let second = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(1), first);
+        assert_eq!(Ok(0), second);
+        let events: Vec<_> = fixture
+            .event_store
+            .read_events("Scheduler-fees", 1)
+            .unwrap()
+            .into_iter()
+            .map(|versioned| versioned.event)
+            .collect();
+        assert_eq!(
+            vec![ScheduleEvent::Failed {
+                key: "fee-1".to_owned(),
+                error: "no thanks".to_owned(),
+            }],
+            events
+        );
+    }
+
+    #[test]
+    fn transient_failure_is_retried_with_growing_delay() {
+        // Arrange
+        let fixture = Fixture::new();
+        *fixture.mailbox.unavailable.lock().unwrap() = 2;
+        let scheduler = fixture.scheduler();
+        scheduler
+            .schedule("fee-1", START, "charge fee".to_owned())
+            .unwrap();
+
+        // Act
+        let first = scheduler.run_due();
+        fixture.clock.set(START + 1000);
+        let second = scheduler.run_due();
+        fixture.clock.set(START + 2999);
+        let early = scheduler.run_due();
+        fixture.clock.set(START + 3000);
+        let third = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(1), first);
+        assert_eq!(Ok(1), second);
+        assert_eq!(Ok(0), early);
+        assert_eq!(Ok(1), third);
+        assert_eq!(vec!["charge fee".to_owned()], fixture.mailbox.received());
+        assert_eq!(
+            Some(&ScheduleEvent::Delayed {
+                key: "fee-1".to_owned(),
+                error: "unavailable".to_owned(),
+                retry_at: START + 3000,
+            }),
+            fixture.events().get(2)
+        );
+    }
+
+    #[test]
+    fn command_out_of_attempts_fails() {
+        // Arrange
+        let fixture = Fixture::new();
+        *fixture.mailbox.unavailable.lock().unwrap() = 5;
+        let scheduler = fixture.scheduler().with_max_attempts(2);
+        scheduler
+            .schedule("fee-1", START, "charge fee".to_owned())
+            .unwrap();
+        scheduler.run_due().unwrap();
+        fixture.clock.set(START + 1000);
+
+        // Act
+        let result = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        assert!(scheduler.pending().is_empty());
+        assert_eq!(
+            Some(&ScheduleEvent::Failed {
+                key: "fee-1".to_owned(),
+                error: "unavailable".to_owned(),
+            }),
+            fixture.events().last()
+        );
+    }
+
+    #[test]
+    fn dispatcher_can_schedule_while_dispatching() {
+        // Arrange
+        let fixture = Fixture::new();
+        let recurring = Arc::new(Recurring::default());
+        let scheduler = Arc::new(
+            Scheduler::open(
+                fixture.event_store.clone(),
+                "Scheduler-ticks",
+                recurring.clone(),
+            )
+            .unwrap()
+            .with_clock(fixture.clock.clone()),
+        );
+        *recurring.scheduler.lock().unwrap() = Arc::downgrade(&scheduler);
+        scheduler
+            .schedule("tick", START, "tick".to_owned())
+            .unwrap();
+
+        // Act
+        let result = scheduler.run_due();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        assert_eq!(
+            vec![ScheduledCommand {
+                key: "tick".to_owned(),
+                due: START + 1000,
+                command: "tick".to_owned(),
+            }],
+            scheduler.pending()
+        );
+    }
+
+    #[test]
+    fn scheduler_starts_from_latest_snapshot() {
+        // Arrange
+        let fixture = Fixture::new();
+        let snapshot_store = Arc::new(InMemorySnapshotStore::new());
+        let open = || {
+            Scheduler::open_with_snapshots(
+                fixture.event_store.clone(),
+                snapshot_store.clone(),
+                2,
+                "Scheduler-fees",
+                fixture.mailbox.clone(),
+            )
+            .unwrap()
+        };
+        let scheduler = open();
+        for key in &["fee-1", "fee-2", "fee-3"] {
+            scheduler
+                .schedule(key, START, "charge fee".to_owned())
+                .unwrap();
+        }
+
+        // Act
+        let reopened = open();
+
+        // Assert
+        let snapshot = snapshot_store.load_snapshot("Scheduler-fees").unwrap();
+        assert_eq!(Some(2), snapshot.map(|schedule| schedule.generation()));
+        assert_eq!(3, reopened.pending().len());
+    }
+}
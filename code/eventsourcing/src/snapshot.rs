@@ -0,0 +1,139 @@
+use crate::{Aggregate, CqrsError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::{error::Error, fmt};
+
+pub trait SnapshotStore<A>
+where
+    A: Aggregate,
+{
+    type Error: CqrsError;
+
+    fn load_snapshot(&self, id: &str) -> Result<Option<A>, Self::Error>;
+    fn save_snapshot(&self, id: &str, aggregate: &A) -> Result<(), Self::Error>;
+}
+
+impl<A, S> SnapshotStore<A> for Arc<S>
+where
+    A: Aggregate,
+    S: SnapshotStore<A>,
+{
+    type Error = S::Error;
+
+    fn load_snapshot(&self, id: &str) -> Result<Option<A>, Self::Error> {
+        (**self).load_snapshot(id)
+    }
+
+    fn save_snapshot(&self, id: &str, aggregate: &A) -> Result<(), Self::Error> {
+        (**self).save_snapshot(id, aggregate)
+    }
+}
+
+/// Snapshot store for aggregates that are always rebuilt from their events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoSnapshots;
+
+impl<A> SnapshotStore<A> for NoSnapshots
+where
+    A: Aggregate,
+{
+    type Error = SnapshotStoreError;
+
+    fn load_snapshot(&self, _id: &str) -> Result<Option<A>, Self::Error> {
+        Ok(None)
+    }
+
+    fn save_snapshot(&self, _id: &str, _aggregate: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub struct InMemorySnapshotStore<A> {
+    snapshots: Mutex<HashMap<String, A>>,
+}
+
+impl<A> InMemorySnapshotStore<A> {
+    pub fn new() -> InMemorySnapshotStore<A> {
+        InMemorySnapshotStore {
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<A> Default for InMemorySnapshotStore<A> {
+    fn default() -> Self {
+        InMemorySnapshotStore::new()
+    }
+}
+
+impl<A> SnapshotStore<A> for InMemorySnapshotStore<A>
+where
+    A: Aggregate + Clone,
+{
+    type Error = SnapshotStoreError;
+
+    fn load_snapshot(&self, id: &str) -> Result<Option<A>, Self::Error> {
+        let snapshots = self.snapshots.lock().unwrap();
+
+        Ok(snapshots.get(id).cloned())
+    }
+
+    fn save_snapshot(&self, id: &str, aggregate: &A) -> Result<(), Self::Error> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+
+        snapshots.insert(id.to_owned(), aggregate.clone());
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotStoreError {}
+
+impl Error for SnapshotStoreError {}
+
+impl fmt::Display for SnapshotStoreError {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snapshot::*;
+    use crate::test_helpers::CounterAggregate;
+
+    #[test]
+    fn saved_snapshot_can_be_loaded() {
+        // Arrange
+        let snapshot_store = InMemorySnapshotStore::new();
+        let aggregate = CounterAggregate {
+            value: 7,
+            generation: 3,
+        };
+        snapshot_store.save_snapshot("a", &aggregate).unwrap();
+
+        // Act
+        let result = snapshot_store.load_snapshot("a");
+
+        // Assert
+        assert_eq!(Ok(Some(aggregate)), result);
+    }
+
+    #[test]
+    fn no_snapshots_never_returns_a_snapshot() {
+        // Arrange
+        let snapshot_store = NoSnapshots;
+        let aggregate = CounterAggregate {
+            value: 7,
+            generation: 3,
+        };
+        snapshot_store.save_snapshot("a", &aggregate).unwrap();
+
+        // Act
+        let result: Result<Option<CounterAggregate>, _> = snapshot_store.load_snapshot("a");
+
+        // Assert
+        assert_eq!(Ok(None), result);
+    }
+}
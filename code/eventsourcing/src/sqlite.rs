@@ -0,0 +1,224 @@
+//! Read models stored in SQLite.
+
+use crate::eventstore::Position;
+use crate::projection::{ReadModelError, ReadModelStore};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Read model tables live next to a `projection_checkpoints` table in the same database, so a
+/// projection's writes and its checkpoint commit in one SQLite transaction.
+pub struct SqliteReadModelStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteReadModelStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteReadModelStore, ReadModelError> {
+        SqliteReadModelStore::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteReadModelStore, ReadModelError> {
+        SqliteReadModelStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<SqliteReadModelStore, ReadModelError> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS projection_checkpoints (
+                projection TEXT PRIMARY KEY,
+                position INTEGER NOT NULL
+            )",
+        )?;
+
+        Ok(SqliteReadModelStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Runs a query against the read models.
+    pub fn read<T, F>(&self, query: F) -> Result<T, ReadModelError>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>,
+    {
+        let connection = self.connection.lock().unwrap();
+
+        Ok(query(&connection)?)
+    }
+}
+
+impl ReadModelStore for SqliteReadModelStore {
+    type Tx = Connection;
+
+    fn checkpoint(&self, projection: &str) -> Result<Position, ReadModelError> {
+        let connection = self.connection.lock().unwrap();
+        let position: Option<i64> = connection
+            .query_row(
+                "SELECT position FROM projection_checkpoints WHERE projection = ?1",
+                params![projection],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(position.unwrap_or(0) as Position)
+    }
+
+    fn commit<F>(
+        &self,
+        projection: &str,
+        position: Position,
+        write: F,
+    ) -> Result<(), ReadModelError>
+    where
+        F: FnOnce(&mut Connection) -> Result<(), ReadModelError>,
+    {
+        let mut connection = self.connection.lock().unwrap();
+
+        connection.execute_batch("BEGIN IMMEDIATE")?;
+
+        let result = write(&mut connection).and_then(|_| {
+            connection.execute(
+                "INSERT INTO projection_checkpoints (projection, position) VALUES (?1, ?2)
+                 ON CONFLICT (projection) DO UPDATE SET position = excluded.position",
+                params![projection, position as i64],
+            )?;
+            Ok(connection.execute_batch("COMMIT")?)
+        });
+
+        if result.is_err() && !connection.is_autocommit() {
+            connection.execute_batch("ROLLBACK")?;
+        }
+
+        result
+    }
+}
+
+impl From<rusqlite::Error> for ReadModelError {
+    fn from(err: rusqlite::Error) -> ReadModelError {
+        ReadModelError::Storage(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eventstore::{EventEnvelope, EventStore, ExpectedVersion, InMemoryEventStore};
+    use crate::projection::*;
+    use crate::sqlite::SqliteReadModelStore;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// Keeps the sum of every stream in a `totals` table.
+    struct Totals {
+        fail_on: Option<u64>,
+    }
+
+    impl Projection<CounterEvent, SqliteReadModelStore> for Totals {
+        fn name(&self) -> &str {
+            "totals"
+        }
+
+        fn handle(
+            &self,
+            tx: &mut Connection,
+            envelope: &EventEnvelope<CounterEvent>,
+        ) -> Result<(), ReadModelError> {
+            let CounterEvent::Added(value) = envelope.event;
+            if Some(value) == self.fail_on {
+                return Err(ReadModelError::Projection("refused".to_owned()));
+            }
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS totals (stream TEXT PRIMARY KEY, total INTEGER)",
+                params![],
+            )?;
+            tx.execute(
+                "INSERT INTO totals (stream, total) VALUES (?1, ?2)
+                 ON CONFLICT (stream) DO UPDATE SET total = total + excluded.total",
+                params![envelope.stream_id, value as i64],
+            )?;
+            Ok(())
+        }
+
+        fn reset(&self, tx: &mut Connection) -> Result<(), ReadModelError> {
+            tx.execute("DROP TABLE IF EXISTS totals", params![])?;
+            Ok(())
+        }
+    }
+
+    fn total(store: &SqliteReadModelStore, stream: &str) -> Option<i64> {
+        store
+            .read(|connection| {
+                connection
+                    .query_row(
+                        "SELECT total FROM totals WHERE stream = ?1",
+                        params![stream],
+                        |row| row.get(0),
+                    )
+                    .optional()
+            })
+            .unwrap_or(None)
+    }
+
+    fn event_store(values: &[u64]) -> Arc<InMemoryEventStore<CounterAggregate, CounterEvent>> {
+        let event_store = Arc::new(InMemoryEventStore::new());
+        for value in values {
+            event_store
+                .append_events("a", vec![CounterEvent::Added(*value)], ExpectedVersion::Any)
+                .unwrap();
+        }
+        event_store
+    }
+
+    #[test]
+    fn checkpoint_and_read_model_survive_reopening() {
+        // Arrange
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("read_models.db");
+        let store = Arc::new(SqliteReadModelStore::open(&path).unwrap());
+        let runner = ProjectionRunner::new(event_store(&[1, 2]), store, Totals { fail_on: None });
+        runner.run_once().unwrap();
+        drop(runner);
+
+        // Act
+        let reopened = SqliteReadModelStore::open(&path).unwrap();
+
+        // Assert
+        assert_eq!(Ok(2), reopened.checkpoint("totals"));
+        assert_eq!(Some(3), total(&reopened, "a"));
+    }
+
+    #[test]
+    fn failed_batch_is_rolled_back_with_its_checkpoint() {
+        // Arrange
+        let store = Arc::new(SqliteReadModelStore::open_in_memory().unwrap());
+        let runner = ProjectionRunner::new(
+            event_store(&[1, 2, 3]),
+            store.clone(),
+            Totals { fail_on: Some(3) },
+        )
+        .with_batch_size(2);
+
+        // Act
+        let result = runner.run_once();
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(Ok(2), store.checkpoint("totals"));
+        assert_eq!(Some(3), total(&store, "a"));
+    }
+
+    #[test]
+    fn reset_drops_read_model_and_checkpoint() {
+        // Arrange
+        let store = Arc::new(SqliteReadModelStore::open_in_memory().unwrap());
+        let runner =
+            ProjectionRunner::new(event_store(&[1]), store.clone(), Totals { fail_on: None });
+        runner.run_once().unwrap();
+
+        // Act
+        runner.reset().unwrap();
+
+        // Assert
+        assert_eq!(Ok(0), store.checkpoint("totals"));
+        assert_eq!(None, total(&store, "a"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Aggregates that are enums of states, such as `Uninitialized`, `Opened` and `Closed`, with
+//! the events each state accepts checked at compile time. `#[derive(Aggregate)]` implements
+//! these traits.
+
+use crate::{Aggregate, AggregateEvent};
+use std::marker::PhantomData;
+
+/// Error applying an event of the aggregate `A`.
+pub type EventErrorOf<A> = <<A as RecordingAggregate>::Event as AggregateEvent<A>>::Error;
+
+/// An aggregate that keeps the events recorded on it until they are stored.
+pub trait RecordingAggregate: Aggregate {
+    type Event: AggregateEvent<Self>;
+
+    /// Applies `event` and keeps it among the new events.
+    fn record(&mut self, event: Self::Event) -> Result<(), EventErrorOf<Self>>;
+
+    fn new_events(&self) -> &[Self::Event];
+
+    /// The aggregate as being in state `S`, if it is.
+    fn in_state<S>(&mut self) -> Option<InState<'_, Self, S>>
+    where
+        S: AggregateState<Self>,
+    {
+        InState::new(self)
+    }
+}
+
+/// One of the states of the aggregate `A`.
+pub trait AggregateState<A> {
+    fn is_current(aggregate: &A) -> bool;
+}
+
+/// Marks the events a state accepts.
+pub trait Accepts<E> {}
+
+/// An aggregate known to be in state `S`, which only records events `S` accepts.
+pub struct InState<'a, A, S> {
+    aggregate: &'a mut A,
+    state: PhantomData<S>,
+}
+
+impl<'a, A, S> InState<'a, A, S>
+where
+    A: RecordingAggregate,
+    S: AggregateState<A>,
+{
+    pub fn new(aggregate: &'a mut A) -> Option<InState<'a, A, S>> {
+        if !S::is_current(aggregate) {
+            return None;
+        }
+        Some(InState {
+            aggregate,
+            state: PhantomData,
+        })
+    }
+
+    pub fn aggregate(&self) -> &A {
+        self.aggregate
+    }
+
+    /// Records `event`. The aggregate may be in another state afterwards, so this gives up
+    /// the handle.
+    pub fn record<E>(self, event: E) -> Result<&'a mut A, EventErrorOf<A>>
+    where
+        S: Accepts<E>,
+        A::Event: From<E>,
+    {
+        self.aggregate.record(A::Event::from(event))?;
+        Ok(self.aggregate)
+    }
+}
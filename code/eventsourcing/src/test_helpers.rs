@@ -0,0 +1,56 @@
+use crate::{Aggregate, AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CounterAggregate {
+    pub value: u64,
+    pub generation: u64,
+}
+
+impl Aggregate for CounterAggregate {
+    fn aggregate_type() -> &'static str {
+        "Counter"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CounterEvent {
+    Added(u64),
+}
+
+impl Event for CounterEvent {
+    fn event_type(&self) -> &'static str {
+        "added"
+    }
+}
+
+impl AggregateEvent<CounterAggregate> for CounterEvent {
+    type Error = CounterError;
+
+    fn apply_to(self, aggregate: &mut CounterAggregate) -> Result<(), Self::Error> {
+        match self {
+            CounterEvent::Added(value) => aggregate.value += value,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CounterError {}
+
+impl Error for CounterError {}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
@@ -0,0 +1,437 @@
+//! Given/when/then fixtures for aggregate and command handler tests, and invariants of
+//! aggregates checked against generated command sequences.
+//!
+//! ```ignore
+//! given(vec![BankAccountEvent::opened(123, 5000)])
+//!     .when(DepositMoney::new(123, 49))
+//!     .then_expect(vec![BankAccountEvent::credited(123, 49)]);
+//! ```
+
+use crate::eventstore::{EventStore, ExpectedVersion, GlobalEventStore, InMemoryEventStore};
+use crate::handler::CommandHandler;
+use crate::{Aggregate, AggregateCommand, AggregateEvent, ProducedEvent};
+use proptest::strategy::Strategy;
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+use std::fmt::{Debug, Write};
+use std::sync::Arc;
+
+/// The aggregate rebuilt from `events`, ready for a command. Panics if an event doesn't apply.
+pub fn given<A, E>(events: Vec<E>) -> Given<A>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Debug,
+{
+    let mut aggregate = A::default();
+    for (i, event) in events.into_iter().enumerate() {
+        let description = format!("{:?}", event);
+        if let Err(err) = aggregate.apply(event) {
+            panic!("given event #{} {} does not apply: {}", i, description, err);
+        }
+    }
+    Given { aggregate }
+}
+
+pub struct Given<A> {
+    aggregate: A,
+}
+
+impl<A> Given<A>
+where
+    A: Aggregate,
+{
+    pub fn when<C>(self, command: C) -> Then<ProducedEvent<A, C>, C::Error>
+    where
+        C: AggregateCommand<A>,
+    {
+        Then {
+            result: self
+                .aggregate
+                .execute(command)
+                .map(|events| events.into_iter().collect()),
+        }
+    }
+}
+
+/// Streams in an in-memory store, ready for a command handler built on `store()`.
+pub struct HandlerFixture<A, E> {
+    store: Arc<InMemoryEventStore<A, E>>,
+}
+
+impl<A, E> HandlerFixture<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    pub fn new() -> HandlerFixture<A, E> {
+        HandlerFixture {
+            store: Arc::new(InMemoryEventStore::new()),
+        }
+    }
+
+    /// Appends `events` to stream `id` before the handler runs.
+    pub fn given(self, id: &str, events: Vec<E>) -> HandlerFixture<A, E> {
+        self.store
+            .append_events(id, events, ExpectedVersion::Any)
+            .expect("given events could not be stored");
+        self
+    }
+
+    pub fn store(&self) -> Arc<InMemoryEventStore<A, E>> {
+        self.store.clone()
+    }
+
+    /// Hands `command` to `handler`. The events expected are the ones it stores, in commit order.
+    pub fn when<C, H>(self, handler: &H, command: C) -> Then<E, H::Error>
+    where
+        H: CommandHandler<C>,
+    {
+        let before = self.store.head_position().unwrap();
+        let result = handler.handle(command).map(|()| {
+            self.store
+                .read_all(before, usize::MAX)
+                .unwrap()
+                .into_iter()
+                .map(|envelope| envelope.event)
+                .collect()
+        });
+        Then { result }
+    }
+}
+
+impl<A, E> Default for HandlerFixture<A, E>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone,
+{
+    fn default() -> Self {
+        HandlerFixture::new()
+    }
+}
+
+/// What a command resulted in, to be checked against the expected outcome.
+#[derive(Debug)]
+pub struct Then<E, Err> {
+    result: Result<Vec<E>, Err>,
+}
+
+impl<E, Err> Then<E, Err>
+where
+    E: Debug + PartialEq,
+    Err: Debug,
+{
+    /// Panics with a diff unless the command produced exactly `expected`.
+    pub fn then_expect(self, expected: Vec<E>) {
+        match self.result {
+            Ok(actual) => {
+                if actual != expected {
+                    panic!("events differ:\n{}", diff(&expected, &actual));
+                }
+            }
+            Err(err) => panic!(
+                "expected events {:#?}\nbut the command failed with {:?}",
+                expected, err
+            ),
+        }
+    }
+
+    /// Panics unless the command failed with `expected`.
+    pub fn then_error(self, expected: Err)
+    where
+        Err: PartialEq,
+    {
+        match self.result {
+            Err(ref actual) if *actual == expected => {}
+            Err(actual) => panic!("expected error {:?}\nbut got error {:?}", expected, actual),
+            Ok(events) => panic!(
+                "expected error {:?}\nbut the command produced {:#?}",
+                expected, events
+            ),
+        }
+    }
+}
+
+/// One line per position, `-` for the expected event and `+` for the actual one where they
+/// differ.
+fn diff<E: Debug + PartialEq>(expected: &[E], actual: &[E]) -> String {
+    let mut lines = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                writeln!(lines, "  #{} {:?}", i, actual).unwrap()
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    writeln!(lines, "- #{} {:?}", i, expected).unwrap();
+                }
+                if let Some(actual) = actual {
+                    writeln!(lines, "+ #{} {:?}", i, actual).unwrap();
+                }
+            }
+        }
+    }
+    lines
+}
+
+type Invariant<A> = Box<dyn Fn(&A) -> bool>;
+type Transition<A, E> = Box<dyn Fn(&A, &E) -> bool>;
+
+/// Invariants of aggregate `A`, checked against generated sequences of commands.
+pub struct Properties<A, E> {
+    cases: u32,
+    invariants: Vec<(&'static str, Invariant<A>)>,
+    transitions: Vec<(&'static str, Transition<A, E>)>,
+}
+
+impl<A, E> Properties<A, E>
+where
+    A: Aggregate + Debug + PartialEq,
+    E: AggregateEvent<A> + Clone + Debug,
+{
+    pub fn new() -> Properties<A, E> {
+        Properties {
+            cases: Config::default().cases,
+            invariants: Vec::new(),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Number of command sequences to generate.
+    pub fn with_cases(mut self, cases: u32) -> Properties<A, E> {
+        self.cases = cases;
+        self
+    }
+
+    /// `invariant` has to hold after every event.
+    pub fn with_invariant<F>(mut self, name: &'static str, invariant: F) -> Properties<A, E>
+    where
+        F: Fn(&A) -> bool + 'static,
+    {
+        self.invariants.push((name, Box::new(invariant)));
+        self
+    }
+
+    /// `transition` has to hold for every event, given the aggregate before it.
+    pub fn with_transition<F>(mut self, name: &'static str, transition: F) -> Properties<A, E>
+    where
+        F: Fn(&A, &E) -> bool + 'static,
+    {
+        self.transitions.push((name, Box::new(transition)));
+        self
+    }
+
+    /// Executes every sequence `commands` generates on a new aggregate, applying the events
+    /// each command produces and skipping the commands it refuses. Replaying the events has to
+    /// rebuild the same aggregate. Panics with the shortest failing sequence found.
+    pub fn check<C, S>(&self, commands: S)
+    where
+        C: AggregateCommand<A, Event = E> + Debug,
+        S: Strategy<Value = Vec<C>>,
+    {
+        let mut runner = TestRunner::new(Config {
+            cases: self.cases,
+            failure_persistence: None,
+            ..Config::default()
+        });
+
+        match runner.run(&commands, |commands| {
+            self.run(commands).map_err(TestCaseError::fail)
+        }) {
+            Ok(()) => {}
+            Err(TestError::Fail(reason, commands)) => {
+                panic!("{}\nshortest failing commands: {:#?}", reason, commands)
+            }
+            Err(TestError::Abort(reason)) => panic!("{}", reason),
+        }
+    }
+
+    fn run<C>(&self, commands: Vec<C>) -> Result<(), String>
+    where
+        C: AggregateCommand<A, Event = E> + Debug,
+    {
+        let mut aggregate = A::default();
+        let mut history = Vec::new();
+
+        for command in commands {
+            let description = format!("{:?}", command);
+            let events = match aggregate.execute(command) {
+                Ok(events) => events,
+                Err(_) => continue,
+            };
+
+            for event in events {
+                for (name, holds) in &self.transitions {
+                    if !holds(&aggregate, &event) {
+                        return Err(format!(
+                            "`{}` broken by {:?} from {} on {:?}",
+                            name, event, description, aggregate
+                        ));
+                    }
+                }
+                aggregate.apply(event.clone()).map_err(|err| {
+                    format!("{:?} from {} does not apply: {}", event, description, err)
+                })?;
+                for (name, holds) in &self.invariants {
+                    if !holds(&aggregate) {
+                        return Err(format!(
+                            "`{}` broken by {:?} from {}, leaving {:?}",
+                            name, event, description, aggregate
+                        ));
+                    }
+                }
+                history.push(event);
+            }
+        }
+
+        let mut replayed = A::default();
+        for event in history {
+            replayed
+                .apply(event)
+                .map_err(|err| format!("replaying failed: {}", err))?;
+        }
+        if replayed != aggregate {
+            return Err(format!(
+                "replaying the events rebuilt {:?}\ninstead of {:?}",
+                replayed, aggregate
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<A, E> Default for Properties<A, E>
+where
+    A: Aggregate + Debug + PartialEq,
+    E: AggregateEvent<A> + Clone + Debug,
+{
+    fn default() -> Self {
+        Properties::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handler::CommandHandler;
+    use crate::test_helpers::{CounterAggregate, CounterEvent};
+    use crate::testing::*;
+    use crate::AggregateCommand;
+    use proptest::collection::vec;
+    use proptest::strategy::{Just, Strategy};
+    use std::{error::Error, fmt};
+
+    #[derive(Debug, Clone)]
+    struct Add(u64);
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TooMuch(u64);
+
+    impl Error for TooMuch {}
+
+    impl fmt::Display for TooMuch {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "value would be {}", self.0)
+        }
+    }
+
+    impl AggregateCommand<CounterAggregate> for Add {
+        type Event = CounterEvent;
+        type Events = Vec<CounterEvent>;
+        type Error = TooMuch;
+
+        fn execute_on(self, counter: &CounterAggregate) -> Result<Self::Events, Self::Error> {
+            match counter.value + self.0 {
+                value if value > 10 => Err(TooMuch(value)),
+                _ => Ok(vec![CounterEvent::Added(self.0)]),
+            }
+        }
+    }
+
+    /// Adds to the counter in stream "counter".
+    struct AddHandler(Arc<InMemoryEventStore<CounterAggregate, CounterEvent>>);
+
+    impl CommandHandler<Add> for AddHandler {
+        type Error = TooMuch;
+
+        fn handle(&self, command: Add) -> Result<(), TooMuch> {
+            let mut counter = CounterAggregate::default();
+            for event in self.0.read_events("counter", 0).unwrap() {
+                counter.apply(event.event).unwrap();
+            }
+            let events = counter.execute(command)?;
+            self.0
+                .append_events("counter", events, ExpectedVersion::Any)
+                .unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn command_produces_expected_events() {
+        given(vec![CounterEvent::Added(2)])
+            .when(Add(3))
+            .then_expect(vec![CounterEvent::Added(3)]);
+    }
+
+    #[test]
+    fn command_fails_with_expected_error() {
+        given(vec![CounterEvent::Added(8)])
+            .when(Add(3))
+            .then_error(TooMuch(11));
+    }
+
+    #[test]
+    #[should_panic(expected = "events differ:\n- #0 Added(4)\n+ #0 Added(3)\n")]
+    fn unexpected_events_are_reported_as_diff() {
+        given::<CounterAggregate, CounterEvent>(vec![])
+            .when(Add(3))
+            .then_expect(vec![CounterEvent::Added(4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected error TooMuch(11)")]
+    fn unexpected_success_is_reported() {
+        given::<CounterAggregate, CounterEvent>(vec![])
+            .when(Add(3))
+            .then_error(TooMuch(11));
+    }
+
+    #[test]
+    fn handler_stores_expected_events() {
+        let fixture = HandlerFixture::new()
+            .given("counter", vec![CounterEvent::Added(2)])
+            .given("other", vec![CounterEvent::Added(9)]);
+        let handler = AddHandler(fixture.store());
+
+        fixture
+            .when(&handler, Add(3))
+            .then_expect(vec![CounterEvent::Added(3)]);
+    }
+
+    #[test]
+    fn handler_fails_with_expected_error() {
+        let fixture = HandlerFixture::new().given("counter", vec![CounterEvent::Added(8)]);
+        let handler = AddHandler(fixture.store());
+
+        fixture.when(&handler, Add(3)).then_error(TooMuch(11));
+    }
+
+    #[test]
+    fn generated_commands_keep_invariants() {
+        Properties::new()
+            .with_invariant("at most ten", |counter: &CounterAggregate| {
+                counter.value <= 10
+            })
+            .with_transition("adds something", |_: &CounterAggregate, event| {
+                *event != CounterEvent::Added(0)
+            })
+            .check(vec((1..20u64).prop_map(Add), 0..10));
+    }
+
+    #[test]
+    #[should_panic(expected = "`below five` broken by Added(1) from Add(1), leaving \
+                               CounterAggregate { value: 5, generation: 5 }")]
+    fn broken_invariant_is_reported_with_shortest_commands() {
+        Properties::new()
+            .with_invariant("below five", |counter: &CounterAggregate| counter.value < 5)
+            .check(vec(Just(Add(1)), 0..10));
+    }
+}
@@ -0,0 +1,234 @@
+//! Outbox transport posting every message to a plain HTTP webhook.
+
+use crate::outbox::{OutboxEntry, Transport};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use std::{error::Error, fmt, io};
+
+/// Posts the payload of each entry as JSON to an `http://` URL, with the message id and topic
+/// in the `X-Message-Id` and `X-Message-Topic` headers. Any 2xx response counts as delivered.
+#[derive(Debug, Clone)]
+pub struct WebhookTransport {
+    host: String,
+    path: String,
+    timeout: Duration,
+}
+
+impl WebhookTransport {
+    pub fn new(url: &str) -> Result<WebhookTransport, WebhookError> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| WebhookError::InvalidUrl(url.to_owned()))?;
+        let (host, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(WebhookError::InvalidUrl(url.to_owned()));
+        }
+
+        Ok(WebhookTransport {
+            host: host.to_owned(),
+            path: path.to_owned(),
+            timeout: Duration::from_secs(5),
+        })
+    }
+
+    /// Time allowed for connecting, and for each read and write.
+    pub fn with_timeout(mut self, timeout: Duration) -> WebhookTransport {
+        self.timeout = timeout;
+        self
+    }
+
+    fn post(&self, entry: &OutboxEntry) -> Result<u16, WebhookError> {
+        let address = if self.host.contains(':') {
+            self.host.clone()
+        } else {
+            format!("{}:80", self.host)
+        };
+        let socket_address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| WebhookError::InvalidUrl(address.clone()))?;
+
+        let mut stream = TcpStream::connect_timeout(&socket_address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let payload = entry.message.payload.as_bytes();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             X-Message-Id: {}\r\n\
+             X-Message-Topic: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.path,
+            self.host,
+            payload.len(),
+            entry.id,
+            entry.message.topic
+        )?;
+        stream.write_all(payload)?;
+        stream.flush()?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        status_code(&response)
+    }
+}
+
+/// Status code from the status line of an HTTP response, e.g. `HTTP/1.1 204 No Content`.
+fn status_code(response: &[u8]) -> Result<u16, WebhookError> {
+    let status_line = response
+        .split(|byte| *byte == b'\n')
+        .next()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| WebhookError::InvalidResponse(status_line.trim().to_owned()))
+}
+
+impl Transport for WebhookTransport {
+    type Error = WebhookError;
+
+    fn send(&self, entry: &OutboxEntry) -> Result<(), WebhookError> {
+        match self.post(entry)? {
+            200..=299 => Ok(()),
+            code => Err(WebhookError::Status(code)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    InvalidUrl(String),
+    Io(String),
+    InvalidResponse(String),
+    Status(u16),
+}
+
+impl Error for WebhookError {}
+
+impl From<io::Error> for WebhookError {
+    fn from(err: io::Error) -> WebhookError {
+        WebhookError::Io(err.to_string())
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookError::InvalidUrl(url) => write!(f, "WebhookError: invalid url {}", url),
+            WebhookError::Io(message) => write!(f, "WebhookError: io: {}", message),
+            WebhookError::InvalidResponse(line) => {
+                write!(f, "WebhookError: invalid response {:?}", line)
+            }
+            WebhookError::Status(code) => write!(f, "WebhookError: webhook answered {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outbox::{OutboxMessage, Transport};
+    use crate::webhook::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Answers one request with `status` and returns the request it got.
+    fn webhook(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/bank", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            let mut stream = reader.into_inner();
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            request
+        });
+
+        (url, handle)
+    }
+
+    fn entry() -> OutboxEntry {
+        OutboxEntry {
+            id: 7,
+            message: OutboxMessage::new("bank.credited", r#"{"amount":5}"#.to_owned()),
+            attempts: 0,
+        }
+    }
+
+    #[test]
+    fn entry_is_posted_as_json() {
+        // Arrange
+        let (url, server) = webhook("204 No Content");
+        let transport = WebhookTransport::new(&url).unwrap();
+
+        // Act
+        let result = transport.send(&entry());
+
+        // Assert
+        assert_eq!(Ok(()), result);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hooks/bank HTTP/1.1\r\n"));
+        assert!(request.contains("X-Message-Id: 7\r\n"));
+        assert!(request.contains("X-Message-Topic: bank.credited\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"amount\":5}"));
+    }
+
+    #[test]
+    fn error_status_fails_delivery() {
+        // Arrange
+        let (url, server) = webhook("503 Service Unavailable");
+        let transport = WebhookTransport::new(&url).unwrap();
+
+        // Act
+        let result = transport.send(&entry());
+
+        // Assert
+        server.join().unwrap();
+        assert_eq!(Err(WebhookError::Status(503)), result);
+    }
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        // Act
+        let result = WebhookTransport::new("https://example.com/hooks");
+
+        // Assert
+        assert_eq!(
+            Err(WebhookError::InvalidUrl(
+                "https://example.com/hooks".to_owned()
+            )),
+            result.map(|_| ())
+        );
+    }
+}
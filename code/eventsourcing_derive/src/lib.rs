@@ -0,0 +1,474 @@
+#![crate_type = "proc-macro"]
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::parse::{Error, Result};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Fields, Ident, Lit, LitInt, LitStr,
+    Meta, MetaNameValue, NestedMeta, Type, Variant,
+};
+
+/// Implements `Event`. A struct's event type is its name in snake_case, and an enum's variants
+/// answer with the event they hold, which converts into the enum. `#[event(type = "...",
+/// version = N)]` on the struct or on a variant overrides them.
+#[proc_macro_derive(Event, attributes(event))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    let expanded = match ast.data {
+        Data::Enum(ref data) => expand_enum_event(&ast, data),
+        _ => expand_event(&ast),
+    };
+    TokenStream::from(expanded.unwrap_or_else(|err| err.to_compile_error()))
+}
+
+/// Implements `AggregateEvent` for an enum by applying the event each variant holds. The
+/// aggregate is named in `#[event(aggregate = "...")]`, and the error defaults to the one of
+/// the first variant's event unless `error = "..."` names another that the others convert into.
+#[proc_macro_derive(AggregateEvent, attributes(event))]
+pub fn derive_aggregate_event(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    let expanded = match ast.data {
+        Data::Enum(ref data) => expand_aggregate_event(&ast, data),
+        _ => Err(Error::new(
+            ast.ident.span(),
+            "#[derive(AggregateEvent)] is for enums of events; implement it by hand for a struct",
+        )),
+    };
+    TokenStream::from(expanded.unwrap_or_else(|err| err.to_compile_error()))
+}
+
+/// Implements `Aggregate` and `state::RecordingAggregate` for an enum of states. A state
+/// either holds its data, which has a `generation`, and the new events, as in
+/// `Opened(BankAccountState, Vec<BankAccountEvent>)`, or nothing, as in `Uninitialized`. The
+/// event is named in `#[aggregate(event = "...")]`, and the aggregate type defaults to the
+/// enum's name without `Aggregate`.
+///
+/// Every state gets a marker type in a module named after the enum in snake_case, and
+/// `#[aggregate(accepts(Credited, Debited))]` on a variant lets `InState` record just those
+/// events in that state.
+#[proc_macro_derive(Aggregate, attributes(aggregate))]
+pub fn derive_aggregate(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    let expanded = match ast.data {
+        Data::Enum(ref data) => expand_aggregate(&ast, data),
+        _ => Err(Error::new(
+            ast.ident.span(),
+            "#[derive(Aggregate)] is for enums of states; implement it by hand for a struct",
+        )),
+    };
+    TokenStream::from(expanded.unwrap_or_else(|err| err.to_compile_error()))
+}
+
+fn expand_aggregate(ast: &DeriveInput, data: &DataEnum) -> Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let vis = &ast.vis;
+    let options = AggregateOptions::from_attrs(&ast.attrs)?;
+
+    let aggregate_type = options.aggregate_type.unwrap_or_else(|| {
+        let type_name = name.to_string();
+        let type_name = type_name.trim_end_matches("Aggregate");
+        LitStr::new(type_name, name.span())
+    });
+    let event = match options.event {
+        Some(event) => event,
+        None => {
+            let message = "missing #[aggregate(event = \"...\")] naming the aggregate's event";
+            return Err(Error::new(Span::call_site(), message));
+        }
+    };
+
+    let states = Ident::new(&snake_case(&name.to_string()), name.span());
+    let mut generations = Vec::new();
+    let mut increments = Vec::new();
+    let mut records = Vec::new();
+    let mut new_events = Vec::new();
+    let mut markers = Vec::new();
+    let mut state_impls = Vec::new();
+    for variant in &data.variants {
+        let variant_name = &variant.ident;
+        match variant.fields {
+            Fields::Unit => {
+                generations.push(quote!(#name::#variant_name => 0,));
+                increments.push(quote!(#name::#variant_name => {}));
+                records.push(quote!(#name::#variant_name => {}));
+                new_events.push(quote!(#name::#variant_name => &[],));
+            }
+            Fields::Unnamed(ref fields) if fields.unnamed.len() == 2 => {
+                let pattern = quote!(#name::#variant_name(ref mut state, ref mut events));
+                generations.push(quote!(#name::#variant_name(ref state, _) => state.generation,));
+                increments.push(quote!(#pattern => state.generation += 1,));
+                records.push(quote!(#pattern => events.push(event),));
+                new_events.push(quote!(#name::#variant_name(_, ref events) => events,));
+            }
+            _ => {
+                let message = format!(
+                    "expected `{0}(State, Vec<Event>)` or `{0}`, a state with or without data",
+                    variant_name
+                );
+                return Err(Error::new(variant.span(), message));
+            }
+        }
+
+        let accepts = AggregateOptions::from_attrs(&variant.attrs)?
+            .accepts
+            .into_iter()
+            .map(|event| {
+                quote!(impl eventsourcing::state::Accepts<#event> for #states::#variant_name {})
+            });
+        markers.push(quote! {
+            #[derive(Debug)]
+            pub struct #variant_name;
+        });
+        state_impls.push(quote! {
+            impl eventsourcing::state::AggregateState<#name> for #states::#variant_name {
+                fn is_current(aggregate: &#name) -> bool {
+                    match *aggregate {
+                        #name::#variant_name { .. } => true,
+                        #[allow(unreachable_patterns)]
+                        _ => false,
+                    }
+                }
+            }
+
+            #(#accepts)*
+        });
+    }
+
+    Ok(quote! {
+        impl eventsourcing::Aggregate for #name {
+            fn aggregate_type() -> &'static str {
+                #aggregate_type
+            }
+
+            fn generation(&self) -> u64 {
+                match *self {
+                    #(#generations)*
+                }
+            }
+
+            fn increment_generation(&mut self) {
+                match *self {
+                    #(#increments)*
+                }
+            }
+        }
+
+        impl eventsourcing::state::RecordingAggregate for #name {
+            type Event = #event;
+
+            fn record(
+                &mut self,
+                event: #event,
+            ) -> std::result::Result<(), eventsourcing::state::EventErrorOf<Self>> {
+                eventsourcing::Aggregate::apply(self, std::clone::Clone::clone(&event))?;
+                #[allow(unused_variables)]
+                match *self {
+                    #(#records)*
+                }
+                Ok(())
+            }
+
+            fn new_events(&self) -> &[#event] {
+                match *self {
+                    #(#new_events)*
+                }
+            }
+        }
+
+        /// States of the aggregate, for `eventsourcing::state::InState`.
+        #vis mod #states {
+            #(#markers)*
+        }
+
+        #(#state_impls)*
+    })
+}
+
+fn expand_event(ast: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let options = EventOptions::from_attrs(&ast.attrs)?;
+
+    let event_type = options
+        .event_type
+        .unwrap_or_else(|| LitStr::new(&snake_case(&name.to_string()), name.span()));
+    let event_version = options.version.map(|version| {
+        quote! {
+            fn event_version(&self) -> u32 {
+                #version
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics eventsourcing::Event for #name #ty_generics #where_clause {
+            fn event_type(&self) -> &'static str {
+                #event_type
+            }
+
+            #event_version
+        }
+    })
+}
+
+fn expand_enum_event(ast: &DeriveInput, data: &DataEnum) -> Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut event_types = Vec::new();
+    let mut event_versions = Vec::new();
+    let mut conversions = Vec::new();
+    for variant in &data.variants {
+        let options = EventOptions::from_attrs(&variant.attrs)?;
+        let variant_name = &variant.ident;
+
+        let held = held_event(variant);
+        if let Ok(event) = held {
+            conversions.push(quote! {
+                impl #impl_generics std::convert::From<#event> for #name #ty_generics #where_clause {
+                    fn from(event: #event) -> Self {
+                        #name::#variant_name(event)
+                    }
+                }
+            });
+        }
+
+        let pattern = match held {
+            Ok(_) => quote!(#name::#variant_name(ref event)),
+            Err(_) => quote!(#name::#variant_name { .. }),
+        };
+        let event_version = match (options.version, &held) {
+            (Some(version), _) => quote!(#version),
+            (None, Ok(_)) => quote!(eventsourcing::Event::event_version(event)),
+            (None, Err(_)) => quote!(1),
+        };
+        let event_type = match options.event_type {
+            Some(event_type) => quote!(#event_type),
+            None => {
+                held?;
+                quote!(eventsourcing::Event::event_type(event))
+            }
+        };
+        event_types.push(quote!(#pattern => #event_type,));
+        event_versions.push(quote!(#pattern => #event_version,));
+    }
+
+    Ok(quote! {
+        impl #impl_generics eventsourcing::Event for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn event_type(&self) -> &'static str {
+                match *self {
+                    #(#event_types)*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn event_version(&self) -> u32 {
+                match *self {
+                    #(#event_versions)*
+                }
+            }
+        }
+
+        #(#conversions)*
+    })
+}
+
+fn expand_aggregate_event(ast: &DeriveInput, data: &DataEnum) -> Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let options = EventOptions::from_attrs(&ast.attrs)?;
+
+    let aggregate = match options.aggregate {
+        Some(aggregate) => aggregate,
+        None => {
+            let message = "missing #[event(aggregate = \"...\")] naming the aggregate";
+            return Err(Error::new(Span::call_site(), message));
+        }
+    };
+
+    let mut arms = Vec::new();
+    let mut first_event = None;
+    for variant in &data.variants {
+        let variant_name = &variant.ident;
+        let event = held_event(variant)?;
+        // Spanned so that an event the aggregate can't apply is reported at its variant.
+        let apply = quote_spanned! {event.span()=>
+            <#event as eventsourcing::AggregateEvent<#aggregate>>::apply_to(event, aggregate)
+        };
+        arms.push(quote! {
+            #name::#variant_name(event) => #apply.map_err(std::convert::Into::into),
+        });
+        first_event.get_or_insert(event);
+    }
+
+    let error = match (options.error, first_event) {
+        (Some(error), _) => quote!(#error),
+        (None, Some(event)) => quote!(<#event as eventsourcing::AggregateEvent<#aggregate>>::Error),
+        (None, None) => {
+            let message = "an enum without variants has no events to apply";
+            return Err(Error::new(name.span(), message));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics eventsourcing::AggregateEvent<#aggregate> for #name #ty_generics #where_clause {
+            type Error = #error;
+
+            fn apply_to(self, aggregate: &mut #aggregate) -> std::result::Result<(), Self::Error> {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// The type of the one event a variant like `Opened(Opened)` holds.
+fn held_event(variant: &Variant) -> Result<&Type> {
+    match variant.fields {
+        Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+            Ok(&fields.unnamed.first().unwrap().value().ty)
+        }
+        _ => {
+            let message = format!(
+                "expected `{0}(...)` holding one event, or #[event(type = \"...\")] on `{0}`",
+                variant.ident
+            );
+            Err(Error::new(variant.span(), message))
+        }
+    }
+}
+
+#[derive(Default)]
+struct EventOptions {
+    event_type: Option<LitStr>,
+    version: Option<LitInt>,
+    aggregate: Option<Type>,
+    error: Option<Type>,
+}
+
+impl EventOptions {
+    fn from_attrs(attrs: &[Attribute]) -> Result<EventOptions> {
+        let message =
+            "expected #[event(type = \"...\", version = N, aggregate = \"...\", error = \"...\")]";
+        let mut options = EventOptions::default();
+
+        for attr in attrs {
+            if !attr.path.is_ident("event") {
+                continue;
+            }
+
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                other => return Err(Error::new(other.span(), message)),
+            };
+            for nested in list.nested {
+                let (ident, lit) = match nested {
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue { ident, lit, .. })) => {
+                        (ident, lit)
+                    }
+                    other => return Err(Error::new(other.span(), message)),
+                };
+
+                match (ident.to_string().as_str(), lit) {
+                    ("type", Lit::Str(lit_str)) => options.event_type = Some(lit_str),
+                    ("version", Lit::Int(lit_int)) => options.version = Some(lit_int),
+                    ("aggregate", Lit::Str(lit_str)) => options.aggregate = Some(lit_str.parse()?),
+                    ("error", Lit::Str(lit_str)) => options.error = Some(lit_str.parse()?),
+                    (_, lit) => return Err(Error::new(expected_span(&ident, &lit), message)),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Default)]
+struct AggregateOptions {
+    aggregate_type: Option<LitStr>,
+    event: Option<Type>,
+    accepts: Vec<Ident>,
+}
+
+impl AggregateOptions {
+    fn from_attrs(attrs: &[Attribute]) -> Result<AggregateOptions> {
+        let message =
+            "expected #[aggregate(type = \"...\", event = \"...\")] or #[aggregate(accepts(...))]";
+        let mut options = AggregateOptions::default();
+
+        for attr in attrs {
+            if !attr.path.is_ident("aggregate") {
+                continue;
+            }
+
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                other => return Err(Error::new(other.span(), message)),
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                        ref ident,
+                        lit: Lit::Str(ref lit_str),
+                        ..
+                    })) if ident == "type" => options.aggregate_type = Some(lit_str.clone()),
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                        ref ident,
+                        lit: Lit::Str(ref lit_str),
+                        ..
+                    })) if ident == "event" => options.event = Some(lit_str.parse()?),
+                    NestedMeta::Meta(Meta::List(ref list)) if list.ident == "accepts" => {
+                        for accepted in &list.nested {
+                            match accepted {
+                                NestedMeta::Meta(Meta::Word(event)) => {
+                                    options.accepts.push(event.clone())
+                                }
+                                other => return Err(Error::new(other.span(), message)),
+                            }
+                        }
+                    }
+                    other => return Err(Error::new(other.span(), message)),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Points at the value of a known option, and at the name of an unknown one.
+fn expected_span(ident: &Ident, lit: &Lit) -> Span {
+    match ident.to_string().as_str() {
+        "type" | "version" | "aggregate" | "error" => lit.span(),
+        _ => ident.span(),
+    }
+}
+
+/// `ClosingFailedDueToFundsAvailable` to `closing_failed_due_to_funds_available`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let after_lower = chars[i - 1].is_lowercase() || chars[i - 1].is_numeric();
+            let starts_word = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if after_lower || (chars[i - 1].is_uppercase() && starts_word) {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
@@ -0,0 +1,129 @@
+use eventsourcing::state::{InState, RecordingAggregate};
+use eventsourcing::Aggregate as _;
+use eventsourcing_derive::{Aggregate, AggregateEvent, Event};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Door {
+    generation: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Aggregate)]
+#[aggregate(event = "DoorEvent")]
+enum DoorAggregate {
+    #[default]
+    #[aggregate(accepts(Installed))]
+    Uninstalled,
+    #[aggregate(accepts(Locked))]
+    Open(Door, Vec<DoorEvent>),
+    Locked(Door, Vec<DoorEvent>),
+}
+
+#[derive(Debug, Clone, PartialEq, Event)]
+struct Installed;
+
+impl eventsourcing::AggregateEvent<DoorAggregate> for Installed {
+    type Error = String;
+
+    fn apply_to(self, door: &mut DoorAggregate) -> Result<(), String> {
+        *door = DoorAggregate::Open(Door { generation: 0 }, Vec::new());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Event)]
+struct Locked;
+
+impl eventsourcing::AggregateEvent<DoorAggregate> for Locked {
+    type Error = String;
+
+    fn apply_to(self, door: &mut DoorAggregate) -> Result<(), String> {
+        match door.clone() {
+            DoorAggregate::Open(state, events) => {
+                *door = DoorAggregate::Locked(state, events);
+                Ok(())
+            }
+            _ => Err("only an open door locks".to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Event, AggregateEvent)]
+#[event(aggregate = "DoorAggregate")]
+enum DoorEvent {
+    Installed(Installed),
+    Locked(Locked),
+}
+
+#[test]
+fn aggregate_type_is_enum_name_without_aggregate() {
+    assert_eq!("Door", DoorAggregate::aggregate_type());
+}
+
+#[test]
+fn recorded_events_are_kept_in_every_state() {
+    // Arrange
+    let mut door = DoorAggregate::default();
+
+    // Act
+    door.record(DoorEvent::Installed(Installed)).unwrap();
+    door.record(DoorEvent::Locked(Locked)).unwrap();
+
+    // Assert
+    assert_eq!(
+        &[DoorEvent::Installed(Installed), DoorEvent::Locked(Locked)],
+        door.new_events()
+    );
+    assert_eq!(2, door.generation());
+}
+
+#[test]
+fn uninitialized_state_has_no_generation_or_events() {
+    // Arrange
+    let mut door = DoorAggregate::default();
+
+    // Act
+    let result = door.record(DoorEvent::Locked(Locked));
+
+    // Assert
+    assert!(result.is_err());
+    assert_eq!(0, door.generation());
+    assert!(door.new_events().is_empty());
+}
+
+#[test]
+fn state_records_events_it_accepts() {
+    // Arrange
+    let mut door = DoorAggregate::default();
+
+    // Act
+    let door = door
+        .in_state::<door_aggregate::Uninstalled>()
+        .unwrap()
+        .record(Installed)
+        .unwrap();
+    let door = InState::<_, door_aggregate::Open>::new(door)
+        .unwrap()
+        .record(Locked)
+        .unwrap();
+
+    // Assert
+    match door {
+        DoorAggregate::Locked(state, events) => {
+            assert_eq!(2, state.generation);
+            assert_eq!(2, events.len());
+        }
+        _ => panic!("expected a locked door"),
+    }
+}
+
+#[test]
+fn aggregate_in_another_state_is_not_handed_out() {
+    // Arrange
+    let mut door = DoorAggregate::default();
+
+    // Act
+    let open = door.in_state::<door_aggregate::Open>();
+
+    // Assert
+    assert!(open.is_none());
+}
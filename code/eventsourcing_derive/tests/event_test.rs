@@ -0,0 +1,172 @@
+use eventsourcing::{Aggregate, AggregateEvent as _, Event as _};
+use eventsourcing_derive::{AggregateEvent, Event};
+use std::fmt;
+
+#[derive(Debug, Default, PartialEq)]
+struct Counter {
+    value: u64,
+    generation: u64,
+}
+
+impl Aggregate for Counter {
+    fn aggregate_type() -> &'static str {
+        "Counter"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CounterError {
+    Overflow,
+    Closed(ClosedError),
+}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct ClosedError;
+
+impl fmt::Display for ClosedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("closed")
+    }
+}
+
+impl From<ClosedError> for CounterError {
+    fn from(err: ClosedError) -> CounterError {
+        CounterError::Closed(err)
+    }
+}
+
+#[derive(Debug, Event)]
+struct IncrementedBy(u64);
+
+impl eventsourcing::AggregateEvent<Counter> for IncrementedBy {
+    type Error = CounterError;
+
+    fn apply_to(self, counter: &mut Counter) -> Result<(), Self::Error> {
+        counter.value = counter
+            .value
+            .checked_add(self.0)
+            .ok_or(CounterError::Overflow)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Event)]
+#[event(type = "counter_reset", version = 2)]
+struct ResetToZero;
+
+impl eventsourcing::AggregateEvent<Counter> for ResetToZero {
+    type Error = ClosedError;
+
+    fn apply_to(self, counter: &mut Counter) -> Result<(), Self::Error> {
+        counter.value = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Event, AggregateEvent)]
+#[event(aggregate = "Counter")]
+enum CounterEvent {
+    Incremented(IncrementedBy),
+    Reset(ResetToZero),
+}
+
+#[derive(Debug, Event)]
+enum CounterLog {
+    Counted(CounterEvent),
+    #[event(type = "noted")]
+    Noted,
+}
+
+#[derive(Debug, Event, AggregateEvent)]
+#[event(aggregate = "Counter", error = "CounterError")]
+enum CounterChange {
+    Incremented(IncrementedBy),
+    #[event(version = 3)]
+    Reset(ResetToZero),
+}
+
+#[test]
+fn struct_event_type_is_snake_case_name() {
+    // Act
+    let event = IncrementedBy(5);
+
+    // Assert
+    assert_eq!("incremented_by", event.event_type());
+    assert_eq!(1, event.event_version());
+}
+
+#[test]
+fn struct_event_type_and_version_can_be_overridden() {
+    // Act
+    let event = ResetToZero;
+
+    // Assert
+    assert_eq!("counter_reset", event.event_type());
+    assert_eq!(2, event.event_version());
+}
+
+#[test]
+fn enum_event_takes_type_of_held_event() {
+    // Arrange
+    let incremented = CounterLog::Counted(CounterEvent::Incremented(IncrementedBy(5)));
+    let reset = CounterChange::Reset(ResetToZero);
+    let noted = CounterLog::Noted;
+
+    // Assert
+    assert_eq!("incremented_by", incremented.event_type());
+    assert_eq!(
+        ("counter_reset", 3),
+        (reset.event_type(), reset.event_version())
+    );
+    assert_eq!(("noted", 1), (noted.event_type(), noted.event_version()));
+}
+
+#[test]
+fn enum_aggregate_event_applies_held_event() {
+    // Arrange
+    let mut counter = Counter::default();
+
+    // Act
+    counter
+        .apply(CounterChange::Incremented(IncrementedBy(5)))
+        .unwrap();
+    let overflow = counter.apply(CounterChange::Incremented(IncrementedBy(u64::MAX)));
+    counter.apply(CounterChange::Reset(ResetToZero)).unwrap();
+
+    // Assert
+    assert_eq!(Err(CounterError::Overflow), overflow);
+    assert_eq!(0, counter.value);
+    assert_eq!(3, counter.generation);
+}
+
+#[test]
+fn enum_aggregate_event_error_defaults_to_first_events() {
+    // Arrange
+    let mut counter = Counter::default();
+
+    // Act
+    let result: Result<(), CounterError> = CounterEvent::Reset(ResetToZero).apply_to(&mut counter);
+
+    // Assert
+    assert_eq!(Ok(()), result);
+}
+
+#[test]
+fn derive_misuse_fails_with_clear_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
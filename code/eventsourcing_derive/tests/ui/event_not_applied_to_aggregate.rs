@@ -0,0 +1,45 @@
+use eventsourcing::Aggregate;
+use eventsourcing_derive::{AggregateEvent, Event};
+
+#[derive(Default)]
+struct Account {
+    generation: u64,
+}
+
+impl Aggregate for Account {
+    fn aggregate_type() -> &'static str {
+        "Account"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Event)]
+struct Opened;
+
+impl eventsourcing::AggregateEvent<Account> for Opened {
+    type Error = String;
+
+    fn apply_to(self, _account: &mut Account) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Nobody said how `Closed` changes an `Account`.
+#[derive(Event)]
+struct Closed;
+
+#[derive(Event, AggregateEvent)]
+#[event(aggregate = "Account")]
+enum AccountEvent {
+    Opened(Opened),
+    Closed(Closed),
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use eventsourcing_derive::{AggregateEvent, Event};
+
+#[derive(Event)]
+struct Opened;
+
+#[derive(Event, AggregateEvent)]
+enum AccountEvent {
+    Opened(Opened),
+}
+
+fn main() {}
@@ -0,0 +1,44 @@
+use eventsourcing::state::RecordingAggregate;
+use eventsourcing_derive::{Aggregate, AggregateEvent, Event};
+
+#[derive(Debug, Clone)]
+struct Door {
+    generation: u64,
+}
+
+#[derive(Debug, Clone, Aggregate)]
+#[aggregate(event = "DoorEvent")]
+enum DoorAggregate {
+    #[aggregate(accepts(Locked))]
+    Open(Door, Vec<DoorEvent>),
+    Locked(Door, Vec<DoorEvent>),
+}
+
+impl Default for DoorAggregate {
+    fn default() -> Self {
+        DoorAggregate::Open(Door { generation: 0 }, Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, Event)]
+struct Locked;
+
+impl eventsourcing::AggregateEvent<DoorAggregate> for Locked {
+    type Error = String;
+
+    fn apply_to(self, _door: &mut DoorAggregate) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Event, AggregateEvent)]
+#[event(aggregate = "DoorAggregate")]
+enum DoorEvent {
+    Locked(Locked),
+}
+
+fn main() {
+    let mut door = DoorAggregate::default();
+    let locked = door.in_state::<door_aggregate::Locked>().unwrap();
+    locked.record(Locked).unwrap();
+}
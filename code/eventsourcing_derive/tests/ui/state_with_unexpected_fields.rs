@@ -0,0 +1,9 @@
+use eventsourcing_derive::Aggregate;
+
+#[derive(Aggregate)]
+#[aggregate(event = "()")]
+enum DoorAggregate {
+    Open { generation: u64 },
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use eventsourcing_derive::Event;
+
+#[derive(Event)]
+#[event(name = "opened")]
+struct Opened;
+
+fn main() {}
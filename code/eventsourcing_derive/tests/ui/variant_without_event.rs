@@ -0,0 +1,8 @@
+use eventsourcing_derive::Event;
+
+#[derive(Event)]
+enum AccountEvent {
+    Opened { id: u64 },
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use eventsourcing_derive::Event;
+
+#[derive(Event)]
+#[event(version = "2")]
+struct Opened;
+
+fn main() {}
@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use eventsourcing::eventstore::{EventStore, ExpectedVersion, InMemoryEventStore};
+use eventsourcing::projection::{Projection, ProjectionRunner};
+use example_banking::bank::account::prelude::*;
+use example_banking::bank::account::read_models::{
+    AccountSummaryProjection, BankingReadModelStore, CustomerAccountsProjection,
+    TransactionHistoryProjection,
+};
+use std::sync::Arc;
+
+const ACCOUNTS: u64 = 200;
+const EVENTS: u64 = 20_000;
+
+type BankingEventStore = Arc<InMemoryEventStore<BankAccountAggregate, BankAccountEvent>>;
+
+/// Opens `ACCOUNTS` accounts of 20 customers and credits and debits them until the store
+/// holds `EVENTS` events.
+fn event_store() -> BankingEventStore {
+    let event_store = Arc::new(InMemoryEventStore::new());
+
+    for event in 0..EVENTS {
+        let account = event % ACCOUNTS;
+        let version = event / ACCOUNTS;
+        let appended = match version {
+            0 => BankAccountEvent::opened(account, account % 20),
+            _ if version % 2 == 1 => BankAccountEvent::credited(account, 100),
+            _ => BankAccountEvent::debited(account, 50),
+        };
+        event_store
+            .append_events(&stream_id(account), vec![appended], ExpectedVersion::Any)
+            .unwrap();
+    }
+
+    event_store
+}
+
+/// Catches the projection `projection` makes up from the start of the store into an empty
+/// read model.
+fn catch_up<P>(
+    c: &mut Criterion,
+    event_store: &BankingEventStore,
+    name: &str,
+    projection: fn() -> P,
+) where
+    P: Projection<BankAccountEvent, BankingReadModelStore> + Sync,
+{
+    let mut group = c.benchmark_group("banking_projection_catch_up");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(EVENTS));
+
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let runner = ProjectionRunner::new(
+                event_store.clone(),
+                Arc::new(BankingReadModelStore::new()),
+                projection(),
+            )
+            .with_batch_size(1000);
+            runner.run_once().unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+fn projection_catch_up(c: &mut Criterion) {
+    let event_store = event_store();
+
+    catch_up(c, &event_store, "account_summary", || {
+        AccountSummaryProjection
+    });
+    catch_up(c, &event_store, "transaction_history", || {
+        TransactionHistoryProjection
+    });
+    catch_up(c, &event_store, "customer_accounts", || {
+        CustomerAccountsProjection
+    });
+}
+
+criterion_group!(benches, projection_catch_up);
+criterion_main!(benches);
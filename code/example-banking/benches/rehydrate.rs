@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use eventsourcing::eventstore::InMemoryEventStore;
+use eventsourcing::repository::{EventSourcedRepository, Repository};
+use eventsourcing::snapshot::InMemorySnapshotStore;
+use eventsourcing::Aggregate;
+use example_banking::bank::account::prelude::*;
+use std::sync::Arc;
+
+const SNAPSHOT_EVERY: u64 = 100;
+
+/// Saves an opened account credited until its stream holds `events` events, in batches of
+/// `SNAPSHOT_EVERY` like a busy account would be.
+fn save_account<R>(repository: &R, events: u64)
+where
+    R: Repository<BankAccountAggregate, Event = BankAccountEvent>,
+{
+    let id = stream_id(1);
+    let mut account = repository
+        .save(
+            &id,
+            BankAccountAggregate::default(),
+            vec![BankAccountEvent::opened(1, 1)],
+        )
+        .unwrap();
+
+    while account.generation() < events {
+        let batch = SNAPSHOT_EVERY.min(events - account.generation());
+        let credits = (0..batch)
+            .map(|amount| BankAccountEvent::credited(1, amount))
+            .collect();
+        account = repository.save(&id, account, credits).unwrap();
+    }
+}
+
+fn rehydrate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("banking_rehydrate");
+    group.sample_size(10);
+
+    for events in [10, 1_000, 100_000].iter() {
+        let repository = EventSourcedRepository::new(Arc::new(InMemoryEventStore::new()));
+        save_account(&repository, *events);
+        group.bench_with_input(BenchmarkId::new("events", events), events, |b, _| {
+            b.iter(|| repository.load(&stream_id(1)).unwrap())
+        });
+
+        let repository = EventSourcedRepository::with_snapshots(
+            Arc::new(InMemoryEventStore::new()),
+            Arc::new(InMemorySnapshotStore::new()),
+            SNAPSHOT_EVERY,
+        );
+        save_account(&repository, *events);
+        group.bench_with_input(BenchmarkId::new("snapshots", events), events, |b, _| {
+            b.iter(|| repository.load(&stream_id(1)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, rehydrate);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use eventsourcing::eventstore::{
+    EventStore, ExpectedVersion, GlobalEventStore, InMemoryEventStore,
+};
+use eventsourcing::filestore::FileEventStore;
+use eventsourcing::group_commit::{GroupCommitConfig, GroupCommitEventStore};
+use eventsourcing::partitioned::PartitionedEventStore;
+use example_banking::bank::account::prelude::*;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const ACCOUNTS: u64 = 100;
+const EVENTS: u64 = 10_000;
+const PAGE: usize = 1_000;
+
+/// Opens and credits `ACCOUNTS` accounts, one append per event, until the store holds `EVENTS`.
+fn append_events<S>(event_store: &S)
+where
+    S: EventStore<BankAccountAggregate, BankAccountEvent>,
+{
+    for event in 0..EVENTS {
+        let account = event % ACCOUNTS;
+        let version = event / ACCOUNTS;
+        let appended = if version == 0 {
+            BankAccountEvent::opened(account, account)
+        } else {
+            BankAccountEvent::credited(account, version)
+        };
+        event_store
+            .append_events(
+                &stream_id(account),
+                vec![appended],
+                ExpectedVersion::from_generation(version),
+            )
+            .unwrap();
+    }
+}
+
+/// Reads `$all` from the start in pages of `PAGE` and returns how many events it held.
+fn read_all<S>(event_store: &S) -> usize
+where
+    S: GlobalEventStore<BankAccountAggregate, BankAccountEvent>,
+{
+    let mut position = 0;
+    let mut read = 0;
+    loop {
+        let page = event_store.read_all(position, PAGE).unwrap();
+        match page.last() {
+            Some(last) => position = last.position,
+            None => return read,
+        }
+        read += page.len();
+    }
+}
+
+/// Time it takes to fill a store `open` creates in a fresh directory, over `iters` stores.
+fn time_appends<S, F>(iters: u64, open: F) -> Duration
+where
+    S: EventStore<BankAccountAggregate, BankAccountEvent>,
+    F: Fn(&TempDir) -> S,
+{
+    (0..iters)
+        .map(|_| {
+            let dir = TempDir::new().unwrap();
+            let event_store = open(&dir);
+            let start = Instant::now();
+            append_events(&event_store);
+            start.elapsed()
+        })
+        .sum()
+}
+
+fn file_store(dir: &TempDir) -> FileEventStore<BankAccountAggregate, BankAccountEvent> {
+    FileEventStore::open(dir.path().join("events.log")).unwrap()
+}
+
+fn partitioned_store(
+    dir: &TempDir,
+) -> PartitionedEventStore<BankAccountAggregate, BankAccountEvent> {
+    PartitionedEventStore::open(dir.path(), 4).unwrap()
+}
+
+fn group_commit_store(
+    dir: &TempDir,
+) -> GroupCommitEventStore<
+    BankAccountAggregate,
+    BankAccountEvent,
+    FileEventStore<BankAccountAggregate, BankAccountEvent>,
+> {
+    GroupCommitEventStore::new(file_store(dir), GroupCommitConfig::default()).unwrap()
+}
+
+fn append_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("banking_append");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(EVENTS));
+
+    group.bench_function("in_memory", |b| {
+        b.iter_custom(|iters| time_appends(iters, |_| InMemoryEventStore::new()))
+    });
+    group.bench_function("file", |b| {
+        b.iter_custom(|iters| time_appends(iters, file_store))
+    });
+    group.bench_function("partitioned", |b| {
+        b.iter_custom(|iters| time_appends(iters, partitioned_store))
+    });
+    group.bench_function("file_group_commit", |b| {
+        b.iter_custom(|iters| time_appends(iters, group_commit_store))
+    });
+
+    group.finish();
+}
+
+fn read_all_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("banking_read_all");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(EVENTS));
+
+    let in_memory = InMemoryEventStore::new();
+    append_events(&in_memory);
+    group.bench_with_input(
+        BenchmarkId::new("in_memory", PAGE),
+        &in_memory,
+        |b, store| b.iter(|| read_all(store)),
+    );
+
+    let dir = TempDir::new().unwrap();
+    let file = file_store(&dir);
+    append_events(&file);
+    group.bench_with_input(BenchmarkId::new("file", PAGE), &file, |b, store| {
+        b.iter(|| read_all(store))
+    });
+
+    let dir = TempDir::new().unwrap();
+    let partitioned = partitioned_store(&dir);
+    append_events(&partitioned);
+    group.bench_with_input(
+        BenchmarkId::new("partitioned", PAGE),
+        &partitioned,
+        |b, store| b.iter(|| read_all(store)),
+    );
+
+    group.finish();
+}
+
+criterion_group!(benches, append_throughput, read_all_throughput);
+criterion_main!(benches);
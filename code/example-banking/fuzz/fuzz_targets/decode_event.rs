@@ -0,0 +1,15 @@
+//! Decoding arbitrary bytes as a `BankAccountEvent` fails cleanly or gives an event that
+//! encodes back to itself.
+
+#![no_main]
+
+use example_banking::bank::account::prelude::BankAccountEvent;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(event) = serde_json::from_slice::<BankAccountEvent>(data) {
+        let encoded = serde_json::to_vec(&event).unwrap();
+        let decoded: BankAccountEvent = serde_json::from_slice(&encoded).unwrap();
+        assert_eq!(event, decoded);
+    }
+});
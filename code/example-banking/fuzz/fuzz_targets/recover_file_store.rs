@@ -0,0 +1,64 @@
+//! A file store opened on an arbitrary log either refuses it with a codec error or keeps
+//! the records of every append written in full and cuts off the rest.
+
+#![no_main]
+
+use eventsourcing::eventstore::{EventStoreError, GlobalEventStore};
+use eventsourcing::filestore::FileEventStore;
+use example_banking::bank::account::prelude::{BankAccountAggregate, BankAccountEvent};
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+use std::fs;
+use tempfile::TempDir;
+
+type Store = FileEventStore<BankAccountAggregate, BankAccountEvent>;
+
+/// Records and bytes of the appends `data` holds in full. The first record of an append of
+/// several events says how many records it wrote; any other record is an append of its own.
+fn committed(data: &[u8]) -> (usize, usize) {
+    let (mut records, mut len) = (0, 0);
+    let (mut pending, mut missing, mut offset) = (0, 0, 0);
+
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        if !line.ends_with(b"\n") {
+            break;
+        }
+        let record: Value = serde_json::from_slice(line).unwrap();
+        match record.get("batch").and_then(Value::as_u64) {
+            Some(size) => missing = size,
+            None if missing == 0 => missing = 1,
+            None => (),
+        }
+        missing -= 1;
+        pending += 1;
+        offset += line.len();
+        if missing == 0 {
+            records += pending;
+            pending = 0;
+            len = offset;
+        }
+    }
+
+    (records, len)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("events.log");
+    fs::write(&path, data).unwrap();
+
+    let store = match Store::open(&path) {
+        Ok(store) => store,
+        Err(EventStoreError::Codec(_)) => return,
+        Err(err) => panic!("unexpected error: {}", err),
+    };
+
+    let (committed, committed_len) = committed(data);
+    let recovered = store.read_all(0, usize::MAX).unwrap();
+    assert_eq!(committed, recovered.len(), "committed records lost");
+    assert_eq!(committed_len as u64, fs::metadata(&path).unwrap().len());
+
+    drop(store);
+    let reopened = Store::open(&path).unwrap();
+    assert_eq!(recovered, reopened.read_all(0, usize::MAX).unwrap());
+});
@@ -0,0 +1,54 @@
+//! Writes the seed corpora from real banking events: `cargo run --bin seed_corpus`.
+
+use eventsourcing::eventstore::{EventStore, ExpectedVersion};
+use eventsourcing::filestore::FileEventStore;
+use eventsourcing::Event;
+use example_banking::bank::account::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+const ACCOUNT_ID: BankAccountId = 123;
+const TARGET_ID: BankAccountId = 456;
+const CUSTOMER_ID: CustomerId = 5000;
+
+fn main() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    let source = vec![
+        BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+        BankAccountEvent::credited(ACCOUNT_ID, 50),
+        BankAccountEvent::debited(ACCOUNT_ID, 20),
+        BankAccountEvent::not_enough_funds(ACCOUNT_ID, 49, 30),
+        BankAccountEvent::closing_failed_due_to_funds_available(ACCOUNT_ID, 30),
+        BankAccountEvent::transfer_initiated(ACCOUNT_ID, 1, TARGET_ID, 30),
+        BankAccountEvent::transfer_completed(ACCOUNT_ID, 1),
+        BankAccountEvent::closed(ACCOUNT_ID),
+    ];
+    let target = vec![
+        BankAccountEvent::opened(TARGET_ID, CUSTOMER_ID),
+        BankAccountEvent::transfer_received(TARGET_ID, 1, ACCOUNT_ID, 30),
+    ];
+
+    let decode = corpus.join("decode_event");
+    fs::create_dir_all(&decode).unwrap();
+    for event in source.iter().chain(target.iter()) {
+        let encoded = serde_json::to_vec(event).unwrap();
+        fs::write(decode.join(event.event_type()), encoded).unwrap();
+    }
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("events.log");
+    let store = FileEventStore::<BankAccountAggregate, BankAccountEvent>::open(&path).unwrap();
+    store
+        .append_events(&stream_id(ACCOUNT_ID), source, ExpectedVersion::NoStream)
+        .unwrap();
+    store
+        .append_events(&stream_id(TARGET_ID), target, ExpectedVersion::NoStream)
+        .unwrap();
+    let log = fs::read(&path).unwrap();
+
+    let recover = corpus.join("recover_file_store");
+    fs::create_dir_all(&recover).unwrap();
+    fs::write(recover.join("log"), &log).unwrap();
+    fs::write(recover.join("torn"), &log[..log.len() - 20]).unwrap();
+}
@@ -1,6 +1,6 @@
 use super::errors::CommandError;
 use super::types::BankAccountId;
-use super::BankAccountAggregate;
+use super::{BankAccountAggregate, TransferStatus};
 use crate::bank::account::events::BankAccountEvent;
 use eventsourcing::AggregateCommand;
 
@@ -23,6 +23,15 @@ impl AggregateCommand<BankAccountAggregate> for CloseBankAccount {
 
     fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
         if let BankAccountAggregate::Opened(ref data, _) = aggregate {
+            // A transfer that fails later pays its amount back into the account.
+            if data
+                .transfers
+                .values()
+                .any(|status| *status == TransferStatus::Initiated)
+            {
+                return Err(CommandError::TransferPending);
+            }
+
             if data.balance == 0 {
                 Ok(vec![BankAccountEvent::closed(self.id)])
             } else {
@@ -40,51 +49,53 @@ impl AggregateCommand<BankAccountAggregate> for CloseBankAccount {
 mod tests {
     use crate::bank::account::errors::CommandError;
     use crate::bank::account::prelude::{
-        BankAccountAggregate, BankAccountEvent, BankAccountId, CloseBankAccount, CustomerId,
+        BankAccountEvent, BankAccountId, CloseBankAccount, CustomerId,
     };
-    use eventsourcing::Aggregate;
+    use eventsourcing::testing::given;
 
     const ACCOUNT_ID: BankAccountId = 123;
     const CUSTOMER_ID: CustomerId = 5000;
+    const TARGET_ID: BankAccountId = 456;
 
     #[test]
     fn closing_works() {
-        assert_close(
-            vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)],
-            CloseBankAccount::new(ACCOUNT_ID),
-            Ok(vec![BankAccountEvent::closed(ACCOUNT_ID)]),
-        );
+        given(vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)])
+            .when(CloseBankAccount::new(ACCOUNT_ID))
+            .then_expect(vec![BankAccountEvent::closed(ACCOUNT_ID)]);
     }
 
     #[test]
     fn cant_close_account_that_has_funds() {
-        assert_close(
-            vec![
-                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
-                BankAccountEvent::credited(ACCOUNT_ID, 20),
-            ],
-            CloseBankAccount::new(ACCOUNT_ID),
-            Ok(vec![
-                BankAccountEvent::closing_failed_due_to_funds_available(ACCOUNT_ID, 20),
-            ]),
-        );
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 20),
+        ])
+        .when(CloseBankAccount::new(ACCOUNT_ID))
+        .then_expect(vec![
+            BankAccountEvent::closing_failed_due_to_funds_available(ACCOUNT_ID, 20),
+        ]);
     }
 
-    fn assert_close(
-        intitial_events: Vec<BankAccountEvent>,
-        cmd: CloseBankAccount,
-        expected: Result<Vec<BankAccountEvent>, CommandError>,
-    ) {
-        // Arrange
-        let mut agg = BankAccountAggregate::default();
-        for event in intitial_events {
-            agg.apply(event).unwrap();
-        }
-
-        // Act
-        let result = agg.execute(cmd);
+    #[test]
+    fn cant_close_account_with_transfer_under_way() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 20),
+            BankAccountEvent::transfer_initiated(ACCOUNT_ID, 1, TARGET_ID, 20),
+        ])
+        .when(CloseBankAccount::new(ACCOUNT_ID))
+        .then_error(CommandError::TransferPending);
+    }
 
-        // Assert
-        assert_eq!(expected, result);
+    #[test]
+    fn closing_works_once_transfer_completed() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 20),
+            BankAccountEvent::transfer_initiated(ACCOUNT_ID, 1, TARGET_ID, 20),
+            BankAccountEvent::transfer_completed(ACCOUNT_ID, 1),
+        ])
+        .when(CloseBankAccount::new(ACCOUNT_ID))
+        .then_expect(vec![BankAccountEvent::closed(ACCOUNT_ID)]);
     }
 }
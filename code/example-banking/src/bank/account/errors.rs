@@ -5,6 +5,7 @@ use std::fmt;
 pub enum CommandError {
     AlreadyCreated,
     NotOpened,
+    TransferPending,
 }
 
 impl error::Error for CommandError {
@@ -12,6 +13,7 @@ impl error::Error for CommandError {
         match *self {
             CommandError::NotOpened => "attempt to execute command on account that is not opened",
             CommandError::AlreadyCreated => "attempt to create when already created",
+            CommandError::TransferPending => "attempt to close while a transfer is under way",
         }
     }
 }
@@ -28,6 +30,7 @@ pub enum EventError {
     AlreadyOpened,
     NotInitialized,
     NotOpened,
+    InsufficientFunds,
 }
 
 impl error::Error for EventError {
@@ -36,6 +39,7 @@ impl error::Error for EventError {
             EventError::NotInitialized => "attempt to execute event before creation",
             EventError::AlreadyOpened => "attempt to open when already opened",
             EventError::NotOpened => "attempt to closed when not opened",
+            EventError::InsufficientFunds => "attempt to take more than the balance",
         }
     }
 }
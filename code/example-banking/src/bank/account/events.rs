@@ -1,9 +1,11 @@
 use super::types::*;
-use super::{BankAccountAggregate, BankAccountState};
+use super::{BankAccountAggregate, BankAccountState, TransferStatus};
 use crate::bank::account::errors::EventError;
 use eventsourcing::{AggregateEvent, Event};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event, AggregateEvent)]
+#[event(aggregate = "BankAccountAggregate")]
 pub enum BankAccountEvent {
     Opened(Opened),
     Credited(Credited),
@@ -11,6 +13,10 @@ pub enum BankAccountEvent {
     NotEnoughFunds(NotEnoughFunds),
     Closed(Closed),
     ClosingFailedDueToFundsAvailable(ClosingFailedDueToFundsAvailable),
+    TransferInitiated(TransferInitiated),
+    TransferReceived(TransferReceived),
+    TransferCompleted(TransferCompleted),
+    TransferFailed(TransferFailed),
 }
 
 impl BankAccountEvent {
@@ -46,47 +52,56 @@ impl BankAccountEvent {
             current_balance,
         })
     }
-}
-
-impl Event for BankAccountEvent {
-    fn event_type(&self) -> &'static str {
-        match *self {
-            BankAccountEvent::Opened(ref evt) => evt.event_type(),
-            BankAccountEvent::Credited(ref evt) => evt.event_type(),
-            BankAccountEvent::Debited(ref evt) => evt.event_type(),
-            BankAccountEvent::NotEnoughFunds(ref evt) => evt.event_type(),
-            BankAccountEvent::Closed(ref evt) => evt.event_type(),
-            BankAccountEvent::ClosingFailedDueToFundsAvailable(ref evt) => evt.event_type(),
-        }
+    pub fn transfer_initiated(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        target: BankAccountId,
+        amount: u64,
+    ) -> BankAccountEvent {
+        BankAccountEvent::TransferInitiated(TransferInitiated {
+            id,
+            transfer_id,
+            target,
+            amount,
+        })
     }
-}
-
-impl AggregateEvent<BankAccountAggregate> for BankAccountEvent {
-    type Error = EventError;
-    fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
-        match self {
-            BankAccountEvent::Opened(evt) => evt.apply_to(aggregate),
-            BankAccountEvent::Credited(evt) => evt.apply_to(aggregate),
-            BankAccountEvent::Debited(evt) => evt.apply_to(aggregate),
-            BankAccountEvent::NotEnoughFunds(evt) => evt.apply_to(aggregate),
-            BankAccountEvent::Closed(evt) => evt.apply_to(aggregate),
-            BankAccountEvent::ClosingFailedDueToFundsAvailable(evt) => evt.apply_to(aggregate),
-        }
+    pub fn transfer_received(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        source: BankAccountId,
+        amount: u64,
+    ) -> BankAccountEvent {
+        BankAccountEvent::TransferReceived(TransferReceived {
+            id,
+            transfer_id,
+            source,
+            amount,
+        })
+    }
+    pub fn transfer_completed(id: BankAccountId, transfer_id: TransferId) -> BankAccountEvent {
+        BankAccountEvent::TransferCompleted(TransferCompleted { id, transfer_id })
+    }
+    pub fn transfer_failed(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        reason: TransferFailureReason,
+        refunded: u64,
+    ) -> BankAccountEvent {
+        BankAccountEvent::TransferFailed(TransferFailed {
+            id,
+            transfer_id,
+            reason,
+            refunded,
+        })
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct Opened {
     pub id: BankAccountId,
     pub customer_id: CustomerId,
 }
 
-impl Event for Opened {
-    fn event_type(&self) -> &'static str {
-        "opened"
-    }
-}
-
 impl AggregateEvent<BankAccountAggregate> for Opened {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
@@ -102,18 +117,12 @@ impl AggregateEvent<BankAccountAggregate> for Opened {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct Credited {
     pub id: BankAccountId,
     pub amount: u64,
 }
 
-impl Event for Credited {
-    fn event_type(&self) -> &'static str {
-        "credited"
-    }
-}
-
 impl AggregateEvent<BankAccountAggregate> for Credited {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
@@ -126,23 +135,20 @@ impl AggregateEvent<BankAccountAggregate> for Credited {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct Debited {
     pub id: BankAccountId,
     pub amount: u64,
 }
 
-impl Event for Debited {
-    fn event_type(&self) -> &'static str {
-        "debited"
-    }
-}
-
 impl AggregateEvent<BankAccountAggregate> for Debited {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
         if let BankAccountAggregate::Opened(ref mut data, _) = aggregate {
-            data.balance -= self.amount;
+            data.balance = data
+                .balance
+                .checked_sub(self.amount)
+                .ok_or(EventError::InsufficientFunds)?;
             Ok(())
         } else {
             Err(EventError::NotInitialized)
@@ -150,19 +156,13 @@ impl AggregateEvent<BankAccountAggregate> for Debited {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct NotEnoughFunds {
     pub id: BankAccountId,
     pub amount: u64,
     pub current_balance: u64,
 }
 
-impl Event for NotEnoughFunds {
-    fn event_type(&self) -> &'static str {
-        "not_enough_funds"
-    }
-}
-
 impl AggregateEvent<BankAccountAggregate> for NotEnoughFunds {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
@@ -174,17 +174,11 @@ impl AggregateEvent<BankAccountAggregate> for NotEnoughFunds {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct Closed {
     pub id: BankAccountId,
 }
 
-impl Event for Closed {
-    fn event_type(&self) -> &'static str {
-        "closed"
-    }
-}
-
 impl AggregateEvent<BankAccountAggregate> for Closed {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
@@ -197,22 +191,42 @@ impl AggregateEvent<BankAccountAggregate> for Closed {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
 pub struct ClosingFailedDueToFundsAvailable {
     pub id: BankAccountId,
     pub current_balance: u64,
 }
 
-impl Event for ClosingFailedDueToFundsAvailable {
-    fn event_type(&self) -> &'static str {
-        "closing_failed_due_to_funds_available"
+impl AggregateEvent<BankAccountAggregate> for ClosingFailedDueToFundsAvailable {
+    type Error = EventError;
+    fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
+        if let BankAccountAggregate::Opened(_, _) = aggregate {
+            Ok(())
+        } else {
+            Err(EventError::NotOpened)
+        }
     }
 }
 
-impl AggregateEvent<BankAccountAggregate> for ClosingFailedDueToFundsAvailable {
+/// Money left the source account of a transfer.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
+pub struct TransferInitiated {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub target: BankAccountId,
+    pub amount: u64,
+}
+
+impl AggregateEvent<BankAccountAggregate> for TransferInitiated {
     type Error = EventError;
     fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
-        if let BankAccountAggregate::Opened(_, _) = aggregate {
+        if let BankAccountAggregate::Opened(ref mut data, _) = aggregate {
+            data.balance = data
+                .balance
+                .checked_sub(self.amount)
+                .ok_or(EventError::InsufficientFunds)?;
+            data.transfers
+                .insert((self.id, self.transfer_id), TransferStatus::Initiated);
             Ok(())
         } else {
             Err(EventError::NotOpened)
@@ -220,16 +234,119 @@ impl AggregateEvent<BankAccountAggregate> for ClosingFailedDueToFundsAvailable {
     }
 }
 
+/// Money of a transfer arrived on the target account.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
+pub struct TransferReceived {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub source: BankAccountId,
+    pub amount: u64,
+}
+
+impl AggregateEvent<BankAccountAggregate> for TransferReceived {
+    type Error = EventError;
+    fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
+        if let BankAccountAggregate::Opened(ref mut data, _) = aggregate {
+            data.balance += self.amount;
+            data.transfers
+                .insert((self.source, self.transfer_id), TransferStatus::Received);
+            Ok(())
+        } else {
+            Err(EventError::NotOpened)
+        }
+    }
+}
+
+/// The target of a transfer the source account initiated received the money.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
+pub struct TransferCompleted {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+}
+
+impl AggregateEvent<BankAccountAggregate> for TransferCompleted {
+    type Error = EventError;
+    fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
+        match aggregate {
+            BankAccountAggregate::Opened(ref mut data, _)
+            | BankAccountAggregate::Closed(ref mut data, _) => {
+                data.transfers
+                    .insert((self.id, self.transfer_id), TransferStatus::Completed);
+                Ok(())
+            }
+            BankAccountAggregate::Uninitialized => Err(EventError::NotInitialized),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferFailureReason {
+    InsufficientFunds,
+    SameAccount,
+    /// The target account is closed or was never opened.
+    TargetUnavailable,
+}
+
+/// A transfer was refused, or given up on and paid back to the source account.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Event)]
+pub struct TransferFailed {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub reason: TransferFailureReason,
+    /// Amount paid back, 0 when the transfer was refused before any money left the account.
+    pub refunded: u64,
+}
+
+impl AggregateEvent<BankAccountAggregate> for TransferFailed {
+    type Error = EventError;
+    fn apply_to(self, aggregate: &mut BankAccountAggregate) -> Result<(), Self::Error> {
+        match aggregate {
+            BankAccountAggregate::Opened(ref mut data, _)
+            | BankAccountAggregate::Closed(ref mut data, _) => {
+                data.balance += self.refunded;
+                data.transfers
+                    .insert((self.id, self.transfer_id), TransferStatus::Failed);
+                Ok(())
+            }
+            BankAccountAggregate::Uninitialized => Err(EventError::NotInitialized),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bank::account::errors::EventError;
+    use crate::bank::account::events::TransferFailureReason;
     use crate::bank::account::prelude::{
-        BankAccountAggregate, BankAccountEvent, BankAccountId, CustomerId,
+        BankAccountAggregate, BankAccountEvent, BankAccountId, CustomerId, TransferStatus,
     };
-    use eventsourcing::Aggregate;
+    use eventsourcing::{Aggregate, Event};
     const ACCOUNT_ID: BankAccountId = 123;
     const CUSTOMER_ID: CustomerId = 5000;
 
+    #[test]
+    fn event_types_are_snake_case_names_of_held_events() {
+        // Arrange
+        let events = [
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::closing_failed_due_to_funds_available(ACCOUNT_ID, 49),
+            BankAccountEvent::transfer_completed(ACCOUNT_ID, 1),
+        ];
+
+        // Act
+        let event_types: Vec<_> = events.iter().map(Event::event_type).collect();
+
+        // Assert
+        assert_eq!(
+            vec![
+                "opened",
+                "closing_failed_due_to_funds_available",
+                "transfer_completed"
+            ],
+            event_types
+        );
+    }
+
     #[test]
     fn bank_account_opened() {
         // Arrange
@@ -310,6 +427,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn debit_beyond_balance_is_refused() {
+        // Arrange
+        let mut agg = BankAccountAggregate::default();
+        agg.apply(BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+        agg.apply(BankAccountEvent::credited(ACCOUNT_ID, 48))
+            .unwrap();
+
+        // Act
+        let result = agg.apply(BankAccountEvent::debited(ACCOUNT_ID, 49));
+
+        // Assert
+        assert_eq!(Err(EventError::InsufficientFunds), result);
+        if let BankAccountAggregate::Opened(state, _) = agg {
+            assert_eq!(48, state.balance);
+        } else {
+            panic!("Aggregate not in Opened state");
+        }
+    }
+
     #[test]
     fn bank_account_not_enough_funds() {
         // Arrange
@@ -374,4 +512,38 @@ mod tests {
             panic!("Aggregate not in Opened state");
         }
     }
+
+    #[test]
+    fn failed_transfer_pays_back_initiated_amount() {
+        // Arrange
+        let mut agg = BankAccountAggregate::default();
+        agg.apply(BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+        let events = vec![
+            BankAccountEvent::credited(ACCOUNT_ID, 49),
+            BankAccountEvent::transfer_initiated(ACCOUNT_ID, 1, 456, 40),
+            BankAccountEvent::transfer_failed(
+                ACCOUNT_ID,
+                1,
+                TransferFailureReason::TargetUnavailable,
+                40,
+            ),
+        ];
+
+        // Act
+        for event in events {
+            agg.apply(event).unwrap();
+        }
+
+        // Assert
+        if let BankAccountAggregate::Opened(state, _) = agg {
+            assert_eq!(49, state.balance);
+            assert_eq!(
+                Some(&TransferStatus::Failed),
+                state.transfers.get(&(ACCOUNT_ID, 1))
+            );
+        } else {
+            panic!("Aggregate not in Opened state");
+        }
+    }
 }
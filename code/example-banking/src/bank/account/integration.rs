@@ -0,0 +1,100 @@
+//! Messages about bank accounts for other systems, published through the outbox.
+
+use super::events::BankAccountEvent;
+use eventsourcing::outbox::{OutboxMessage, OutboxWriter};
+use eventsourcing::Event;
+
+pub type AccountMapper = fn(&str, &BankAccountEvent) -> Option<OutboxMessage>;
+
+/// Event store that publishes the integration messages of every bank account event it stores.
+pub type PublishingEventStore<ES> = OutboxWriter<ES, AccountMapper>;
+
+pub fn publishing<ES>(event_store: ES) -> PublishingEventStore<ES> {
+    OutboxWriter::new(event_store, account_message)
+}
+
+/// Message with topic `bank_account.<event type>` for events that change what an account holds,
+/// `None` for events that stay internal.
+pub fn account_message(_stream_id: &str, event: &BankAccountEvent) -> Option<OutboxMessage> {
+    let payload = match event {
+        BankAccountEvent::Opened(evt) => {
+            format!(r#"{{"account":{},"customer":{}}}"#, evt.id, evt.customer_id)
+        }
+        BankAccountEvent::Credited(evt) => {
+            format!(r#"{{"account":{},"amount":{}}}"#, evt.id, evt.amount)
+        }
+        BankAccountEvent::Debited(evt) => {
+            format!(r#"{{"account":{},"amount":{}}}"#, evt.id, evt.amount)
+        }
+        BankAccountEvent::Closed(evt) => format!(r#"{{"account":{}}}"#, evt.id),
+        BankAccountEvent::TransferInitiated(evt) => format!(
+            r#"{{"account":{},"transfer":{},"target":{},"amount":{}}}"#,
+            evt.id, evt.transfer_id, evt.target, evt.amount
+        ),
+        BankAccountEvent::TransferReceived(evt) => format!(
+            r#"{{"account":{},"transfer":{},"source":{},"amount":{}}}"#,
+            evt.id, evt.transfer_id, evt.source, evt.amount
+        ),
+        BankAccountEvent::TransferFailed(evt) if evt.refunded > 0 => format!(
+            r#"{{"account":{},"transfer":{},"refunded":{}}}"#,
+            evt.id, evt.transfer_id, evt.refunded
+        ),
+        _ => return None,
+    };
+
+    Some(OutboxMessage::new(
+        &format!("bank_account.{}", event.event_type()),
+        payload,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::integration::publishing;
+    use crate::bank::account::prelude::*;
+    use crate::bank::account::service::BankAccountService;
+    use eventsourcing::eventstore::InMemoryEventStore;
+    use eventsourcing::outbox::{InProcessTransport, OutboxMessage, OutboxRelay};
+    use std::sync::Arc;
+
+    const ACCOUNT_ID: BankAccountId = 123;
+    const CUSTOMER_ID: CustomerId = 5000;
+
+    #[test]
+    fn saved_account_events_are_relayed() {
+        // Arrange
+        let event_store = Arc::new(InMemoryEventStore::new());
+        let service = BankAccountService::new(publishing(event_store.clone()));
+        let transport = Arc::new(InProcessTransport::new());
+        let relay = OutboxRelay::new(event_store, transport.clone());
+        service
+            .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+        service.handle(DepositMoney::new(ACCOUNT_ID, 50)).unwrap();
+        service.handle(WithdrawMoney::new(ACCOUNT_ID, 80)).unwrap();
+
+        // Act
+        let result = relay.run_once();
+
+        // Assert
+        assert_eq!(Ok(2), result);
+        let messages: Vec<_> = transport
+            .delivered()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(
+            vec![
+                OutboxMessage::new(
+                    "bank_account.opened",
+                    r#"{"account":123,"customer":5000}"#.to_owned()
+                ),
+                OutboxMessage::new(
+                    "bank_account.credited",
+                    r#"{"account":123,"amount":50}"#.to_owned()
+                ),
+            ],
+            messages
+        );
+    }
+}
@@ -2,29 +2,56 @@ mod close_bank_account;
 mod deposit_money;
 mod errors;
 mod events;
+pub mod integration;
 mod open_bank_account;
 pub mod prelude;
+#[cfg(test)]
+mod properties;
+pub mod queries;
+pub mod read_models;
+pub mod service;
+mod transfer_money;
+pub mod transfers;
 mod types;
 mod withdraw_money;
 
+use crate::bank::account::errors::EventError;
+use crate::bank::account::events::{
+    Closed, ClosingFailedDueToFundsAvailable, Credited, Debited, NotEnoughFunds, Opened,
+    TransferCompleted, TransferFailed, TransferInitiated, TransferReceived,
+};
 use crate::bank::account::prelude::BankAccountEvent;
-use crate::bank::account::types::{BankAccountId, CustomerId};
+use crate::bank::account::types::{BankAccountId, CustomerId, TransferId};
+use eventsourcing::state::RecordingAggregate;
 use eventsourcing::Aggregate;
+use std::collections::HashMap;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BankAccountState {
     pub id: BankAccountId,
     pub customer_id: CustomerId,
     pub balance: u64,
+    /// Transfers from or to the account, by source account and transfer id.
+    pub transfers: HashMap<(BankAccountId, TransferId), TransferStatus>,
     pub generation: u64,
 }
 
+/// Where a transfer from or to the account stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Initiated,
+    Received,
+    Completed,
+    Failed,
+}
+
 impl BankAccountState {
     pub fn new(id: BankAccountId, customer_id: CustomerId) -> BankAccountState {
         BankAccountState {
             id: id,
             customer_id: customer_id,
             balance: 0,
+            transfers: HashMap::new(),
             generation: 0,
         }
     }
@@ -32,10 +59,24 @@ impl BankAccountState {
 
 type NewEvents = Vec<BankAccountEvent>;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Aggregate)]
+#[aggregate(event = "BankAccountEvent")]
 pub enum BankAccountAggregate {
+    #[aggregate(accepts(
+        Credited,
+        Debited,
+        NotEnoughFunds,
+        Closed,
+        ClosingFailedDueToFundsAvailable,
+        TransferInitiated,
+        TransferReceived,
+        TransferCompleted,
+        TransferFailed
+    ))]
     Opened(BankAccountState, NewEvents),
+    #[aggregate(accepts(TransferCompleted, TransferFailed))]
     Closed(BankAccountState, NewEvents),
+    #[aggregate(accepts(Opened))]
     Uninitialized,
 }
 
@@ -45,51 +86,53 @@ impl Default for BankAccountAggregate {
     }
 }
 
-impl Aggregate for BankAccountAggregate {
-    fn aggregate_type() -> &'static str
-    where
-        Self: Sized,
-    {
-        "BankAccount"
+impl BankAccountAggregate {
+    pub fn open(&mut self, id: BankAccountId, customer_id: CustomerId) -> Result<(), EventError> {
+        let uninitialized = self
+            .in_state::<bank_account_aggregate::Uninitialized>()
+            .ok_or(EventError::AlreadyOpened)?;
+        uninitialized.record(Opened { id, customer_id })?;
+        Ok(())
     }
 
-    fn increment_generation(&mut self) {
-        use BankAccountAggregate::*;
-
-        match self {
-            Opened(data, _) => data.generation += 1,
-            Closed(data, _) => data.generation += 1,
-            Uninitialized => panic!("CANT INCREMENT GENERATION ON UNINITIALIZED BANK ACC"),
-        }
+    pub fn get_new_events(&self) -> Vec<BankAccountEvent> {
+        self.new_events().to_vec()
     }
 }
 
-impl BankAccountAggregate {
-    pub fn open(&mut self, id: BankAccountId, customer_id: CustomerId) -> Result<(), ()> {
-        let event = BankAccountEvent::opened(id, customer_id);
-        self.record(event).unwrap();
-        Ok(())
-    }
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::errors::EventError;
+    use crate::bank::account::prelude::{BankAccountAggregate, BankAccountEvent};
+    use eventsourcing::Aggregate;
 
-    pub fn record(&mut self, event: BankAccountEvent) -> Result<(), ()> {
-        self.apply(event.clone()).unwrap();
+    #[test]
+    fn opening_records_opened_event() {
+        // Arrange
+        let mut agg = BankAccountAggregate::default();
 
-        use BankAccountAggregate::*;
-        match self {
-            Opened(_, x) => x.push(event),
-            Closed(_, x) => x.push(event),
-            Uninitialized => panic!("CANT RECORD ON UNINITIALIZED BANK ACC"),
-        }
+        // Act
+        agg.open(123, 5000).unwrap();
 
-        Ok(())
+        // Assert
+        assert_eq!(
+            vec![BankAccountEvent::opened(123, 5000)],
+            agg.get_new_events()
+        );
+        assert_eq!(1, agg.generation());
     }
 
-    pub fn get_new_events(&self) -> Vec<BankAccountEvent> {
-        use BankAccountAggregate::*;
-        match self {
-            Opened(_, x) => x.to_owned(),
-            Closed(_, x) => x.to_owned(),
-            Uninitialized => panic!("NO EVENTS ON UNINITIALIZED BANK ACC"),
-        }
+    #[test]
+    fn opening_an_opened_account_fails() {
+        // Arrange
+        let mut agg = BankAccountAggregate::default();
+        agg.open(123, 5000).unwrap();
+
+        // Act
+        let result = agg.open(123, 5000);
+
+        // Assert
+        assert_eq!(Err(EventError::AlreadyOpened), result);
+        assert_eq!(1, agg.get_new_events().len());
     }
 }
@@ -1,13 +1,14 @@
-use super::errors::CommandError;
+use super::errors::{CommandError, EventError};
 use super::types::{BankAccountId, CustomerId};
 use super::BankAccountAggregate;
 use crate::bank::account::events::BankAccountEvent;
 use eventsourcing::AggregateCommand;
+use std::convert::Infallible;
 
 pub struct BankAccountRepository {}
 
 impl BankAccountRepository {
-    pub fn save(&self, _events: Vec<BankAccountEvent>) -> Result<(), ()> {
+    pub fn save(&self, _events: Vec<BankAccountEvent>) -> Result<(), Infallible> {
         Ok(())
     }
 }
@@ -21,7 +22,7 @@ impl OpenBankAccountHandler {
         OpenBankAccountHandler { repository }
     }
 
-    pub fn handle(&self, cmd: OpenBankAccount) -> Result<(), ()> {
+    pub fn handle(&self, cmd: OpenBankAccount) -> Result<(), EventError> {
         // Create aggregate
         let mut agg = BankAccountAggregate::default();
         // Get events
@@ -37,7 +38,7 @@ impl OpenBankAccountHandler {
         println!("{:?}", &events);
 
         // Store events
-        self.repository.save(events)
+        self.repository.save(events).map_err(|never| match never {})
     }
 }
 
@@ -60,7 +61,7 @@ impl AggregateCommand<BankAccountAggregate> for OpenBankAccount {
     type Events = Vec<Self::Event>;
 
     fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
-        if let BankAccountAggregate::Opened(_, _) = aggregate {
+        if BankAccountAggregate::Uninitialized != *aggregate {
             return Err(CommandError::AlreadyCreated);
         }
 
@@ -76,44 +77,32 @@ mod tests {
     use crate::bank::account::prelude::{
         BankAccountAggregate, BankAccountEvent, BankAccountId, CustomerId, OpenBankAccount,
     };
-    use eventsourcing::Aggregate;
+    use eventsourcing::testing::given;
 
     const ACCOUNT_ID: BankAccountId = 123;
     const CUSTOMER_ID: CustomerId = 5000;
 
     #[test]
     fn open_bank_account_works() {
-        assert_open(
-            vec![],
-            OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID),
-            Ok(vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)]),
-        );
+        given::<BankAccountAggregate, BankAccountEvent>(vec![])
+            .when(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .then_expect(vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)]);
     }
 
     #[test]
     fn cant_open_already_opened_bank_account() {
-        assert_open(
-            vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)],
-            OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID),
-            Err(CommandError::AlreadyCreated),
-        );
+        given(vec![BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID)])
+            .when(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .then_error(CommandError::AlreadyCreated);
     }
 
-    fn assert_open(
-        intitial_events: Vec<BankAccountEvent>,
-        cmd: OpenBankAccount,
-        expected: Result<Vec<BankAccountEvent>, CommandError>,
-    ) {
-        // Arrange
-        let mut agg = BankAccountAggregate::default();
-        for event in intitial_events {
-            agg.apply(event).unwrap();
-        }
-
-        // Act
-        let result = agg.execute(cmd);
-
-        // Assert
-        assert_eq!(expected, result);
+    #[test]
+    fn cant_reopen_closed_bank_account() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::closed(ACCOUNT_ID),
+        ])
+        .when(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+        .then_error(CommandError::AlreadyCreated);
     }
 }
@@ -4,8 +4,12 @@ pub use super::events::BankAccountEvent;
 pub use super::open_bank_account::BankAccountRepository;
 pub use super::open_bank_account::OpenBankAccount;
 pub use super::open_bank_account::OpenBankAccountHandler;
+pub use super::transfer_money::TransferMoney;
+pub use super::types::stream_id;
 pub use super::types::BankAccountId;
 pub use super::types::CustomerId;
+pub use super::types::TransferId;
 pub use super::withdraw_money::WithdrawMoney;
 pub use super::BankAccountAggregate;
 pub use super::BankAccountState;
+pub use super::TransferStatus;
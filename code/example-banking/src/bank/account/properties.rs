@@ -0,0 +1,83 @@
+//! Invariants of bank accounts, checked against generated sequences of commands.
+
+use crate::bank::account::errors::CommandError;
+use crate::bank::account::prelude::*;
+use eventsourcing::testing::Properties;
+use eventsourcing::AggregateCommand;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+const ACCOUNT_ID: BankAccountId = 123;
+const CUSTOMER_ID: CustomerId = 5000;
+
+#[derive(Debug, Clone)]
+enum AccountCommand {
+    Open(OpenBankAccount),
+    Deposit(DepositMoney),
+    Withdraw(WithdrawMoney),
+    Close(CloseBankAccount),
+}
+
+impl AggregateCommand<BankAccountAggregate> for AccountCommand {
+    type Error = CommandError;
+    type Event = BankAccountEvent;
+    type Events = Vec<Self::Event>;
+
+    fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
+        match self {
+            AccountCommand::Open(cmd) => cmd.execute_on(aggregate),
+            AccountCommand::Deposit(cmd) => cmd.execute_on(aggregate),
+            AccountCommand::Withdraw(cmd) => cmd.execute_on(aggregate),
+            AccountCommand::Close(cmd) => cmd.execute_on(aggregate),
+        }
+    }
+}
+
+fn command() -> impl Strategy<Value = AccountCommand> {
+    prop_oneof![
+        Just(AccountCommand::Open(OpenBankAccount::new(
+            ACCOUNT_ID,
+            CUSTOMER_ID
+        ))),
+        (0..100u64)
+            .prop_map(|amount| AccountCommand::Deposit(DepositMoney::new(ACCOUNT_ID, amount))),
+        (0..100u64)
+            .prop_map(|amount| AccountCommand::Withdraw(WithdrawMoney::new(ACCOUNT_ID, amount))),
+        Just(AccountCommand::Close(CloseBankAccount::new(ACCOUNT_ID))),
+    ]
+}
+
+fn balance(account: &BankAccountAggregate) -> Option<u64> {
+    match account {
+        BankAccountAggregate::Opened(state, _) | BankAccountAggregate::Closed(state, _) => {
+            Some(state.balance)
+        }
+        BankAccountAggregate::Uninitialized => None,
+    }
+}
+
+#[test]
+fn bank_account_keeps_its_invariants() {
+    Properties::new()
+        .with_invariant("closed accounts are empty", |account| match account {
+            BankAccountAggregate::Closed(state, _) => state.balance == 0,
+            _ => true,
+        })
+        .with_transition("closed accounts take no money", |account, event| {
+            !matches!(
+                (account, event),
+                (
+                    BankAccountAggregate::Closed(..),
+                    BankAccountEvent::Credited(_)
+                )
+            )
+        })
+        .with_transition(
+            "debits never exceed the balance",
+            |account, event| match event {
+                BankAccountEvent::Debited(debited) => balance(account) >= Some(debited.amount),
+                _ => true,
+            },
+        )
+        .check(vec(command(), 0..40));
+}
@@ -0,0 +1,358 @@
+use super::events::BankAccountEvent;
+use super::read_models::{AccountSummary, BankingProjections, ProjectionResult, Transaction};
+use super::types::{BankAccountId, CustomerId};
+use super::BankAccountAggregate;
+use eventsourcing::eventstore::{GlobalEventStore, Position};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Page {
+    pub fn new(offset: usize, limit: usize) -> Page {
+        Page { offset, limit }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    /// Transactions of the account across all pages.
+    pub total: usize,
+}
+
+/// Answers questions about bank accounts from the read models, without loading aggregates.
+///
+/// Every query takes the position a command returned and first waits for the projection it
+/// reads from to get there; 0 reads whatever the projection has now.
+pub struct BankAccountQueries<ES> {
+    projections: Arc<BankingProjections<ES>>,
+    timeout: Duration,
+}
+
+impl<ES> BankAccountQueries<ES>
+where
+    ES: GlobalEventStore<BankAccountAggregate, BankAccountEvent>,
+{
+    pub fn new(projections: Arc<BankingProjections<ES>>) -> BankAccountQueries<ES> {
+        BankAccountQueries {
+            projections,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Longest a query waits for its projection before failing.
+    pub fn with_timeout(mut self, timeout: Duration) -> BankAccountQueries<ES> {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn account(
+        &self,
+        id: BankAccountId,
+        min_position: Position,
+    ) -> ProjectionResult<Option<AccountSummary>, ES> {
+        let runner = &self.projections.account_summary;
+        runner.wait_for(min_position, self.timeout)?;
+
+        Ok(runner
+            .read_model()
+            .read(|models| models.accounts.get(&id).cloned()))
+    }
+
+    /// Transactions of an account, oldest first.
+    pub fn transactions(
+        &self,
+        id: BankAccountId,
+        page: Page,
+        min_position: Position,
+    ) -> ProjectionResult<TransactionPage, ES> {
+        let runner = &self.projections.transaction_history;
+        runner.wait_for(min_position, self.timeout)?;
+
+        Ok(runner.read_model().read(|models| {
+            let history = models
+                .transactions
+                .get(&id)
+                .map(Vec::as_slice)
+                .unwrap_or(&[]);
+
+            TransactionPage {
+                transactions: history
+                    .iter()
+                    .skip(page.offset)
+                    .take(page.limit)
+                    .cloned()
+                    .collect(),
+                total: history.len(),
+            }
+        }))
+    }
+
+    pub fn customer_accounts(
+        &self,
+        customer_id: CustomerId,
+        min_position: Position,
+    ) -> ProjectionResult<Vec<BankAccountId>, ES> {
+        let runner = &self.projections.customer_accounts;
+        runner.wait_for(min_position, self.timeout)?;
+
+        Ok(runner.read_model().read(|models| {
+            models
+                .customer_accounts
+                .get(&customer_id)
+                .map(|accounts| accounts.iter().cloned().collect())
+                .unwrap_or_default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::prelude::*;
+    use crate::bank::account::queries::{BankAccountQueries, Page};
+    use crate::bank::account::read_models::*;
+    use crate::bank::account::service::BankAccountService;
+    use eventsourcing::eventstore::{EventStore, ExpectedVersion, InMemoryEventStore};
+    use eventsourcing::projection::{ProjectionError, ReadModelError};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type BankAccountEventStore = Arc<InMemoryEventStore<BankAccountAggregate, BankAccountEvent>>;
+
+    const ACCOUNT_ID: BankAccountId = 123;
+    const CUSTOMER_ID: CustomerId = 5000;
+
+    fn project(
+        events: Vec<(BankAccountId, BankAccountEvent)>,
+    ) -> BankAccountQueries<BankAccountEventStore> {
+        let event_store = Arc::new(InMemoryEventStore::new());
+        for (id, event) in events {
+            event_store
+                .append_events(&stream_id(id), vec![event], ExpectedVersion::Any)
+                .unwrap();
+        }
+        let projections =
+            BankingProjections::new(event_store, Arc::new(BankingReadModelStore::new()));
+        projections.account_summary.run_once().unwrap();
+        projections.transaction_history.run_once().unwrap();
+        projections.customer_accounts.run_once().unwrap();
+
+        BankAccountQueries::new(Arc::new(projections))
+    }
+
+    #[test]
+    fn account_summary_follows_balance_and_status() {
+        // Arrange
+        let queries = project(vec![
+            (
+                ACCOUNT_ID,
+                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            ),
+            (ACCOUNT_ID, BankAccountEvent::credited(ACCOUNT_ID, 50)),
+            (ACCOUNT_ID, BankAccountEvent::debited(ACCOUNT_ID, 50)),
+            (ACCOUNT_ID, BankAccountEvent::closed(ACCOUNT_ID)),
+        ]);
+
+        // Act
+        let result = queries.account(ACCOUNT_ID, 0).unwrap();
+
+        // Assert
+        assert_eq!(
+            Some(AccountSummary {
+                id: ACCOUNT_ID,
+                customer_id: CUSTOMER_ID,
+                balance: 0,
+                status: AccountStatus::Closed,
+                last_activity: Activity {
+                    position: 4,
+                    event_type: "closed",
+                },
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn transaction_history_keeps_running_balance_and_refusals() {
+        // Arrange
+        let queries = project(vec![
+            (
+                ACCOUNT_ID,
+                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            ),
+            (ACCOUNT_ID, BankAccountEvent::credited(ACCOUNT_ID, 50)),
+            (
+                ACCOUNT_ID,
+                BankAccountEvent::not_enough_funds(ACCOUNT_ID, 80, 50),
+            ),
+            (ACCOUNT_ID, BankAccountEvent::debited(ACCOUNT_ID, 20)),
+            (
+                ACCOUNT_ID,
+                BankAccountEvent::closing_failed_due_to_funds_available(ACCOUNT_ID, 30),
+            ),
+        ]);
+
+        // Act
+        let result = queries
+            .transactions(ACCOUNT_ID, Page::new(0, 10), 0)
+            .unwrap();
+
+        // Assert
+        let summary: Vec<_> = result
+            .transactions
+            .iter()
+            .map(|t| (t.kind, t.amount, t.balance))
+            .collect();
+        assert_eq!(
+            vec![
+                (TransactionKind::Credit, 50, 50),
+                (TransactionKind::RefusedWithdrawal, 80, 50),
+                (TransactionKind::Debit, 20, 30),
+                (TransactionKind::RefusedClose, 0, 30),
+            ],
+            summary
+        );
+        assert_eq!(4, result.total);
+    }
+
+    #[test]
+    fn transaction_history_is_paginated() {
+        // Arrange
+        let queries = project(vec![
+            (
+                ACCOUNT_ID,
+                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            ),
+            (ACCOUNT_ID, BankAccountEvent::credited(ACCOUNT_ID, 1)),
+            (ACCOUNT_ID, BankAccountEvent::credited(ACCOUNT_ID, 2)),
+            (ACCOUNT_ID, BankAccountEvent::credited(ACCOUNT_ID, 3)),
+        ]);
+
+        // Act
+        let result = queries
+            .transactions(ACCOUNT_ID, Page::new(1, 1), 0)
+            .unwrap();
+
+        // Assert
+        assert_eq!(1, result.transactions.len());
+        assert_eq!(2, result.transactions[0].amount);
+        assert_eq!(3, result.transactions[0].balance);
+        assert_eq!(3, result.total);
+    }
+
+    #[test]
+    fn customer_accounts_lists_every_opened_account() {
+        // Arrange
+        let queries = project(vec![
+            (1, BankAccountEvent::opened(1, CUSTOMER_ID)),
+            (2, BankAccountEvent::opened(2, 7)),
+            (3, BankAccountEvent::opened(3, CUSTOMER_ID)),
+        ]);
+
+        // Act
+        let result = queries.customer_accounts(CUSTOMER_ID, 0).unwrap();
+
+        // Assert
+        assert_eq!(vec![1, 3], result);
+        assert!(queries.customer_accounts(42, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_waits_for_position_returned_by_command() {
+        // Arrange
+        let event_store: BankAccountEventStore = Arc::new(InMemoryEventStore::new());
+        let service = BankAccountService::new(event_store.clone());
+        let projections = Arc::new(BankingProjections::new(
+            event_store,
+            Arc::new(BankingReadModelStore::new()),
+        ));
+        let _running = projections.spawn(Duration::from_millis(1));
+        let queries = BankAccountQueries::new(projections);
+        service
+            .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+
+        // Act
+        let position = service.handle(DepositMoney::new(ACCOUNT_ID, 49)).unwrap();
+        let result = queries.account(ACCOUNT_ID, position).unwrap();
+
+        // Assert
+        assert_eq!(49, result.unwrap().balance);
+    }
+
+    #[test]
+    fn projections_refuse_debit_beyond_balance() {
+        // Arrange
+        let event_store: BankAccountEventStore = Arc::new(InMemoryEventStore::new());
+        event_store
+            .append_events(
+                &stream_id(ACCOUNT_ID),
+                vec![
+                    BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+                    BankAccountEvent::debited(ACCOUNT_ID, 1),
+                ],
+                ExpectedVersion::Any,
+            )
+            .unwrap();
+        let projections =
+            BankingProjections::new(event_store, Arc::new(BankingReadModelStore::new()));
+
+        // Act
+        let summary = projections.account_summary.run_once();
+        let history = projections.transaction_history.run_once();
+
+        // Assert
+        let expected = Err(ProjectionError::ReadModel(ReadModelError::Projection(
+            format!("debit of 1 exceeds balance 0 of account {}", ACCOUNT_ID),
+        )));
+        assert_eq!(expected, summary.map(|_| ()));
+        assert_eq!(expected, history.map(|_| ()));
+    }
+
+    #[test]
+    fn query_fails_when_projection_does_not_catch_up_in_time() {
+        // Arrange
+        let event_store: BankAccountEventStore = Arc::new(InMemoryEventStore::new());
+        let service = BankAccountService::new(event_store.clone());
+        let projections = Arc::new(BankingProjections::new(
+            event_store,
+            Arc::new(BankingReadModelStore::new()),
+        ));
+        let queries =
+            BankAccountQueries::new(projections.clone()).with_timeout(Duration::from_millis(10));
+        let position = service
+            .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+
+        // Act
+        let result = queries.account(ACCOUNT_ID, position);
+
+        // Assert
+        assert_eq!(
+            Err(ProjectionError::Timeout {
+                position: 0,
+                min_position: position,
+            }),
+            result
+        );
+        let lag: Vec<_> = projections
+            .lag()
+            .unwrap()
+            .into_iter()
+            .map(|lag| (lag.projection, lag.behind))
+            .collect();
+        assert_eq!(
+            vec![
+                ("account_summary".to_owned(), 1),
+                ("transaction_history".to_owned(), 1),
+                ("customer_accounts".to_owned(), 1),
+            ],
+            lag
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use super::events::BankAccountEvent;
+use super::types::{BankAccountId, CustomerId};
+use super::BankAccountAggregate;
+use eventsourcing::eventstore::{EventEnvelope, EventStore, GlobalEventStore, Position};
+use eventsourcing::projection::{
+    InMemoryReadModelStore, Projection, ProjectionError, ProjectionLag, ProjectionRunner,
+    ReadModelError, RunningProjection, Subscription,
+};
+use eventsourcing::{Aggregate, Event};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type BankingReadModelStore = InMemoryReadModelStore<BankingReadModels>;
+
+/// Every read model of the banking example, updated by the projections below.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BankingReadModels {
+    pub accounts: HashMap<BankAccountId, AccountSummary>,
+    pub transactions: HashMap<BankAccountId, Vec<Transaction>>,
+    pub customer_accounts: HashMap<CustomerId, BTreeSet<BankAccountId>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Open,
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub id: BankAccountId,
+    pub customer_id: CustomerId,
+    pub balance: u64,
+    pub status: AccountStatus,
+    pub last_activity: Activity,
+}
+
+/// The last event recorded on an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    pub position: Position,
+    pub event_type: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Credit,
+    Debit,
+    RefusedWithdrawal,
+    RefusedClose,
+    TransferOut,
+    TransferIn,
+    /// A transfer that left the account was paid back.
+    TransferRefund,
+    RefusedTransfer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transaction {
+    pub position: Position,
+    pub kind: TransactionKind,
+    /// Zero for a refused close or transfer, which moves no money.
+    pub amount: u64,
+    /// Balance once this transaction was recorded; refused ones leave it unchanged.
+    pub balance: u64,
+}
+
+fn subscription() -> Subscription {
+    Subscription::Category(BankAccountAggregate::aggregate_type().to_owned())
+}
+
+/// Keeps balance, status, customer and last activity of every account.
+pub struct AccountSummaryProjection;
+
+impl Projection<BankAccountEvent, BankingReadModelStore> for AccountSummaryProjection {
+    fn name(&self) -> &str {
+        "account_summary"
+    }
+
+    fn subscription(&self) -> Subscription {
+        subscription()
+    }
+
+    fn handle(
+        &self,
+        models: &mut BankingReadModels,
+        envelope: &EventEnvelope<BankAccountEvent>,
+    ) -> Result<(), ReadModelError> {
+        let activity = Activity {
+            position: envelope.position,
+            event_type: envelope.event.event_type(),
+        };
+
+        if let BankAccountEvent::Opened(ref evt) = envelope.event {
+            models.accounts.insert(
+                evt.id,
+                AccountSummary {
+                    id: evt.id,
+                    customer_id: evt.customer_id,
+                    balance: 0,
+                    status: AccountStatus::Open,
+                    last_activity: activity,
+                },
+            );
+            return Ok(());
+        }
+
+        let id = account_id(&envelope.event);
+        let summary = models
+            .accounts
+            .get_mut(&id)
+            .ok_or_else(|| unknown_account(id))?;
+
+        match envelope.event {
+            BankAccountEvent::Credited(ref evt) => summary.balance += evt.amount,
+            BankAccountEvent::Debited(ref evt) => {
+                summary.balance = debit(id, summary.balance, evt.amount)?
+            }
+            BankAccountEvent::TransferInitiated(ref evt) => {
+                summary.balance = debit(id, summary.balance, evt.amount)?
+            }
+            BankAccountEvent::TransferReceived(ref evt) => summary.balance += evt.amount,
+            BankAccountEvent::TransferFailed(ref evt) => summary.balance += evt.refunded,
+            BankAccountEvent::Closed(_) => summary.status = AccountStatus::Closed,
+            _ => (),
+        }
+        summary.last_activity = activity;
+
+        Ok(())
+    }
+
+    fn reset(&self, models: &mut BankingReadModels) -> Result<(), ReadModelError> {
+        models.accounts.clear();
+        Ok(())
+    }
+}
+
+/// Records money movements and refused operations with the balance after each of them.
+pub struct TransactionHistoryProjection;
+
+impl Projection<BankAccountEvent, BankingReadModelStore> for TransactionHistoryProjection {
+    fn name(&self) -> &str {
+        "transaction_history"
+    }
+
+    fn subscription(&self) -> Subscription {
+        subscription()
+    }
+
+    fn handle(
+        &self,
+        models: &mut BankingReadModels,
+        envelope: &EventEnvelope<BankAccountEvent>,
+    ) -> Result<(), ReadModelError> {
+        let id = account_id(&envelope.event);
+        let history = models.transactions.entry(id).or_default();
+        let balance = history.last().map(|t| t.balance).unwrap_or(0);
+
+        let (kind, amount, balance) = match envelope.event {
+            BankAccountEvent::Credited(ref evt) => {
+                (TransactionKind::Credit, evt.amount, balance + evt.amount)
+            }
+            BankAccountEvent::Debited(ref evt) => (
+                TransactionKind::Debit,
+                evt.amount,
+                debit(id, balance, evt.amount)?,
+            ),
+            BankAccountEvent::NotEnoughFunds(ref evt) => {
+                (TransactionKind::RefusedWithdrawal, evt.amount, balance)
+            }
+            BankAccountEvent::ClosingFailedDueToFundsAvailable(_) => {
+                (TransactionKind::RefusedClose, 0, balance)
+            }
+            BankAccountEvent::TransferInitiated(ref evt) => (
+                TransactionKind::TransferOut,
+                evt.amount,
+                debit(id, balance, evt.amount)?,
+            ),
+            BankAccountEvent::TransferReceived(ref evt) => (
+                TransactionKind::TransferIn,
+                evt.amount,
+                balance + evt.amount,
+            ),
+            BankAccountEvent::TransferFailed(ref evt) if evt.refunded > 0 => (
+                TransactionKind::TransferRefund,
+                evt.refunded,
+                balance + evt.refunded,
+            ),
+            BankAccountEvent::TransferFailed(_) => (TransactionKind::RefusedTransfer, 0, balance),
+            BankAccountEvent::Opened(_)
+            | BankAccountEvent::Closed(_)
+            | BankAccountEvent::TransferCompleted(_) => return Ok(()),
+        };
+
+        history.push(Transaction {
+            position: envelope.position,
+            kind,
+            amount,
+            balance,
+        });
+
+        Ok(())
+    }
+
+    fn reset(&self, models: &mut BankingReadModels) -> Result<(), ReadModelError> {
+        models.transactions.clear();
+        Ok(())
+    }
+}
+
+/// Lists the accounts opened by every customer.
+pub struct CustomerAccountsProjection;
+
+impl Projection<BankAccountEvent, BankingReadModelStore> for CustomerAccountsProjection {
+    fn name(&self) -> &str {
+        "customer_accounts"
+    }
+
+    fn subscription(&self) -> Subscription {
+        subscription()
+    }
+
+    fn handle(
+        &self,
+        models: &mut BankingReadModels,
+        envelope: &EventEnvelope<BankAccountEvent>,
+    ) -> Result<(), ReadModelError> {
+        if let BankAccountEvent::Opened(ref evt) = envelope.event {
+            models
+                .customer_accounts
+                .entry(evt.customer_id)
+                .or_default()
+                .insert(evt.id);
+        }
+
+        Ok(())
+    }
+
+    fn reset(&self, models: &mut BankingReadModels) -> Result<(), ReadModelError> {
+        models.customer_accounts.clear();
+        Ok(())
+    }
+}
+
+fn account_id(event: &BankAccountEvent) -> BankAccountId {
+    match event {
+        BankAccountEvent::Opened(evt) => evt.id,
+        BankAccountEvent::Credited(evt) => evt.id,
+        BankAccountEvent::Debited(evt) => evt.id,
+        BankAccountEvent::NotEnoughFunds(evt) => evt.id,
+        BankAccountEvent::Closed(evt) => evt.id,
+        BankAccountEvent::ClosingFailedDueToFundsAvailable(evt) => evt.id,
+        BankAccountEvent::TransferInitiated(evt) => evt.id,
+        BankAccountEvent::TransferReceived(evt) => evt.id,
+        BankAccountEvent::TransferCompleted(evt) => evt.id,
+        BankAccountEvent::TransferFailed(evt) => evt.id,
+    }
+}
+
+fn unknown_account(id: BankAccountId) -> ReadModelError {
+    ReadModelError::Projection(format!("event for unknown account {}", id))
+}
+
+fn debit(id: BankAccountId, balance: u64, amount: u64) -> Result<u64, ReadModelError> {
+    balance.checked_sub(amount).ok_or_else(|| {
+        ReadModelError::Projection(format!(
+            "debit of {} exceeds balance {} of account {}",
+            amount, balance, id
+        ))
+    })
+}
+
+pub type BankingProjectionRunner<ES, P> =
+    ProjectionRunner<BankAccountAggregate, BankAccountEvent, ES, BankingReadModelStore, P>;
+
+/// Runners of every banking projection, all writing to the same read model store.
+pub struct BankingProjections<ES> {
+    pub account_summary: Arc<BankingProjectionRunner<ES, AccountSummaryProjection>>,
+    pub transaction_history: Arc<BankingProjectionRunner<ES, TransactionHistoryProjection>>,
+    pub customer_accounts: Arc<BankingProjectionRunner<ES, CustomerAccountsProjection>>,
+}
+
+pub type ProjectionResult<T, ES> =
+    Result<T, ProjectionError<<ES as EventStore<BankAccountAggregate, BankAccountEvent>>::Error>>;
+
+pub type RunningBankingProjection<ES> =
+    RunningProjection<<ES as EventStore<BankAccountAggregate, BankAccountEvent>>::Error>;
+
+impl<ES> BankingProjections<ES>
+where
+    ES: GlobalEventStore<BankAccountAggregate, BankAccountEvent> + Clone,
+{
+    pub fn new(event_store: ES, store: Arc<BankingReadModelStore>) -> BankingProjections<ES> {
+        BankingProjections {
+            account_summary: Arc::new(ProjectionRunner::new(
+                event_store.clone(),
+                store.clone(),
+                AccountSummaryProjection,
+            )),
+            transaction_history: Arc::new(ProjectionRunner::new(
+                event_store.clone(),
+                store.clone(),
+                TransactionHistoryProjection,
+            )),
+            customer_accounts: Arc::new(ProjectionRunner::new(
+                event_store,
+                store,
+                CustomerAccountsProjection,
+            )),
+        }
+    }
+
+    pub fn lag(&self) -> ProjectionResult<Vec<ProjectionLag>, ES> {
+        Ok(vec![
+            self.account_summary.lag()?,
+            self.transaction_history.lag()?,
+            self.customer_accounts.lag()?,
+        ])
+    }
+}
+
+impl<ES> BankingProjections<ES>
+where
+    ES: GlobalEventStore<BankAccountAggregate, BankAccountEvent> + Clone + Send + Sync + 'static,
+{
+    pub fn spawn(&self, poll_interval: Duration) -> Vec<RunningBankingProjection<ES>> {
+        vec![
+            ProjectionRunner::spawn(self.account_summary.clone(), poll_interval),
+            ProjectionRunner::spawn(self.transaction_history.clone(), poll_interval),
+            ProjectionRunner::spawn(self.customer_accounts.clone(), poll_interval),
+        ]
+    }
+}
@@ -0,0 +1,186 @@
+use super::close_bank_account::CloseBankAccount;
+use super::deposit_money::DepositMoney;
+use super::errors::{CommandError, EventError};
+use super::events::BankAccountEvent;
+use super::open_bank_account::OpenBankAccount;
+use super::transfer_money::{CompleteTransfer, FailTransfer, ReceiveTransfer, TransferMoney};
+use super::types::{stream_id, BankAccountId};
+use super::withdraw_money::WithdrawMoney;
+use super::BankAccountAggregate;
+use eventsourcing::eventstore::{GlobalEventStore, Position};
+use eventsourcing::repository::{EventSourcedRepository, Repository, RepositoryError};
+use eventsourcing::snapshot::SnapshotStoreError;
+use eventsourcing::{Aggregate, AggregateCommand};
+use std::{error::Error, fmt};
+
+/// A command addressed to a single bank account.
+pub trait BankAccountCommand:
+    AggregateCommand<BankAccountAggregate, Event = BankAccountEvent, Error = CommandError>
+{
+    fn account_id(&self) -> BankAccountId;
+}
+
+impl BankAccountCommand for OpenBankAccount {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for DepositMoney {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for WithdrawMoney {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for CloseBankAccount {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for TransferMoney {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for ReceiveTransfer {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for CompleteTransfer {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+impl BankAccountCommand for FailTransfer {
+    fn account_id(&self) -> BankAccountId {
+        self.id
+    }
+}
+
+/// Runs bank account commands against the event store.
+pub struct BankAccountService<ES> {
+    repository: EventSourcedRepository<BankAccountAggregate, BankAccountEvent, ES>,
+}
+
+impl<ES> BankAccountService<ES>
+where
+    ES: GlobalEventStore<BankAccountAggregate, BankAccountEvent>,
+{
+    pub fn new(event_store: ES) -> BankAccountService<ES> {
+        BankAccountService {
+            repository: EventSourcedRepository::new(event_store),
+        }
+    }
+
+    pub fn account(
+        &self,
+        id: BankAccountId,
+    ) -> Result<BankAccountAggregate, ServiceError<ES::Error>> {
+        Ok(self.repository.load(&stream_id(id))?)
+    }
+
+    /// Returns the position of the last event the command recorded, to be passed to queries
+    /// that have to reflect it. A command that recorded nothing returns the position of the
+    /// account's last event, 0 when there is none.
+    pub fn handle<C>(&self, command: C) -> Result<Position, ServiceError<ES::Error>>
+    where
+        C: BankAccountCommand,
+    {
+        let id = stream_id(command.account_id());
+        let aggregate = self.repository.load(&id)?;
+        let events: Vec<_> = aggregate
+            .execute(command)
+            .map_err(ServiceError::Command)?
+            .into_iter()
+            .collect();
+
+        let aggregate = self.repository.save(&id, aggregate, events)?;
+
+        let position = self
+            .repository
+            .event_store()
+            .stream_position(&id, aggregate.generation())
+            .map_err(|err| ServiceError::Repository(RepositoryError::EventStore(err)))?;
+
+        Ok(position.unwrap_or(0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError<ES> {
+    Command(CommandError),
+    Repository(RepositoryError<ES, SnapshotStoreError, EventError>),
+}
+
+impl<ES> From<RepositoryError<ES, SnapshotStoreError, EventError>> for ServiceError<ES> {
+    fn from(err: RepositoryError<ES, SnapshotStoreError, EventError>) -> ServiceError<ES> {
+        ServiceError::Repository(err)
+    }
+}
+
+impl<ES> Error for ServiceError<ES> where ES: fmt::Debug + fmt::Display {}
+
+impl<ES> fmt::Display for ServiceError<ES>
+where
+    ES: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceError::Command(err) => write!(f, "ServiceError: {}", err),
+            ServiceError::Repository(err) => write!(f, "ServiceError: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::errors::CommandError;
+    use crate::bank::account::prelude::*;
+    use crate::bank::account::service::{BankAccountService, ServiceError};
+    use eventsourcing::eventstore::InMemoryEventStore;
+    use std::sync::Arc;
+
+    const ACCOUNT_ID: BankAccountId = 123;
+    const CUSTOMER_ID: CustomerId = 5000;
+
+    #[test]
+    fn handle_returns_position_of_recorded_event() {
+        // Arrange
+        let service = BankAccountService::new(Arc::new(InMemoryEventStore::new()));
+        service
+            .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+            .unwrap();
+        service
+            .handle(OpenBankAccount::new(1, CUSTOMER_ID))
+            .unwrap();
+
+        // Act
+        let result = service.handle(DepositMoney::new(ACCOUNT_ID, 49));
+
+        // Assert
+        assert_eq!(Ok(3), result);
+    }
+
+    #[test]
+    fn handle_reports_refused_command() {
+        // Arrange
+        let service = BankAccountService::new(Arc::new(InMemoryEventStore::new()));
+
+        // Act
+        let result = service.handle(DepositMoney::new(ACCOUNT_ID, 49));
+
+        // Assert
+        assert_eq!(Err(ServiceError::Command(CommandError::NotOpened)), result);
+    }
+}
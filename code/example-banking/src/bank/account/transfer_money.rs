@@ -0,0 +1,297 @@
+use super::errors::CommandError;
+use super::events::{BankAccountEvent, TransferFailureReason};
+use super::types::{BankAccountId, TransferId};
+use super::{BankAccountAggregate, BankAccountState, TransferStatus};
+use eventsourcing::AggregateCommand;
+
+/// Moves money from account `id` to `target`. Does nothing when the account already knows the
+/// transfer id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferMoney {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub target: BankAccountId,
+    pub amount: u64,
+}
+
+impl TransferMoney {
+    pub fn new(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        target: BankAccountId,
+        amount: u64,
+    ) -> TransferMoney {
+        TransferMoney {
+            id,
+            transfer_id,
+            target,
+            amount,
+        }
+    }
+}
+
+impl AggregateCommand<BankAccountAggregate> for TransferMoney {
+    type Error = CommandError;
+    type Event = BankAccountEvent;
+    type Events = Vec<Self::Event>;
+
+    fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
+        if let BankAccountAggregate::Opened(ref data, _) = aggregate {
+            if data.transfers.contains_key(&(self.id, self.transfer_id)) {
+                return Ok(vec![]);
+            }
+
+            let refused = if self.target == self.id {
+                Some(TransferFailureReason::SameAccount)
+            } else if data.balance < self.amount {
+                Some(TransferFailureReason::InsufficientFunds)
+            } else {
+                None
+            };
+
+            Ok(vec![match refused {
+                Some(reason) => {
+                    BankAccountEvent::transfer_failed(self.id, self.transfer_id, reason, 0)
+                }
+                None => BankAccountEvent::transfer_initiated(
+                    self.id,
+                    self.transfer_id,
+                    self.target,
+                    self.amount,
+                ),
+            }])
+        } else {
+            Err(CommandError::NotOpened)
+        }
+    }
+}
+
+/// Credits the target account `id` with a transfer, once per source account and transfer id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiveTransfer {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub source: BankAccountId,
+    pub amount: u64,
+}
+
+impl ReceiveTransfer {
+    pub fn new(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        source: BankAccountId,
+        amount: u64,
+    ) -> ReceiveTransfer {
+        ReceiveTransfer {
+            id,
+            transfer_id,
+            source,
+            amount,
+        }
+    }
+}
+
+impl AggregateCommand<BankAccountAggregate> for ReceiveTransfer {
+    type Error = CommandError;
+    type Event = BankAccountEvent;
+    type Events = Vec<Self::Event>;
+
+    fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
+        if let BankAccountAggregate::Opened(ref data, _) = aggregate {
+            if data
+                .transfers
+                .contains_key(&(self.source, self.transfer_id))
+            {
+                return Ok(vec![]);
+            }
+
+            Ok(vec![BankAccountEvent::transfer_received(
+                self.id,
+                self.transfer_id,
+                self.source,
+                self.amount,
+            )])
+        } else {
+            Err(CommandError::NotOpened)
+        }
+    }
+}
+
+/// Marks a transfer the source account `id` initiated as received by its target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompleteTransfer {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+}
+
+impl CompleteTransfer {
+    pub fn new(id: BankAccountId, transfer_id: TransferId) -> CompleteTransfer {
+        CompleteTransfer { id, transfer_id }
+    }
+}
+
+impl AggregateCommand<BankAccountAggregate> for CompleteTransfer {
+    type Error = CommandError;
+    type Event = BankAccountEvent;
+    type Events = Vec<Self::Event>;
+
+    fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
+        if initiated(aggregate, self.transfer_id)? {
+            Ok(vec![BankAccountEvent::transfer_completed(
+                self.id,
+                self.transfer_id,
+            )])
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+/// Gives up on a transfer the source account `id` initiated and pays the amount back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailTransfer {
+    pub id: BankAccountId,
+    pub transfer_id: TransferId,
+    pub amount: u64,
+    pub reason: TransferFailureReason,
+}
+
+impl FailTransfer {
+    pub fn new(
+        id: BankAccountId,
+        transfer_id: TransferId,
+        amount: u64,
+        reason: TransferFailureReason,
+    ) -> FailTransfer {
+        FailTransfer {
+            id,
+            transfer_id,
+            amount,
+            reason,
+        }
+    }
+}
+
+impl AggregateCommand<BankAccountAggregate> for FailTransfer {
+    type Error = CommandError;
+    type Event = BankAccountEvent;
+    type Events = Vec<Self::Event>;
+
+    fn execute_on(self, aggregate: &BankAccountAggregate) -> Result<Self::Events, Self::Error> {
+        if initiated(aggregate, self.transfer_id)? {
+            Ok(vec![BankAccountEvent::transfer_failed(
+                self.id,
+                self.transfer_id,
+                self.reason,
+                self.amount,
+            )])
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+/// Whether the transfer left the account and is still waiting for its outcome.
+fn initiated(
+    aggregate: &BankAccountAggregate,
+    transfer_id: TransferId,
+) -> Result<bool, CommandError> {
+    let state: &BankAccountState = match aggregate {
+        BankAccountAggregate::Opened(data, _) | BankAccountAggregate::Closed(data, _) => data,
+        BankAccountAggregate::Uninitialized => return Err(CommandError::NotOpened),
+    };
+
+    Ok(state.transfers.get(&(state.id, transfer_id)) == Some(&TransferStatus::Initiated))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::errors::CommandError;
+    use crate::bank::account::events::TransferFailureReason;
+    use crate::bank::account::prelude::*;
+    use crate::bank::account::transfer_money::{FailTransfer, ReceiveTransfer};
+    use eventsourcing::testing::given;
+
+    const ACCOUNT_ID: BankAccountId = 123;
+    const TARGET_ID: BankAccountId = 456;
+    const CUSTOMER_ID: CustomerId = 5000;
+    const TRANSFER_ID: TransferId = 1;
+
+    #[test]
+    fn transfer_debits_source() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 50),
+        ])
+        .when(TransferMoney::new(ACCOUNT_ID, TRANSFER_ID, TARGET_ID, 49))
+        .then_expect(vec![BankAccountEvent::transfer_initiated(
+            ACCOUNT_ID,
+            TRANSFER_ID,
+            TARGET_ID,
+            49,
+        )]);
+    }
+
+    #[test]
+    fn transfer_fails_on_insufficient_funds() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 48),
+        ])
+        .when(TransferMoney::new(ACCOUNT_ID, TRANSFER_ID, TARGET_ID, 49))
+        .then_expect(vec![BankAccountEvent::transfer_failed(
+            ACCOUNT_ID,
+            TRANSFER_ID,
+            TransferFailureReason::InsufficientFunds,
+            0,
+        )]);
+    }
+
+    #[test]
+    fn transfer_with_known_id_does_nothing() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 50),
+            BankAccountEvent::transfer_initiated(ACCOUNT_ID, TRANSFER_ID, TARGET_ID, 49),
+        ])
+        .when(TransferMoney::new(ACCOUNT_ID, TRANSFER_ID, TARGET_ID, 49))
+        .then_expect(vec![]);
+    }
+
+    #[test]
+    fn closed_account_does_not_receive_transfer() {
+        given(vec![
+            BankAccountEvent::opened(TARGET_ID, CUSTOMER_ID),
+            BankAccountEvent::closed(TARGET_ID),
+        ])
+        .when(ReceiveTransfer::new(TARGET_ID, TRANSFER_ID, ACCOUNT_ID, 49))
+        .then_error(CommandError::NotOpened);
+    }
+
+    #[test]
+    fn transfer_is_received_once() {
+        given(vec![
+            BankAccountEvent::opened(TARGET_ID, CUSTOMER_ID),
+            BankAccountEvent::transfer_received(TARGET_ID, TRANSFER_ID, ACCOUNT_ID, 49),
+        ])
+        .when(ReceiveTransfer::new(TARGET_ID, TRANSFER_ID, ACCOUNT_ID, 49))
+        .then_expect(vec![]);
+    }
+
+    #[test]
+    fn completed_transfer_cannot_fail() {
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 50),
+            BankAccountEvent::transfer_initiated(ACCOUNT_ID, TRANSFER_ID, TARGET_ID, 49),
+            BankAccountEvent::transfer_completed(ACCOUNT_ID, TRANSFER_ID),
+        ])
+        .when(FailTransfer::new(
+            ACCOUNT_ID,
+            TRANSFER_ID,
+            49,
+            TransferFailureReason::TargetUnavailable,
+        ))
+        .then_expect(vec![]);
+    }
+}
@@ -0,0 +1,543 @@
+//! Money transfers between accounts.
+//!
+//! `TransferMoney` debits the source account with `TransferInitiated`. The transfer process
+//! then credits the target and completes the transfer on the source, or pays the amount back
+//! with `TransferFailed` when the target is closed or missing. Every step is idempotent per
+//! transfer id, so commands the process sends again after a crash change nothing twice.
+
+use super::errors::EventError;
+use super::events::{BankAccountEvent, TransferFailureReason, TransferInitiated};
+use super::service::{BankAccountService, ServiceError};
+use super::transfer_money::{CompleteTransfer, FailTransfer, ReceiveTransfer};
+use super::BankAccountAggregate;
+use eventsourcing::eventstore::{EventEnvelope, GlobalEventStore};
+use eventsourcing::handler::CommandHandler;
+use eventsourcing::process::{
+    ProcessEventOf, ProcessInstanceOf, ProcessManager, ProcessManagerRunner, Step,
+};
+use eventsourcing::repository::RepositoryError;
+use eventsourcing::{Aggregate, AggregateEvent, Event};
+use std::sync::Arc;
+
+/// What the process knows about the transfer it moves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferState {
+    pub transfer: Option<TransferInitiated>,
+    generation: u64,
+}
+
+impl Aggregate for TransferState {
+    fn aggregate_type() -> &'static str {
+        "Transfer"
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn increment_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Event)]
+pub struct TransferStarted(pub TransferInitiated);
+
+impl AggregateEvent<TransferState> for TransferStarted {
+    type Error = EventError;
+
+    fn apply_to(self, state: &mut TransferState) -> Result<(), Self::Error> {
+        state.transfer = Some(self.0);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferCommand {
+    Receive(ReceiveTransfer),
+    Complete(CompleteTransfer),
+    Fail(FailTransfer),
+}
+
+/// Moves the money of every initiated transfer to its target, one instance per source account and
+/// transfer id.
+pub struct TransferProcess;
+
+impl ProcessManager<BankAccountEvent> for TransferProcess {
+    type State = TransferState;
+    type Event = TransferStarted;
+    type Command = TransferCommand;
+
+    fn name(&self) -> &str {
+        "Transfer"
+    }
+
+    fn correlate(&self, envelope: &EventEnvelope<BankAccountEvent>) -> Option<String> {
+        match envelope.event {
+            BankAccountEvent::TransferInitiated(ref evt) => {
+                Some(format!("{}-{}", evt.id, evt.transfer_id))
+            }
+            BankAccountEvent::TransferReceived(ref evt) => {
+                Some(format!("{}-{}", evt.source, evt.transfer_id))
+            }
+            _ => None,
+        }
+    }
+
+    fn handle(
+        &self,
+        state: &TransferState,
+        envelope: &EventEnvelope<BankAccountEvent>,
+    ) -> Vec<Step<TransferStarted, TransferCommand>> {
+        match (&state.transfer, &envelope.event) {
+            (None, BankAccountEvent::TransferInitiated(evt)) => vec![
+                Step::Record(TransferStarted(evt.clone())),
+                Step::Issue(TransferCommand::Receive(ReceiveTransfer::new(
+                    evt.target,
+                    evt.transfer_id,
+                    evt.id,
+                    evt.amount,
+                ))),
+            ],
+            (Some(transfer), BankAccountEvent::TransferReceived(_)) => vec![
+                Step::Issue(TransferCommand::Complete(CompleteTransfer::new(
+                    transfer.id,
+                    transfer.transfer_id,
+                ))),
+                Step::Complete,
+            ],
+            _ => vec![],
+        }
+    }
+
+    fn timeout(
+        &self,
+        _state: &TransferState,
+        _name: &str,
+    ) -> Vec<Step<TransferStarted, TransferCommand>> {
+        vec![]
+    }
+
+    fn compensate(
+        &self,
+        state: &TransferState,
+        command: &TransferCommand,
+        _error: &str,
+    ) -> Vec<Step<TransferStarted, TransferCommand>> {
+        match (command, &state.transfer) {
+            (TransferCommand::Receive(_), Some(transfer)) => vec![
+                Step::Issue(TransferCommand::Fail(FailTransfer::new(
+                    transfer.id,
+                    transfer.transfer_id,
+                    transfer.amount,
+                    TransferFailureReason::TargetUnavailable,
+                ))),
+                Step::Complete,
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+/// Attempts made to run a transfer command that keeps losing races with other writers of the
+/// account before it counts as refused.
+const ATTEMPTS: usize = 3;
+
+impl<ES> CommandHandler<TransferCommand> for BankAccountService<ES>
+where
+    ES: GlobalEventStore<BankAccountAggregate, BankAccountEvent>,
+{
+    type Error = ServiceError<ES::Error>;
+
+    fn handle(&self, command: TransferCommand) -> Result<(), Self::Error> {
+        let mut attempt = 1;
+
+        loop {
+            let result = match command.clone() {
+                TransferCommand::Receive(cmd) => BankAccountService::handle(self, cmd),
+                TransferCommand::Complete(cmd) => BankAccountService::handle(self, cmd),
+                TransferCommand::Fail(cmd) => BankAccountService::handle(self, cmd),
+            };
+
+            match result {
+                Err(ServiceError::Repository(_)) if attempt < ATTEMPTS => attempt += 1,
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
+    /// Failures of the stores may go away; refusals and events that don't apply won't.
+    fn is_transient(error: &Self::Error) -> bool {
+        matches!(
+            error,
+            ServiceError::Repository(RepositoryError::EventStore(_))
+                | ServiceError::Repository(RepositoryError::SnapshotStore(_))
+        )
+    }
+}
+
+pub type TransferInstance = ProcessInstanceOf<BankAccountEvent, TransferProcess>;
+pub type TransferProcessEvent = ProcessEventOf<BankAccountEvent, TransferProcess>;
+
+/// Runs transfers of the accounts in `ES`, keeping the transfer instances in `PS`.
+pub type TransferRunner<ES, PS> = ProcessManagerRunner<
+    BankAccountAggregate,
+    BankAccountEvent,
+    ES,
+    PS,
+    TransferProcess,
+    Arc<BankAccountService<ES>>,
+>;
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::account::errors::CommandError;
+    use crate::bank::account::events::{TransferFailureReason, TransferInitiated};
+    use crate::bank::account::prelude::*;
+    use crate::bank::account::service::{BankAccountService, ServiceError};
+    use crate::bank::account::transfer_money::ReceiveTransfer;
+    use crate::bank::account::transfers::{
+        TransferCommand, TransferInstance, TransferProcess, TransferProcessEvent, TransferRunner,
+        TransferStarted,
+    };
+    use eventsourcing::eventstore::{EventStore, ExpectedVersion, InMemoryEventStore};
+    use eventsourcing::process::ProcessEvent;
+    use eventsourcing::testing::HandlerFixture;
+    use std::sync::Arc;
+
+    type BankAccountEventStore = Arc<InMemoryEventStore<BankAccountAggregate, BankAccountEvent>>;
+    type TransferStore = Arc<InMemoryEventStore<TransferInstance, TransferProcessEvent>>;
+
+    const SOURCE_ID: BankAccountId = 123;
+    const TARGET_ID: BankAccountId = 456;
+    const OTHER_SOURCE_ID: BankAccountId = 789;
+    const CUSTOMER_ID: CustomerId = 5000;
+    const TRANSFER_ID: TransferId = 1;
+
+    struct Fixture {
+        event_store: BankAccountEventStore,
+        service: Arc<BankAccountService<BankAccountEventStore>>,
+        process_store: TransferStore,
+    }
+
+    impl Fixture {
+        fn new(source_balance: u64) -> Fixture {
+            let event_store = Arc::new(InMemoryEventStore::new());
+            let service = Arc::new(BankAccountService::new(event_store.clone()));
+            service
+                .handle(OpenBankAccount::new(SOURCE_ID, CUSTOMER_ID))
+                .unwrap();
+            service
+                .handle(DepositMoney::new(SOURCE_ID, source_balance))
+                .unwrap();
+
+            Fixture {
+                event_store,
+                service,
+                process_store: Arc::new(InMemoryEventStore::new()),
+            }
+        }
+
+        fn runner(&self) -> TransferRunner<BankAccountEventStore, TransferStore> {
+            TransferRunner::new(
+                self.event_store.clone(),
+                self.process_store.clone(),
+                TransferProcess,
+                self.service.clone(),
+            )
+        }
+
+        fn transfer(&self, amount: u64) -> u64 {
+            self.service
+                .handle(TransferMoney::new(
+                    SOURCE_ID,
+                    TRANSFER_ID,
+                    TARGET_ID,
+                    amount,
+                ))
+                .unwrap()
+        }
+
+        fn account(&self, id: BankAccountId) -> BankAccountState {
+            match self.service.account(id).unwrap() {
+                BankAccountAggregate::Opened(state, _) | BankAccountAggregate::Closed(state, _) => {
+                    state
+                }
+                BankAccountAggregate::Uninitialized => panic!("Aggregate not initialized"),
+            }
+        }
+
+        fn last_event(&self, id: BankAccountId) -> BankAccountEvent {
+            let events = self.event_store.read_events(&stream_id(id), 0).unwrap();
+            events.last().unwrap().event.clone()
+        }
+    }
+
+    #[test]
+    fn transfer_moves_money_to_target() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture.transfer(30);
+
+        // Act
+        let result = fixture.runner().run_once();
+
+        // Assert
+        assert_eq!(Ok(2), result);
+        let source = fixture.account(SOURCE_ID);
+        assert_eq!(70, source.balance);
+        assert_eq!(
+            Some(&TransferStatus::Completed),
+            source.transfers.get(&(SOURCE_ID, TRANSFER_ID))
+        );
+        assert_eq!(30, fixture.account(TARGET_ID).balance);
+    }
+
+    #[test]
+    fn insufficient_funds_fail_transfer_without_moving_money() {
+        // Arrange
+        let fixture = Fixture::new(10);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture.transfer(30);
+
+        // Act
+        let result = fixture.runner().run_once();
+
+        // Assert
+        assert_eq!(Ok(0), result);
+        assert_eq!(
+            BankAccountEvent::transfer_failed(
+                SOURCE_ID,
+                TRANSFER_ID,
+                TransferFailureReason::InsufficientFunds,
+                0,
+            ),
+            fixture.last_event(SOURCE_ID)
+        );
+        assert_eq!(10, fixture.account(SOURCE_ID).balance);
+        assert_eq!(0, fixture.account(TARGET_ID).balance);
+    }
+
+    #[test]
+    fn closed_target_reverses_debit() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture
+            .service
+            .handle(CloseBankAccount::new(TARGET_ID))
+            .unwrap();
+        fixture.transfer(30);
+
+        // Act
+        let result = fixture.runner().run_once();
+
+        // Assert
+        assert_eq!(Ok(1), result);
+        assert_eq!(
+            BankAccountEvent::transfer_failed(
+                SOURCE_ID,
+                TRANSFER_ID,
+                TransferFailureReason::TargetUnavailable,
+                30,
+            ),
+            fixture.last_event(SOURCE_ID)
+        );
+        assert_eq!(100, fixture.account(SOURCE_ID).balance);
+        assert_eq!(0, fixture.account(TARGET_ID).balance);
+    }
+
+    #[test]
+    fn missing_target_reverses_debit() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture.transfer(30);
+
+        // Act
+        fixture.runner().run_once().unwrap();
+
+        // Assert
+        let source = fixture.account(SOURCE_ID);
+        assert_eq!(100, source.balance);
+        assert_eq!(
+            Some(&TransferStatus::Failed),
+            source.transfers.get(&(SOURCE_ID, TRANSFER_ID))
+        );
+    }
+
+    #[test]
+    fn retried_transfer_moves_money_once() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture.transfer(30);
+        let runner = fixture.runner();
+        runner.run_once().unwrap();
+
+        // Act
+        fixture.transfer(30);
+        let result = runner.run_once();
+
+        // Assert
+        assert_eq!(Ok(0), result);
+        assert_eq!(70, fixture.account(SOURCE_ID).balance);
+        assert_eq!(30, fixture.account(TARGET_ID).balance);
+    }
+
+    #[test]
+    fn transfers_from_two_sources_with_same_id_both_arrive() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture
+            .service
+            .handle(OpenBankAccount::new(OTHER_SOURCE_ID, CUSTOMER_ID))
+            .unwrap();
+        fixture
+            .service
+            .handle(DepositMoney::new(OTHER_SOURCE_ID, 100))
+            .unwrap();
+        fixture.transfer(30);
+        fixture
+            .service
+            .handle(TransferMoney::new(
+                OTHER_SOURCE_ID,
+                TRANSFER_ID,
+                TARGET_ID,
+                20,
+            ))
+            .unwrap();
+
+        // Act
+        fixture.runner().run_once().unwrap();
+
+        // Assert
+        let target = fixture.account(TARGET_ID);
+        assert_eq!(50, target.balance);
+        assert_eq!(
+            Some(&TransferStatus::Received),
+            target.transfers.get(&(OTHER_SOURCE_ID, TRANSFER_ID))
+        );
+        let other_source = fixture.account(OTHER_SOURCE_ID);
+        assert_eq!(80, other_source.balance);
+        assert_eq!(
+            Some(&TransferStatus::Completed),
+            other_source.transfers.get(&(OTHER_SOURCE_ID, TRANSFER_ID))
+        );
+    }
+
+    #[test]
+    fn crash_between_credit_and_its_record_credits_target_once() {
+        // Arrange
+        let fixture = Fixture::new(100);
+        fixture
+            .service
+            .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+            .unwrap();
+        let position = fixture.transfer(30);
+        let initiated = TransferInitiated {
+            id: SOURCE_ID,
+            transfer_id: TRANSFER_ID,
+            target: TARGET_ID,
+            amount: 30,
+        };
+        let receive = ReceiveTransfer::new(TARGET_ID, TRANSFER_ID, SOURCE_ID, 30);
+        // The process recorded the credit and sent it, but went down before recording that.
+        fixture
+            .process_store
+            .append_events(
+                "Transfer-123-1",
+                vec![
+                    ProcessEvent::Handled(position),
+                    ProcessEvent::Recorded(TransferStarted(initiated)),
+                    ProcessEvent::CommandIssued(TransferCommand::Receive(receive.clone())),
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .unwrap();
+        fixture.service.handle(receive).unwrap();
+        let runner = fixture.runner();
+
+        // Act
+        let active = runner.recover();
+        let handled = runner.run_once();
+
+        // Assert
+        assert_eq!(Ok(1), active);
+        assert_eq!(Ok(1), handled);
+        let source = fixture.account(SOURCE_ID);
+        assert_eq!(70, source.balance);
+        assert_eq!(
+            Some(&TransferStatus::Completed),
+            source.transfers.get(&(SOURCE_ID, TRANSFER_ID))
+        );
+        assert_eq!(30, fixture.account(TARGET_ID).balance);
+        assert!(runner.instance("123-1").unwrap().completed);
+    }
+
+    #[test]
+    fn receiving_transfer_credits_target() {
+        let fixture = HandlerFixture::new().given(
+            "BankAccount-456",
+            vec![BankAccountEvent::opened(TARGET_ID, CUSTOMER_ID)],
+        );
+        let service = BankAccountService::new(fixture.store());
+
+        fixture
+            .when(
+                &service,
+                TransferCommand::Receive(ReceiveTransfer::new(
+                    TARGET_ID,
+                    TRANSFER_ID,
+                    SOURCE_ID,
+                    30,
+                )),
+            )
+            .then_expect(vec![BankAccountEvent::transfer_received(
+                TARGET_ID,
+                TRANSFER_ID,
+                SOURCE_ID,
+                30,
+            )]);
+    }
+
+    #[test]
+    fn closed_target_refuses_transfer() {
+        let fixture = HandlerFixture::new().given(
+            "BankAccount-456",
+            vec![
+                BankAccountEvent::opened(TARGET_ID, CUSTOMER_ID),
+                BankAccountEvent::closed(TARGET_ID),
+            ],
+        );
+        let service = BankAccountService::new(fixture.store());
+
+        fixture
+            .when(
+                &service,
+                TransferCommand::Receive(ReceiveTransfer::new(
+                    TARGET_ID,
+                    TRANSFER_ID,
+                    SOURCE_ID,
+                    30,
+                )),
+            )
+            .then_error(ServiceError::Command(CommandError::NotOpened));
+    }
+}
@@ -1,2 +1,9 @@
 pub type BankAccountId = u64;
 pub type CustomerId = u64;
+/// Chosen by the client, so a transfer that is retried with the same id happens once.
+pub type TransferId = u64;
+
+/// Event stream of a bank account, in the `BankAccount` category.
+pub fn stream_id(id: BankAccountId) -> String {
+    format!("BankAccount-{}", id)
+}
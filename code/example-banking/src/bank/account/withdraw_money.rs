@@ -41,55 +41,31 @@ impl AggregateCommand<BankAccountAggregate> for WithdrawMoney {
 
 #[cfg(test)]
 mod tests {
-    use crate::bank::account::errors::CommandError;
     use crate::bank::account::prelude::{
-        BankAccountAggregate, BankAccountEvent, BankAccountId, CustomerId, WithdrawMoney,
+        BankAccountEvent, BankAccountId, CustomerId, WithdrawMoney,
     };
-    use eventsourcing::Aggregate;
+    use eventsourcing::testing::given;
 
     const ACCOUNT_ID: BankAccountId = 123;
     const CUSTOMER_ID: CustomerId = 5000;
 
     #[test]
     fn withdrawing_money_works() {
-        assert_withdraw(
-            vec![
-                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
-                BankAccountEvent::credited(ACCOUNT_ID, 50),
-            ],
-            WithdrawMoney::new(ACCOUNT_ID, 49),
-            Ok(vec![BankAccountEvent::debited(ACCOUNT_ID, 49)]),
-        );
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 50),
+        ])
+        .when(WithdrawMoney::new(ACCOUNT_ID, 49))
+        .then_expect(vec![BankAccountEvent::debited(ACCOUNT_ID, 49)]);
     }
 
     #[test]
     fn not_enough_funds() {
-        assert_withdraw(
-            vec![
-                BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
-                BankAccountEvent::credited(ACCOUNT_ID, 48),
-            ],
-            WithdrawMoney::new(ACCOUNT_ID, 49),
-            Ok(vec![BankAccountEvent::not_enough_funds(ACCOUNT_ID, 49, 48)]),
-        );
+        given(vec![
+            BankAccountEvent::opened(ACCOUNT_ID, CUSTOMER_ID),
+            BankAccountEvent::credited(ACCOUNT_ID, 48),
+        ])
+        .when(WithdrawMoney::new(ACCOUNT_ID, 49))
+        .then_expect(vec![BankAccountEvent::not_enough_funds(ACCOUNT_ID, 49, 48)]);
     }
-
-    fn assert_withdraw(
-        intitial_events: Vec<BankAccountEvent>,
-        cmd: WithdrawMoney,
-        expected: Result<Vec<BankAccountEvent>, CommandError>,
-    ) {
-        // Arrange
-        let mut agg = BankAccountAggregate::default();
-        for event in intitial_events {
-            agg.apply(event).unwrap();
-        }
-
-        // Act
-        let result = agg.execute(cmd);
-
-        // Assert
-        assert_eq!(expected, result);
-    }
-
 }
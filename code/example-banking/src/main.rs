@@ -1,8 +1,16 @@
-mod bank;
-
-use crate::bank::account::prelude::*;
-use eventsourcing::eventstore::DummyEventStore;
+use eventsourcing::eventstore::{GlobalEventStore, InMemoryEventStore};
+use eventsourcing::outbox::{InProcessTransport, OutboxRelay};
 use eventsourcing::Aggregate;
+use example_banking::bank::account::integration::publishing;
+use example_banking::bank::account::prelude::*;
+use example_banking::bank::account::queries::{BankAccountQueries, Page};
+use example_banking::bank::account::read_models::*;
+use example_banking::bank::account::service::BankAccountService;
+use example_banking::bank::account::transfers::{
+    TransferInstance, TransferProcess, TransferProcessEvent, TransferRunner,
+};
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() {
     open_bank_account_example1();
@@ -11,16 +19,19 @@ fn main() {
     withdraw_example();
     not_enough_funds_example();
     close_example();
+    read_models_example();
+    transfer_example();
+    outbox_example();
     println!("Done!");
 }
 
 const ACCOUNT_ID: BankAccountId = 123;
 const CUSTOMER_ID: CustomerId = 123;
+const TARGET_ID: BankAccountId = 456;
 
 fn open_bank_account_example1() {
     // Arrange
     let cmd = OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID);
-    let _event_store = DummyEventStore {};
     let repository = BankAccountRepository {};
     let handler = OpenBankAccountHandler::new(repository);
 
@@ -142,3 +153,111 @@ fn close_example() {
         panic!("Aggregate not in Closed state");
     }
 }
+
+fn read_models_example() {
+    // Arrange
+    let event_store = Arc::new(InMemoryEventStore::new());
+    let service = BankAccountService::new(event_store.clone());
+    let projections = Arc::new(BankingProjections::new(
+        event_store.clone(),
+        Arc::new(BankingReadModelStore::new()),
+    ));
+    let _running = projections.spawn(Duration::from_millis(10));
+    let queries = BankAccountQueries::new(projections.clone()).with_timeout(Duration::from_secs(1));
+
+    // Act
+    service
+        .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+        .unwrap();
+    service.handle(DepositMoney::new(ACCOUNT_ID, 50)).unwrap();
+    let position = service.handle(WithdrawMoney::new(ACCOUNT_ID, 80)).unwrap();
+
+    // Assert
+    let account = queries.account(ACCOUNT_ID, position).unwrap().unwrap();
+    assert_eq!(50, account.balance);
+    assert_eq!(AccountStatus::Open, account.status);
+    let history = queries
+        .transactions(ACCOUNT_ID, Page::new(0, 10), position)
+        .unwrap();
+    assert_eq!(2, history.total);
+    assert_eq!(
+        vec![ACCOUNT_ID],
+        queries.customer_accounts(CUSTOMER_ID, position).unwrap()
+    );
+    assert_eq!(
+        Ok(Some(position)),
+        event_store.stream_position(&stream_id(ACCOUNT_ID), 3)
+    );
+    for lag in projections.lag().unwrap() {
+        assert_eq!(0, lag.behind);
+    }
+}
+
+fn transfer_example() {
+    // Arrange
+    let event_store = Arc::new(InMemoryEventStore::new());
+    let service = Arc::new(BankAccountService::new(event_store.clone()));
+    let transfers = TransferRunner::new(
+        event_store,
+        Arc::new(InMemoryEventStore::<TransferInstance, TransferProcessEvent>::new()),
+        TransferProcess,
+        service.clone(),
+    );
+    service
+        .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+        .unwrap();
+    service.handle(DepositMoney::new(ACCOUNT_ID, 50)).unwrap();
+    service
+        .handle(OpenBankAccount::new(TARGET_ID, CUSTOMER_ID))
+        .unwrap();
+    let first: TransferId = 1;
+    let second: TransferId = 2;
+
+    // Act
+    service
+        .handle(TransferMoney::new(ACCOUNT_ID, first, TARGET_ID, 30))
+        .unwrap();
+    service
+        .handle(TransferMoney::new(ACCOUNT_ID, second, TARGET_ID, 30))
+        .unwrap();
+    transfers.run_once().unwrap();
+
+    // Assert
+    if let BankAccountAggregate::Opened(state, _) = service.account(ACCOUNT_ID).unwrap() {
+        assert_eq!(20, state.balance);
+        assert_eq!(
+            Some(&TransferStatus::Completed),
+            state.transfers.get(&(ACCOUNT_ID, first))
+        );
+        assert_eq!(
+            Some(&TransferStatus::Failed),
+            state.transfers.get(&(ACCOUNT_ID, second))
+        );
+    } else {
+        panic!("Aggregate not in Opened state");
+    }
+}
+
+fn outbox_example() {
+    // Arrange
+    let event_store = Arc::new(InMemoryEventStore::new());
+    let service = BankAccountService::new(publishing(event_store.clone()));
+    let transport = Arc::new(InProcessTransport::new());
+    let relay = OutboxRelay::new(event_store, transport.clone());
+
+    // Act
+    service
+        .handle(OpenBankAccount::new(ACCOUNT_ID, CUSTOMER_ID))
+        .unwrap();
+    service.handle(DepositMoney::new(ACCOUNT_ID, 50)).unwrap();
+    let sent = relay.run_once().unwrap();
+
+    // Assert
+    assert_eq!(2, sent);
+    let topics: Vec<_> = transport
+        .delivered()
+        .into_iter()
+        .map(|entry| entry.message.topic)
+        .collect();
+    assert_eq!(vec!["bank_account.opened", "bank_account.credited"], topics);
+}
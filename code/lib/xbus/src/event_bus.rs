@@ -0,0 +1,601 @@
+//! Domain events published to subscribing actors.
+
+use actix::dev::SendError;
+use actix::prelude::*;
+use eventsourcing::eventstore::{
+    EventEnvelope, EventStore, ExpectedVersion, GlobalEventStore, Position, Version, VersionedEvent,
+};
+use eventsourcing::{Aggregate, AggregateEvent, Event};
+use futures::{stream, Future, Stream};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// An event as it was committed to its stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Committed<E> {
+    pub stream_id: String,
+    pub version: Version,
+    pub event: E,
+}
+
+impl<E: 'static> Message for Committed<E> {
+    type Result = ();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The bus waits for each event to be handled before it delivers anything else.
+    Synchronous,
+    /// The bus drops events while the subscriber's mailbox is full, so its mailbox capacity
+    /// bounds how far the subscriber lags behind.
+    FireAndForget,
+}
+
+/// Subscribes a recipient to events of type `E`.
+pub struct Subscribe<E: Send + 'static> {
+    recipient: Recipient<Committed<E>>,
+    delivery: Delivery,
+    event_types: Option<Vec<&'static str>>,
+}
+
+impl<E: Send + 'static> Subscribe<E> {
+    pub fn new(recipient: Recipient<Committed<E>>, delivery: Delivery) -> Subscribe<E> {
+        Subscribe {
+            recipient,
+            delivery,
+            event_types: None,
+        }
+    }
+
+    /// Only delivers events whose `event_type` is one of `event_types`.
+    pub fn only(mut self, event_types: &[&'static str]) -> Subscribe<E> {
+        self.event_types = Some(event_types.to_vec());
+        self
+    }
+
+    fn wants(&self, event: &E) -> bool
+    where
+        E: Event,
+    {
+        self.event_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&event.event_type()))
+    }
+}
+
+impl<E: Send + 'static> Message for Subscribe<E> {
+    type Result = ();
+}
+
+/// Events committed together, in stream order.
+pub struct Publish<E> {
+    pub events: Vec<Committed<E>>,
+}
+
+impl<E> Publish<E> {
+    pub fn new(events: Vec<Committed<E>>) -> Publish<E> {
+        Publish { events }
+    }
+}
+
+impl<E: 'static> Message for Publish<E> {
+    type Result = ();
+}
+
+/// Number of events fire-and-forget subscribers missed because their mailbox was full.
+pub struct DroppedEvents;
+
+impl Message for DroppedEvents {
+    type Result = u64;
+}
+
+/// Hands published events to their subscribers, in the order they were published.
+#[derive(Default)]
+pub struct EventBus {
+    /// `Vec<Subscribe<E>>` by the `TypeId` of `E`.
+    subscribers: HashMap<TypeId, Box<dyn Any>>,
+    dropped: u64,
+}
+
+impl EventBus {
+    fn subscribers<E: Send + 'static>(&mut self) -> &mut Vec<Subscribe<E>> {
+        self.subscribers
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Vec::<Subscribe<E>>::new()))
+            .downcast_mut()
+            .expect("subscribers are keyed by their event type")
+    }
+}
+
+impl Actor for EventBus {
+    type Context = Context<Self>;
+}
+
+impl<E> Handler<Subscribe<E>> for EventBus
+where
+    E: Send + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, subscribe: Subscribe<E>, _ctx: &mut Context<Self>) {
+        self.subscribers::<E>().push(subscribe);
+    }
+}
+
+impl<E> Handler<Publish<E>> for EventBus
+where
+    E: Event + Clone + Send + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, publish: Publish<E>, ctx: &mut Context<Self>) {
+        let mut synchronous = Vec::new();
+        let mut dropped = 0;
+        let subscribers = self.subscribers::<E>();
+
+        for committed in publish.events {
+            subscribers.retain(|subscriber| match subscriber.delivery {
+                _ if !subscriber.wants(&committed.event) => true,
+                Delivery::Synchronous => {
+                    synchronous.push((subscriber.recipient.clone(), committed.clone()));
+                    true
+                }
+                Delivery::FireAndForget => match subscriber.recipient.try_send(committed.clone()) {
+                    Ok(()) => true,
+                    Err(SendError::Full(_)) => {
+                        dropped += 1;
+                        true
+                    }
+                    Err(SendError::Closed(_)) => false,
+                },
+            });
+        }
+        self.dropped += dropped;
+
+        if !synchronous.is_empty() {
+            let deliveries = stream::iter_ok(synchronous)
+                .for_each(|(recipient, committed)| recipient.send(committed).then(|_| Ok(())));
+            ctx.wait(deliveries.into_actor(self));
+        }
+    }
+}
+
+impl Handler<DroppedEvents> for EventBus {
+    type Result = u64;
+
+    fn handle(&mut self, _: DroppedEvents, _ctx: &mut Context<Self>) -> u64 {
+        self.dropped
+    }
+}
+
+/// Event store that publishes every event it commits to an `EventBus`.
+pub struct PublishingEventStore<ES> {
+    event_store: ES,
+    bus: Addr<EventBus>,
+    /// Lock of every stream being appended to, held from append to publish so the events of a
+    /// stream reach the bus in commit order.
+    publishing: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl<ES> PublishingEventStore<ES> {
+    pub fn new(event_store: ES, bus: Addr<EventBus>) -> PublishingEventStore<ES> {
+        PublishingEventStore {
+            event_store,
+            bus,
+            publishing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn stream_lock(&self, id: &str) -> Arc<Mutex<()>> {
+        let mut publishing = self.publishing.lock().unwrap();
+        Arc::clone(publishing.entry(id.to_owned()).or_default())
+    }
+
+    /// Forgets the lock of stream `id` once no other append holds on to it.
+    fn release(&self, id: &str, lock: Arc<Mutex<()>>) {
+        let mut publishing = self.publishing.lock().unwrap();
+        drop(lock);
+        if publishing
+            .get(id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            publishing.remove(id);
+        }
+    }
+}
+
+impl<A, E, ES> EventStore<A, E> for PublishingEventStore<ES>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Send + 'static,
+    ES: EventStore<A, E>,
+{
+    type Error = ES::Error;
+
+    fn read_events(&self, id: &str, since: Version) -> Result<Vec<VersionedEvent<E>>, ES::Error> {
+        self.event_store.read_events(id, since)
+    }
+
+    fn append_events(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, ES::Error> {
+        let lock = self.stream_lock(id);
+        let result = {
+            let _publishing = lock.lock().unwrap();
+            self.append_and_publish(id, events, expected)
+        };
+        self.release(id, lock);
+
+        result
+    }
+
+    fn delete_stream(&self, id: &str, expected: ExpectedVersion) -> Result<(), ES::Error> {
+        self.event_store.delete_stream(id, expected)
+    }
+}
+
+impl<ES> PublishingEventStore<ES> {
+    fn append_and_publish<A, E>(
+        &self,
+        id: &str,
+        events: Vec<E>,
+        expected: ExpectedVersion,
+    ) -> Result<Version, ES::Error>
+    where
+        A: Aggregate,
+        E: AggregateEvent<A> + Clone + Send + 'static,
+        ES: EventStore<A, E>,
+    {
+        let version = self
+            .event_store
+            .append_events(id, events.clone(), expected)?;
+
+        let first_version = version - events.len() as Version;
+        let committed = events
+            .into_iter()
+            .zip(first_version + 1..)
+            .map(|(event, version)| Committed {
+                stream_id: id.to_owned(),
+                version,
+                event,
+            })
+            .collect();
+        self.bus.do_send(Publish::new(committed));
+
+        Ok(version)
+    }
+}
+
+impl<A, E, ES> GlobalEventStore<A, E> for PublishingEventStore<ES>
+where
+    A: Aggregate,
+    E: AggregateEvent<A> + Clone + Send + 'static,
+    ES: GlobalEventStore<A, E>,
+{
+    fn read_all(&self, since: Position, limit: usize) -> Result<Vec<EventEnvelope<E>>, ES::Error> {
+        self.event_store.read_all(since, limit)
+    }
+
+    fn head_position(&self) -> Result<Position, ES::Error> {
+        self.event_store.head_position()
+    }
+
+    fn stream_position(&self, id: &str, version: Version) -> Result<Option<Position>, ES::Error> {
+        self.event_store.stream_position(id, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event_bus::*;
+    use eventsourcing::eventstore::{EventStoreError, InMemoryEventStore};
+    use std::convert::Infallible;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum AccountEvent {
+        Opened,
+        Credited(u64),
+    }
+
+    impl Event for AccountEvent {
+        fn event_type(&self) -> &'static str {
+            match self {
+                AccountEvent::Opened => "opened",
+                AccountEvent::Credited(_) => "credited",
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Account {
+        generation: u64,
+    }
+
+    impl Aggregate for Account {
+        fn aggregate_type() -> &'static str {
+            "Account"
+        }
+
+        fn generation(&self) -> u64 {
+            self.generation
+        }
+
+        fn increment_generation(&mut self) {
+            self.generation += 1;
+        }
+    }
+
+    impl AggregateEvent<Account> for AccountEvent {
+        type Error = Infallible;
+
+        fn apply_to(self, _aggregate: &mut Account) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    /// Holds appends to `Account-1` until an append to another stream went through.
+    struct GatedEventStore {
+        inner: InMemoryEventStore<Account, AccountEvent>,
+        passed: Mutex<Sender<()>>,
+        gate: Mutex<Receiver<()>>,
+    }
+
+    impl GatedEventStore {
+        fn new() -> GatedEventStore {
+            let (passed, gate) = mpsc::channel();
+            GatedEventStore {
+                inner: InMemoryEventStore::new(),
+                passed: Mutex::new(passed),
+                gate: Mutex::new(gate),
+            }
+        }
+    }
+
+    impl EventStore<Account, AccountEvent> for GatedEventStore {
+        type Error = EventStoreError;
+
+        fn read_events(
+            &self,
+            id: &str,
+            since: Version,
+        ) -> Result<Vec<VersionedEvent<AccountEvent>>, EventStoreError> {
+            self.inner.read_events(id, since)
+        }
+
+        fn append_events(
+            &self,
+            id: &str,
+            events: Vec<AccountEvent>,
+            expected: ExpectedVersion,
+        ) -> Result<Version, EventStoreError> {
+            if id == "Account-1" {
+                let gate = self.gate.lock().unwrap();
+                gate.recv_timeout(Duration::from_secs(5))
+                    .map_err(|_| EventStoreError::Io("no other append went through".to_owned()))?;
+                return self.inner.append_events(id, events, expected);
+            }
+
+            let version = self.inner.append_events(id, events, expected)?;
+            self.passed.lock().unwrap().send(()).unwrap();
+            Ok(version)
+        }
+
+        fn delete_stream(
+            &self,
+            id: &str,
+            expected: ExpectedVersion,
+        ) -> Result<(), EventStoreError> {
+            self.inner.delete_stream(id, expected)
+        }
+    }
+
+    /// Keeps what it receives.
+    struct Notifications {
+        received: Vec<Committed<AccountEvent>>,
+    }
+
+    impl Notifications {
+        /// Notifications with a mailbox of `capacity` messages.
+        fn start(capacity: usize) -> Addr<Notifications> {
+            Notifications::create(move |ctx| {
+                ctx.set_mailbox_capacity(capacity);
+                Notifications {
+                    received: Vec::new(),
+                }
+            })
+        }
+    }
+
+    impl Actor for Notifications {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Committed<AccountEvent>> for Notifications {
+        type Result = ();
+
+        fn handle(&mut self, committed: Committed<AccountEvent>, _ctx: &mut Context<Self>) {
+            self.received.push(committed);
+        }
+    }
+
+    struct Received;
+
+    impl Message for Received {
+        type Result = Vec<Committed<AccountEvent>>;
+    }
+
+    impl Handler<Received> for Notifications {
+        type Result = MessageResult<Received>;
+
+        fn handle(&mut self, _: Received, _ctx: &mut Context<Self>) -> Self::Result {
+            MessageResult(self.received.clone())
+        }
+    }
+
+    fn committed(version: Version, event: AccountEvent) -> Committed<AccountEvent> {
+        Committed {
+            stream_id: "Account-1".to_owned(),
+            version,
+            event,
+        }
+    }
+
+    /// Runs `test` in a fresh actor system and waits for the future it returns.
+    fn run<F, T>(test: T)
+    where
+        T: FnOnce() -> F + 'static,
+        F: Future<Item = (), Error = MailboxError> + 'static,
+    {
+        System::run(move || {
+            Arbiter::spawn(test().then(|result| {
+                System::current().stop();
+                result.map_err(|err| panic!("{}", err))
+            }));
+        });
+    }
+
+    #[test]
+    fn committed_events_reach_subscriber_in_order() {
+        run(|| {
+            // Arrange
+            let bus = EventBus::default().start();
+            let notifications = Notifications::start(16);
+            bus.do_send(Subscribe::new(
+                notifications.clone().recipient(),
+                Delivery::Synchronous,
+            ));
+            let event_store = PublishingEventStore::new(
+                Arc::new(InMemoryEventStore::<Account, _>::new()),
+                bus.clone(),
+            );
+
+            // Act
+            event_store
+                .append_events(
+                    "Account-1",
+                    vec![AccountEvent::Opened, AccountEvent::Credited(5)],
+                    ExpectedVersion::NoStream,
+                )
+                .unwrap();
+            event_store
+                .append_events(
+                    "Account-1",
+                    vec![AccountEvent::Credited(7)],
+                    ExpectedVersion::Exact(2),
+                )
+                .unwrap();
+
+            // Assert
+            // The bus answers once it delivered everything published before.
+            bus.send(DroppedEvents)
+                .and_then(move |_| notifications.send(Received))
+                .map(|received| {
+                    assert_eq!(
+                        vec![
+                            committed(1, AccountEvent::Opened),
+                            committed(2, AccountEvent::Credited(5)),
+                            committed(3, AccountEvent::Credited(7)),
+                        ],
+                        received
+                    );
+                })
+        });
+    }
+
+    #[test]
+    fn subscriber_gets_only_selected_event_types() {
+        run(|| {
+            // Arrange
+            let bus = EventBus::default().start();
+            let notifications = Notifications::start(16);
+            bus.do_send(
+                Subscribe::new(notifications.clone().recipient(), Delivery::Synchronous)
+                    .only(&["credited"]),
+            );
+
+            // Act
+            bus.do_send(Publish::new(vec![
+                committed(1, AccountEvent::Opened),
+                committed(2, AccountEvent::Credited(5)),
+            ]));
+
+            // Assert
+            // The bus answers once it delivered everything published before.
+            bus.send(DroppedEvents)
+                .and_then(move |_| notifications.send(Received))
+                .map(|received| {
+                    assert_eq!(vec![committed(2, AccountEvent::Credited(5))], received);
+                })
+        });
+    }
+
+    #[test]
+    fn fire_and_forget_drops_events_beyond_mailbox_capacity() {
+        run(|| {
+            // Arrange
+            let bus = EventBus::default().start();
+            let notifications = Notifications::start(1);
+            bus.do_send(Subscribe::new(
+                notifications.clone().recipient(),
+                Delivery::FireAndForget,
+            ));
+
+            // Act
+            bus.do_send(Publish::new(
+                (1..=5)
+                    .map(|version| committed(version, AccountEvent::Credited(version)))
+                    .collect(),
+            ));
+
+            // Assert
+            bus.send(DroppedEvents)
+                .and_then(move |dropped| {
+                    notifications
+                        .send(Received)
+                        .map(move |received| (dropped, received))
+                })
+                .map(|(dropped, received)| {
+                    assert!(dropped > 0);
+                    assert_eq!(5, dropped + received.len() as u64);
+                    assert_eq!(committed(1, AccountEvent::Credited(1)), received[0]);
+                })
+        });
+    }
+
+    #[test]
+    fn appends_to_different_streams_do_not_wait_for_each_other() {
+        run(|| {
+            // Arrange
+            let bus = EventBus::default().start();
+            let event_store = Arc::new(PublishingEventStore::new(GatedEventStore::new(), bus));
+            let append = |id: &'static str| {
+                let event_store = Arc::clone(&event_store);
+                thread::spawn(move || {
+                    event_store.append_events(
+                        id,
+                        vec![AccountEvent::Opened],
+                        ExpectedVersion::NoStream,
+                    )
+                })
+            };
+
+            // Act
+            let held = append("Account-1");
+            thread::sleep(Duration::from_millis(50));
+            let other = append("Account-2");
+
+            // Assert
+            assert_eq!(Ok(1), other.join().unwrap());
+            assert_eq!(Ok(1), held.join().unwrap());
+            futures::future::ok(())
+        });
+    }
+}
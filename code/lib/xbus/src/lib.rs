@@ -1,9 +1,145 @@
 extern crate actix;
 extern crate futures;
 use actix::*;
+use futures::Future;
+use std::{error::Error, fmt};
 
-pub struct CommandBus;
+mod event_bus;
+mod middleware;
+mod query_bus;
+
+pub use crate::event_bus::{
+    Committed, Delivery, DroppedEvents, EventBus, Publish, PublishingEventStore, Subscribe,
+};
+pub use crate::middleware::{
+    guard, CommandContext, CommandTimings, CorrelationIds, Failure, Guard, Idempotency, Logging,
+    Middleware, Next, Retry, Timing,
+};
+pub use crate::query_bus::{ask, Query, QueryBus, QueryError, QueryHandler};
+
+/// Handles commands of type `C`. Needed only by handlers of several commands; a handler of one
+/// may have a plain `handle` function instead.
+pub trait CommandHandler<C> {
+    type Result;
+    type Error;
+
+    fn handle(command: C) -> Result<Self::Result, Self::Error>;
+}
+
+/// Runs every command through its middleware, in the order they were added, around the
+/// command's handler.
+#[derive(Default)]
+pub struct CommandBus {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl CommandBus {
+    pub fn new() -> CommandBus {
+        CommandBus::default()
+    }
+
+    pub fn with_middleware<M>(mut self, middleware: M) -> CommandBus
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Hands `command` to `handler` through the middleware, along with the metadata the
+    /// middleware added. Handlers derived with `XbusCommandHandler` are run this way.
+    pub fn run<C, R, E, H>(&self, command: C, handler: H) -> Result<R, CommandError<E>>
+    where
+        C: Clone + 'static,
+        E: fmt::Debug,
+        H: Fn(C, &CommandContext) -> Result<R, E>,
+    {
+        let mut outcome = None;
+        let mut context = CommandContext::new(&command);
+        let result = middleware::run_chain(&self.middleware, &mut context, &mut |context| {
+            let result = handler(command.clone(), context);
+            let failure = result
+                .as_ref()
+                .err()
+                .map(|err| Failure::Failed(format!("{:?}", err)));
+            outcome = Some(result);
+            failure.map_or(Ok(()), Err)
+        });
+
+        match (result, outcome) {
+            (Err(Failure::Rejected(reason)), _) => Err(CommandError::Rejected(reason)),
+            (_, Some(outcome)) => outcome.map_err(CommandError::Failed),
+            (_, None) => Err(CommandError::Rejected(format!(
+                "{} was not handled",
+                context.name()
+            ))),
+        }
+    }
+}
 
 impl Actor for CommandBus {
     type Context = Context<Self>;
 }
+
+/// Sends `command` to the bus and resolves to what its handler returned.
+pub fn dispatch<C, R, E>(
+    bus: &Addr<CommandBus>,
+    command: C,
+) -> impl Future<Item = R, Error = DispatchError<E>>
+where
+    C: Message<Result = Result<R, CommandError<E>>> + Send + 'static,
+    CommandBus: Handler<C>,
+    R: Send + 'static,
+    E: Send + 'static,
+{
+    bus.send(command)
+        .map_err(DispatchError::Mailbox)
+        .and_then(|result| {
+            result.map_err(|err| match err {
+                CommandError::Rejected(reason) => DispatchError::Rejected(reason),
+                CommandError::Failed(err) => DispatchError::Command(err),
+            })
+        })
+}
+
+/// Error result of a command handled by the `CommandBus`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError<E> {
+    /// A middleware refused the command.
+    Rejected(String),
+    /// The handler refused the command.
+    Failed(E),
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for CommandError<E> {}
+
+impl<E: fmt::Display> fmt::Display for CommandError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Rejected(reason) => write!(f, "CommandError: rejected: {}", reason),
+            CommandError::Failed(err) => write!(f, "CommandError: {}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DispatchError<E> {
+    /// The bus is gone or its mailbox is closed.
+    Mailbox(MailboxError),
+    /// A middleware refused the command, or no handler answers the query.
+    Rejected(String),
+    /// The handler refused the command or query.
+    Command(E),
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for DispatchError<E> {}
+
+impl<E: fmt::Display> fmt::Display for DispatchError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DispatchError::Mailbox(err) => write!(f, "DispatchError: {}", err),
+            DispatchError::Rejected(reason) => write!(f, "DispatchError: rejected: {}", reason),
+            DispatchError::Command(err) => write!(f, "DispatchError: {}", err),
+        }
+    }
+}
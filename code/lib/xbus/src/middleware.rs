@@ -0,0 +1,483 @@
+//! Behaviour wrapped around every command the `CommandBus` handles.
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Why a command did not succeed, as middleware sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// A middleware refused the command.
+    Rejected(String),
+    /// The handler returned an error, given by its `Debug` output.
+    Failed(String),
+}
+
+/// The command on its way through the middleware chain.
+pub struct CommandContext<'a> {
+    command: &'a dyn Any,
+    name: &'static str,
+    metadata: BTreeMap<String, String>,
+}
+
+impl<'a> CommandContext<'a> {
+    pub(crate) fn new<C: 'static>(command: &'a C) -> CommandContext<'a> {
+        CommandContext {
+            command,
+            name: std::any::type_name::<C>(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Type name of the command.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The command, when it is a `C`.
+    pub fn command<C: 'static>(&self) -> Option<&C> {
+        self.command.downcast_ref()
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    /// Metadata for the middleware further down the chain.
+    pub fn set_metadata(&mut self, key: &str, value: String) {
+        self.metadata.insert(key.to_owned(), value);
+    }
+}
+
+/// Runs the rest of the chain and the handler; may be called again to retry them.
+pub type Next<'n> = &'n mut dyn FnMut(&mut CommandContext) -> Result<(), Failure>;
+
+pub trait Middleware {
+    /// Does its work around `next`, or returns a `Failure::Rejected` without calling it.
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure>;
+}
+
+pub(crate) fn run_chain(
+    middleware: &[Box<dyn Middleware>],
+    context: &mut CommandContext,
+    handler: Next,
+) -> Result<(), Failure> {
+    match middleware.split_first() {
+        Some((first, rest)) => {
+            first.handle(context, &mut |context| run_chain(rest, context, handler))
+        }
+        None => handler(context),
+    }
+}
+
+/// Rejects commands `check` refuses, for validation and authorization.
+pub struct Guard<F>(F);
+
+pub fn guard<F>(check: F) -> Guard<F>
+where
+    F: Fn(&CommandContext) -> Result<(), String>,
+{
+    Guard(check)
+}
+
+impl<F> Middleware for Guard<F>
+where
+    F: Fn(&CommandContext) -> Result<(), String>,
+{
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        (self.0)(context).map_err(Failure::Rejected)?;
+        next(context)
+    }
+}
+
+/// Gives every command without one a `correlation_id`, numbered from 1.
+#[derive(Debug, Default)]
+pub struct CorrelationIds {
+    last: AtomicU64,
+}
+
+impl CorrelationIds {
+    pub fn new() -> CorrelationIds {
+        CorrelationIds::default()
+    }
+}
+
+impl Middleware for CorrelationIds {
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        if context.metadata("correlation_id").is_none() {
+            let id = self.last.fetch_add(1, Ordering::SeqCst) + 1;
+            context.set_metadata("correlation_id", id.to_string());
+        }
+        next(context)
+    }
+}
+
+/// Writes one `key=value` line per command, to stderr unless told otherwise.
+pub struct Logging {
+    write: Box<dyn Fn(&str)>,
+}
+
+impl Logging {
+    pub fn new() -> Logging {
+        Logging::to(|line| eprintln!("{}", line))
+    }
+
+    pub fn to<W>(write: W) -> Logging
+    where
+        W: Fn(&str) + 'static,
+    {
+        Logging {
+            write: Box::new(write),
+        }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging::new()
+    }
+}
+
+impl Middleware for Logging {
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        let result = next(context);
+
+        let mut line = format!("command={}", context.name());
+        for (key, value) in &context.metadata {
+            line.push_str(&format!(" {}={:?}", key, value));
+        }
+        match &result {
+            Ok(()) => line.push_str(" status=ok"),
+            Err(Failure::Rejected(reason)) => {
+                line.push_str(&format!(" status=rejected reason={:?}", reason))
+            }
+            Err(Failure::Failed(error)) => {
+                line.push_str(&format!(" status=failed error={:?}", error))
+            }
+        }
+        (self.write)(&line);
+
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandTimings {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Measures how long commands take, by command name. Clones share their measurements.
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    timings: Arc<Mutex<BTreeMap<&'static str, CommandTimings>>>,
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing::default()
+    }
+
+    pub fn timings(&self) -> BTreeMap<&'static str, CommandTimings> {
+        self.timings.lock().unwrap().clone()
+    }
+}
+
+impl Middleware for Timing {
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        let started = Instant::now();
+        let result = next(context);
+        let elapsed = started.elapsed();
+
+        let mut timings = self.timings.lock().unwrap();
+        let timing = timings.entry(context.name()).or_default();
+        timing.count += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+
+        result
+    }
+}
+
+/// Runs a command again when its handler fails with an error `is_transient` accepts, up to
+/// `attempts` times in total. Other errors, like a refused command, are returned at once.
+pub struct Retry<F> {
+    attempts: u32,
+    is_transient: F,
+}
+
+impl<F> Retry<F>
+where
+    F: Fn(&str) -> bool,
+{
+    /// `is_transient` is given the error as `Failure::Failed` has it.
+    pub fn new(attempts: u32, is_transient: F) -> Retry<F> {
+        Retry {
+            attempts,
+            is_transient,
+        }
+    }
+}
+
+impl<F> Middleware for Retry<F>
+where
+    F: Fn(&str) -> bool,
+{
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        let mut attempt = 1;
+        loop {
+            match next(context) {
+                Err(Failure::Failed(ref error))
+                    if attempt < self.attempts && (self.is_transient)(error) =>
+                {
+                    attempt += 1
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Rejects a command whose key, e.g. a client chosen id, already belongs to a handled one.
+/// Commands without a key pass through.
+pub struct Idempotency<K> {
+    key: K,
+    handled: Mutex<HashSet<String>>,
+}
+
+impl<K> Idempotency<K>
+where
+    K: Fn(&CommandContext) -> Option<String>,
+{
+    pub fn new(key: K) -> Idempotency<K> {
+        Idempotency {
+            key,
+            handled: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+impl<K> Middleware for Idempotency<K>
+where
+    K: Fn(&CommandContext) -> Option<String>,
+{
+    fn handle(&self, context: &mut CommandContext, next: Next) -> Result<(), Failure> {
+        let key = match (self.key)(context) {
+            Some(key) => key,
+            None => return next(context),
+        };
+        if self.handled.lock().unwrap().contains(&key) {
+            return Err(Failure::Rejected(format!("{} was already handled", key)));
+        }
+
+        next(context)?;
+        self.handled.lock().unwrap().insert(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::middleware::*;
+    use crate::{CommandBus, CommandError};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Withdraw {
+        transfer: u64,
+        amount: u64,
+    }
+
+    /// Counts the calls of a test handler.
+    type Calls = Rc<Cell<u32>>;
+
+    /// Fails its first `failures` calls with "busy", then returns the amount, refusing
+    /// amounts over 100.
+    fn handler(
+        failures: u32,
+    ) -> (
+        Calls,
+        impl Fn(Withdraw, &CommandContext) -> Result<u64, String>,
+    ) {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let handler = move |command: Withdraw, _: &CommandContext| {
+            counted.set(counted.get() + 1);
+            if command.amount > 100 {
+                return Err("not enough funds".to_owned());
+            }
+            if counted.get() <= failures {
+                return Err("busy".to_owned());
+            }
+            Ok(command.amount)
+        };
+        (calls, handler)
+    }
+
+    fn is_busy(error: &str) -> bool {
+        error == "\"busy\""
+    }
+
+    fn withdraw(amount: u64) -> Withdraw {
+        Withdraw {
+            transfer: 1,
+            amount,
+        }
+    }
+
+    /// Logs into the returned lines.
+    fn logging() -> (Rc<RefCell<Vec<String>>>, Logging) {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let written = lines.clone();
+        let logging = Logging::to(move |line| written.borrow_mut().push(line.to_owned()));
+        (lines, logging)
+    }
+
+    #[test]
+    fn middleware_runs_in_order_around_handler() {
+        // Arrange
+        let (lines, logging) = logging();
+        let bus = CommandBus::new()
+            .with_middleware(CorrelationIds::new())
+            .with_middleware(logging);
+        let (_, handler) = handler(0);
+
+        // Act
+        let result = bus.run(withdraw(5), handler);
+
+        // Assert
+        assert_eq!(Ok(5), result);
+        assert_eq!(
+            vec![format!(
+                "command={} correlation_id=\"1\" status=ok",
+                std::any::type_name::<Withdraw>()
+            )],
+            *lines.borrow()
+        );
+    }
+
+    #[test]
+    fn handler_sees_metadata_added_by_middleware() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(CorrelationIds::new());
+
+        // Act
+        let result = bus.run(withdraw(5), |_, context| {
+            Ok::<_, String>(context.metadata("correlation_id").map(str::to_owned))
+        });
+
+        // Assert
+        assert_eq!(Ok(Some("1".to_owned())), result);
+    }
+
+    #[test]
+    fn guard_short_circuits_before_handler() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(guard(|context| {
+            match context.command::<Withdraw>() {
+                Some(command) if command.amount == 0 => Err("amount must be positive".to_owned()),
+                _ => Ok(()),
+            }
+        }));
+        let (calls, handler) = handler(0);
+
+        // Act
+        let result = bus.run(withdraw(0), handler);
+
+        // Assert
+        assert_eq!(
+            Err(CommandError::Rejected("amount must be positive".to_owned())),
+            result
+        );
+        assert_eq!(0, calls.get());
+    }
+
+    #[test]
+    fn retry_runs_failed_handler_again() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(Retry::new(3, is_busy));
+        let (calls, handler) = handler(2);
+
+        // Act
+        let result = bus.run(withdraw(5), handler);
+
+        // Assert
+        assert_eq!(Ok(5), result);
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn retry_gives_up_with_handler_error() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(Retry::new(2, is_busy));
+        let (calls, handler) = handler(5);
+
+        // Act
+        let result = bus.run(withdraw(5), handler);
+
+        // Assert
+        assert_eq!(Err(CommandError::Failed("busy".to_owned())), result);
+        assert_eq!(2, calls.get());
+    }
+
+    #[test]
+    fn retry_runs_refused_command_once() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(Retry::new(3, is_busy));
+        let (calls, handler) = handler(0);
+
+        // Act
+        let result = bus.run(withdraw(500), handler);
+
+        // Assert
+        assert_eq!(
+            Err(CommandError::Failed("not enough funds".to_owned())),
+            result
+        );
+        assert_eq!(1, calls.get());
+    }
+
+    #[test]
+    fn idempotency_rejects_repeated_command() {
+        // Arrange
+        let bus = CommandBus::new().with_middleware(Idempotency::new(|context| {
+            context
+                .command::<Withdraw>()
+                .map(|command| format!("transfer-{}", command.transfer))
+        }));
+        let (calls, handler) = handler(0);
+        bus.run(withdraw(5), &handler).unwrap();
+
+        // Act
+        let result = bus.run(withdraw(5), &handler);
+
+        // Assert
+        assert_eq!(
+            Err(CommandError::Rejected(
+                "transfer-1 was already handled".to_owned()
+            )),
+            result
+        );
+        assert_eq!(1, calls.get());
+    }
+
+    #[test]
+    fn timing_counts_commands_by_name() {
+        // Arrange
+        let timing = Timing::new();
+        let bus = CommandBus::new().with_middleware(timing.clone());
+        let (_, handler) = handler(1);
+
+        // Act
+        let _ = bus.run(withdraw(5), &handler);
+        let _ = bus.run(withdraw(5), &handler);
+
+        // Assert
+        let timings = timing.timings();
+        assert_eq!(2, timings[std::any::type_name::<Withdraw>()].count);
+    }
+}
@@ -0,0 +1,275 @@
+//! Read-side requests answered by handlers reading projections.
+
+use crate::DispatchError;
+use actix::prelude::*;
+use eventsourcing::eventstore::Position;
+use futures::Future;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::{error::Error, fmt};
+
+/// A request for data; derived with `XbusQuery` along with its `actix::Message` impl.
+pub trait Query {
+    type Response;
+    type Error;
+}
+
+/// Answers queries of type `Q`.
+pub trait QueryHandler<Q: Query> {
+    fn handle(&self, query: Q) -> Result<Q::Response, Q::Error>;
+
+    /// Position of the read model the answers come from, `None` when unknown. Cached answers
+    /// are dropped once it moves, and nothing is cached while it is unknown.
+    fn position(&self) -> Option<Position> {
+        None
+    }
+}
+
+impl<Q, H> QueryHandler<Q> for Arc<H>
+where
+    Q: Query,
+    H: QueryHandler<Q>,
+{
+    fn handle(&self, query: Q) -> Result<Q::Response, Q::Error> {
+        (**self).handle(query)
+    }
+
+    fn position(&self) -> Option<Position> {
+        (**self).position()
+    }
+}
+
+type Answer<Q> = Box<dyn FnMut(Q) -> Result<<Q as Query>::Response, <Q as Query>::Error>>;
+
+/// Routes every query to the one handler registered for its type.
+#[derive(Default)]
+pub struct QueryBus {
+    /// `Answer<Q>` by the `TypeId` of `Q`.
+    handlers: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl QueryBus {
+    pub fn new() -> QueryBus {
+        QueryBus::default()
+    }
+
+    pub fn with_handler<Q, H>(self, handler: H) -> QueryBus
+    where
+        Q: Query + 'static,
+        H: QueryHandler<Q> + 'static,
+    {
+        self.with_answer::<Q>(Box::new(move |query| handler.handle(query)))
+    }
+
+    /// Like `with_handler`, but answers a repeated query from a cache until the handler's
+    /// position moves. Errors are not cached.
+    pub fn with_cached_handler<Q, H>(self, handler: H) -> QueryBus
+    where
+        Q: Query + Hash + Eq + Clone + 'static,
+        Q::Response: Clone,
+        H: QueryHandler<Q> + 'static,
+    {
+        let mut cached_at = None;
+        let mut cache: HashMap<Q, Q::Response> = HashMap::new();
+
+        self.with_answer::<Q>(Box::new(move |query| {
+            let position = handler.position();
+            if position.is_none() || position != cached_at {
+                cache.clear();
+                cached_at = position;
+            }
+            if let Some(response) = cache.get(&query) {
+                return Ok(response.clone());
+            }
+
+            let response = handler.handle(query.clone())?;
+            if position.is_some() {
+                cache.insert(query, response.clone());
+            }
+            Ok(response)
+        }))
+    }
+
+    fn with_answer<Q: Query + 'static>(mut self, answer: Answer<Q>) -> QueryBus {
+        self.handlers.insert(TypeId::of::<Q>(), Box::new(answer));
+        self
+    }
+
+    /// Answers `query` with its registered handler. Handlers derived with `XbusQueryHandler`
+    /// are run this way.
+    pub fn run<Q>(&mut self, query: Q) -> Result<Q::Response, QueryError<Q::Error>>
+    where
+        Q: Query + 'static,
+    {
+        let answer = self
+            .handlers
+            .get_mut(&TypeId::of::<Q>())
+            .and_then(|answer| answer.downcast_mut::<Answer<Q>>())
+            .ok_or_else(|| QueryError::NoHandler(std::any::type_name::<Q>()))?;
+
+        answer(query).map_err(QueryError::Failed)
+    }
+}
+
+impl Actor for QueryBus {
+    type Context = Context<Self>;
+}
+
+/// Sends `query` to the bus and resolves to its handler's answer.
+pub fn ask<Q, R, E>(
+    bus: &Addr<QueryBus>,
+    query: Q,
+) -> impl Future<Item = R, Error = DispatchError<E>>
+where
+    Q: Message<Result = Result<R, QueryError<E>>> + Send + 'static,
+    QueryBus: Handler<Q>,
+    R: Send + 'static,
+    E: Send + 'static,
+{
+    bus.send(query)
+        .map_err(DispatchError::Mailbox)
+        .and_then(|result| {
+            result.map_err(|err| match err {
+                QueryError::NoHandler(query) => {
+                    DispatchError::Rejected(format!("no handler answers {}", query))
+                }
+                QueryError::Failed(err) => DispatchError::Command(err),
+            })
+        })
+}
+
+/// Error result of a query answered by the `QueryBus`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError<E> {
+    /// No handler was registered for the query type.
+    NoHandler(&'static str),
+    Failed(E),
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for QueryError<E> {}
+
+impl<E: fmt::Display> fmt::Display for QueryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::NoHandler(query) => write!(f, "QueryError: no handler for {}", query),
+            QueryError::Failed(err) => write!(f, "QueryError: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query_bus::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct GetBalance(u64);
+
+    impl Query for GetBalance {
+        type Response = u64;
+        type Error = String;
+    }
+
+    /// Balances by account, as projected up to `position`.
+    #[derive(Default)]
+    struct Balances {
+        balances: HashMap<u64, u64>,
+        position: Option<Position>,
+        reads: Cell<u32>,
+    }
+
+    impl QueryHandler<GetBalance> for Rc<RefCell<Balances>> {
+        fn handle(&self, query: GetBalance) -> Result<u64, String> {
+            let balances = self.borrow();
+            balances.reads.set(balances.reads.get() + 1);
+            balances
+                .balances
+                .get(&query.0)
+                .cloned()
+                .ok_or_else(|| format!("no account {}", query.0))
+        }
+
+        fn position(&self) -> Option<Position> {
+            self.borrow().position
+        }
+    }
+
+    fn balances(position: Option<Position>) -> Rc<RefCell<Balances>> {
+        let mut balances = Balances::default();
+        balances.balances.insert(123, 50);
+        balances.position = position;
+        Rc::new(RefCell::new(balances))
+    }
+
+    #[test]
+    fn query_is_answered_by_registered_handler() {
+        // Arrange
+        let mut bus = QueryBus::new().with_handler(balances(None));
+
+        // Act
+        let found = bus.run(GetBalance(123));
+        let missing = bus.run(GetBalance(456));
+
+        // Assert
+        assert_eq!(Ok(50), found);
+        assert_eq!(
+            Err(QueryError::Failed("no account 456".to_owned())),
+            missing
+        );
+    }
+
+    #[test]
+    fn query_without_handler_fails() {
+        // Arrange
+        let mut bus = QueryBus::new();
+
+        // Act
+        let result = bus.run(GetBalance(123));
+
+        // Assert
+        assert_eq!(
+            Err(QueryError::NoHandler(std::any::type_name::<GetBalance>())),
+            result
+        );
+    }
+
+    #[test]
+    fn cached_answer_is_reused_until_position_moves() {
+        // Arrange
+        let balances = balances(Some(3));
+        let mut bus = QueryBus::new().with_cached_handler(balances.clone());
+        bus.run(GetBalance(123)).unwrap();
+
+        // Act
+        let cached = bus.run(GetBalance(123));
+        {
+            let mut balances = balances.borrow_mut();
+            balances.balances.insert(123, 70);
+            balances.position = Some(4);
+        }
+        let refreshed = bus.run(GetBalance(123));
+
+        // Assert
+        assert_eq!(Ok(50), cached);
+        assert_eq!(Ok(70), refreshed);
+        assert_eq!(2, balances.borrow().reads.get());
+    }
+
+    #[test]
+    fn nothing_is_cached_without_position() {
+        // Arrange
+        let balances = balances(None);
+        let mut bus = QueryBus::new().with_cached_handler(balances.clone());
+
+        // Act
+        bus.run(GetBalance(123)).unwrap();
+        bus.run(GetBalance(123)).unwrap();
+
+        // Assert
+        assert_eq!(2, balances.borrow().reads.get());
+    }
+}
@@ -0,0 +1,172 @@
+use actix::prelude::*;
+use futures::Future;
+use std::sync::mpsc;
+use xbus::{dispatch, guard, CommandBus, CommandError, CommandHandler, DispatchError};
+use xbus_derive::{XbusCommand, XbusCommandHandler};
+
+#[derive(Clone, XbusCommand)]
+#[xbus(result = "Vec<u64>", error = "WithdrawError")]
+struct WithdrawMoney {
+    balance: u64,
+    amount: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum WithdrawError {
+    NotEnoughFunds { balance: u64 },
+}
+
+/// Withdraws money.
+#[derive(Debug, XbusCommandHandler)]
+#[handles = "WithdrawMoney"]
+struct WithdrawMoneyHandler;
+
+impl WithdrawMoneyHandler {
+    fn handle(command: WithdrawMoney) -> Result<Vec<u64>, WithdrawError> {
+        if command.amount > command.balance {
+            return Err(WithdrawError::NotEnoughFunds {
+                balance: command.balance,
+            });
+        }
+        Ok(vec![command.amount])
+    }
+}
+
+#[derive(Clone, XbusCommand)]
+struct Ping;
+
+#[derive(XbusCommandHandler)]
+#[handles = "Ping"]
+struct PingHandler;
+
+impl PingHandler {
+    fn handle(_command: Ping) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// Dispatches `command` on `bus` and returns the outcome.
+fn run<C, R, E>(bus: CommandBus, command: C) -> Result<R, DispatchError<E>>
+where
+    C: Message<Result = Result<R, CommandError<E>>> + Send + 'static,
+    CommandBus: Handler<C>,
+    R: Send + 'static,
+    E: Send + 'static,
+{
+    let (outcome, received) = mpsc::channel();
+
+    System::run(move || {
+        let bus = bus.start();
+        Arbiter::spawn(dispatch(&bus, command).then(move |result| {
+            outcome.send(result).unwrap();
+            System::current().stop();
+            Ok(())
+        }));
+    });
+
+    received.recv().unwrap()
+}
+
+#[derive(Clone, XbusCommand)]
+#[xbus(result = "u64")]
+struct OpenAccount;
+
+#[derive(Clone, XbusCommand)]
+#[xbus(result = "u64")]
+struct CloseAccount;
+
+#[derive(XbusCommandHandler)]
+#[handles(OpenAccount, CloseAccount)]
+struct AccountHandler;
+
+impl CommandHandler<OpenAccount> for AccountHandler {
+    type Result = u64;
+    type Error = ();
+
+    fn handle(_command: OpenAccount) -> Result<u64, ()> {
+        Ok(1)
+    }
+}
+
+impl CommandHandler<CloseAccount> for AccountHandler {
+    type Result = u64;
+    type Error = ();
+
+    fn handle(_command: CloseAccount) -> Result<u64, ()> {
+        Ok(2)
+    }
+}
+
+#[test]
+fn handler_returns_typed_result() {
+    // Arrange
+    let command = WithdrawMoney {
+        balance: 49,
+        amount: 9,
+    };
+
+    // Act
+    let result = run(CommandBus::new(), command);
+
+    // Assert
+    assert_eq!(Ok(vec![9]), result.map_err(|_| ()));
+}
+
+#[test]
+fn handler_returns_typed_error() {
+    // Arrange
+    let command = WithdrawMoney {
+        balance: 49,
+        amount: 90,
+    };
+
+    // Act
+    let result = run(CommandBus::new(), command);
+
+    // Assert
+    match result {
+        Err(DispatchError::Command(err)) => {
+            assert_eq!(WithdrawError::NotEnoughFunds { balance: 49 }, err)
+        }
+        _ => panic!("expected the handler's error"),
+    }
+}
+
+#[test]
+fn command_without_types_results_in_unit() {
+    // Act
+    let result = run(CommandBus::new(), Ping);
+
+    // Assert
+    assert!(result.is_ok());
+}
+
+#[test]
+fn middleware_rejection_reaches_sender() {
+    // Arrange
+    let bus = CommandBus::new().with_middleware(guard(|_| Err("not allowed".to_owned())));
+    let command = WithdrawMoney {
+        balance: 49,
+        amount: 9,
+    };
+
+    // Act
+    let result = run(bus, command);
+
+    // Assert
+    match result {
+        Err(DispatchError::Rejected(reason)) => assert_eq!("not allowed", reason),
+        _ => panic!("expected the middleware's rejection"),
+    }
+}
+
+#[test]
+fn handler_handles_every_listed_command() {
+    // Act
+    let opened = run(CommandBus::new(), OpenAccount);
+    let closed = run(CommandBus::new(), CloseAccount);
+
+    // Assert
+    assert_eq!(Ok(1), opened.map_err(|_| ()));
+    assert_eq!(Ok(2), closed.map_err(|_| ()));
+}
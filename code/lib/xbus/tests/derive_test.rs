@@ -0,0 +1,5 @@
+#[test]
+fn derive_misuse_fails_with_clear_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
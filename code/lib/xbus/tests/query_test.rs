@@ -0,0 +1,152 @@
+use actix::prelude::*;
+use eventsourcing::eventstore::Position;
+use futures::Future;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+use xbus::{ask, DispatchError, QueryBus, QueryError, QueryHandler};
+use xbus_derive::{XbusQuery, XbusQueryHandler};
+
+type BankAccountId = String;
+
+#[derive(Clone, PartialEq, Eq, Hash, XbusQuery)]
+#[xbus(result = "u64", error = "UnknownAccount")]
+struct GetBalance(BankAccountId);
+
+#[derive(Clone, PartialEq, Eq, Hash, XbusQuery)]
+#[xbus(result = "Vec<u64>", error = "UnknownAccount")]
+struct ListTransactions {
+    id: BankAccountId,
+    page: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct UnknownAccount;
+
+/// Read model of the bank accounts, projected up to `position`.
+struct Accounts {
+    transactions: HashMap<BankAccountId, Vec<u64>>,
+    position: Position,
+}
+
+const PAGE_SIZE: usize = 2;
+
+#[derive(XbusQueryHandler)]
+#[handles = "GetBalance"]
+struct GetBalanceHandler(Arc<Accounts>);
+
+impl QueryHandler<GetBalance> for GetBalanceHandler {
+    fn handle(&self, query: GetBalance) -> Result<u64, UnknownAccount> {
+        let transactions = self.0.transactions.get(&query.0).ok_or(UnknownAccount)?;
+        Ok(transactions.iter().sum())
+    }
+
+    fn position(&self) -> Option<Position> {
+        Some(self.0.position)
+    }
+}
+
+#[derive(XbusQueryHandler)]
+#[handles = "ListTransactions"]
+struct ListTransactionsHandler(Arc<Accounts>);
+
+impl QueryHandler<ListTransactions> for ListTransactionsHandler {
+    fn handle(&self, query: ListTransactions) -> Result<Vec<u64>, UnknownAccount> {
+        let transactions = self.0.transactions.get(&query.id).ok_or(UnknownAccount)?;
+        Ok(transactions
+            .iter()
+            .skip(query.page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .cloned()
+            .collect())
+    }
+}
+
+fn accounts() -> Arc<Accounts> {
+    let mut transactions = HashMap::new();
+    transactions.insert("123".to_owned(), vec![50, 20, 5]);
+    Arc::new(Accounts {
+        transactions,
+        position: 3,
+    })
+}
+
+/// Asks `query` on `bus` and returns the answer.
+fn run<Q, R, E>(bus: QueryBus, query: Q) -> Result<R, DispatchError<E>>
+where
+    Q: Message<Result = Result<R, QueryError<E>>> + Send + 'static,
+    QueryBus: Handler<Q>,
+    R: Send + 'static,
+    E: Send + 'static,
+{
+    let (outcome, received) = mpsc::channel();
+
+    System::run(move || {
+        let bus = bus.start();
+        Arbiter::spawn(ask(&bus, query).then(move |result| {
+            outcome.send(result).unwrap();
+            System::current().stop();
+            Ok(())
+        }));
+    });
+
+    received.recv().unwrap()
+}
+
+#[test]
+fn query_returns_typed_response() {
+    // Arrange
+    let accounts = accounts();
+    let bus = QueryBus::new()
+        .with_cached_handler(GetBalanceHandler(accounts.clone()))
+        .with_handler(ListTransactionsHandler(accounts));
+
+    // Act
+    let result = run(bus, GetBalance("123".to_owned()));
+
+    // Assert
+    assert_eq!(Ok(75), result.map_err(|_| ()));
+}
+
+#[test]
+fn query_returns_requested_page() {
+    // Arrange
+    let bus = QueryBus::new().with_handler(ListTransactionsHandler(accounts()));
+    let query = ListTransactions {
+        id: "123".to_owned(),
+        page: 1,
+    };
+
+    // Act
+    let result = run(bus, query);
+
+    // Assert
+    assert_eq!(Ok(vec![5]), result.map_err(|_| ()));
+}
+
+#[test]
+fn query_returns_typed_error() {
+    // Arrange
+    let bus = QueryBus::new().with_handler(GetBalanceHandler(accounts()));
+
+    // Act
+    let result = run(bus, GetBalance("456".to_owned()));
+
+    // Assert
+    match result {
+        Err(DispatchError::Command(err)) => assert_eq!(UnknownAccount, err),
+        _ => panic!("expected the handler's error"),
+    }
+}
+
+#[test]
+fn query_without_registered_handler_is_rejected() {
+    // Act
+    let result = run(QueryBus::new(), GetBalance("123".to_owned()));
+
+    // Assert
+    match result {
+        Err(DispatchError::Rejected(reason)) => assert!(reason.contains("GetBalance")),
+        _ => panic!("expected the query to be rejected"),
+    }
+}
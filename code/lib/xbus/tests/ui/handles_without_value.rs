@@ -0,0 +1,10 @@
+use xbus_derive::{XbusCommand, XbusCommandHandler};
+
+#[derive(Clone, XbusCommand)]
+struct Ping;
+
+#[derive(XbusCommandHandler)]
+#[handles]
+struct PingHandler;
+
+fn main() {}
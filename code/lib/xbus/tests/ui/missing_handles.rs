@@ -0,0 +1,10 @@
+use xbus_derive::{XbusCommand, XbusCommandHandler};
+
+#[derive(Clone, XbusCommand)]
+struct Ping;
+
+/// Forgot to say what it handles.
+#[derive(Debug, XbusCommandHandler)]
+struct PingHandler;
+
+fn main() {}
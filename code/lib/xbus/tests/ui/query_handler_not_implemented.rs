@@ -0,0 +1,11 @@
+use xbus_derive::{XbusQuery, XbusQueryHandler};
+
+#[derive(XbusQuery)]
+#[xbus(result = "u64")]
+struct GetBalance;
+
+#[derive(XbusQueryHandler)]
+#[handles = "GetBalance"]
+struct BalanceHandler;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use xbus_derive::XbusCommand;
+
+#[derive(Clone, XbusCommand)]
+#[xbus(reslt = "u64")]
+struct Ping;
+
+fn main() {}
@@ -7,61 +7,195 @@ extern crate syn;
 use proc_macro::TokenStream;
 use syn::export::Span;
 use syn::parse::{Error, Result};
-use syn::{parse_macro_input, Attribute, DeriveInput, Ident, Lit, Meta, MetaNameValue};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, DeriveInput, Lit, Meta, MetaNameValue, NestedMeta,
+    Type,
+};
 
+/// Routes the commands named in `#[handles = "..."]` or `#[handles(A, B)]` to the deriving
+/// type's `handle`. A handler of several commands implements `xbus::CommandHandler` for each.
 #[proc_macro_derive(XbusCommandHandler, attributes(handles))]
 pub fn add_handle(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     let handler_name = ast.ident;
-    let command_name: Ident = get_command(&ast.attrs[0]).unwrap();
+    let command_names = match get_handled(&ast.attrs) {
+        Ok(names) => names,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
 
-    let expanded = quote! {
-        impl Handler<#command_name> for xbus::CommandBus {
-            type Result = Result<(), ()>;
+    let expanded = command_names.iter().map(|command_name| {
+        quote! {
+            impl actix::Handler<#command_name> for xbus::CommandBus {
+                type Result = <#command_name as actix::Message>::Result;
 
-            fn handle(&mut self, command: #command_name, _ctx: &mut Context<Self>) -> Self::Result {
-                #handler_name::handle(command)
+                fn handle(&mut self, command: #command_name, _ctx: &mut actix::Context<Self>) -> Self::Result {
+                    #[allow(unused_imports)]
+                    use xbus::CommandHandler as _;
+                    self.run(command, |command: #command_name, _| #handler_name::handle(command))
+                }
             }
         }
-    };
-    return TokenStream::from(expanded);
+    });
+    TokenStream::from(quote!(#(#expanded)*))
 }
 
-fn get_command(attr: &Attribute) -> Result<Ident> {
-    let meta = match attr.interpret_meta() {
-        Some(meta) => meta,
-        None => return Err(Error::new(Span::call_site(), "An error...")),
-    };
+/// The types listed by the `#[handles]` attributes, wherever they are among `attrs`.
+fn get_handled(attrs: &[Attribute]) -> Result<Vec<Type>> {
+    let message = "expected #[handles = \"...\"] or #[handles(...)]";
+    let mut handled = Vec::new();
 
-    if meta.name() != "handles" {
-        let message = "What would you like me to handle?";
-        return Err(Error::new(Span::call_site(), message));
-    }
+    for attr in attrs {
+        if !attr.path.is_ident("handles") {
+            continue;
+        }
 
-    match meta {
-        Meta::NameValue(MetaNameValue {
-            lit: Lit::Str(lit_str),
-            ..
-        }) => lit_str.parse(),
-        _ => {
-            let error_span = attr.bracket_token.span;
-            let message = "expected #[handles = \"...\"]";
-            Err(Error::new(error_span, message))
+        match attr.parse_meta()? {
+            Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(lit_str),
+                ..
+            }) => handled.push(lit_str.parse()?),
+            Meta::NameValue(MetaNameValue { lit, .. }) => {
+                return Err(Error::new(lit.span(), message));
+            }
+            Meta::List(list) => {
+                if list.nested.is_empty() {
+                    let message = "#[handles(...)] needs at least one type";
+                    return Err(Error::new(list.paren_token.span, message));
+                }
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(Meta::Word(ident)) => handled.push(parse_quote!(#ident)),
+                        NestedMeta::Literal(Lit::Str(lit_str)) => handled.push(lit_str.parse()?),
+                        other => return Err(Error::new(other.span(), message)),
+                    }
+                }
+            }
+            Meta::Word(ident) => return Err(Error::new(ident.span(), message)),
         }
     }
+
+    if handled.is_empty() {
+        let message = "missing #[handles = \"...\"] or #[handles(...)] naming what this handles";
+        return Err(Error::new(Span::call_site(), message));
+    }
+    Ok(handled)
 }
 
-#[proc_macro_derive(XbusCommand, attributes(handles))]
+/// Commands succeed and fail with `()` unless they name other types in
+/// `#[xbus(result = "...", error = "...")]`. They have to be `Clone` so that middleware can
+/// retry them.
+#[proc_macro_derive(XbusCommand, attributes(xbus))]
 pub fn add_command_message(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     let command_name = ast.ident;
+    let (success, error) = match get_result_types(&ast.attrs) {
+        Ok(types) => types,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
 
     let expanded = quote! {
         impl actix::Message for #command_name {
-            type Result = std::result::Result<(), ()>;
+            type Result = std::result::Result<#success, xbus::CommandError<#error>>;
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+/// Queries answer with `()` unless they name other types in
+/// `#[xbus(result = "...", error = "...")]`.
+#[proc_macro_derive(XbusQuery, attributes(xbus))]
+pub fn add_query_message(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    let query_name = ast.ident;
+    let (response, error) = match get_result_types(&ast.attrs) {
+        Ok(types) => types,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    let expanded = quote! {
+        impl actix::Message for #query_name {
+            type Result = std::result::Result<#response, xbus::QueryError<#error>>;
+        }
+
+        impl xbus::Query for #query_name {
+            type Response = #response;
+            type Error = #error;
         }
     };
-    return TokenStream::from(expanded);
+    TokenStream::from(expanded)
+}
+
+/// Routes the queries named in `#[handles = "..."]` or `#[handles(A, B)]` to whichever
+/// handler the `QueryBus` was given for each. The deriving type has to implement
+/// `xbus::QueryHandler` for all of them.
+#[proc_macro_derive(XbusQueryHandler, attributes(handles))]
+pub fn add_query_handle(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    let handler_name = ast.ident;
+    let query_names = match get_handled(&ast.attrs) {
+        Ok(names) => names,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    let expanded = query_names.iter().map(|query_name| {
+        quote! {
+            impl actix::Handler<#query_name> for xbus::QueryBus {
+                type Result = <#query_name as actix::Message>::Result;
+
+                fn handle(&mut self, query: #query_name, _ctx: &mut actix::Context<Self>) -> Self::Result {
+                    self.run(query)
+                }
+            }
+
+            const _: fn() = || {
+                fn answers<H: xbus::QueryHandler<#query_name>>() {}
+                answers::<#handler_name>();
+            };
+        }
+    });
+    TokenStream::from(quote!(#(#expanded)*))
+}
+
+fn get_result_types(attrs: &[Attribute]) -> Result<(Type, Type)> {
+    let mut success: Type = parse_quote!(());
+    let mut error: Type = parse_quote!(());
+
+    let message = "expected #[xbus(result = \"...\", error = \"...\")]";
+
+    for attr in attrs {
+        if !attr.path.is_ident("xbus") {
+            continue;
+        }
+
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            other => return Err(Error::new(other.span(), message)),
+        };
+
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                    ident,
+                    lit: Lit::Str(lit_str),
+                    ..
+                })) => {
+                    if ident == "result" {
+                        success = lit_str.parse()?;
+                    } else if ident == "error" {
+                        error = lit_str.parse()?;
+                    } else {
+                        return Err(Error::new(ident.span(), message));
+                    }
+                }
+                other => return Err(Error::new(other.span(), message)),
+            }
+        }
+    }
+
+    Ok((success, error))
 }
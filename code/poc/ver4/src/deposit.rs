@@ -2,22 +2,25 @@ use crate::prelude::*;
 use std::sync::Arc;
 
 pub struct DepositHandler {
-    pub repository: Arc<BankAccountRepository>,
+    pub context: HandlerContext,
 }
 
 impl DepositHandler {
-    pub fn new(repository: Arc<BankAccountRepository>) -> DepositHandler {
+    pub fn new<C>(context: C) -> DepositHandler
+    where
+        C: Into<HandlerContext>,
+    {
         DepositHandler {
-            repository: repository,
+            context: context.into(),
         }
     }
 
     pub fn handle(&self, command: DepositMoney) -> Result<(), BankAccountError> {
-        let repo = Arc::clone(&self.repository);
+        let repo = Arc::clone(&self.context.repository);
 
-        let mut agg = self.repository.load(command.id)?;
+        let mut agg = self.context.repository.load(command.id)?;
 
-        agg.deposit(command)?;
+        agg.deposit(command, self.context.clock())?;
 
         let _result = repo.save(agg)?;
 
@@ -28,6 +31,7 @@ impl DepositHandler {
 #[cfg(test)]
 mod tests {
     use crate::prelude::*;
+    use crate::test_helpers::{at, NOW};
     use std::sync::Arc;
 
     type GetEventsResult = Result<Vec<BankAccountEvent>, BankAccountEventStoreError>;
@@ -38,7 +42,7 @@ mod tests {
         // Arrange
         let event_store = Arc::new(TestBankAccountEventStore {});
         let repo = Arc::new(BankAccountRepository::new(event_store));
-        let handler = DepositHandler::new(repo);
+        let handler = DepositHandler::new(HandlerContext::new(repo).with_clock(NOW));
 
         // Act
         let result = handler.handle(DepositMoney::new(100, 49));
@@ -51,7 +55,7 @@ mod tests {
 
     impl BankAccountEventStore for TestBankAccountEventStore {
         fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult {
-            let expected = vec![BankAccountEvent::credited(100, 49)];
+            let expected = vec![BankAccountEvent::credited(100, 49, at())];
             match events == expected {
                 true => Ok(()),
                 false => Err(BankAccountEventStoreError::TestFailed),
@@ -59,7 +63,7 @@ mod tests {
         }
 
         fn get_events(&self, _id: BankAccountId) -> GetEventsResult {
-            Ok(vec![BankAccountEvent::acc_opened(100, 20)])
+            Ok(vec![BankAccountEvent::acc_opened(100, 20, at())])
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::model::{BankAccountId, CustomerId};
 use chrono::prelude::*;
+use eventsourcing::clock::Clock;
 
 //
 //     Events
@@ -52,34 +53,48 @@ pub struct BankAccountWithdrawalRefused {
     pub refused_at: DateTime<Utc>,
 }
 
+/// The time `clock` tells, to the second, as events keep it.
+pub fn event_time(clock: &dyn Clock) -> DateTime<Utc> {
+    Utc.timestamp_millis(clock.now() as i64).round_subsecs(0)
+}
+
 impl BankAccountEvent {
-    pub fn acc_opened(id: BankAccountId, customer_id: CustomerId) -> BankAccountEvent {
+    pub fn acc_opened(
+        id: BankAccountId,
+        customer_id: CustomerId,
+        at: DateTime<Utc>,
+    ) -> BankAccountEvent {
         BankAccountEvent::BankAccountOpened(BankAccountOpened {
             id: id,
             customer_id: customer_id,
-            opened_at: Utc::now().round_subsecs(0),
+            opened_at: at,
         })
     }
-    pub fn credited(id: BankAccountId, amount: u64) -> BankAccountEvent {
+    pub fn credited(id: BankAccountId, amount: u64, at: DateTime<Utc>) -> BankAccountEvent {
         BankAccountEvent::Credited(BankAccountCredited {
             id: id,
             amount: amount,
-            credited_at: Utc::now().round_subsecs(0),
+            credited_at: at,
         })
     }
-    pub fn debited(id: BankAccountId, amount: u64) -> BankAccountEvent {
+    pub fn debited(id: BankAccountId, amount: u64, at: DateTime<Utc>) -> BankAccountEvent {
         BankAccountEvent::Debited(BankAccountDebited {
             id: id,
             amount: amount,
-            debited_at: Utc::now().round_subsecs(0),
+            debited_at: at,
         })
     }
-    pub fn withdrawal_refused(id: BankAccountId, amount: u64, balance: u64) -> BankAccountEvent {
+    pub fn withdrawal_refused(
+        id: BankAccountId,
+        amount: u64,
+        balance: u64,
+        at: DateTime<Utc>,
+    ) -> BankAccountEvent {
         BankAccountEvent::WithdrawalRefused(BankAccountWithdrawalRefused {
             id: id,
             amount: amount,
             balance: balance,
-            refused_at: Utc::now().round_subsecs(0),
+            refused_at: at,
         })
     }
 }
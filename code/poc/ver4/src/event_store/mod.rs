@@ -1,6 +1,9 @@
-use crate::event::BankAccountEvent;
+use crate::event::{event_time, BankAccountEvent};
 use crate::model::BankAccountError;
 use crate::model::BankAccountId;
+use chrono::prelude::*;
+use eventsourcing::clock::{Clock, SystemClock};
+use eventsourcing::id::{Id, IdGenerator, RandomIds};
 use std::sync::Mutex;
 use std::{error::Error, fmt};
 
@@ -12,35 +15,81 @@ pub trait BankAccountEventStore {
     fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult;
 }
 
+/// An event together with the id and time the store gave it.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StoredEvent {
+    pub id: Id,
+    pub stored_at: DateTime<Utc>,
+    pub event: BankAccountEvent,
+}
+
 pub struct InMemoryBankAccountEventStore {
-    pub events: Mutex<Vec<BankAccountEvent>>,
+    pub events: Mutex<Vec<StoredEvent>>,
+    clock: Box<dyn Clock + Send + Sync>,
+    ids: Box<dyn IdGenerator + Send + Sync>,
 }
 
 impl InMemoryBankAccountEventStore {
     pub fn new() -> InMemoryBankAccountEventStore {
         InMemoryBankAccountEventStore {
             events: Mutex::new(Vec::new()),
+            clock: Box::new(SystemClock),
+            ids: Box::new(RandomIds),
         }
     }
+
+    pub fn with_clock<C>(mut self, clock: C) -> InMemoryBankAccountEventStore
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_ids<G>(mut self, ids: G) -> InMemoryBankAccountEventStore
+    where
+        G: IdGenerator + Send + Sync + 'static,
+    {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    pub fn stored_events(&self, id: BankAccountId) -> Vec<StoredEvent> {
+        let m_entities = self.events.lock().unwrap();
+        m_entities
+            .iter()
+            .filter(|stored| id == stored.event.get_aggregate_id())
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for InMemoryBankAccountEventStore {
+    fn default() -> Self {
+        InMemoryBankAccountEventStore::new()
+    }
 }
 
 impl BankAccountEventStore for InMemoryBankAccountEventStore {
     fn get_events(&self, id: BankAccountId) -> GetEventsResult {
-        let m_entities = self.events.lock().unwrap();
-        let mut values = Vec::new();
-        for value in m_entities.iter() {
-            if id == value.get_aggregate_id() {
-                values.push((*value).clone());
-            }
-        }
+        let values = self
+            .stored_events(id)
+            .into_iter()
+            .map(|stored| stored.event)
+            .collect();
 
         Ok(values)
     }
     fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult {
         let mut m_entities = self.events.lock().unwrap();
+        let stored_at = event_time(&*self.clock);
 
         for event in events {
-            m_entities.push(event.clone());
+            m_entities.push(StoredEvent {
+                id: self.ids.next_id(),
+                stored_at,
+                event,
+            });
         }
 
         Ok(())
@@ -68,8 +117,10 @@ impl From<BankAccountEventStoreError> for BankAccountError {
 
 #[cfg(test)]
 mod tests {
-    use crate::event_store::{BankAccountEventStore, InMemoryBankAccountEventStore};
+    use crate::event_store::{BankAccountEventStore, InMemoryBankAccountEventStore, StoredEvent};
     use crate::prelude::BankAccountEvent;
+    use crate::test_helpers::{at, NOW};
+    use eventsourcing::id::SequentialIds;
 
     #[test]
     fn check_get_events_returns_only_events_with_expected_id() {
@@ -77,10 +128,10 @@ mod tests {
         let event_store = InMemoryBankAccountEventStore::new();
 
         let events = vec![
-            BankAccountEvent::acc_opened(100, 20),
-            BankAccountEvent::acc_opened(101, 20),
+            BankAccountEvent::acc_opened(100, 20, at()),
+            BankAccountEvent::acc_opened(101, 20, at()),
         ];
-        let expected = vec![BankAccountEvent::acc_opened(100, 20)];
+        let expected = vec![BankAccountEvent::acc_opened(100, 20, at())];
 
         match event_store.save_events(events) {
             Ok(_) => println!("Events saved"),
@@ -93,4 +144,35 @@ mod tests {
         // Assert
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn saved_events_get_id_and_time_of_storing() {
+        // Arrange
+        let event_store = InMemoryBankAccountEventStore::new()
+            .with_clock(NOW)
+            .with_ids(SequentialIds::new(1));
+        let opened = BankAccountEvent::acc_opened(100, 20, at());
+        let credited = BankAccountEvent::credited(100, 49, at());
+        let stored_at = at();
+
+        // Act
+        event_store.save_events(vec![opened, credited]).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                StoredEvent {
+                    id: 1,
+                    stored_at,
+                    event: opened,
+                },
+                StoredEvent {
+                    id: 2,
+                    stored_at,
+                    event: credited,
+                },
+            ],
+            event_store.stored_events(100)
+        );
+    }
 }
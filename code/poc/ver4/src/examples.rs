@@ -2,12 +2,13 @@ use crate::command::DepositMoney;
 use crate::command::OpenBankAccount;
 use crate::command::WithdrawMoney;
 use crate::deposit::DepositHandler;
-use crate::event::BankAccountEvent;
+use crate::event::{event_time, BankAccountEvent};
 use crate::event_store::BankAccountEventStore;
 use crate::event_store::InMemoryBankAccountEventStore;
 use crate::open_bank_account::OpenBankAccountHandler;
 use crate::repository::BankAccountRepository;
 use crate::withdraw::WithdrawHandler;
+use eventsourcing::clock::SystemClock;
 use std::sync::Arc;
 
 pub fn examples() {
@@ -34,7 +35,8 @@ fn example_open_bank_account() {
 }
 
 fn example_deposit_money() {
-    let initial_events = vec![BankAccountEvent::acc_opened(100, 20)];
+    let now = event_time(&SystemClock);
+    let initial_events = vec![BankAccountEvent::acc_opened(100, 20, now)];
 
     let (repo, event_store) = build_repo(initial_events);
 
@@ -51,9 +53,10 @@ fn example_deposit_money() {
 }
 
 fn example_withdraw_money() {
+    let now = event_time(&SystemClock);
     let initial_events = vec![
-        BankAccountEvent::acc_opened(100, 20),
-        BankAccountEvent::credited(100, 49),
+        BankAccountEvent::acc_opened(100, 20, now),
+        BankAccountEvent::credited(100, 49, now),
     ];
     let (repo, event_store) = build_repo(initial_events);
 
@@ -70,9 +73,10 @@ fn example_withdraw_money() {
 }
 
 fn example_withdraw_refused() {
+    let now = event_time(&SystemClock);
     let initial_events = vec![
-        BankAccountEvent::acc_opened(100, 20),
-        BankAccountEvent::credited(100, 49),
+        BankAccountEvent::acc_opened(100, 20, now),
+        BankAccountEvent::credited(100, 49, now),
     ];
     let (repo, event_store) = build_repo(initial_events);
 
@@ -0,0 +1,37 @@
+use crate::repository::BankAccountRepository;
+use eventsourcing::clock::{Clock, SystemClock};
+use std::sync::Arc;
+
+/// What every command handler works with: the repository it loads and saves accounts
+/// through and the clock telling when the events of handled commands happen.
+pub struct HandlerContext {
+    pub repository: Arc<BankAccountRepository>,
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl HandlerContext {
+    pub fn new(repository: Arc<BankAccountRepository>) -> HandlerContext {
+        HandlerContext {
+            repository: repository,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock<C>(mut self, clock: C) -> HandlerContext
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+}
+
+impl From<Arc<BankAccountRepository>> for HandlerContext {
+    fn from(repository: Arc<BankAccountRepository>) -> HandlerContext {
+        HandlerContext::new(repository)
+    }
+}
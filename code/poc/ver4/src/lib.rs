@@ -3,8 +3,11 @@ mod deposit;
 mod event;
 mod event_store;
 pub mod examples;
+mod handler;
 mod model;
 mod open_bank_account;
 pub mod prelude;
 mod repository;
+#[cfg(test)]
+mod test_helpers;
 mod withdraw;
@@ -1,5 +1,8 @@
 use crate::command::{DepositMoney, OpenBankAccount, WithdrawMoney};
-use crate::event::{BankAccountCredited, BankAccountDebited, BankAccountEvent, BankAccountOpened};
+use crate::event::{
+    event_time, BankAccountCredited, BankAccountDebited, BankAccountEvent, BankAccountOpened,
+};
+use eventsourcing::clock::Clock;
 use std::{error::Error, fmt};
 
 //
@@ -77,8 +80,8 @@ impl BankAccountAggregate {
 }
 
 impl BankAccountAggregate {
-    pub fn open_acc(input: OpenBankAccount) -> FactoryResult {
-        let event = BankAccountEvent::acc_opened(input.id, input.customer_id);
+    pub fn open_acc(input: OpenBankAccount, clock: &dyn Clock) -> FactoryResult {
+        let event = BankAccountEvent::acc_opened(input.id, input.customer_id, event_time(clock));
 
         let mut aggregate = BankAccountAggregate::new();
         aggregate.record_event(event)?;
@@ -86,20 +89,23 @@ impl BankAccountAggregate {
         Ok(aggregate)
     }
 
-    pub fn deposit(&mut self, input: DepositMoney) -> OkOrError {
-        let event = BankAccountEvent::credited(input.id, input.amount);
+    pub fn deposit(&mut self, input: DepositMoney, clock: &dyn Clock) -> OkOrError {
+        let event = BankAccountEvent::credited(input.id, input.amount, event_time(clock));
 
         self.record_event(event)?;
 
         Ok(())
     }
 
-    pub fn withdraw(&mut self, input: WithdrawMoney) -> OkOrError {
+    pub fn withdraw(&mut self, input: WithdrawMoney, clock: &dyn Clock) -> OkOrError {
         let state = self.get_state()?;
+        let at = event_time(clock);
 
         let event = match state.balance >= input.amount {
-            true => BankAccountEvent::debited(input.id, input.amount),
-            false => BankAccountEvent::withdrawal_refused(input.id, input.amount, state.balance),
+            true => BankAccountEvent::debited(input.id, input.amount, at),
+            false => {
+                BankAccountEvent::withdrawal_refused(input.id, input.amount, state.balance, at)
+            }
         };
 
         self.record_event(event)?;
@@ -2,19 +2,24 @@ use crate::prelude::*;
 use std::sync::Arc;
 
 pub struct OpenBankAccountHandler {
-    pub repository: Arc<BankAccountRepository>,
+    pub context: HandlerContext,
 }
 
 impl OpenBankAccountHandler {
-    pub fn new(repository: Arc<BankAccountRepository>) -> OpenBankAccountHandler {
+    pub fn new<C>(context: C) -> OpenBankAccountHandler
+    where
+        C: Into<HandlerContext>,
+    {
         OpenBankAccountHandler {
-            repository: repository,
+            context: context.into(),
         }
     }
+
     pub fn handle(&self, command: OpenBankAccount) -> Result<(), BankAccountError> {
-        let agg: BankAccountAggregate = BankAccountAggregate::open_acc(command)?;
+        let agg: BankAccountAggregate =
+            BankAccountAggregate::open_acc(command, self.context.clock())?;
 
-        let repo = Arc::clone(&self.repository);
+        let repo = Arc::clone(&self.context.repository);
 
         let _result = repo.save(agg)?;
 
@@ -25,6 +30,7 @@ impl OpenBankAccountHandler {
 #[cfg(test)]
 mod tests {
     use crate::prelude::*;
+    use crate::test_helpers::{at, NOW};
     use std::sync::Arc;
 
     type GetEventsResult = Result<Vec<BankAccountEvent>, BankAccountEventStoreError>;
@@ -35,7 +41,7 @@ mod tests {
         // Arrange
         let event_store = Arc::new(TestBankAccountEventStore {});
         let repo = Arc::new(BankAccountRepository::new(event_store));
-        let handler = OpenBankAccountHandler::new(repo);
+        let handler = OpenBankAccountHandler::new(HandlerContext::new(repo).with_clock(NOW));
 
         // Act
         let result = handler.handle(OpenBankAccount::new(100, 20));
@@ -48,7 +54,7 @@ mod tests {
 
     impl BankAccountEventStore for TestBankAccountEventStore {
         fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult {
-            let expected = vec![BankAccountEvent::acc_opened(100, 20)];
+            let expected = vec![BankAccountEvent::acc_opened(100, 20, at())];
             match events == expected {
                 true => Ok(()),
                 false => Err(BankAccountEventStoreError::TestFailed),
@@ -59,5 +65,4 @@ mod tests {
             unimplemented!()
         }
     }
-
 }
@@ -1,7 +1,10 @@
 pub use crate::command::{DepositMoney, OpenBankAccount, WithdrawMoney};
 pub use crate::deposit::DepositHandler;
-pub use crate::event::BankAccountEvent;
-pub use crate::event_store::{BankAccountEventStore, BankAccountEventStoreError};
+pub use crate::event::{event_time, BankAccountEvent};
+pub use crate::event_store::{
+    BankAccountEventStore, BankAccountEventStoreError, InMemoryBankAccountEventStore, StoredEvent,
+};
+pub use crate::handler::HandlerContext;
 pub use crate::model::{BankAccountAggregate, BankAccountError, BankAccountId, BankAccountState};
 pub use crate::open_bank_account::OpenBankAccountHandler;
 pub use crate::repository::{BankAccountRepository, BankAccountRepositoryError};
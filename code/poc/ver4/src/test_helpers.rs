@@ -0,0 +1,11 @@
+use crate::event::event_time;
+use chrono::prelude::*;
+use eventsourcing::clock::FixedClock;
+
+/// The clock every test runs at.
+pub const NOW: FixedClock = FixedClock(1_546_300_800_000);
+
+/// The event time recorded at `NOW`.
+pub fn at() -> DateTime<Utc> {
+    event_time(&NOW)
+}
@@ -2,21 +2,25 @@ use crate::prelude::*;
 use std::sync::Arc;
 
 pub struct WithdrawHandler {
-    pub repository: Arc<BankAccountRepository>,
+    pub context: HandlerContext,
 }
 
 impl WithdrawHandler {
-    pub fn new(repository: Arc<BankAccountRepository>) -> WithdrawHandler {
+    pub fn new<C>(context: C) -> WithdrawHandler
+    where
+        C: Into<HandlerContext>,
+    {
         WithdrawHandler {
-            repository: repository,
+            context: context.into(),
         }
     }
+
     pub fn handle(&self, command: WithdrawMoney) -> Result<(), BankAccountError> {
-        let repo = Arc::clone(&self.repository);
+        let repo = Arc::clone(&self.context.repository);
 
-        let mut agg = self.repository.load(command.id)?;
+        let mut agg = self.context.repository.load(command.id)?;
 
-        agg.withdraw(command)?;
+        agg.withdraw(command, self.context.clock())?;
 
         let _result = repo.save(agg)?;
 
@@ -27,6 +31,7 @@ impl WithdrawHandler {
 #[cfg(test)]
 mod tests {
     use crate::prelude::*;
+    use crate::test_helpers::{at, NOW};
     use std::sync::Arc;
 
     type GetEventsResult = Result<Vec<BankAccountEvent>, BankAccountEventStoreError>;
@@ -37,7 +42,7 @@ mod tests {
         // Arrange
         let event_store = Arc::new(TestBankAccountEventStore {});
         let repo = Arc::new(BankAccountRepository::new(event_store));
-        let handler = WithdrawHandler::new(repo);
+        let handler = WithdrawHandler::new(HandlerContext::new(repo).with_clock(NOW));
 
         // Act
         let result = handler.handle(WithdrawMoney::new(100, 7));
@@ -51,7 +56,7 @@ mod tests {
         // Arrange
         let event_store = Arc::new(Test2BankAccountEventStore {});
         let repo = BankAccountRepository::new(event_store);
-        let handler = WithdrawHandler::new(Arc::new(repo));
+        let handler = WithdrawHandler::new(HandlerContext::new(Arc::new(repo)).with_clock(NOW));
 
         // Act
         let result = handler.handle(WithdrawMoney::new(100, 70));
@@ -66,7 +71,7 @@ mod tests {
 
     impl BankAccountEventStore for TestBankAccountEventStore {
         fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult {
-            let expected = vec![BankAccountEvent::debited(100, 7)];
+            let expected = vec![BankAccountEvent::debited(100, 7, at())];
             match events == expected {
                 true => Ok(()),
                 false => Err(BankAccountEventStoreError::TestFailed),
@@ -75,15 +80,15 @@ mod tests {
 
         fn get_events(&self, _id: BankAccountId) -> GetEventsResult {
             Ok(vec![
-                BankAccountEvent::acc_opened(100, 20),
-                BankAccountEvent::credited(100, 49),
+                BankAccountEvent::acc_opened(100, 20, at()),
+                BankAccountEvent::credited(100, 49, at()),
             ])
         }
     }
 
     impl BankAccountEventStore for Test2BankAccountEventStore {
         fn save_events(&self, events: Vec<BankAccountEvent>) -> SaveEventsResult {
-            let expected = vec![BankAccountEvent::withdrawal_refused(100, 70, 49)];
+            let expected = vec![BankAccountEvent::withdrawal_refused(100, 70, 49, at())];
             match events == expected {
                 true => Ok(()),
                 false => Err(BankAccountEventStoreError::TestFailed),
@@ -92,10 +97,9 @@ mod tests {
 
         fn get_events(&self, _id: BankAccountId) -> GetEventsResult {
             Ok(vec![
-                BankAccountEvent::acc_opened(100, 20),
-                BankAccountEvent::credited(100, 49),
+                BankAccountEvent::acc_opened(100, 20, at()),
+                BankAccountEvent::credited(100, 49, at()),
             ])
         }
     }
-
 }